@@ -27,7 +27,7 @@ echo "Base directory: $BASE_DIR"
 /bin/bash -c "cd $BASE_DIR && $CARGO_LOC build $CARGO_BUILD_FLAG" > "$BASE_DIR"/build.log 2>&1
 
 rm -rf "$BASE_DIR"/build
-mkdir -p "$BASE_DIR"/build/resources/definitions
+mkdir -p "$BASE_DIR"/build/resources/definitions/consumer_def
 mkdir -p "$BASE_DIR"/build/resources/certs/proxy
 cp -vf "$BASE_DIR"/target/"$BUILD_MODE"/Gateman "$BASE_DIR"/build/
 chmod go-rwx "$BASE_DIR"/build/Gateman
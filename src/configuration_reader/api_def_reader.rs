@@ -1,5 +1,13 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use log::{debug, trace};
+use serde::de::Error;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::configuration_reader::origin_def_reader::{RateLimitKey, RateLimiterConfig};
+use crate::core::ip_access::IpAccessRules;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct APISpecification {
@@ -8,6 +16,287 @@ pub struct APISpecification {
     pub(crate) hostnames: Vec<String>,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TrafficMirrorConfig {
+    pub(crate) origin_id: String,
+    pub(crate) percentage: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HedgingConfig {
+    pub(crate) latency_percentile: f64,
+    pub(crate) min_delay: u64,
+    pub(crate) budget_percentage: f64,
+}
+
+/// `name`, unique among the rate limit policies of the API, keeps the state of
+/// the limiter when the policy is changed in ways that alter what it selects.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct APIRateLimitPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) methods: Vec<String>,
+    pub(crate) rate_limiter: RateLimiterConfig,
+}
+
+impl APIRateLimitPolicy {
+    pub fn applies_to(&self, method: &str) -> bool {
+        self.methods.is_empty() || self.methods.iter().any(|allowed| allowed == method)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum QuotaPeriod {
+    Day,
+    Month,
+}
+
+/// Allows `limit` requests per calendar day or month, counted per `key` when
+/// one is given and for the whole API otherwise. Periods start at midnight in
+/// `timezone` (an IANA name, UTC by default). `name`, unique among the quotas of
+/// the API, keeps the counters when the period or key of the quota is changed.
+/// Up to `max_keys` keys (10000 by default) are counted in a period; further keys
+/// share one counter.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct QuotaPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    pub(crate) period: QuotaPeriod,
+    pub(crate) limit: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) key: Option<RateLimitKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_keys: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) timezone: Option<String>,
+}
+
+/// Requires the API key of a registered consumer, read from `header` (x-api-key
+/// unless configured) or from `query_parameter` when one is configured. An empty
+/// `allowed_consumers` admits every consumer. The consumer ID is forwarded to the
+/// Origin in `consumer_header` (X-Consumer-ID unless configured).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct APIKeyAuthConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) header: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) query_parameter: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) allowed_consumers: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) consumer_header: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JwtAlgorithm {
+    HS256,
+    RS256,
+    ES256,
+}
+
+/// Requires a bearer JWT signed with the HS256 `secret`, the public key in the
+/// PEM file at `public_key_file`, or a key of the JWKS document at `jwks` (a file
+/// path or an http(s) URL, refetched every `jwks_refresh_interval` seconds).
+/// Relative paths are resolved against the directory of the executable. `exp` is
+/// required, `nbf` is checked when present, and `issuer` and `audiences` when
+/// configured, with `leeway` seconds of clock skew (60 unless configured). Tokens
+/// must carry every scope of `required_scopes` in their `scope` or `scp` claim
+/// and every value of `required_claims`. Claims in `claim_headers` are forwarded
+/// to the Origin in the mapped header.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JwtAuthConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) algorithms: Vec<JwtAlgorithm>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) public_key_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) jwks: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) jwks_refresh_interval: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) issuer: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) audiences: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) leeway: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) required_scopes: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) required_claims: HashMap<String, Value>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) claim_headers: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) realm: Option<String>,
+}
+
+/// What callers get while the introspection endpoint cannot be reached. Requests
+/// are never admitted without an answer, only tokens cached as active are.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IntrospectionFailureMode {
+    #[default]
+    ServiceUnavailable,
+    Unauthorized,
+}
+
+/// Requires a bearer token that the RFC 7662 `introspection_endpoint` reports as
+/// active, authenticating to it as `client_id` with `client_secret`. Active
+/// results are cached until the token expires, but for at most
+/// `max_cache_duration` seconds (300 unless configured). `timeout` is in
+/// milliseconds. Scopes, claims and claim headers work as for JWTs.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IntrospectionAuthConfig {
+    pub(crate) introspection_endpoint: String,
+    pub(crate) client_id: String,
+    pub(crate) client_secret: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_cache_duration: Option<u64>,
+    #[serde(default)]
+    pub(crate) failure_mode: IntrospectionFailureMode,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) required_scopes: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) required_claims: HashMap<String, Value>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) claim_headers: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) realm: Option<String>,
+}
+
+/// Requires HTTP Basic credentials of a user of the htpasswd file at
+/// `htpasswd_file`, resolved against the directory of the executable if relative.
+/// Passwords may be hashed with bcrypt, SHA-1 (`{SHA}`) or MD5 (`$apr1$`), and
+/// the file is reloaded when it changes. `realm` is sent in the challenge, and
+/// the `Authorization` header is removed before forwarding if
+/// `strip_authorization` is set.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BasicAuthConfig {
+    pub(crate) htpasswd_file: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) realm: Option<String>,
+    #[serde(default)]
+    pub(crate) strip_authorization: bool,
+}
+
+/// Logs browsers in at an OpenID Connect provider with the authorization code
+/// flow and PKCE. Page loads without a session are redirected to the
+/// `authorization_endpoint` (other requests get a 401), and the provider
+/// redirects back to `redirect_uri`, whose path the API must cover. The code is exchanged at the `token_endpoint`, the ID token verified
+/// with the keys of `jwks`, and the session is kept in a cookie encrypted with
+/// `session_secret`. It ends after `session_duration` seconds (8 hours unless
+/// configured), and expired ID tokens are refreshed in the meantime if the
+/// provider issued a refresh token. `scopes` default to `openid`; claims of the
+/// ID token work as for JWTs.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OidcAuthConfig {
+    pub(crate) issuer: String,
+    pub(crate) authorization_endpoint: String,
+    pub(crate) token_endpoint: String,
+    pub(crate) jwks: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) jwks_refresh_interval: Option<u64>,
+    pub(crate) client_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) client_secret: Option<String>,
+    pub(crate) redirect_uri: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) scopes: Vec<String>,
+    pub(crate) session_secret: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cookie_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) session_duration: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) leeway: Option<u64>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) required_claims: HashMap<String, Value>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) claim_headers: HashMap<String, String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum AuthPolicy {
+    ApiKey(APIKeyAuthConfig),
+    Jwt(Box<JwtAuthConfig>),
+    Introspection(Box<IntrospectionAuthConfig>),
+    Oidc(Box<OidcAuthConfig>),
+    Basic(BasicAuthConfig),
+}
+
+/// Requires a client certificate verified by the TLS listener. When configured,
+/// its subject must match one of `subject_patterns` and one of its subject
+/// alternative names one of `san_patterns` (glob patterns such as
+/// `spiffe://acme/*`). The subject, the SANs and the SHA-256 fingerprint of the
+/// certificate are forwarded to the Origin in `subject_header`, `san_header` and
+/// `fingerprint_header` (X-Client-Cert-Subject, X-Client-Cert-SAN and
+/// X-Client-Cert-Fingerprint unless configured).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ClientCertPolicy {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) subject_patterns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) san_patterns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) subject_header: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) san_header: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) fingerprint_header: Option<String>,
+}
+
+/// Client IP addresses admitted to the API, as IPv4/IPv6 CIDR ranges. Addresses
+/// in `deny` are rejected; when `allow` is not empty, only addresses in it are
+/// admitted.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IpAccessList {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) allow: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) deny: Vec<String>,
+    /// Parsed from `allow` and `deny` when the definition is loaded.
+    #[serde(skip)]
+    pub(crate) rules: Option<Arc<IpAccessRules>>,
+}
+
+/// Cross-origin access for browser clients. `allowed_origins` holds origins or
+/// glob patterns such as `https://*.example.com`, `*` admitting every origin.
+/// `allow_credentials` only applies to origins listed or matched by a pattern:
+/// origins only admitted by `*` are answered with `*` and never with credentials,
+/// as browsers would otherwise send cookies for any site. Without `allowed_methods`, the methods of the API specification are allowed.
+/// `allowed_headers` may be `*` to allow every request header. The gateway
+/// answers preflight requests itself and replaces CORS headers of the Origin.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CorsPolicy {
+    pub(crate) allowed_origins: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) allowed_methods: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) allowed_headers: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) exposed_headers: Vec<String>,
+    #[serde(default)]
+    pub(crate) allow_credentials: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_age: Option<u64>,
+}
+
+/// Limits on the request bodies accepted for the API. `max_size` is in bytes and
+/// applies to streamed bodies as well as to the announced Content-Length.
+/// Requests with a body must carry one of `allowed_content_types`, given as media
+/// types such as `application/json` or `image/*`, when any are configured.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RequestBodyPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) allowed_content_types: Vec<String>,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct APIDefinition {
     pub(crate) api_id: String,
@@ -17,18 +306,46 @@ pub struct APIDefinition {
     pub(crate) specification: APISpecification,
     pub(crate) backend_response_timeout: u64,
     pub(crate) origin_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) traffic_mirror: Option<TrafficMirrorConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) hedging: Option<HedgingConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) rate_limiters: Vec<APIRateLimitPolicy>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) quotas: Vec<QuotaPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) auth: Option<AuthPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) client_certificate: Option<ClientCertPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) ip_access: Option<IpAccessList>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cors: Option<CorsPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) request_body: Option<RequestBodyPolicy>,
 }
 
 impl APIDefinition {
     pub fn from_json_string(json_payload: &String) -> Result<Self, serde_json::Error> {
         debug!("Constructing APIDefinition from JSON payload!");
         trace!("Trying to create APIDefinition from {}", json_payload);
-        serde_json::from_str::<Self>(json_payload.as_str())
+        serde_json::from_str::<Self>(json_payload.as_str())?.validated()
     }
     pub fn from_json_str_slice(json_payload: &str) -> Result<Self, serde_json::Error> {
         debug!("Constructing APIDefinition from JSON payload!");
         trace!("Trying to create APIDefinition from {}", json_payload);
-        serde_json::from_str::<Self>(json_payload)
+        serde_json::from_str::<Self>(json_payload)?.validated()
+    }
+    /// Parses the settings that are checked per request once, rejecting the
+    /// definition if they are invalid.
+    fn validated(mut self) -> Result<Self, serde_json::Error> {
+        if let Some(ip_access_list) = self.ip_access.as_mut() {
+            let rules = IpAccessRules::new(&ip_access_list.allow, &ip_access_list.deny)
+                .map_err(serde_json::Error::custom)?;
+            ip_access_list.rules = Some(Arc::new(rules));
+        }
+        Ok(self)
     }
     pub fn to_json(&self) -> Result<String, serde_json::Error> {
         debug!("Serializing APIDefinition to JSON!");
@@ -53,7 +370,9 @@ mod test {
     use std::io::Read;
     use std::path::Path;
 
-    use crate::configuration_reader::api_def_reader::APIDefinition;
+    use crate::configuration_reader::api_def_reader::{
+        APIDefinition, AuthPolicy, IntrospectionFailureMode, JwtAlgorithm,
+    };
 
     #[test]
     fn test_everything() {
@@ -139,4 +458,109 @@ mod test {
             second_api_definition.specification.hostnames[0]
         );
     }
+
+    #[test]
+    fn test_api_key_auth_policy() {
+        let api_definition = APIDefinition::from_json_str_slice(
+            r#"{
+                "api_id": "billing",
+                "api_name": "Billing API",
+                "api_version": "1.0.0",
+                "api_desc": "Invoices",
+                "specification": {"methods": ["GET"], "paths": ["/invoices"], "hostnames": ["localhost"]},
+                "backend_response_timeout": 1000,
+                "origin_id": "billing",
+                "auth": {"type": "ApiKey", "query_parameter": "key", "allowed_consumers": ["web"]}
+            }"#,
+        )
+        .unwrap();
+        match api_definition.auth {
+            Some(AuthPolicy::ApiKey(config)) => {
+                assert_eq!(None, config.header);
+                assert_eq!(Some(String::from("key")), config.query_parameter);
+                assert_eq!(vec![String::from("web")], config.allowed_consumers);
+                assert_eq!(None, config.consumer_header);
+            }
+            _ => panic!("Expected an API key auth policy"),
+        }
+    }
+
+    #[test]
+    fn test_jwt_auth_policy() {
+        let api_definition = APIDefinition::from_json_str_slice(
+            r#"{
+                "api_id": "billing",
+                "api_name": "Billing API",
+                "api_version": "1.0.0",
+                "api_desc": "Invoices",
+                "specification": {"methods": ["GET"], "paths": ["/invoices"], "hostnames": ["localhost"]},
+                "backend_response_timeout": 1000,
+                "origin_id": "billing",
+                "auth": {
+                    "type": "Jwt",
+                    "algorithms": ["RS256", "ES256"],
+                    "jwks": "https://idp.example.com/.well-known/jwks.json",
+                    "issuer": "https://idp.example.com/",
+                    "audiences": ["billing"],
+                    "required_scopes": ["invoices:read"],
+                    "required_claims": {"tenant": "acme"},
+                    "claim_headers": {"sub": "X-User-ID"}
+                }
+            }"#,
+        )
+        .unwrap();
+        match api_definition.auth {
+            Some(AuthPolicy::Jwt(config)) => {
+                assert_eq!(
+                    vec![JwtAlgorithm::RS256, JwtAlgorithm::ES256],
+                    config.algorithms
+                );
+                assert_eq!(None, config.secret);
+                assert_eq!(vec![String::from("billing")], config.audiences);
+                assert_eq!(
+                    Some(&serde_json::json!("acme")),
+                    config.required_claims.get("tenant")
+                );
+                assert_eq!(
+                    Some(&String::from("X-User-ID")),
+                    config.claim_headers.get("sub")
+                );
+            }
+            _ => panic!("Expected a JWT auth policy"),
+        }
+    }
+
+    #[test]
+    fn test_introspection_auth_policy() {
+        let api_definition = APIDefinition::from_json_str_slice(
+            r#"{
+                "api_id": "billing",
+                "api_name": "Billing API",
+                "api_version": "1.0.0",
+                "api_desc": "Invoices",
+                "specification": {"methods": ["GET"], "paths": ["/invoices"], "hostnames": ["localhost"]},
+                "backend_response_timeout": 1000,
+                "origin_id": "billing",
+                "auth": {
+                    "type": "Introspection",
+                    "introspection_endpoint": "https://idp.example.com/oauth2/introspect",
+                    "client_id": "gateman",
+                    "client_secret": "s3cret",
+                    "required_scopes": ["invoices:read"]
+                }
+            }"#,
+        )
+        .unwrap();
+        match api_definition.auth {
+            Some(AuthPolicy::Introspection(config)) => {
+                assert_eq!(
+                    IntrospectionFailureMode::ServiceUnavailable,
+                    config.failure_mode
+                );
+                assert_eq!(None, config.max_cache_duration);
+                assert_eq!(vec![String::from("invoices:read")], config.required_scopes);
+            }
+            _ => panic!("Expected an introspection auth policy"),
+        }
+    }
 }
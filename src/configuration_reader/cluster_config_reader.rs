@@ -0,0 +1,68 @@
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+/// How limits are shared between Gateman instances. `Strict` checks every request
+/// against the backend with an atomic GCRA script. `LocalApproximation` checks
+/// locally and exchanges counters with the backend every `sync_interval`, so the
+/// cluster may overshoot a limit by what was admitted within one interval.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClusterMode {
+    #[default]
+    Strict,
+    LocalApproximation,
+}
+
+/// Shared-state backend speaking the Redis protocol at `address` (host:port).
+/// `timeout` and `sync_interval` are in milliseconds. Instances whose backend is
+/// unreachable fall back to enforcing every limit locally.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ClusterConfig {
+    pub(crate) address: String,
+    #[serde(default)]
+    pub(crate) mode: ClusterMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) key_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) sync_interval: Option<u64>,
+}
+
+impl ClusterConfig {
+    pub fn from_json_string(json_payload: &String) -> Result<Self, serde_json::Error> {
+        debug!("Constructing ClusterConfig from JSON payload!");
+        trace!("Trying to create ClusterConfig from {}", json_payload);
+        serde_json::from_str::<Self>(json_payload.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ClusterConfig, ClusterMode};
+
+    #[test]
+    fn test_deserialize() {
+        let cluster_config = ClusterConfig::from_json_string(&String::from(
+            r#"{
+                "address": "127.0.0.1:6379",
+                "mode": "LocalApproximation",
+                "key_prefix": "edge:",
+                "sync_interval": 500
+            }"#,
+        ))
+        .unwrap();
+        assert_eq!("127.0.0.1:6379", cluster_config.address);
+        assert_eq!(ClusterMode::LocalApproximation, cluster_config.mode);
+        assert_eq!(Some(String::from("edge:")), cluster_config.key_prefix);
+        assert_eq!(None, cluster_config.timeout);
+        assert_eq!(Some(500), cluster_config.sync_interval);
+    }
+
+    #[test]
+    fn test_mode_defaults_to_strict() {
+        let cluster_config =
+            ClusterConfig::from_json_string(&String::from(r#"{"address": "redis:6379"}"#)).unwrap();
+        assert_eq!(ClusterMode::Strict, cluster_config.mode);
+    }
+}
@@ -0,0 +1,68 @@
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+/// A client of the gateway. Only SHA-256 digests of its API keys (hex encoded)
+/// are stored; the keys themselves are handed out once when they are issued.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Consumer {
+    pub(crate) consumer_id: String,
+    pub(crate) consumer_name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) key_hashes: Vec<String>,
+}
+
+impl Consumer {
+    pub fn from_json_string(json_payload: &String) -> Result<Self, serde_json::Error> {
+        debug!("Constructing Consumer from JSON payload!");
+        trace!("Trying to create Consumer from {}", json_payload);
+        serde_json::from_str::<Self>(json_payload.as_str())
+    }
+    pub fn to_json_pretty(&self) -> Result<String, serde_json::Error> {
+        debug!("Serializing Consumer to pretty JSON!");
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Consumer IDs name the definition files, so they are limited to characters
+/// that are safe in file names.
+pub(crate) fn is_valid_consumer_id(consumer_id: &str) -> bool {
+    !consumer_id.is_empty()
+        && consumer_id.len() <= 128
+        && !consumer_id.starts_with('.')
+        && consumer_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_valid_consumer_id, Consumer};
+
+    #[test]
+    fn test_deserialize() {
+        let consumer = Consumer::from_json_string(&String::from(
+            r#"{
+                "consumer_id": "billing-service",
+                "consumer_name": "Billing Service",
+                "key_hashes": ["2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"]
+            }"#,
+        ))
+        .unwrap();
+        assert_eq!("billing-service", consumer.consumer_id);
+        assert_eq!("Billing Service", consumer.consumer_name);
+        assert_eq!(1, consumer.key_hashes.len());
+
+        let consumer = Consumer::from_json_string(&consumer.to_json_pretty().unwrap()).unwrap();
+        assert_eq!("billing-service", consumer.consumer_id);
+    }
+
+    #[test]
+    fn test_consumer_ids_are_safe_file_names() {
+        assert!(is_valid_consumer_id("billing-service_2.eu"));
+        assert!(!is_valid_consumer_id(""));
+        assert!(!is_valid_consumer_id("../origin_def/origin"));
+        assert!(!is_valid_consumer_id(".hidden"));
+        assert!(!is_valid_consumer_id("with space"));
+    }
+}
@@ -0,0 +1,57 @@
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+/// Gateway wide access control by client IP address, applied before requests
+/// are matched to an API. `allow`, `deny` and `trusted_proxies` hold IPv4/IPv6
+/// CIDR ranges such as `10.0.0.0/8` or single addresses. Requests arriving from
+/// a trusted proxy are checked against the client address the proxies recorded
+/// in `client_ip_header` (X-Forwarded-For unless configured) instead.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct IpAccessConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) allow: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) deny: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) trusted_proxies: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) client_ip_header: Option<String>,
+}
+
+impl IpAccessConfig {
+    pub fn from_json_string(json_payload: &String) -> Result<Self, serde_json::Error> {
+        debug!("Constructing IpAccessConfig from JSON payload!");
+        trace!("Trying to create IpAccessConfig from {}", json_payload);
+        serde_json::from_str::<Self>(json_payload.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::IpAccessConfig;
+
+    #[test]
+    fn test_deserialize() {
+        let ip_access_config = IpAccessConfig::from_json_string(&String::from(
+            r#"{
+                "deny": ["203.0.113.0/24", "2001:db8::/32"],
+                "trusted_proxies": ["10.0.0.0/8"]
+            }"#,
+        ))
+        .unwrap();
+        assert!(ip_access_config.allow.is_empty());
+        assert_eq!(
+            vec![
+                String::from("203.0.113.0/24"),
+                String::from("2001:db8::/32")
+            ],
+            ip_access_config.deny
+        );
+        assert_eq!(
+            vec![String::from("10.0.0.0/8")],
+            ip_access_config.trusted_proxies
+        );
+        assert_eq!(None, ip_access_config.client_ip_header);
+    }
+}
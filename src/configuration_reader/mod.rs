@@ -1,2 +1,6 @@
 pub mod api_def_reader;
+pub mod cluster_config_reader;
+pub mod consumer_def_reader;
+pub mod ip_access_config_reader;
 pub mod origin_def_reader;
+pub mod tls_config_reader;
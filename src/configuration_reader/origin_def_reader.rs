@@ -1,4 +1,5 @@
 use log::{debug, trace};
+use serde::de::Error;
 use serde::{Deserialize, Serialize};
 use serde_json;
 
@@ -9,10 +10,74 @@ pub enum TimeUnit {
     Second,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+/// Algorithm enforcing `req_per_time_unit`. `TokenBucket` is implemented as GCRA
+/// and is the only one honoring `burst_size`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RateLimitAlgorithm {
+    #[default]
+    #[serde(alias = "GCRA")]
+    TokenBucket,
+    FixedWindow,
+    SlidingWindowLog,
+    SlidingWindowCounter,
+}
+
+/// Rate limit headers added to proxied responses and 429 responses.
+/// `Draft` follows the IETF RateLimit header fields draft.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RateLimitHeaderStyle {
+    Draft,
+    XRateLimit,
+    Disabled,
+}
+
+/// `Shadow` evaluates and counts a limiter's decisions without ever rejecting,
+/// so that a limit can be tried out before it is enforced.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RateLimitMode {
+    #[default]
+    #[serde(alias = "enforce")]
+    Enforce,
+    #[serde(alias = "shadow")]
+    Shadow,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum RateLimitKey {
+    ClientIp,
+    ApiKey {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        header: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        query_parameter: Option<String>,
+    },
+    JwtClaim {
+        claim: String,
+    },
+    Header {
+        name: String,
+    },
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct RateLimiterConfig {
+    #[serde(default)]
+    pub(crate) algorithm: RateLimitAlgorithm,
     pub(crate) time_unit: TimeUnit,
     pub(crate) req_per_time_unit: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) burst_size: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) key: Option<RateLimitKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_keys: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) idle_key_timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) header_style: Option<RateLimitHeaderStyle>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) mode: Option<RateLimitMode>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -21,12 +86,103 @@ pub struct Server {
     pub(crate) port: u16,
     pub(crate) secure: bool,
     pub(crate) verify_cert: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) priority: Option<u32>,
+}
+
+impl Server {
+    pub fn server_key(&self) -> String {
+        format!("{}:{}", self.hostname, self.port)
+    }
+    pub fn priority(&self) -> u32 {
+        self.priority.unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FailoverConfig {
+    pub(crate) min_healthy_percentage: u8,
+    pub(crate) max_consecutive_failures: u32,
+    pub(crate) ejection_duration: u64,
+}
+
+impl FailoverConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.max_consecutive_failures < 1 {
+            return Err(String::from(
+                "failover.max_consecutive_failures must be at least 1",
+            ));
+        }
+        if self.min_healthy_percentage > 100 {
+            return Err(String::from(
+                "failover.min_healthy_percentage must be at most 100",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AdaptiveConcurrencyAlgorithm {
+    Aimd,
+    Gradient,
+}
+
+/// Lets the concurrency limit move between `min_limit` and `max_limit` based on
+/// observed latencies. `latency_threshold` (milliseconds) only applies to `Aimd`.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveConcurrencyConfig {
+    pub(crate) algorithm: AdaptiveConcurrencyAlgorithm,
+    pub(crate) min_limit: usize,
+    pub(crate) max_limit: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) latency_threshold: Option<u64>,
+}
+
+/// Bounds the requests in flight to an Origin. Requests over the limit wait in a
+/// queue of `max_queue_size` for at most `queue_timeout` milliseconds. With
+/// `adaptive`, `max_concurrent_requests` is only the initial limit.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ConcurrencyLimitConfig {
+    pub(crate) max_concurrent_requests: usize,
+    #[serde(default)]
+    pub(crate) max_queue_size: usize,
+    #[serde(default)]
+    pub(crate) queue_timeout: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) adaptive: Option<AdaptiveConcurrencyConfig>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SessionAffinityConfig {
+    pub(crate) cookie_name: String,
+    pub(crate) secret: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_age: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SlowStartConfig {
+    pub(crate) window: u64,
+    pub(crate) initial_weight_percentage: u8,
+    #[serde(default)]
+    pub(crate) pre_connect: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) warm_up_path: Option<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct OriginSpecification {
     pub(crate) rate_limiter: RateLimiterConfig,
     pub(crate) servers: Vec<Server>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) failover: Option<FailoverConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) session_affinity: Option<SessionAffinityConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) slow_start: Option<SlowStartConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) concurrency_limit: Option<ConcurrencyLimitConfig>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -41,12 +197,20 @@ impl Origin {
     pub fn from_json_string(json_payload: &String) -> Result<Self, serde_json::Error> {
         debug!("Constructing Origin from JSON payload!");
         trace!("Trying to create Origin from {}", json_payload);
-        serde_json::from_str::<Self>(json_payload.as_str())
+        serde_json::from_str::<Self>(json_payload.as_str())?.validated()
     }
     pub fn from_json_str_slice(json_payload: &str) -> Result<Self, serde_json::Error> {
         debug!("Constructing Origin from JSON payload!");
         trace!("Trying to create Origin from {}", json_payload);
-        serde_json::from_str::<Self>(json_payload)
+        serde_json::from_str::<Self>(json_payload)?.validated()
+    }
+    /// Rejects settings the load balancer cannot work with, which the types of
+    /// the fields do not rule out.
+    fn validated(self) -> Result<Self, serde_json::Error> {
+        if let Some(failover) = &self.specification.failover {
+            failover.validate().map_err(serde_json::Error::custom)?;
+        }
+        Ok(self)
     }
     pub fn to_json(&self) -> Result<String, serde_json::Error> {
         debug!("Serializing Origin to JSON!");
@@ -64,6 +228,38 @@ mod test {
 
     use crate::configuration_reader::origin_def_reader::{Origin, TimeUnit};
 
+    fn origin_with_failover(failover: &str) -> Result<Origin, serde_json::Error> {
+        Origin::from_json_str_slice(&format!(
+            r#"{{
+                "origin_id": "billing",
+                "origin_name": "Billing",
+                "origin_desc": "Invoices",
+                "specification": {{
+                    "rate_limiter": {{"algorithm": "TokenBucket", "time_unit": "Second", "req_per_time_unit": 10}},
+                    "servers": [{{"hostname": "localhost", "port": 8000, "secure": false, "verify_cert": false}}],
+                    "failover": {}
+                }}
+            }}"#,
+            failover
+        ))
+    }
+
+    #[test]
+    fn test_invalid_failover_is_rejected() {
+        assert!(origin_with_failover(
+            r#"{"min_healthy_percentage": 50, "max_consecutive_failures": 3, "ejection_duration": 1000}"#
+        )
+        .is_ok());
+        assert!(origin_with_failover(
+            r#"{"min_healthy_percentage": 50, "max_consecutive_failures": 0, "ejection_duration": 1000}"#
+        )
+        .is_err());
+        assert!(origin_with_failover(
+            r#"{"min_healthy_percentage": 101, "max_consecutive_failures": 3, "ejection_duration": 1000}"#
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_deserialize() {
         let mut file_contents = String::new();
@@ -129,6 +325,6 @@ mod test {
             }
         }
         let origin = Origin::from_json_string(&file_contents).unwrap();
-        assert_eq!(String::from("{\n  \"origin_id\": \"RFX829635\",\n  \"origin_name\": \"Sample Origin\",\n  \"origin_desc\": \"Some nice origin description that can be pretty long\",\n  \"specification\": {\n    \"rate_limiter\": {\n      \"time_unit\": \"Minute\",\n      \"req_per_time_unit\": 200\n    },\n    \"servers\": [\n      {\n        \"hostname\": \"localhost\",\n        \"port\": 8000,\n        \"secure\": true,\n        \"verify_cert\": false\n      }\n    ]\n  }\n}"), origin.to_json_pretty().unwrap());
+        assert_eq!(String::from("{\n  \"origin_id\": \"RFX829635\",\n  \"origin_name\": \"Sample Origin\",\n  \"origin_desc\": \"Some nice origin description that can be pretty long\",\n  \"specification\": {\n    \"rate_limiter\": {\n      \"algorithm\": \"TokenBucket\",\n      \"time_unit\": \"Minute\",\n      \"req_per_time_unit\": 200\n    },\n    \"servers\": [\n      {\n        \"hostname\": \"localhost\",\n        \"port\": 8000,\n        \"secure\": true,\n        \"verify_cert\": false\n      }\n    ]\n  }\n}"), origin.to_json_pretty().unwrap());
     }
 }
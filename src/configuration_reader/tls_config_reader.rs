@@ -0,0 +1,52 @@
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+/// Whether the TLS listener asks clients for a certificate. `Request` also
+/// admits clients without one, leaving it to each API to require one.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClientAuthMode {
+    #[default]
+    None,
+    Request,
+    Require,
+}
+
+/// Settings of the TLS listener. Client certificates are verified against the
+/// CA certificates of the PEM file at `client_ca_file`, resolved against the
+/// directory of the executable if relative.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TlsListenerConfig {
+    #[serde(default)]
+    pub(crate) client_auth: ClientAuthMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) client_ca_file: Option<String>,
+}
+
+impl TlsListenerConfig {
+    pub fn from_json_string(json_payload: &String) -> Result<Self, serde_json::Error> {
+        debug!("Constructing TlsListenerConfig from JSON payload!");
+        trace!("Trying to create TlsListenerConfig from {}", json_payload);
+        serde_json::from_str::<Self>(json_payload.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ClientAuthMode, TlsListenerConfig};
+
+    #[test]
+    fn test_deserialize() {
+        let tls_listener_config = TlsListenerConfig::from_json_string(&String::from(
+            r#"{"client_auth": "Request", "client_ca_file": "resources/certs/clients/ca.crt"}"#,
+        ))
+        .unwrap();
+        assert_eq!(ClientAuthMode::Request, tls_listener_config.client_auth);
+        assert_eq!(
+            Some(String::from("resources/certs/clients/ca.crt")),
+            tls_listener_config.client_ca_file
+        );
+        let tls_listener_config = TlsListenerConfig::from_json_string(&String::from("{}")).unwrap();
+        assert_eq!(ClientAuthMode::None, tls_listener_config.client_auth);
+    }
+}
@@ -0,0 +1,273 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, Request};
+use log::{debug, error, trace};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::Sender;
+
+use crate::configuration_reader::api_def_reader::APIKeyAuthConfig;
+use crate::configuration_reader::consumer_def_reader::Consumer;
+use crate::core::auth::authentication::AuthRejection;
+use crate::core::config::config_mgr_proxy_api::ConfigMgrProxyAPI;
+use crate::core::rate_limiter::rate_limit_key::{header_value, query_parameter};
+use crate::ConfigMgrProxyAPI::GetConsumerByKeyHash;
+
+const DEFAULT_API_KEY_HEADER: &str = "x-api-key";
+const DEFAULT_CONSUMER_HEADER: &str = "x-consumer-id";
+const API_KEY_BYTES: usize = 32;
+
+/// Keys are stored and looked up by their hex encoded SHA-256 digest.
+pub(crate) fn hash_api_key(api_key: &str) -> String {
+    hex::encode(Sha256::digest(api_key.as_bytes()))
+}
+
+pub(crate) fn generate_api_key() -> String {
+    let mut api_key = [0u8; API_KEY_BYTES];
+    rand::thread_rng().fill_bytes(&mut api_key);
+    URL_SAFE_NO_PAD.encode(api_key)
+}
+
+fn read_api_key(config: &APIKeyAuthConfig, request: &Request<Body>) -> Option<String> {
+    header_value(
+        request,
+        config.header.as_deref().unwrap_or(DEFAULT_API_KEY_HEADER),
+    )
+    .or_else(|| {
+        config
+            .query_parameter
+            .as_ref()
+            .and_then(|parameter| query_parameter(request, parameter))
+    })
+    .filter(|api_key| !api_key.is_empty())
+}
+
+fn authorize_consumer(
+    config: &APIKeyAuthConfig,
+    consumer: Option<Consumer>,
+) -> Result<Consumer, AuthRejection> {
+    let consumer = consumer.ok_or(AuthRejection::Unauthenticated(None))?;
+    if config.allowed_consumers.is_empty()
+        || config.allowed_consumers.contains(&consumer.consumer_id)
+    {
+        Ok(consumer)
+    } else {
+        Err(AuthRejection::Forbidden(None))
+    }
+}
+
+/// Replaces whatever the client sent in the consumer header, so that the Origin
+/// can trust it.
+fn forward_consumer(
+    config: &APIKeyAuthConfig,
+    consumer: &Consumer,
+    request: &mut Request<Body>,
+) -> Result<(), AuthRejection> {
+    let consumer_header = config
+        .consumer_header
+        .as_deref()
+        .unwrap_or(DEFAULT_CONSUMER_HEADER);
+    match (
+        HeaderName::from_bytes(consumer_header.as_bytes()),
+        HeaderValue::from_str(&consumer.consumer_id),
+    ) {
+        (Ok(header_name), Ok(header_value)) => {
+            request.headers_mut().insert(header_name, header_value);
+            Ok(())
+        }
+        _ => {
+            error!(
+                "Cannot forward Consumer (Consumer ID: {}) in header {}",
+                consumer.consumer_id, consumer_header
+            );
+            Err(AuthRejection::Unavailable)
+        }
+    }
+}
+
+async fn find_consumer(
+    config_mgr_tx: &Sender<ConfigMgrProxyAPI>,
+    api_key: &str,
+) -> Result<Option<Consumer>, AuthRejection> {
+    let (responder, receiver) = tokio::sync::oneshot::channel();
+    let find_consumer_call = GetConsumerByKeyHash {
+        key_hash: hash_api_key(api_key),
+        responder,
+    };
+    if config_mgr_tx.send(find_consumer_call).await.is_err() {
+        trace!("Failed to query configuration manager for Consumer by key hash");
+        return Err(AuthRejection::Unavailable);
+    }
+    receiver.await.map_err(|_| AuthRejection::Unavailable)
+}
+
+pub(crate) async fn authenticate_api_key(
+    config_mgr_tx: &Sender<ConfigMgrProxyAPI>,
+    api_id: &str,
+    config: &APIKeyAuthConfig,
+    mut request: Request<Body>,
+) -> Result<Request<Body>, AuthRejection> {
+    let api_key = match read_api_key(config, &request) {
+        None => {
+            debug!(
+                "Rejected request without API key to APIDefinition (APIDefinition ID: {})",
+                api_id
+            );
+            return Err(AuthRejection::Unauthenticated(None));
+        }
+        Some(api_key) => api_key,
+    };
+    let consumer = authorize_consumer(config, find_consumer(config_mgr_tx, &api_key).await?)
+        .map_err(|rejection| {
+            debug!(
+                "Rejected request to APIDefinition (APIDefinition ID: {}) - {:?}",
+                api_id, rejection
+            );
+            rejection
+        })?;
+    forward_consumer(config, &consumer, &mut request)?;
+    trace!(
+        "Authenticated Consumer (Consumer ID: {}) for APIDefinition (APIDefinition ID: {})",
+        consumer.consumer_id,
+        api_id
+    );
+    Ok(request)
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::{Body, Request};
+    use tokio::sync::mpsc::Sender;
+
+    use crate::configuration_reader::api_def_reader::APIKeyAuthConfig;
+    use crate::configuration_reader::consumer_def_reader::Consumer;
+    use crate::core::auth::authentication::AuthRejection;
+    use crate::core::config::config_mgr_proxy_api::ConfigMgrProxyAPI;
+    use crate::core::config::consumer_registry::ConsumerRegistry;
+
+    use super::{authenticate_api_key, generate_api_key, hash_api_key};
+
+    /// Answers consumer lookups the way the configuration manager does.
+    fn config_mgr(consumers: Vec<Consumer>) -> Sender<ConfigMgrProxyAPI> {
+        let (config_mgr_tx, mut receiver) = tokio::sync::mpsc::channel(32);
+        let consumers = ConsumerRegistry::from_consumers(consumers);
+        tokio::spawn(async move {
+            while let Some(api_call) = receiver.recv().await {
+                if let ConfigMgrProxyAPI::GetConsumerByKeyHash {
+                    key_hash,
+                    responder,
+                } = api_call
+                {
+                    let _ = responder.send(consumers.find_by_key_hash(&key_hash).cloned());
+                }
+            }
+        });
+        config_mgr_tx
+    }
+
+    fn consumer(consumer_id: &str, api_key: &str) -> Consumer {
+        Consumer {
+            consumer_id: String::from(consumer_id),
+            consumer_name: String::from(consumer_id),
+            key_hashes: vec![hash_api_key(api_key)],
+        }
+    }
+
+    fn auth_config(allowed_consumers: &[&str]) -> APIKeyAuthConfig {
+        APIKeyAuthConfig {
+            header: None,
+            query_parameter: Some(String::from("api_key")),
+            allowed_consumers: allowed_consumers
+                .iter()
+                .map(|id| String::from(*id))
+                .collect(),
+            consumer_header: None,
+        }
+    }
+
+    #[test]
+    fn test_generated_keys_are_unique() {
+        let api_key = generate_api_key();
+        assert_eq!(43, api_key.len());
+        assert_ne!(api_key, generate_api_key());
+        assert_eq!(64, hash_api_key(&api_key).len());
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_read_from_header_or_query() {
+        let config_mgr_tx = config_mgr(vec![consumer("web", "secret")]);
+        let request = Request::builder()
+            .uri("/invoices")
+            .header("x-api-key", "secret")
+            .body(Body::empty())
+            .unwrap();
+        assert!(
+            authenticate_api_key(&config_mgr_tx, "billing", &auth_config(&[]), request)
+                .await
+                .is_ok()
+        );
+        let request = Request::builder()
+            .uri("/invoices?page=2&api_key=secret")
+            .body(Body::empty())
+            .unwrap();
+        assert!(
+            authenticate_api_key(&config_mgr_tx, "billing", &auth_config(&[]), request)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_or_missing_keys_are_unauthenticated() {
+        let config_mgr_tx = config_mgr(vec![consumer("web", "secret")]);
+        for uri in ["/invoices", "/invoices?api_key=", "/invoices?api_key=guess"] {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            assert_eq!(
+                Some(AuthRejection::Unauthenticated(None)),
+                authenticate_api_key(&config_mgr_tx, "billing", &auth_config(&[]), request)
+                    .await
+                    .err()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_consumers_not_allowed_are_forbidden() {
+        let config_mgr_tx = config_mgr(vec![consumer("web", "secret")]);
+        let request = Request::builder()
+            .uri("/invoices?api_key=secret")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            Some(AuthRejection::Forbidden(None)),
+            authenticate_api_key(
+                &config_mgr_tx,
+                "billing",
+                &auth_config(&["mobile"]),
+                request
+            )
+            .await
+            .err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_consumer_identity_replaces_client_supplied_header() {
+        let config_mgr_tx = config_mgr(vec![consumer("web", "secret")]);
+        let mut config = auth_config(&["web"]);
+        config.consumer_header = Some(String::from("X-Caller"));
+        let request = Request::builder()
+            .uri("/invoices")
+            .header("x-api-key", "secret")
+            .header("x-caller", "admin")
+            .header("x-caller", "root")
+            .body(Body::empty())
+            .unwrap();
+        let request = authenticate_api_key(&config_mgr_tx, "billing", &config, request)
+            .await
+            .unwrap();
+        let callers: Vec<_> = request.headers().get_all("x-caller").iter().collect();
+        assert_eq!(vec!["web"], callers);
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt::{Display, Formatter};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Client, Method, Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde_json::{Map, Value};
+use tokio::time::timeout;
+
+const MAX_PROVIDER_RESPONSE_SIZE: usize = 64 * 1024;
+
+static AUTH_CLIENT: OnceLock<Client<HttpsConnector<HttpConnector>>> = OnceLock::new();
+
+/// Client shared by calls to identity providers (JWKS documents, introspection
+/// endpoints), which are usually only served over https.
+pub(crate) fn auth_client() -> Client<HttpsConnector<HttpConnector>> {
+    AUTH_CLIENT
+        .get_or_init(|| {
+            Client::builder().build(
+                HttpsConnectorBuilder::new()
+                    .with_webpki_roots()
+                    .https_or_http()
+                    .enable_http1()
+                    .build(),
+            )
+        })
+        .clone()
+}
+
+/// Reads a response body of at most `max_size` bytes, waiting at most
+/// `read_timeout` for each chunk.
+pub(crate) async fn read_limited_body(
+    mut body: Body,
+    max_size: usize,
+    read_timeout: Duration,
+) -> Result<Vec<u8>, String> {
+    let mut payload = Vec::new();
+    while let Some(chunk) = timeout(read_timeout, body.data())
+        .await
+        .map_err(|_| String::from("Timed out"))?
+    {
+        payload.extend_from_slice(&chunk.map_err(|e| e.to_string())?);
+        if payload.len() > max_size {
+            return Err(String::from("Response is too large"));
+        }
+    }
+    Ok(payload)
+}
+
+/// `application/x-www-form-urlencoded` encoding, which RFC 6749 also requires for
+/// client credentials sent with HTTP Basic authentication.
+pub(crate) fn form_urlencode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => {
+                encoded.push(byte as char)
+            }
+            b' ' => encoded.push('+'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Decodes an `application/x-www-form-urlencoded` value, such as the parameters
+/// a provider redirects back with.
+pub(crate) fn form_urldecode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = value.get(index + 1..index + 3)?;
+                if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                    return None;
+                }
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                index += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8(decoded).ok()
+}
+
+pub(crate) enum ProviderError {
+    /// The provider answered, but refused the request.
+    Rejected(StatusCode),
+    /// The provider could not be reached or gave an unusable answer.
+    Unavailable(String),
+}
+
+impl Display for ProviderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderError::Rejected(status) => write!(f, "Responded with status {}", status),
+            ProviderError::Unavailable(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Posts a form to an OAuth2 endpoint (token, introspection) and returns the JSON
+/// object it responds with. The client authenticates with HTTP Basic if it has a
+/// secret, and by sending its ID in the form otherwise.
+pub(crate) async fn post_form(
+    endpoint: &str,
+    client_id: &str,
+    client_secret: Option<&str>,
+    form: &[(&str, &str)],
+    request_timeout: Duration,
+) -> Result<Map<String, Value>, ProviderError> {
+    let mut fields: Vec<String> = form
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, form_urlencode(value)))
+        .collect();
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(endpoint)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(ACCEPT, "application/json");
+    match client_secret {
+        Some(client_secret) => {
+            let credentials = format!(
+                "{}:{}",
+                form_urlencode(client_id),
+                form_urlencode(client_secret)
+            );
+            request = request.header(
+                AUTHORIZATION,
+                format!("Basic {}", STANDARD.encode(credentials)),
+            );
+        }
+        None => fields.push(format!("client_id={}", form_urlencode(client_id))),
+    }
+    let request = request
+        .body(Body::from(fields.join("&")))
+        .map_err(|e| ProviderError::Unavailable(e.to_string()))?;
+    let response = timeout(request_timeout, auth_client().request(request))
+        .await
+        .map_err(|_| ProviderError::Unavailable(String::from("Timed out")))?
+        .map_err(|e| ProviderError::Unavailable(e.to_string()))?;
+    if response.status().is_client_error() {
+        return Err(ProviderError::Rejected(response.status()));
+    }
+    if response.status() != StatusCode::OK {
+        return Err(ProviderError::Unavailable(format!(
+            "Responded with status {}",
+            response.status()
+        )));
+    }
+    let payload = read_limited_body(
+        response.into_body(),
+        MAX_PROVIDER_RESPONSE_SIZE,
+        request_timeout,
+    )
+    .await
+    .map_err(ProviderError::Unavailable)?;
+    match serde_json::from_slice(&payload) {
+        Ok(Value::Object(response)) => Ok(response),
+        _ => Err(ProviderError::Unavailable(String::from(
+            "Responded with invalid JSON",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{form_urldecode, form_urlencode};
+
+    #[test]
+    fn test_form_values_are_encoded() {
+        assert_eq!("a+b%3Ac%2F%C3%A9-_.*", form_urlencode("a b:c/é-_.*"));
+        assert_eq!(
+            Some(String::from("a b:c/é-_.*")),
+            form_urldecode("a+b%3Ac%2F%C3%A9-_.*")
+        );
+        assert_eq!(None, form_urldecode("100%"));
+        assert_eq!(None, form_urldecode("%zz"));
+        assert_eq!(None, form_urldecode("%+1"));
+    }
+}
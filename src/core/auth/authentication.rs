@@ -0,0 +1,201 @@
+use std::convert::Infallible;
+
+use hyper::header::{HeaderValue, CONTENT_TYPE, LOCATION, SET_COOKIE, WWW_AUTHENTICATE};
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::json;
+use tokio::sync::mpsc::Sender;
+
+use crate::configuration_reader::api_def_reader::{APIDefinition, AuthPolicy};
+use crate::core::auth::api_key::authenticate_api_key;
+use crate::core::auth::basic::authenticate_basic;
+use crate::core::auth::introspection::authenticate_introspection;
+use crate::core::auth::jwt::authenticate_jwt;
+use crate::core::auth::oidc::authenticate_oidc;
+use crate::core::config::config_mgr_proxy_api::ConfigMgrProxyAPI;
+use crate::core::standard_response::{
+    create_401_unauthorized_response, create_403_forbidden_response, create_500_int_error_response,
+    create_503_service_unavailable_response,
+};
+
+pub(crate) const DEFAULT_REALM: &str = "gateman";
+
+/// An RFC 6750 style challenge telling the client how to authenticate.
+#[derive(Debug, PartialEq)]
+pub(crate) struct AuthChallenge {
+    pub(crate) scheme: &'static str,
+    pub(crate) realm: String,
+    pub(crate) error: Option<&'static str>,
+    pub(crate) error_description: Option<String>,
+    pub(crate) scope: Option<String>,
+}
+
+impl AuthChallenge {
+    pub(crate) fn to_header_value(&self) -> Option<HeaderValue> {
+        let quote = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut challenge = format!("{} realm=\"{}\"", self.scheme, quote(&self.realm));
+        if let Some(error) = self.error {
+            challenge.push_str(&format!(", error=\"{}\"", error));
+        }
+        if let Some(error_description) = &self.error_description {
+            challenge.push_str(&format!(
+                ", error_description=\"{}\"",
+                quote(error_description)
+            ));
+        }
+        if let Some(scope) = &self.scope {
+            challenge.push_str(&format!(", scope=\"{}\"", quote(scope)));
+        }
+        HeaderValue::from_str(&challenge).ok()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum AuthRejection {
+    /// No valid credentials were presented (401).
+    Unauthenticated(Option<AuthChallenge>),
+    /// The credentials are valid but not allowed to call the API (403).
+    Forbidden(Option<AuthChallenge>),
+    /// The credentials could not be checked (503).
+    Unavailable,
+    /// The client is sent elsewhere to log in, or back after logging in (302).
+    Redirect {
+        location: String,
+        cookies: Vec<HeaderValue>,
+    },
+}
+
+/// A cookie to set on the response of the Origin, e.g. for a renewed session.
+/// Auth policies leave it in the extensions of the request they admit.
+#[derive(Clone)]
+pub(crate) struct AuthCookie(pub(crate) HeaderValue);
+
+/// Sets the cookie the auth policy left in the extensions of the request.
+pub(crate) fn append_auth_cookie(
+    auth_cookie: Option<AuthCookie>,
+    response: Result<Response<Body>, Infallible>,
+) -> Result<Response<Body>, Infallible> {
+    response.map(|mut response| {
+        if let Some(AuthCookie(cookie)) = auth_cookie {
+            response.headers_mut().append(SET_COOKIE, cookie);
+        }
+        response
+    })
+}
+
+/// Checks the request against the auth policy of the API and returns it with the
+/// identity of the caller added for the Origin. APIs without a policy are open.
+pub(crate) async fn authenticate(
+    config_mgr_tx: &Sender<ConfigMgrProxyAPI>,
+    api_definition: &APIDefinition,
+    request: Request<Body>,
+) -> Result<Request<Body>, AuthRejection> {
+    match &api_definition.auth {
+        None => Ok(request),
+        Some(AuthPolicy::ApiKey(config)) => {
+            authenticate_api_key(config_mgr_tx, &api_definition.api_id, config, request).await
+        }
+        Some(AuthPolicy::Jwt(config)) => {
+            authenticate_jwt(&api_definition.api_id, config, request).await
+        }
+        Some(AuthPolicy::Introspection(config)) => {
+            authenticate_introspection(&api_definition.api_id, config, request).await
+        }
+        Some(AuthPolicy::Oidc(config)) => {
+            authenticate_oidc(&api_definition.api_id, config, request).await
+        }
+        Some(AuthPolicy::Basic(config)) => {
+            authenticate_basic(&api_definition.api_id, config, request).await
+        }
+    }
+}
+
+/// Rejections with a challenge carry it in `WWW-Authenticate` and describe the
+/// error in a JSON body.
+pub(crate) fn create_auth_rejection_response(
+    rejection: AuthRejection,
+) -> Result<Response<Body>, Infallible> {
+    let (status, challenge) = match rejection {
+        AuthRejection::Unauthenticated(None) => return create_401_unauthorized_response(),
+        AuthRejection::Forbidden(None) => return create_403_forbidden_response(),
+        AuthRejection::Unavailable => return create_503_service_unavailable_response(),
+        AuthRejection::Redirect { location, cookies } => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::FOUND;
+            match HeaderValue::from_str(&location) {
+                Ok(location) => response.headers_mut().insert(LOCATION, location),
+                Err(_) => return create_500_int_error_response(),
+            };
+            for cookie in cookies {
+                response.headers_mut().append(SET_COOKIE, cookie);
+            }
+            return Ok(response);
+        }
+        AuthRejection::Unauthenticated(Some(challenge)) => (StatusCode::UNAUTHORIZED, challenge),
+        AuthRejection::Forbidden(Some(challenge)) => (StatusCode::FORBIDDEN, challenge),
+    };
+    let body = json!({
+        "error": challenge.error.unwrap_or(if status == StatusCode::UNAUTHORIZED {
+            "unauthorized"
+        } else {
+            "forbidden"
+        }),
+        "error_description": challenge.error_description,
+    });
+    let response = Response::new(body.to_string().into());
+    let (mut parts, body) = response.into_parts();
+    parts.status = status;
+    parts
+        .headers
+        .append(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if let Some(challenge) = challenge.to_header_value() {
+        parts.headers.append(WWW_AUTHENTICATE, challenge);
+    }
+    Ok(Response::from_parts(parts, body))
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::header::{HeaderValue, LOCATION, SET_COOKIE, WWW_AUTHENTICATE};
+    use hyper::StatusCode;
+
+    use super::{create_auth_rejection_response, AuthChallenge, AuthRejection};
+
+    #[tokio::test]
+    async fn test_challenges_are_sent_in_www_authenticate() {
+        let response =
+            create_auth_rejection_response(AuthRejection::Forbidden(Some(AuthChallenge {
+                scheme: "Bearer",
+                realm: String::from("billing"),
+                error: Some("insufficient_scope"),
+                error_description: Some(String::from("Missing \"scope\"")),
+                scope: Some(String::from("invoices:read invoices:write")),
+            })))
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        assert_eq!(
+            "Bearer realm=\"billing\", error=\"insufficient_scope\", error_description=\"Missing \\\"scope\\\"\", scope=\"invoices:read invoices:write\"",
+            response.headers().get(WWW_AUTHENTICATE).unwrap()
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!("insufficient_scope", body["error"]);
+    }
+
+    #[test]
+    fn test_redirects_set_cookies() {
+        let response = create_auth_rejection_response(AuthRejection::Redirect {
+            location: String::from("/invoices?page=2"),
+            cookies: vec![
+                HeaderValue::from_static("session=a; Path=/"),
+                HeaderValue::from_static("login=; Path=/; Max-Age=0"),
+            ],
+        })
+        .unwrap();
+        assert_eq!(StatusCode::FOUND, response.status());
+        assert_eq!(
+            "/invoices?page=2",
+            response.headers().get(LOCATION).unwrap()
+        );
+        assert_eq!(2, response.headers().get_all(SET_COOKIE).iter().count());
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::header::AUTHORIZATION;
+use hyper::{Body, Request};
+use log::{debug, error, info, trace, warn};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::configuration_reader::api_def_reader::BasicAuthConfig;
+use crate::core::auth::authentication::{AuthChallenge, AuthRejection, DEFAULT_REALM};
+use crate::file_utils::file_reader::FileReader;
+use crate::utils::path_utils::get_directory_of_executable;
+
+/// htpasswd files are checked for changes no more often than this.
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_VERIFIED_CREDENTIALS: usize = 1000;
+const APR1_MAGIC: &str = "$apr1$";
+/// Verified in place of the hash of an unknown user, so that unknown users take
+/// as long to reject as wrong passwords. Uses the cost of `htpasswd -B`.
+const UNKNOWN_USER_HASH: &str = "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW";
+const APR1_ALPHABET: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// The MD5 based crypt of Apache (`$apr1$`), as produced by `htpasswd -m`.
+fn apr1_crypt(password: &str, salt: &str) -> String {
+    let password = password.as_bytes();
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+    let alternate = Md5::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+    let mut context = Md5::new()
+        .chain_update(password)
+        .chain_update(APR1_MAGIC)
+        .chain_update(salt);
+    for chunk in password.chunks(16) {
+        context.update(&alternate[..chunk.len()]);
+    }
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            context.update([0u8]);
+        } else {
+            context.update(&password[..1]);
+        }
+        length >>= 1;
+    }
+    let mut digest = context.finalize();
+    for round in 0..1000 {
+        let mut context = Md5::new();
+        if round & 1 == 1 {
+            context.update(password);
+        } else {
+            context.update(digest);
+        }
+        if round % 3 != 0 {
+            context.update(salt);
+        }
+        if round % 7 != 0 {
+            context.update(password);
+        }
+        if round & 1 == 1 {
+            context.update(digest);
+        } else {
+            context.update(password);
+        }
+        digest = context.finalize();
+    }
+    let mut encoded = format!("{}{}$", APR1_MAGIC, String::from_utf8_lossy(salt));
+    let mut encode = |value: u32, characters: usize| {
+        let mut value = value;
+        for _ in 0..characters {
+            encoded.push(APR1_ALPHABET[(value & 0x3f) as usize] as char);
+            value >>= 6;
+        }
+    };
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        encode(
+            (digest[a] as u32) << 16 | (digest[b] as u32) << 8 | digest[c] as u32,
+            4,
+        );
+    }
+    encode(digest[11] as u32, 2);
+    encoded
+}
+
+fn is_supported_hash(hash: &str) -> bool {
+    hash.starts_with("$2") || hash.starts_with("{SHA}") || hash.starts_with(APR1_MAGIC)
+}
+
+fn verify_password(hash: &str, password: &str) -> bool {
+    if hash.starts_with("$2") {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    } else if let Some(sha1) = hash.strip_prefix("{SHA}") {
+        let encoded = STANDARD.encode(Sha1::digest(password.as_bytes()));
+        encoded.as_bytes().ct_eq(sha1.as_bytes()).into()
+    } else if let Some(salted_hash) = hash.strip_prefix(APR1_MAGIC) {
+        let salt = salted_hash.split('$').next().unwrap_or_default();
+        apr1_crypt(password, salt)
+            .as_bytes()
+            .ct_eq(hash.as_bytes())
+            .into()
+    } else {
+        false
+    }
+}
+
+/// Password hashes by user. Users with a hash in another format, such as crypt,
+/// are skipped.
+fn parse_htpasswd(path: &str, content: &str) -> HashMap<String, String> {
+    let mut users = HashMap::new();
+    for (line_number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(':') {
+            Some((user, hash)) if is_supported_hash(hash) => {
+                users.insert(String::from(user), String::from(hash));
+            }
+            Some((user, _)) => warn!(
+                "Skipping user {} of htpasswd file {}, only bcrypt, SHA and apr1 hashes are supported",
+                user, path
+            ),
+            None => warn!(
+                "Skipping malformed line {} of htpasswd file {}",
+                line_number + 1,
+                path
+            ),
+        }
+    }
+    users
+}
+
+struct HtpasswdFile {
+    users: HashMap<String, String>,
+    version: Option<(SystemTime, u64)>,
+    checked_at: Instant,
+    /// Digests of credentials that passed verification, sparing a bcrypt round
+    /// on every request. Cleared with every reload.
+    verified: HashSet<String>,
+}
+
+fn file_version(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn credentials_digest(user: &str, password: &str) -> String {
+    hex::encode(
+        Sha256::new()
+            .chain_update(user.as_bytes())
+            .chain_update(b":")
+            .chain_update(password.as_bytes())
+            .finalize(),
+    )
+}
+
+enum HtpasswdReload {
+    Unchanged,
+    Loaded(HashMap<String, String>, Option<(SystemTime, u64)>),
+    Failed(String),
+}
+
+/// Reads the htpasswd file unless it is still at `known_version`.
+fn reload_htpasswd(location: &str, known_version: Option<(SystemTime, u64)>) -> HtpasswdReload {
+    let path_buffer = get_directory_of_executable().join(Path::new(location));
+    let version = file_version(&path_buffer);
+    if version.is_some() && version == known_version {
+        return HtpasswdReload::Unchanged;
+    }
+    match FileReader::from_path(path_buffer.to_str().unwrap_or(location)).read() {
+        Ok(content) => {
+            let users = parse_htpasswd(location, &content);
+            info!(
+                "Loaded {} users from htpasswd file {}",
+                users.len(),
+                location
+            );
+            HtpasswdReload::Loaded(users, version)
+        }
+        Err(e) => HtpasswdReload::Failed(e.message),
+    }
+}
+
+static HTPASSWD_CACHE: OnceLock<HtpasswdCache> = OnceLock::new();
+
+fn htpasswd_cache() -> HtpasswdCache {
+    HTPASSWD_CACHE.get_or_init(HtpasswdCache::new).clone()
+}
+
+/// htpasswd files by configured path, reloaded when their modification time or
+/// size changes. A file that cannot be read keeps being served as last loaded.
+#[derive(Clone)]
+struct HtpasswdCache {
+    files: Arc<Mutex<HashMap<String, HtpasswdFile>>>,
+    check_interval: Duration,
+}
+
+impl HtpasswdCache {
+    fn new() -> Self {
+        HtpasswdCache {
+            files: Arc::new(Mutex::new(HashMap::new())),
+            check_interval: FILE_CHECK_INTERVAL,
+        }
+    }
+
+    fn lock_files(&self) -> MutexGuard<'_, HashMap<String, HtpasswdFile>> {
+        self.files.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    /// The password hash of the user, and whether the credentials with the digest
+    /// were verified before. Files are checked and read outside the lock, which
+    /// the other requests keep using the loaded file under meanwhile.
+    async fn lookup(
+        &self,
+        location: &str,
+        user: &str,
+        digest: &str,
+    ) -> Result<Option<(String, bool)>, String> {
+        let now = Instant::now();
+        let known_version = match self.lock_files().get_mut(location) {
+            None => Some(None),
+            Some(file) if now.duration_since(file.checked_at) >= self.check_interval => {
+                file.checked_at = now;
+                Some(file.version)
+            }
+            Some(_) => None,
+        };
+        if let Some(known_version) = known_version {
+            let reloaded_location = String::from(location);
+            let reload = tokio::task::spawn_blocking(move || {
+                reload_htpasswd(&reloaded_location, known_version)
+            })
+            .await
+            .unwrap_or_else(|error| HtpasswdReload::Failed(error.to_string()));
+            let mut files = self.lock_files();
+            match reload {
+                HtpasswdReload::Unchanged => {}
+                HtpasswdReload::Loaded(users, version) => {
+                    files.insert(
+                        String::from(location),
+                        HtpasswdFile {
+                            users,
+                            version,
+                            checked_at: now,
+                            verified: HashSet::new(),
+                        },
+                    );
+                }
+                HtpasswdReload::Failed(message) => {
+                    error!("Failed to load htpasswd file {} - {}", location, message);
+                    if !files.contains_key(location) {
+                        return Err(message);
+                    }
+                }
+            }
+        }
+        Ok(self.lock_files().get(location).and_then(|file| {
+            let hash = file.users.get(user)?;
+            Some((hash.clone(), file.verified.contains(digest)))
+        }))
+    }
+
+    fn remember(&self, location: &str, user: &str, hash: &str, digest: String) {
+        let mut files = self.lock_files();
+        if let Some(file) = files.get_mut(location) {
+            if file.users.get(user).map(String::as_str) == Some(hash) {
+                if file.verified.len() >= MAX_VERIFIED_CREDENTIALS {
+                    file.verified.clear();
+                }
+                file.verified.insert(digest);
+            }
+        }
+    }
+
+    async fn verify(&self, location: &str, user: &str, password: &str) -> Result<bool, String> {
+        let digest = credentials_digest(user, password);
+        let hash = match self.lookup(location, user, &digest).await? {
+            None => None,
+            Some((_, true)) => return Ok(true),
+            Some((hash, false)) => Some(hash),
+        };
+        let verified_hash = hash
+            .clone()
+            .unwrap_or_else(|| String::from(UNKNOWN_USER_HASH));
+        let password = String::from(password);
+        let is_valid =
+            tokio::task::spawn_blocking(move || verify_password(&verified_hash, &password))
+                .await
+                .unwrap_or(false);
+        match hash {
+            Some(hash) if is_valid => {
+                self.remember(location, user, &hash, digest);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+fn read_basic_credentials(request: &Request<Body>) -> Option<(String, String)> {
+    let authorization = request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = authorization.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let credentials = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+    let (user, password) = credentials.split_once(':')?;
+    Some((String::from(user), String::from(password)))
+}
+
+pub(crate) async fn authenticate_basic(
+    api_id: &str,
+    config: &BasicAuthConfig,
+    mut request: Request<Body>,
+) -> Result<Request<Body>, AuthRejection> {
+    let challenge = || {
+        AuthRejection::Unauthenticated(Some(AuthChallenge {
+            scheme: "Basic",
+            realm: String::from(config.realm.as_deref().unwrap_or(DEFAULT_REALM)),
+            error: None,
+            error_description: None,
+            scope: None,
+        }))
+    };
+    let (user, password) = match read_basic_credentials(&request) {
+        None => {
+            debug!(
+                "Rejected request without basic credentials to APIDefinition (APIDefinition ID: {})",
+                api_id
+            );
+            return Err(challenge());
+        }
+        Some(credentials) => credentials,
+    };
+    match htpasswd_cache()
+        .verify(&config.htpasswd_file, &user, &password)
+        .await
+    {
+        Err(_) => return Err(AuthRejection::Unavailable),
+        Ok(false) => {
+            debug!(
+                "Rejected invalid basic credentials of user {} to APIDefinition (APIDefinition ID: {})",
+                user, api_id
+            );
+            return Err(challenge());
+        }
+        Ok(true) => {}
+    }
+    if config.strip_authorization {
+        request.headers_mut().remove(AUTHORIZATION);
+    }
+    trace!(
+        "Authenticated user {} for APIDefinition (APIDefinition ID: {})",
+        user,
+        api_id
+    );
+    Ok(request)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use hyper::header::AUTHORIZATION;
+    use hyper::{Body, Request};
+
+    use crate::configuration_reader::api_def_reader::BasicAuthConfig;
+    use crate::core::auth::authentication::AuthRejection;
+
+    use super::{
+        apr1_crypt, authenticate_basic, verify_password, HtpasswdCache, UNKNOWN_USER_HASH,
+    };
+
+    fn htpasswd_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("gateman-{}-{}.htpasswd", name, std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    fn request(authorization: &str) -> Request<Body> {
+        Request::builder()
+            .uri("/reports")
+            .header(AUTHORIZATION, authorization)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_password_hashes_are_verified() {
+        assert_eq!(
+            "$apr1$rOb3MQ.h$TWSg1u5ow6vjS.G7kWn.p1",
+            apr1_crypt("open sesame", "rOb3MQ.h")
+        );
+        assert_eq!("$apr1$x$c1lfMpyyDm.graHWYyv3p/", apr1_crypt("pw", "x"));
+        assert!(verify_password(
+            "$apr1$rOb3MQ.h$TWSg1u5ow6vjS.G7kWn.p1",
+            "open sesame"
+        ));
+        assert!(verify_password(
+            "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=",
+            "password"
+        ));
+        assert!(verify_password(
+            "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW",
+            "U*U"
+        ));
+        assert!(!verify_password(
+            "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=",
+            "secret"
+        ));
+        assert!(!verify_password("rOb3MQ.hTWSg1", "open sesame"));
+    }
+
+    #[tokio::test]
+    async fn test_files_are_reloaded_on_change() {
+        let path = htpasswd_path("reload");
+        std::fs::write(
+            &path,
+            "# Reporting\nalice:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\nbob:rOb3MQ.hTWSg1\n",
+        )
+        .unwrap();
+        let htpasswd_cache = HtpasswdCache {
+            files: Arc::new(Mutex::new(HashMap::new())),
+            check_interval: Duration::ZERO,
+        };
+        assert_eq!(
+            Ok(true),
+            htpasswd_cache.verify(&path, "alice", "password").await
+        );
+        assert_eq!(
+            Ok(true),
+            htpasswd_cache.verify(&path, "alice", "password").await
+        );
+        assert_eq!(
+            Ok(false),
+            htpasswd_cache.verify(&path, "alice", "secret").await
+        );
+        assert_eq!(
+            Ok(false),
+            htpasswd_cache.verify(&path, "bob", "rOb3MQ.hTWSg1").await
+        );
+        // Unknown users are checked against a valid hash, which never admits them
+        assert!(verify_password(UNKNOWN_USER_HASH, "U*U"));
+        assert_eq!(
+            Ok(false),
+            htpasswd_cache.verify(&path, "mallory", "U*U").await
+        );
+
+        std::fs::write(&path, "alice:$apr1$rOb3MQ.h$TWSg1u5ow6vjS.G7kWn.p1\n").unwrap();
+        assert_eq!(
+            Ok(false),
+            htpasswd_cache.verify(&path, "alice", "password").await
+        );
+        assert_eq!(
+            Ok(true),
+            htpasswd_cache.verify(&path, "alice", "open sesame").await
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            Ok(true),
+            htpasswd_cache.verify(&path, "alice", "open sesame").await
+        );
+        assert!(htpasswd_cache
+            .verify(&htpasswd_path("missing"), "alice", "open sesame")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_basic_credentials_are_required() {
+        let path = htpasswd_path("policy");
+        std::fs::write(&path, "alice:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n").unwrap();
+        let mut config = BasicAuthConfig {
+            htpasswd_file: path.clone(),
+            realm: Some(String::from("reports")),
+            strip_authorization: false,
+        };
+        // alice:password
+        let valid = "Basic YWxpY2U6cGFzc3dvcmQ=";
+        let forwarded = authenticate_basic("reports", &config, request(valid))
+            .await
+            .unwrap();
+        assert_eq!(valid, forwarded.headers().get(AUTHORIZATION).unwrap());
+
+        config.strip_authorization = true;
+        let forwarded = authenticate_basic("reports", &config, request(valid))
+            .await
+            .unwrap();
+        assert!(forwarded.headers().get(AUTHORIZATION).is_none());
+
+        // alice:secret
+        for authorization in ["Basic YWxpY2U6c2VjcmV0", "Bearer YWxpY2U6cGFzc3dvcmQ="] {
+            match authenticate_basic("reports", &config, request(authorization)).await {
+                Err(AuthRejection::Unauthenticated(Some(challenge))) => assert_eq!(
+                    "Basic realm=\"reports\"",
+                    challenge.to_header_value().unwrap()
+                ),
+                _ => panic!("Expected the credentials to be challenged"),
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use hyper::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use hyper::{Body, Request};
+use log::{debug, error};
+use serde_json::{Map, Value};
+
+use crate::core::auth::authentication::{AuthChallenge, AuthRejection, DEFAULT_REALM};
+
+pub(crate) fn challenge(
+    realm: Option<&str>,
+    error: Option<&'static str>,
+    error_description: Option<String>,
+) -> AuthChallenge {
+    AuthChallenge {
+        scheme: "Bearer",
+        realm: String::from(realm.unwrap_or(DEFAULT_REALM)),
+        error,
+        error_description,
+        scope: None,
+    }
+}
+
+pub(crate) fn invalid_token(realm: Option<&str>, error_description: &str) -> AuthRejection {
+    AuthRejection::Unauthenticated(Some(challenge(
+        realm,
+        Some("invalid_token"),
+        Some(String::from(error_description)),
+    )))
+}
+
+pub(crate) fn read_bearer_token(request: &Request<Body>) -> Option<&str> {
+    let authorization = request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = authorization.split_once(' ')?;
+    let token = token.trim();
+    if scheme.eq_ignore_ascii_case("Bearer") && !token.is_empty() {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+/// Scopes granted by the space separated `scope` claim or the `scp` claim, which
+/// some issuers send as an array.
+fn token_scopes(claims: &Map<String, Value>) -> Vec<&str> {
+    let mut scopes = vec![];
+    for claim in ["scope", "scp"] {
+        match claims.get(claim) {
+            Some(Value::String(value)) => scopes.extend(value.split_whitespace()),
+            Some(Value::Array(values)) => {
+                scopes.extend(values.iter().filter_map(|value| value.as_str()))
+            }
+            _ => {}
+        }
+    }
+    scopes
+}
+
+/// A claim matches the required value if it equals it or, for array claims such
+/// as `groups`, contains it.
+fn claim_matches(claim: Option<&Value>, required: &Value) -> bool {
+    match claim {
+        Some(Value::Array(values)) if !required.is_array() => values.contains(required),
+        Some(value) => value == required,
+        None => false,
+    }
+}
+
+/// Checks that the token grants every required scope and carries every required
+/// claim value.
+pub(crate) fn authorize_claims(
+    realm: Option<&str>,
+    required_scopes: &[String],
+    required_claims: &HashMap<String, Value>,
+    claims: &Map<String, Value>,
+) -> Result<(), AuthRejection> {
+    let scopes = token_scopes(claims);
+    if !required_scopes
+        .iter()
+        .all(|required| scopes.contains(&required.as_str()))
+    {
+        let mut insufficient_scope = challenge(
+            realm,
+            Some("insufficient_scope"),
+            Some(String::from("The token lacks a required scope")),
+        );
+        insufficient_scope.scope = Some(required_scopes.join(" "));
+        return Err(AuthRejection::Forbidden(Some(insufficient_scope)));
+    }
+    let mut required_claims: Vec<_> = required_claims.iter().collect();
+    required_claims.sort_by(|a, b| a.0.cmp(b.0));
+    for (claim, required) in required_claims {
+        if !claim_matches(claims.get(claim), required) {
+            return Err(AuthRejection::Forbidden(Some(challenge(
+                realm,
+                Some("insufficient_scope"),
+                Some(format!("The {} claim of the token is not accepted", claim)),
+            ))));
+        }
+    }
+    Ok(())
+}
+
+/// Sets the headers mapped from claims, removing whatever the client sent in them
+/// so that the Origin can trust them.
+pub(crate) fn forward_claims(
+    claim_headers: &HashMap<String, String>,
+    claims: &Map<String, Value>,
+    request: &mut Request<Body>,
+) -> Result<(), AuthRejection> {
+    for (claim, header) in claim_headers {
+        let header_name = match HeaderName::from_bytes(header.as_bytes()) {
+            Ok(header_name) => header_name,
+            Err(_) => {
+                error!("Cannot forward claim {} in header {}", claim, header);
+                return Err(AuthRejection::Unavailable);
+            }
+        };
+        request.headers_mut().remove(&header_name);
+        let value = match claims.get(claim) {
+            None | Some(Value::Null) => continue,
+            Some(Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+        };
+        match HeaderValue::from_str(&value) {
+            Ok(header_value) => {
+                request.headers_mut().insert(header_name, header_value);
+            }
+            Err(_) => debug!("Claim {} cannot be sent in a header", claim),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,317 @@
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use glob::Pattern;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, Request};
+use log::{debug, error, trace};
+use sha2::{Digest, Sha256};
+use x509_parser::extensions::GeneralName;
+use x509_parser::parse_x509_certificate;
+
+use crate::configuration_reader::api_def_reader::{APIDefinition, ClientCertPolicy};
+use crate::core::auth::authentication::AuthRejection;
+use crate::core::connection_info::ConnectionInfo;
+
+const DEFAULT_SUBJECT_HEADER: &str = "x-client-cert-subject";
+const DEFAULT_SAN_HEADER: &str = "x-client-cert-san";
+const DEFAULT_FINGERPRINT_HEADER: &str = "x-client-cert-fingerprint";
+
+/// Identity of the certificate a client presented to the TLS listener, which
+/// verified it against the client CA bundle.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ClientCertificate {
+    pub(crate) subject: String,
+    pub(crate) subject_alt_names: Vec<String>,
+    /// Hex encoded SHA-256 of the DER encoded certificate.
+    pub(crate) fingerprint: String,
+}
+
+fn ip_address(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?))),
+        16 => Some(IpAddr::V6(Ipv6Addr::from(
+            <[u8; 16]>::try_from(bytes).ok()?,
+        ))),
+        _ => None,
+    }
+}
+
+impl ClientCertificate {
+    /// Subject alternative names other than DNS names, URIs, email and IP
+    /// addresses are left out.
+    pub(crate) fn from_der(certificate: &[u8]) -> Option<Self> {
+        let (_, parsed) = parse_x509_certificate(certificate).ok()?;
+        let subject_alt_names = match parsed.subject_alternative_name() {
+            Ok(Some(extension)) => extension
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name)
+                    | GeneralName::URI(name)
+                    | GeneralName::RFC822Name(name) => Some(String::from(*name)),
+                    GeneralName::IPAddress(bytes) => ip_address(bytes).map(|ip| ip.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+        Some(ClientCertificate {
+            subject: parsed.subject().to_string(),
+            subject_alt_names,
+            fingerprint: hex::encode(Sha256::digest(certificate)),
+        })
+    }
+}
+
+/// Invalid patterns match nothing, so that a typo cannot admit every client.
+fn matches_any(patterns: &[String], values: &[&str]) -> bool {
+    patterns.iter().any(|pattern| match Pattern::new(pattern) {
+        Ok(pattern) => values.iter().any(|value| pattern.matches(value)),
+        Err(e) => {
+            error!("Invalid client certificate pattern {} - {}", pattern, e);
+            false
+        }
+    })
+}
+
+/// Removes identity headers under their default names, which Origins may trust
+/// even on APIs without a client certificate policy.
+fn strip_default_identity_headers(request: &mut Request<Body>) {
+    for header in [
+        DEFAULT_SUBJECT_HEADER,
+        DEFAULT_SAN_HEADER,
+        DEFAULT_FINGERPRINT_HEADER,
+    ] {
+        request.headers_mut().remove(header);
+    }
+}
+
+fn forward_identity(
+    policy: &ClientCertPolicy,
+    certificate: Option<&ClientCertificate>,
+    request: &mut Request<Body>,
+) -> Result<(), AuthRejection> {
+    let headers = [
+        (
+            &policy.subject_header,
+            DEFAULT_SUBJECT_HEADER,
+            certificate.map(|certificate| certificate.subject.clone()),
+        ),
+        (
+            &policy.san_header,
+            DEFAULT_SAN_HEADER,
+            certificate.map(|certificate| certificate.subject_alt_names.join(", ")),
+        ),
+        (
+            &policy.fingerprint_header,
+            DEFAULT_FINGERPRINT_HEADER,
+            certificate.map(|certificate| certificate.fingerprint.clone()),
+        ),
+    ];
+    for (header, default_header, value) in headers {
+        let header = header.as_deref().unwrap_or(default_header);
+        let header_name = match HeaderName::from_bytes(header.as_bytes()) {
+            Ok(header_name) => header_name,
+            Err(_) => {
+                error!("Cannot forward client certificate in header {}", header);
+                return Err(AuthRejection::Unavailable);
+            }
+        };
+        request.headers_mut().remove(&header_name);
+        match value.filter(|value| !value.is_empty()) {
+            None => {}
+            Some(value) => match HeaderValue::from_str(&value) {
+                Ok(header_value) => {
+                    request.headers_mut().insert(header_name, header_value);
+                }
+                Err(_) => debug!("Client certificate cannot be sent in header {}", header),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Checks the client certificate of the connection against the policy of the
+/// API, if it has one, and forwards its identity to the Origin. Identity headers
+/// sent by the client are always removed.
+pub(crate) fn authenticate_client_certificate(
+    api_definition: &APIDefinition,
+    connection_info: &ConnectionInfo,
+    mut request: Request<Body>,
+) -> Result<Request<Body>, AuthRejection> {
+    strip_default_identity_headers(&mut request);
+    let policy = match &api_definition.client_certificate {
+        None => return Ok(request),
+        Some(policy) => policy,
+    };
+    let certificate = connection_info.client_certificate.as_deref();
+    forward_identity(policy, certificate, &mut request)?;
+    let certificate = match certificate {
+        None => {
+            debug!(
+                "Rejected request without client certificate to APIDefinition (APIDefinition ID: {})",
+                api_definition.api_id
+            );
+            return Err(AuthRejection::Unauthenticated(None));
+        }
+        Some(certificate) => certificate,
+    };
+    let subject_alt_names: Vec<&str> = certificate
+        .subject_alt_names
+        .iter()
+        .map(String::as_str)
+        .collect();
+    if (!policy.subject_patterns.is_empty()
+        && !matches_any(&policy.subject_patterns, &[&certificate.subject]))
+        || (!policy.san_patterns.is_empty()
+            && !matches_any(&policy.san_patterns, &subject_alt_names))
+    {
+        debug!(
+            "Rejected client certificate {} for APIDefinition (APIDefinition ID: {})",
+            certificate.subject, api_definition.api_id
+        );
+        return Err(AuthRejection::Forbidden(None));
+    }
+    trace!(
+        "Authenticated client certificate {} for APIDefinition (APIDefinition ID: {})",
+        certificate.subject,
+        api_definition.api_id
+    );
+    Ok(request)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use hyper::{Body, Request};
+
+    use crate::configuration_reader::api_def_reader::APIDefinition;
+    use crate::core::auth::authentication::AuthRejection;
+    use crate::core::connection_info::ConnectionInfo;
+
+    use super::{authenticate_client_certificate, ClientCertificate};
+
+    /// Issued by the test CA of the TLS listener tests to `O=Acme, CN=billing`.
+    const CLIENT_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----\nMIIB7zCCAZagAwIBAgIUXs/dDwgCJQh2SIApTGw870Gy9a8wCgYIKoZIzj0EAwIw\nJjENMAsGA1UECgwEQWNtZTEVMBMGA1UEAwwMQWNtZSBUZXN0IENBMCAXDTI2MTAx\nOTAxMDgxMloYDzIxMjYwOTI1MDEwODEyWjAhMQ0wCwYDVQQKDARBY21lMRAwDgYD\nVQQDDAdiaWxsaW5nMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEF567rf0FJCpp\nq3dEAZbE+pJshQv+RobBN+iN1c6Da/bmQS+pTxyIvQeKMeXKzRYkqr56JAAFG0qs\na65+Mge4uKOBpDCBoTAyBgNVHREEKzApghBiaWxsaW5nLmludGVybmFshhVzcGlm\nZmU6Ly9hY21lL2JpbGxpbmcwCQYDVR0TBAIwADALBgNVHQ8EBAMCB4AwEwYDVR0l\nBAwwCgYIKwYBBQUHAwIwHQYDVR0OBBYEFOyCxUmH/dY/gVswnWkrhEVDIYE2MB8G\nA1UdIwQYMBaAFK3L061zobU6kAvA3v569BPBhweqMAoGCCqGSM49BAMCA0cAMEQC\nIHKEzGczGvU2l3Z/OCNYU0r+gODrtXU+wy6nGz+nnOHmAiAFEy301VZZnAQTqErh\nAGATSmiSAXtlQgd4B8biq/AqZg==\n-----END CERTIFICATE-----\n";
+
+    fn client_certificate() -> ClientCertificate {
+        let certificates =
+            rustls_pemfile::certs(&mut BufReader::new(CLIENT_CERTIFICATE.as_bytes())).unwrap();
+        ClientCertificate::from_der(&certificates[0]).unwrap()
+    }
+
+    fn api_definition(client_certificate_policy: &str) -> APIDefinition {
+        APIDefinition::from_json_str_slice(&format!(
+            r#"{{
+                "api_id": "billing",
+                "api_name": "Billing API",
+                "api_version": "1.0.0",
+                "api_desc": "Invoices",
+                "specification": {{"methods": ["GET"], "paths": ["/invoices"], "hostnames": ["localhost"]}},
+                "backend_response_timeout": 1000,
+                "origin_id": "billing",
+                "client_certificate": {}
+            }}"#,
+            client_certificate_policy
+        ))
+        .unwrap()
+    }
+
+    fn connection_info(client_certificate: Option<ClientCertificate>) -> ConnectionInfo {
+        ConnectionInfo {
+            remote_addr: SocketAddr::from(([10, 1, 2, 3], 52000)),
+            client_certificate: client_certificate.map(Arc::new),
+        }
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .uri("/invoices")
+            .header("x-client-cert-subject", "O=Acme, CN=admin")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_certificates_are_parsed() {
+        assert_eq!(
+            ClientCertificate {
+                subject: String::from("O=Acme, CN=billing"),
+                subject_alt_names: vec![
+                    String::from("billing.internal"),
+                    String::from("spiffe://acme/billing"),
+                ],
+                fingerprint: String::from(
+                    "5b16680f48ac8b4b0ef0033a7670e361cff816270a656111208ebc3f92d1c062"
+                ),
+            },
+            client_certificate()
+        );
+        assert!(ClientCertificate::from_der(b"not a certificate").is_none());
+    }
+
+    #[test]
+    fn test_verified_identity_is_forwarded() {
+        let api_definition = api_definition(
+            r#"{"san_patterns": ["spiffe://acme/*"], "fingerprint_header": "X-Client-Fingerprint"}"#,
+        );
+        let forwarded = authenticate_client_certificate(
+            &api_definition,
+            &connection_info(Some(client_certificate())),
+            request(),
+        )
+        .unwrap();
+        let header = |name| forwarded.headers().get(name).unwrap().to_str().unwrap();
+        assert_eq!("O=Acme, CN=billing", header("x-client-cert-subject"));
+        assert_eq!(
+            "billing.internal, spiffe://acme/billing",
+            header("x-client-cert-san")
+        );
+        assert_eq!(
+            "5b16680f48ac8b4b0ef0033a7670e361cff816270a656111208ebc3f92d1c062",
+            header("x-client-fingerprint")
+        );
+    }
+
+    #[test]
+    fn test_certificates_must_match_the_policy() {
+        assert_eq!(
+            Some(AuthRejection::Unauthenticated(None)),
+            authenticate_client_certificate(
+                &api_definition("{}"),
+                &connection_info(None),
+                request()
+            )
+            .err()
+        );
+        for policy in [
+            r#"{"subject_patterns": ["*CN=reporting"]}"#,
+            r#"{"subject_patterns": ["*CN=billing"], "san_patterns": ["spiffe://partner/*"]}"#,
+            r#"{"san_patterns": ["[invalid"]}"#,
+        ] {
+            assert_eq!(
+                Some(AuthRejection::Forbidden(None)),
+                authenticate_client_certificate(
+                    &api_definition(policy),
+                    &connection_info(Some(client_certificate())),
+                    request()
+                )
+                .err()
+            );
+        }
+
+        let unprotected = APIDefinition {
+            client_certificate: None,
+            ..api_definition("{}")
+        };
+        let forwarded =
+            authenticate_client_certificate(&unprotected, &connection_info(None), request())
+                .unwrap();
+        assert!(forwarded.headers().get("x-client-cert-subject").is_none());
+    }
+}
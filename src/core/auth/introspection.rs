@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hyper::{Body, Request};
+use log::{debug, trace, warn};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::configuration_reader::api_def_reader::{
+    IntrospectionAuthConfig, IntrospectionFailureMode,
+};
+use crate::core::auth::auth_client::{post_form, ProviderError};
+use crate::core::auth::authentication::AuthRejection;
+use crate::core::auth::bearer::{
+    authorize_claims, challenge, forward_claims, invalid_token, read_bearer_token,
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(2000);
+const DEFAULT_MAX_CACHE_DURATION: u64 = 300;
+const MAX_CACHED_TOKENS: usize = 10000;
+const CACHE_PURGE_INTERVAL: Duration = Duration::from_secs(10);
+
+struct CachedIntrospection {
+    claims: Arc<Map<String, Value>>,
+    expires_at: Instant,
+}
+
+/// Active introspection results, keyed by a hash of the endpoint and the token so
+/// that tokens are not kept in memory.
+#[derive(Default)]
+struct IntrospectionCache {
+    entries: HashMap<String, CachedIntrospection>,
+    last_purge: Option<Instant>,
+}
+
+impl IntrospectionCache {
+    fn get(&mut self, cache_key: &str, now: Instant) -> Option<Arc<Map<String, Value>>> {
+        match self.entries.get(cache_key) {
+            Some(cached) if cached.expires_at > now => Some(cached.claims.clone()),
+            Some(_) => {
+                self.entries.remove(cache_key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// When the cache is full, expired results are purged, at most once every
+    /// `CACHE_PURGE_INTERVAL` so that a full cache of live results does not make
+    /// every miss scan it. If there is no room, the result is simply not cached.
+    fn insert(
+        &mut self,
+        cache_key: String,
+        claims: Arc<Map<String, Value>>,
+        expires_at: Instant,
+        now: Instant,
+    ) {
+        if self.entries.len() >= MAX_CACHED_TOKENS {
+            if self
+                .last_purge
+                .is_some_and(|last_purge| now.duration_since(last_purge) < CACHE_PURGE_INTERVAL)
+            {
+                return;
+            }
+            self.last_purge = Some(now);
+            self.entries.retain(|_, cached| cached.expires_at > now);
+            if self.entries.len() >= MAX_CACHED_TOKENS {
+                return;
+            }
+        }
+        self.entries
+            .insert(cache_key, CachedIntrospection { claims, expires_at });
+    }
+}
+
+static INTROSPECTION_CACHE: OnceLock<Mutex<IntrospectionCache>> = OnceLock::new();
+
+fn introspection_cache() -> MutexGuard<'static, IntrospectionCache> {
+    INTROSPECTION_CACHE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+}
+
+fn cache_key(endpoint: &str, token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(endpoint.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(token.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+async fn introspect(
+    config: &IntrospectionAuthConfig,
+    token: &str,
+) -> Result<Map<String, Value>, ProviderError> {
+    let request_timeout = config
+        .timeout
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_TIMEOUT);
+    post_form(
+        &config.introspection_endpoint,
+        &config.client_id,
+        Some(&config.client_secret),
+        &[("token", token), ("token_type_hint", "access_token")],
+        request_timeout,
+    )
+    .await
+}
+
+/// How long an introspection result may be cached, or `None` if the token is
+/// not active (anymore).
+fn cache_duration(config: &IntrospectionAuthConfig, claims: &Map<String, Value>) -> Option<u64> {
+    if claims.get("active") != Some(&Value::Bool(true)) {
+        return None;
+    }
+    let max_cache_duration = config
+        .max_cache_duration
+        .unwrap_or(DEFAULT_MAX_CACHE_DURATION);
+    match claims.get("exp").and_then(Value::as_u64) {
+        None => Some(max_cache_duration),
+        Some(exp) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            if exp <= now {
+                None
+            } else {
+                Some(max_cache_duration.min(exp - now))
+            }
+        }
+    }
+}
+
+pub(crate) async fn authenticate_introspection(
+    api_id: &str,
+    config: &IntrospectionAuthConfig,
+    mut request: Request<Body>,
+) -> Result<Request<Body>, AuthRejection> {
+    let realm = config.realm.as_deref();
+    let token = match read_bearer_token(&request) {
+        None => {
+            debug!(
+                "Rejected request without bearer token to APIDefinition (APIDefinition ID: {})",
+                api_id
+            );
+            return Err(AuthRejection::Unauthenticated(Some(challenge(
+                realm, None, None,
+            ))));
+        }
+        Some(token) => String::from(token),
+    };
+    let cache_key = cache_key(&config.introspection_endpoint, &token);
+    let cached_claims = introspection_cache().get(&cache_key, Instant::now());
+    let claims = match cached_claims {
+        Some(claims) => claims,
+        None => {
+            let claims = match introspect(config, &token).await {
+                Ok(claims) => claims,
+                Err(error) => {
+                    warn!(
+                        "Failed to introspect token for APIDefinition (APIDefinition ID: {}) at {} - {}",
+                        api_id, config.introspection_endpoint, error
+                    );
+                    return Err(match config.failure_mode {
+                        IntrospectionFailureMode::ServiceUnavailable => AuthRejection::Unavailable,
+                        IntrospectionFailureMode::Unauthorized => {
+                            invalid_token(realm, "The token could not be verified")
+                        }
+                    });
+                }
+            };
+            let cache_duration = match cache_duration(config, &claims) {
+                None => {
+                    debug!(
+                        "Rejected inactive token for APIDefinition (APIDefinition ID: {})",
+                        api_id
+                    );
+                    return Err(invalid_token(realm, "The token is not active"));
+                }
+                Some(cache_duration) => cache_duration,
+            };
+            let claims = Arc::new(claims);
+            let now = Instant::now();
+            introspection_cache().insert(
+                cache_key,
+                claims.clone(),
+                now + Duration::from_secs(cache_duration),
+                now,
+            );
+            claims
+        }
+    };
+    authorize_claims(
+        realm,
+        &config.required_scopes,
+        &config.required_claims,
+        &claims,
+    )?;
+    forward_claims(&config.claim_headers, &claims, &mut request)?;
+    trace!(
+        "Authenticated introspected token for APIDefinition (APIDefinition ID: {})",
+        api_id
+    );
+    Ok(request)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server, StatusCode};
+    use serde_json::{json, Map};
+
+    use crate::configuration_reader::api_def_reader::{
+        IntrospectionAuthConfig, IntrospectionFailureMode,
+    };
+    use crate::core::auth::authentication::AuthRejection;
+
+    use super::{authenticate_introspection, IntrospectionCache, MAX_CACHED_TOKENS};
+
+    /// Authorization server accepting the client `gateman` with secret `s3cret:1`
+    /// and knowing the tokens `active-token` and `expired-token`. Returns its
+    /// introspection endpoint and the number of introspection calls made.
+    fn spawn_authorization_server() -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted_calls = calls.clone();
+        let make_service = make_service_fn(move |_| {
+            let counted_calls = counted_calls.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let counted_calls = counted_calls.clone();
+                    async move {
+                        counted_calls.fetch_add(1, Ordering::SeqCst);
+                        let authorized = request
+                            .headers()
+                            .get("authorization")
+                            .map(|value| value == "Basic Z2F0ZW1hbjpzM2NyZXQlM0Ex")
+                            .unwrap_or(false);
+                        let form = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let result = match std::str::from_utf8(&form).unwrap() {
+                            _ if !authorized => {
+                                let mut response = Response::new(Body::empty());
+                                *response.status_mut() = StatusCode::UNAUTHORIZED;
+                                return Ok::<_, Infallible>(response);
+                            }
+                            "token=active-token&token_type_hint=access_token" => json!({
+                                "active": true,
+                                "sub": "user-7",
+                                "scope": "invoices:read",
+                            }),
+                            "token=expired-token&token_type_hint=access_token" => json!({
+                                "active": true,
+                                "exp": 1,
+                            }),
+                            _ => json!({"active": false}),
+                        };
+                        Ok::<_, Infallible>(Response::new(Body::from(result.to_string())))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let url = format!("http://{}/oauth2/introspect", server.local_addr());
+        tokio::spawn(server);
+        (url, calls)
+    }
+
+    fn config(introspection_endpoint: &str) -> IntrospectionAuthConfig {
+        IntrospectionAuthConfig {
+            introspection_endpoint: String::from(introspection_endpoint),
+            client_id: String::from("gateman"),
+            client_secret: String::from("s3cret:1"),
+            timeout: None,
+            max_cache_duration: None,
+            failure_mode: IntrospectionFailureMode::ServiceUnavailable,
+            required_scopes: vec![],
+            required_claims: HashMap::new(),
+            claim_headers: HashMap::new(),
+            realm: None,
+        }
+    }
+
+    fn request(token: &str) -> Request<Body> {
+        Request::builder()
+            .uri("/invoices")
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn error_description(rejection: AuthRejection) -> Option<String> {
+        match rejection {
+            AuthRejection::Unauthenticated(Some(challenge)) => challenge.error_description,
+            _ => panic!("Expected the token to be rejected with a challenge"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_active_tokens_are_cached() {
+        let (url, calls) = spawn_authorization_server();
+        let mut config = config(&url);
+        config.claim_headers = HashMap::from([(String::from("sub"), String::from("X-User-ID"))]);
+        for _ in 0..2 {
+            let forwarded = authenticate_introspection("billing", &config, request("active-token"))
+                .await
+                .unwrap();
+            assert_eq!("user-7", forwarded.headers().get("x-user-id").unwrap());
+        }
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_inactive_tokens_are_rejected() {
+        let (url, calls) = spawn_authorization_server();
+        let config = config(&url);
+        for token in ["revoked-token", "expired-token", "revoked-token"] {
+            let rejection = authenticate_introspection("billing", &config, request(token))
+                .await
+                .unwrap_err();
+            assert_eq!(
+                Some(String::from("The token is not active")),
+                error_description(rejection)
+            );
+        }
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_scopes_are_required() {
+        let (url, _) = spawn_authorization_server();
+        let mut config = config(&url);
+        config.required_scopes = vec![String::from("invoices:write")];
+        match authenticate_introspection("billing", &config, request("active-token")).await {
+            Err(AuthRejection::Forbidden(Some(challenge))) => {
+                assert_eq!(Some(String::from("invoices:write")), challenge.scope)
+            }
+            _ => panic!("Expected the token to lack a scope"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_introspection_fails_closed() {
+        let (url, _) = spawn_authorization_server();
+        let mut config = config(&url);
+        config.client_secret = String::from("wrong");
+        assert_eq!(
+            Some(AuthRejection::Unavailable),
+            authenticate_introspection("billing", &config, request("active-token"))
+                .await
+                .err()
+        );
+
+        config.introspection_endpoint = String::from("http://127.0.0.1:1/oauth2/introspect");
+        config.failure_mode = IntrospectionFailureMode::Unauthorized;
+        let rejection = authenticate_introspection("billing", &config, request("active-token"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            Some(String::from("The token could not be verified")),
+            error_description(rejection)
+        );
+    }
+
+    #[test]
+    fn test_full_cache_is_purged_at_most_once_per_interval() {
+        let now = Instant::now();
+        let mut cache = IntrospectionCache::default();
+        let claims = Arc::new(Map::new());
+        for index in 0..MAX_CACHED_TOKENS {
+            cache.insert(
+                index.to_string(),
+                claims.clone(),
+                now + Duration::from_secs(1),
+                now,
+            );
+        }
+        cache.insert(
+            String::from("new"),
+            claims.clone(),
+            now + Duration::from_secs(60),
+            now,
+        );
+        assert!(cache.get("new", now).is_none());
+
+        // Every result has expired, yet the cache is only purged again later
+        let later = now + Duration::from_secs(2);
+        cache.insert(
+            String::from("new"),
+            claims.clone(),
+            now + Duration::from_secs(60),
+            later,
+        );
+        assert!(cache.get("new", later).is_none());
+        assert_eq!(MAX_CACHED_TOKENS, cache.entries.len());
+
+        let after_interval = now + Duration::from_secs(11);
+        cache.insert(
+            String::from("new"),
+            claims,
+            now + Duration::from_secs(60),
+            after_interval,
+        );
+        assert!(cache.get("new", after_interval).is_some());
+        assert_eq!(1, cache.entries.len());
+    }
+}
@@ -0,0 +1,10 @@
+pub(crate) mod api_key;
+pub(crate) mod auth_client;
+pub(crate) mod authentication;
+pub(crate) mod basic;
+pub(crate) mod bearer;
+pub(crate) mod client_cert;
+pub(crate) mod introspection;
+pub(crate) mod jwt;
+pub(crate) mod jwt_keys;
+pub(crate) mod oidc;
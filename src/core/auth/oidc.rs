@@ -0,0 +1,889 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hyper::header::{HeaderValue, ACCEPT, COOKIE};
+use hyper::{Body, Method, Request, Uri};
+use log::{debug, error, trace, warn};
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::configuration_reader::api_def_reader::{JwtAlgorithm, JwtAuthConfig, OidcAuthConfig};
+use crate::core::auth::auth_client::{form_urldecode, form_urlencode, post_form, ProviderError};
+use crate::core::auth::authentication::{AuthCookie, AuthRejection};
+use crate::core::auth::bearer::{authorize_claims, forward_claims};
+use crate::core::auth::jwt::verify_token;
+use crate::core::rate_limiter::rate_limit_key::query_parameter;
+
+const DEFAULT_COOKIE_NAME: &str = "gateman_session";
+const DEFAULT_SESSION_DURATION: u64 = 8 * 60 * 60;
+const DEFAULT_SCOPE: &str = "openid";
+/// How long the provider may take to send the browser back after a redirect.
+const LOGIN_DURATION: u64 = 10 * 60;
+/// Lifetime of a session renewed without a new ID token, unless the provider
+/// tells how long its access token is valid.
+const DEFAULT_RENEWAL_DURATION: u64 = 5 * 60;
+const PROVIDER_TIMEOUT: Duration = Duration::from_secs(5);
+/// Browsers drop larger cookies, which would log the user out right away.
+const MAX_COOKIE_SIZE: usize = 4096;
+const RANDOM_BYTES: usize = 32;
+
+/// A login in progress, kept in a cookie until the provider redirects back.
+#[derive(Serialize, Deserialize)]
+struct Login {
+    state: String,
+    nonce: String,
+    code_verifier: String,
+    return_to: String,
+    expires_at: u64,
+}
+
+/// Claims of the ID token and what is needed to renew them, kept in a cookie.
+#[derive(Serialize, Deserialize)]
+struct Session {
+    claims: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    /// When the claims need to be renewed.
+    expires_at: u64,
+    /// When the user has to log in again.
+    ends_at: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn random_token() -> String {
+    let mut token = [0u8; RANDOM_BYTES];
+    rand::thread_rng().fill_bytes(&mut token);
+    URL_SAFE_NO_PAD.encode(token)
+}
+
+/// The S256 PKCE challenge of RFC 7636.
+fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+fn session_cookie_name(config: &OidcAuthConfig) -> &str {
+    config.cookie_name.as_deref().unwrap_or(DEFAULT_COOKIE_NAME)
+}
+
+fn login_cookie_name(config: &OidcAuthConfig) -> String {
+    format!("{}_login", session_cookie_name(config))
+}
+
+fn cipher(config: &OidcAuthConfig) -> Aes256Gcm {
+    Aes256Gcm::new(&Sha256::digest(config.session_secret.as_bytes()))
+}
+
+/// Encrypts the value for the cookie `cookie_name`, which is authenticated along
+/// with it so that a login cookie cannot pass for a session cookie.
+fn seal<T: Serialize>(config: &OidcAuthConfig, cookie_name: &str, value: &T) -> Option<String> {
+    let plaintext = serde_json::to_vec(value).ok()?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: &plaintext,
+        aad: cookie_name.as_bytes(),
+    };
+    let mut sealed = nonce.to_vec();
+    sealed.extend(cipher(config).encrypt(&nonce, payload).ok()?);
+    Some(URL_SAFE_NO_PAD.encode(sealed))
+}
+
+fn open<T: DeserializeOwned>(
+    config: &OidcAuthConfig,
+    cookie_name: &str,
+    sealed: &str,
+) -> Option<T> {
+    let sealed = URL_SAFE_NO_PAD.decode(sealed).ok()?;
+    if sealed.len() < 12 {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(12);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: cookie_name.as_bytes(),
+    };
+    let plaintext = cipher(config)
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .ok()?;
+    serde_json::from_slice(&plaintext).ok()
+}
+
+fn read_cookie<'a>(request: &'a Request<Body>, cookie_name: &str) -> Option<&'a str> {
+    request
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header_value| header_value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == cookie_name)
+        .map(|(_, value)| value)
+}
+
+fn create_cookie(
+    config: &OidcAuthConfig,
+    cookie_name: &str,
+    value: &str,
+    max_age: u64,
+) -> Option<HeaderValue> {
+    let mut cookie = format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+        cookie_name, value, max_age
+    );
+    if config.redirect_uri.starts_with("https://") {
+        cookie.push_str("; Secure");
+    }
+    if cookie.len() > MAX_COOKIE_SIZE {
+        warn!(
+            "Cookie {} is {} bytes long and may be dropped by browsers",
+            cookie_name,
+            cookie.len()
+        );
+    }
+    HeaderValue::from_str(&cookie).ok()
+}
+
+fn create_session_cookie(config: &OidcAuthConfig, session: &Session) -> Option<HeaderValue> {
+    let cookie_name = session_cookie_name(config);
+    create_cookie(
+        config,
+        cookie_name,
+        &seal(config, cookie_name, session)?,
+        session.ends_at.saturating_sub(now()),
+    )
+}
+
+/// Keeps the cookies of the gateway from the Origin, forwarding all others.
+fn remove_gateway_cookies(config: &OidcAuthConfig, request: &mut Request<Body>) {
+    let gateway_cookies = [
+        String::from(session_cookie_name(config)),
+        login_cookie_name(config),
+    ];
+    let cookies: Vec<String> = request
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header_value| header_value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .map(str::trim)
+        .filter(|cookie| {
+            let name = cookie.split_once('=').map_or(*cookie, |(name, _)| name);
+            !cookie.is_empty() && !gateway_cookies.iter().any(|gateway| gateway == name)
+        })
+        .map(String::from)
+        .collect();
+    request.headers_mut().remove(COOKIE);
+    if let Ok(cookies) = HeaderValue::from_str(&cookies.join("; ")) {
+        if !cookies.is_empty() {
+            request.headers_mut().insert(COOKIE, cookies);
+        }
+    }
+}
+
+fn is_callback(config: &OidcAuthConfig, request: &Request<Body>) -> bool {
+    match config.redirect_uri.parse::<Uri>() {
+        Ok(redirect_uri) => redirect_uri.path() == request.uri().path(),
+        Err(_) => false,
+    }
+}
+
+/// Only page loads can follow a redirect to the provider and back.
+fn is_page_load(request: &Request<Body>) -> bool {
+    request.method() == Method::GET
+        && request
+            .headers()
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .map(|accept| accept.contains("text/html"))
+            .unwrap_or(false)
+}
+
+fn authorization_url(config: &OidcAuthConfig, login: &Login) -> String {
+    let scope = if config.scopes.is_empty() {
+        String::from(DEFAULT_SCOPE)
+    } else {
+        config.scopes.join(" ")
+    };
+    let parameters = [
+        ("response_type", "code"),
+        ("client_id", config.client_id.as_str()),
+        ("redirect_uri", config.redirect_uri.as_str()),
+        ("scope", scope.as_str()),
+        ("state", login.state.as_str()),
+        ("nonce", login.nonce.as_str()),
+        ("code_challenge", &code_challenge(&login.code_verifier)),
+        ("code_challenge_method", "S256"),
+    ];
+    let query: Vec<String> = parameters
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, form_urlencode(value)))
+        .collect();
+    let separator = if config.authorization_endpoint.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+    format!(
+        "{}{}{}",
+        config.authorization_endpoint,
+        separator,
+        query.join("&")
+    )
+}
+
+/// Only paths on the gateway itself are returned to after login. `//host` and
+/// `/\host` would be followed by browsers to another site.
+fn local_return_to(path_and_query: &str) -> String {
+    let mut characters = path_and_query.chars();
+    match (characters.next(), characters.next()) {
+        (Some('/'), None) => String::from(path_and_query),
+        (Some('/'), Some(second)) if second != '/' && second != '\\' => {
+            String::from(path_and_query)
+        }
+        _ => String::from("/"),
+    }
+}
+
+/// Sends page loads to the provider to log in, remembering the page to return to.
+fn start_login(api_id: &str, config: &OidcAuthConfig, request: &Request<Body>) -> AuthRejection {
+    if !is_page_load(request) {
+        debug!(
+            "Rejected request without session to APIDefinition (APIDefinition ID: {})",
+            api_id
+        );
+        return AuthRejection::Unauthenticated(None);
+    }
+    let login = Login {
+        state: random_token(),
+        nonce: random_token(),
+        code_verifier: random_token(),
+        return_to: request
+            .uri()
+            .path_and_query()
+            .map(|path_and_query| local_return_to(path_and_query.as_str()))
+            .unwrap_or_else(|| String::from("/")),
+        expires_at: now() + LOGIN_DURATION,
+    };
+    let login_cookie_name = login_cookie_name(config);
+    let login_cookie = seal(config, &login_cookie_name, &login)
+        .and_then(|sealed| create_cookie(config, &login_cookie_name, &sealed, LOGIN_DURATION));
+    match login_cookie {
+        None => {
+            error!(
+                "Failed to create login cookie for APIDefinition (APIDefinition ID: {})",
+                api_id
+            );
+            AuthRejection::Unavailable
+        }
+        Some(login_cookie) => AuthRejection::Redirect {
+            location: authorization_url(config, &login),
+            cookies: vec![login_cookie],
+        },
+    }
+}
+
+/// ID tokens are verified like bearer JWTs, signed by the provider for the client.
+async fn verify_id_token(
+    api_id: &str,
+    config: &OidcAuthConfig,
+    id_token: &str,
+) -> Result<Map<String, Value>, AuthRejection> {
+    let jwt_config = JwtAuthConfig {
+        algorithms: vec![JwtAlgorithm::RS256, JwtAlgorithm::ES256],
+        secret: None,
+        public_key_file: None,
+        jwks: Some(config.jwks.clone()),
+        jwks_refresh_interval: config.jwks_refresh_interval,
+        issuer: Some(config.issuer.clone()),
+        audiences: vec![config.client_id.clone()],
+        leeway: config.leeway,
+        required_scopes: vec![],
+        required_claims: Default::default(),
+        claim_headers: Default::default(),
+        realm: None,
+    };
+    verify_token(api_id, &jwt_config, id_token).await
+}
+
+fn provider_rejection(
+    api_id: &str,
+    config: &OidcAuthConfig,
+    error: ProviderError,
+) -> AuthRejection {
+    warn!(
+        "Token endpoint {} of APIDefinition (APIDefinition ID: {}) failed - {}",
+        config.token_endpoint, api_id, error
+    );
+    match error {
+        ProviderError::Rejected(_) => AuthRejection::Unauthenticated(None),
+        ProviderError::Unavailable(_) => AuthRejection::Unavailable,
+    }
+}
+
+/// Handles the redirect back from the provider: exchanges the code for tokens,
+/// starts the session and sends the browser back to the page it came from.
+async fn complete_login(
+    api_id: &str,
+    config: &OidcAuthConfig,
+    request: &Request<Body>,
+) -> AuthRejection {
+    let parameter = |name| query_parameter(request, name).and_then(|value| form_urldecode(&value));
+    if let Some(error) = parameter("error") {
+        debug!(
+            "Provider of APIDefinition (APIDefinition ID: {}) refused the login - {}",
+            api_id, error
+        );
+        return AuthRejection::Unauthenticated(None);
+    }
+    let login_cookie_name = login_cookie_name(config);
+    let login = read_cookie(request, &login_cookie_name)
+        .and_then(|sealed| open::<Login>(config, &login_cookie_name, sealed))
+        .filter(|login| login.expires_at > now());
+    let (login, code) = match (login, parameter("state"), parameter("code")) {
+        (Some(login), Some(state), Some(code)) if login.state == state => (login, code),
+        _ => {
+            debug!(
+                "Rejected login without matching state to APIDefinition (APIDefinition ID: {})",
+                api_id
+            );
+            return AuthRejection::Unauthenticated(None);
+        }
+    };
+    let tokens = match post_form(
+        &config.token_endpoint,
+        &config.client_id,
+        config.client_secret.as_deref(),
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("redirect_uri", &config.redirect_uri),
+            ("code_verifier", &login.code_verifier),
+        ],
+        PROVIDER_TIMEOUT,
+    )
+    .await
+    {
+        Ok(tokens) => tokens,
+        Err(error) => return provider_rejection(api_id, config, error),
+    };
+    let claims = match tokens.get("id_token").and_then(Value::as_str) {
+        None => {
+            error!(
+                "Token endpoint {} of APIDefinition (APIDefinition ID: {}) issued no ID token",
+                config.token_endpoint, api_id
+            );
+            return AuthRejection::Unavailable;
+        }
+        Some(id_token) => match verify_id_token(api_id, config, id_token).await {
+            Ok(claims) => claims,
+            Err(AuthRejection::Unavailable) => return AuthRejection::Unavailable,
+            Err(_) => return AuthRejection::Unauthenticated(None),
+        },
+    };
+    if claims.get("nonce").and_then(Value::as_str) != Some(login.nonce.as_str()) {
+        debug!(
+            "Rejected ID token with wrong nonce for APIDefinition (APIDefinition ID: {})",
+            api_id
+        );
+        return AuthRejection::Unauthenticated(None);
+    }
+    let session = Session {
+        expires_at: claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .unwrap_or_default(),
+        ends_at: now() + config.session_duration.unwrap_or(DEFAULT_SESSION_DURATION),
+        refresh_token: tokens
+            .get("refresh_token")
+            .and_then(Value::as_str)
+            .map(String::from),
+        claims,
+    };
+    let cookies = vec![
+        create_session_cookie(config, &session),
+        create_cookie(config, &login_cookie_name, "", 0),
+    ];
+    if cookies.iter().any(Option::is_none) {
+        error!(
+            "Failed to create session cookie for APIDefinition (APIDefinition ID: {})",
+            api_id
+        );
+        return AuthRejection::Unavailable;
+    }
+    trace!(
+        "Started session for APIDefinition (APIDefinition ID: {})",
+        api_id
+    );
+    AuthRejection::Redirect {
+        location: local_return_to(&login.return_to),
+        cookies: cookies.into_iter().flatten().collect(),
+    }
+}
+
+/// Renews the claims of the session with its refresh token. The session has
+/// ended if there is none, or if the provider refuses it.
+async fn renew_session(
+    api_id: &str,
+    config: &OidcAuthConfig,
+    session: Session,
+) -> Result<Option<Session>, AuthRejection> {
+    let refresh_token = match &session.refresh_token {
+        None => return Ok(None),
+        Some(refresh_token) => refresh_token,
+    };
+    let tokens = match post_form(
+        &config.token_endpoint,
+        &config.client_id,
+        config.client_secret.as_deref(),
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ],
+        PROVIDER_TIMEOUT,
+    )
+    .await
+    {
+        Ok(tokens) => tokens,
+        Err(ProviderError::Rejected(status)) => {
+            debug!(
+                "Provider of APIDefinition (APIDefinition ID: {}) refused to renew a session - {}",
+                api_id, status
+            );
+            return Ok(None);
+        }
+        Err(error) => return Err(provider_rejection(api_id, config, error)),
+    };
+    let (claims, expires_at) = match tokens.get("id_token").and_then(Value::as_str) {
+        None => {
+            let expires_in = tokens
+                .get("expires_in")
+                .and_then(Value::as_u64)
+                .unwrap_or(DEFAULT_RENEWAL_DURATION);
+            (session.claims, now() + expires_in)
+        }
+        Some(id_token) => match verify_id_token(api_id, config, id_token).await {
+            Ok(claims) if claims.get("sub") == session.claims.get("sub") => {
+                let expires_at = claims
+                    .get("exp")
+                    .and_then(Value::as_u64)
+                    .unwrap_or_default();
+                (claims, expires_at)
+            }
+            Ok(_) | Err(_) => return Ok(None),
+        },
+    };
+    Ok(Some(Session {
+        claims,
+        refresh_token: tokens
+            .get("refresh_token")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .or(session.refresh_token),
+        expires_at,
+        ends_at: session.ends_at,
+    }))
+}
+
+pub(crate) async fn authenticate_oidc(
+    api_id: &str,
+    config: &OidcAuthConfig,
+    mut request: Request<Body>,
+) -> Result<Request<Body>, AuthRejection> {
+    if is_callback(config, &request) {
+        return Err(complete_login(api_id, config, &request).await);
+    }
+    let now = now();
+    let session_cookie_name = session_cookie_name(config);
+    let session = read_cookie(&request, session_cookie_name)
+        .and_then(|sealed| open::<Session>(config, session_cookie_name, sealed))
+        .filter(|session| session.ends_at > now);
+    let (session, renewed) = match session {
+        Some(session) if session.expires_at > now => (Some(session), false),
+        Some(session) => (renew_session(api_id, config, session).await?, true),
+        None => (None, false),
+    };
+    let session = match session {
+        None => return Err(start_login(api_id, config, &request)),
+        Some(session) => session,
+    };
+    authorize_claims(None, &[], &config.required_claims, &session.claims)
+        .map_err(|_| AuthRejection::Forbidden(None))?;
+    remove_gateway_cookies(config, &mut request);
+    forward_claims(&config.claim_headers, &session.claims, &mut request)?;
+    if renewed {
+        if let Some(session_cookie) = create_session_cookie(config, &session) {
+            request.extensions_mut().insert(AuthCookie(session_cookie));
+        }
+    }
+    trace!(
+        "Authenticated session for APIDefinition (APIDefinition ID: {})",
+        api_id
+    );
+    Ok(request)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use hyper::header::{HeaderValue, ACCEPT, COOKIE};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server, StatusCode};
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use serde_json::{json, Map, Value};
+
+    use crate::configuration_reader::api_def_reader::OidcAuthConfig;
+    use crate::core::auth::auth_client::{form_urldecode, form_urlencode};
+    use crate::core::auth::authentication::{AuthCookie, AuthRejection};
+    use crate::core::auth::jwt_keys::jwks_stand_in::{EC_JWKS, EC_PRIVATE_KEY};
+
+    use super::{
+        authenticate_oidc, code_challenge, local_return_to, now, open, seal, Login, Session,
+    };
+
+    /// What the user agreed to at the provider: the PKCE challenge and nonce of
+    /// the login, which the code `code-1` is then issued for.
+    #[derive(Default)]
+    struct ProviderLogin {
+        code_challenge: Option<String>,
+        nonce: Option<String>,
+    }
+
+    fn id_token(claims: Value) -> String {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(String::from("2026-10"));
+        let mut id_token_claims = json!({
+            "iss": "https://idp.example.com/",
+            "aud": "gateman",
+            "sub": "user-7",
+            "exp": now() + 300,
+        });
+        for (name, value) in claims.as_object().unwrap() {
+            id_token_claims[name] = value.clone();
+        }
+        encode(
+            &header,
+            &id_token_claims,
+            &EncodingKey::from_ec_pem(EC_PRIVATE_KEY.as_bytes()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn token_response(form: &HashMap<String, String>, login: &ProviderLogin) -> Option<Value> {
+        match form.get("grant_type")?.as_str() {
+            "authorization_code" => {
+                let verifier = form.get("code_verifier")?;
+                if form.get("code")? != "code-1"
+                    || login.code_challenge.as_ref()? != &code_challenge(verifier)
+                {
+                    return None;
+                }
+                Some(json!({
+                    "id_token": id_token(json!({
+                        "email": "user@example.com",
+                        "nonce": login.nonce.as_ref()?,
+                    })),
+                    "refresh_token": "refresh-1",
+                    "expires_in": 300,
+                }))
+            }
+            "refresh_token" if form.get("refresh_token")? == "refresh-1" => Some(json!({
+                "id_token": id_token(json!({"email": "renewed@example.com"})),
+                "expires_in": 300,
+            })),
+            _ => None,
+        }
+    }
+
+    /// Provider serving its JWKS at `/jwks.json` and its token endpoint at `/token`.
+    fn spawn_provider() -> (String, Arc<Mutex<ProviderLogin>>) {
+        let login = Arc::new(Mutex::new(ProviderLogin::default()));
+        let provider_login = login.clone();
+        let make_service = make_service_fn(move |_| {
+            let provider_login = provider_login.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let provider_login = provider_login.clone();
+                    async move {
+                        if request.uri().path() == "/jwks.json" {
+                            return Ok::<_, Infallible>(Response::new(Body::from(EC_JWKS)));
+                        }
+                        let form = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let form: HashMap<String, String> = std::str::from_utf8(&form)
+                            .unwrap()
+                            .split('&')
+                            .filter_map(|field| field.split_once('='))
+                            .map(|(name, value)| {
+                                (String::from(name), form_urldecode(value).unwrap())
+                            })
+                            .collect();
+                        let response = token_response(&form, &provider_login.lock().unwrap());
+                        Ok::<_, Infallible>(match response {
+                            Some(response) => Response::new(Body::from(response.to_string())),
+                            None => {
+                                let mut response = Response::new(Body::from(
+                                    json!({"error": "invalid_grant"}).to_string(),
+                                ));
+                                *response.status_mut() = StatusCode::BAD_REQUEST;
+                                response
+                            }
+                        })
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (url, login)
+    }
+
+    fn config(provider_url: &str) -> OidcAuthConfig {
+        OidcAuthConfig {
+            issuer: String::from("https://idp.example.com/"),
+            authorization_endpoint: String::from("https://idp.example.com/authorize"),
+            token_endpoint: format!("{}/token", provider_url),
+            jwks: format!("{}/jwks.json", provider_url),
+            jwks_refresh_interval: None,
+            client_id: String::from("gateman"),
+            client_secret: Some(String::from("s3cret")),
+            redirect_uri: String::from("https://tools.example.com/oauth2/callback"),
+            scopes: vec![String::from("openid"), String::from("email")],
+            session_secret: String::from("a-session-secret"),
+            cookie_name: None,
+            session_duration: None,
+            leeway: None,
+            required_claims: HashMap::new(),
+            claim_headers: HashMap::from([(String::from("email"), String::from("X-User-Email"))]),
+        }
+    }
+
+    fn page_load(uri: &str, cookies: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder()
+            .uri(uri)
+            .header(ACCEPT, "text/html,application/xhtml+xml")
+            .body(Body::empty())
+            .unwrap();
+        if let Some(cookies) = cookies {
+            request
+                .headers_mut()
+                .insert(COOKIE, HeaderValue::from_str(cookies).unwrap());
+        }
+        request
+    }
+
+    /// The `name=value` part of a `Set-Cookie` header.
+    fn cookie_pair(set_cookie: &HeaderValue) -> &str {
+        set_cookie.to_str().unwrap().split(';').next().unwrap()
+    }
+
+    fn redirect(rejection: AuthRejection) -> (String, Vec<HeaderValue>) {
+        match rejection {
+            AuthRejection::Redirect { location, cookies } => (location, cookies),
+            _ => panic!("Expected a redirect"),
+        }
+    }
+
+    fn query_value(location: &str, name: &str) -> String {
+        let query = location.split_once('?').unwrap().1;
+        query
+            .split('&')
+            .filter_map(|field| field.split_once('='))
+            .find(|(field_name, _)| *field_name == name)
+            .and_then(|(_, value)| form_urldecode(value))
+            .unwrap()
+    }
+
+    fn session(refresh_token: &str, expires_at: u64, ends_at: u64) -> Session {
+        let mut claims = Map::new();
+        claims.insert(String::from("sub"), json!("user-7"));
+        claims.insert(String::from("email"), json!("user@example.com"));
+        Session {
+            claims,
+            refresh_token: Some(String::from(refresh_token)),
+            expires_at,
+            ends_at,
+        }
+    }
+
+    #[test]
+    fn test_cookies_are_sealed() {
+        let config = config("http://127.0.0.1:1");
+        let sealed = seal(&config, "gateman_session", &session("refresh-1", 1, 2)).unwrap();
+        let opened: Session = open(&config, "gateman_session", &sealed).unwrap();
+        assert_eq!(Some(String::from("refresh-1")), opened.refresh_token);
+        assert!(open::<Session>(&config, "gateman_session_login", &sealed).is_none());
+        let mut tampered = sealed.into_bytes();
+        tampered[20] = if tampered[20] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert!(open::<Session>(&config, "gateman_session", &tampered).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_page_loads_are_redirected_to_the_provider() {
+        let config = config("http://127.0.0.1:1");
+        let rejection = authenticate_oidc("tools", &config, page_load("/invoices?page=2", None))
+            .await
+            .unwrap_err();
+        let (location, cookies) = redirect(rejection);
+        assert!(location.starts_with("https://idp.example.com/authorize?response_type=code&"));
+        assert_eq!("gateman", query_value(&location, "client_id"));
+        assert_eq!(
+            "https://tools.example.com/oauth2/callback",
+            query_value(&location, "redirect_uri")
+        );
+        assert_eq!("openid email", query_value(&location, "scope"));
+        assert_eq!("S256", query_value(&location, "code_challenge_method"));
+        assert_eq!(1, cookies.len());
+        assert!(cookie_pair(&cookies[0]).starts_with("gateman_session_login="));
+        assert!(cookies[0].to_str().unwrap().ends_with("; Secure"));
+
+        let api_call = Request::builder()
+            .uri("/invoices")
+            .header(ACCEPT, "application/json")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            Some(AuthRejection::Unauthenticated(None)),
+            authenticate_oidc("tools", &config, api_call).await.err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_logins_only_return_to_the_gateway() {
+        assert_eq!("/invoices?page=2", local_return_to("/invoices?page=2"));
+        assert_eq!("/", local_return_to("/"));
+        for path_and_query in ["//evil.example", "/\\evil.example", "evil.example", ""] {
+            assert_eq!("/", local_return_to(path_and_query));
+        }
+
+        let config = config("http://127.0.0.1:1");
+        assert_eq!(
+            "//evil.example",
+            page_load("//evil.example", None).uri().path()
+        );
+        let rejection = authenticate_oidc("tools", &config, page_load("//evil.example", None))
+            .await
+            .unwrap_err();
+        let (_, cookies) = redirect(rejection);
+        let (_, sealed) = cookie_pair(&cookies[0]).split_once('=').unwrap();
+        let login: Login = open(&config, "gateman_session_login", sealed).unwrap();
+        assert_eq!("/", login.return_to);
+    }
+
+    #[tokio::test]
+    async fn test_logins_start_sessions_forwarding_id_token_claims() {
+        let (provider_url, provider_login) = spawn_provider();
+        let config = config(&provider_url);
+        let rejection = authenticate_oidc("tools", &config, page_load("/invoices?page=2", None))
+            .await
+            .unwrap_err();
+        let (location, cookies) = redirect(rejection);
+        *provider_login.lock().unwrap() = ProviderLogin {
+            code_challenge: Some(query_value(&location, "code_challenge")),
+            nonce: Some(query_value(&location, "nonce")),
+        };
+        let login_cookie = String::from(cookie_pair(&cookies[0]));
+        let callback = format!(
+            "/oauth2/callback?code=code-1&state={}",
+            form_urlencode(&query_value(&location, "state"))
+        );
+
+        for (uri, cookies) in [
+            (
+                "/oauth2/callback?code=code-1&state=forged",
+                Some(login_cookie.as_str()),
+            ),
+            (callback.as_str(), None),
+            (
+                "/oauth2/callback?error=access_denied",
+                Some(login_cookie.as_str()),
+            ),
+        ] {
+            assert_eq!(
+                Some(AuthRejection::Unauthenticated(None)),
+                authenticate_oidc("tools", &config, page_load(uri, cookies))
+                    .await
+                    .err()
+            );
+        }
+
+        let rejection =
+            authenticate_oidc("tools", &config, page_load(&callback, Some(&login_cookie)))
+                .await
+                .unwrap_err();
+        let (location, cookies) = redirect(rejection);
+        assert_eq!("/invoices?page=2", location);
+        assert_eq!(2, cookies.len());
+        assert!(cookies[1]
+            .to_str()
+            .unwrap()
+            .starts_with("gateman_session_login=; Path=/; Max-Age=0;"));
+
+        let cookies = format!("theme=dark; {}", cookie_pair(&cookies[0]));
+        let forwarded = authenticate_oidc("tools", &config, page_load("/invoices", Some(&cookies)))
+            .await
+            .unwrap();
+        assert_eq!(
+            "user@example.com",
+            forwarded.headers().get("x-user-email").unwrap()
+        );
+        assert_eq!("theme=dark", forwarded.headers().get(COOKIE).unwrap());
+        assert!(forwarded.extensions().get::<AuthCookie>().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_sessions_are_renewed() {
+        let (provider_url, _) = spawn_provider();
+        let config = config(&provider_url);
+        let session_cookie = |session: &Session| {
+            format!(
+                "gateman_session={}",
+                seal(&config, "gateman_session", session).unwrap()
+            )
+        };
+
+        let renewable = session_cookie(&session("refresh-1", now() - 1, now() + 3600));
+        let forwarded = authenticate_oidc("tools", &config, page_load("/", Some(&renewable)))
+            .await
+            .unwrap();
+        assert_eq!(
+            "renewed@example.com",
+            forwarded.headers().get("x-user-email").unwrap()
+        );
+        let AuthCookie(renewed) = forwarded.extensions().get::<AuthCookie>().unwrap();
+        let renewed = cookie_pair(renewed).split_once('=').unwrap().1;
+        let renewed: Session = open(&config, "gateman_session", renewed).unwrap();
+        assert!(renewed.expires_at > now());
+        assert_eq!(Some(String::from("refresh-1")), renewed.refresh_token);
+
+        for session in [
+            session("revoked", now() - 1, now() + 3600),
+            session("refresh-1", now() - 1, now() - 1),
+        ] {
+            let rejection = authenticate_oidc(
+                "tools",
+                &config,
+                page_load("/", Some(&session_cookie(&session))),
+            )
+            .await
+            .unwrap_err();
+            let (location, _) = redirect(rejection);
+            assert!(location.starts_with("https://idp.example.com/authorize?"));
+        }
+    }
+}
@@ -2,14 +2,21 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use glob::Pattern;
-use log::{debug, info, trace};
+use log::{debug, error, info, trace};
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::oneshot::Sender;
 
 use crate::configuration_reader::api_def_reader::{APIDefinition, APISpecification};
+use crate::configuration_reader::consumer_def_reader::Consumer;
 use crate::configuration_reader::origin_def_reader::Origin;
-use crate::core::config::read_config::{read_all_api_definitions, read_all_origin_definitions};
-use crate::{ConfigMgrProxyAPI, RateLimiterAPI};
+use crate::core::config::consumer_registry::{
+    ConsumerChange, ConsumerChangeError, ConsumerRegistry,
+};
+use crate::core::config::read_config::{
+    read_all_api_definitions, read_all_consumer_definitions, read_all_origin_definitions,
+    remove_consumer_definition, write_consumer_definition,
+};
+use crate::{ConfigMgrProxyAPI, LoadBalancerAPI, RateLimiterAPI};
 
 async fn send_origin_definitions_to_rate_limiter(
     rate_limiter_tx: tokio::sync::mpsc::Sender<RateLimiterAPI>,
@@ -48,8 +55,130 @@ async fn send_origin_definitions_to_rate_limiter(
     }
 }
 
+async fn send_api_definitions_to_rate_limiter(
+    rate_limiter_tx: tokio::sync::mpsc::Sender<RateLimiterAPI>,
+    api_definitions: &Vec<APIDefinition>,
+) {
+    debug!(
+        "Sending {} api definitions to rate limiter",
+        api_definitions.len()
+    );
+    for api_def in api_definitions {
+        trace!(
+            "Sending api definition (APIDefinition ID: {}) to rate limiter",
+            api_def.api_id
+        );
+        match rate_limiter_tx
+            .send(RateLimiterAPI::UpdateAPISpecification {
+                api_id: api_def.api_id.clone(),
+                rate_limit_policies: api_def.rate_limiters.clone(),
+                quota_policies: api_def.quotas.clone(),
+            })
+            .await
+        {
+            Err(error) => {
+                trace!(
+                    "Failed to send api definition (APIDefinition ID: {}) to rate limiter - {}",
+                    api_def.api_id,
+                    error
+                );
+            }
+            Ok(_) => {
+                trace!(
+                    "Sent api definition (APIDefinition ID: {}) to rate limiter",
+                    api_def.api_id
+                );
+            }
+        }
+    }
+}
+
+async fn send_origin_definitions_to_load_balancer(
+    load_balancer_tx: tokio::sync::mpsc::Sender<LoadBalancerAPI>,
+    origin_definitions: &Vec<Origin>,
+) {
+    debug!(
+        "Sending {} origin definitions to load balancer",
+        origin_definitions.len()
+    );
+    for origin_def in origin_definitions {
+        match load_balancer_tx
+            .send(LoadBalancerAPI::UpdateOriginSpecification {
+                origin_id: origin_def.origin_id.clone(),
+                origin_spec: Box::new(origin_def.specification.clone()),
+            })
+            .await
+        {
+            Err(error) => {
+                trace!(
+                    "Failed to send origin definition (Origin ID: {}) to load balancer - {}",
+                    origin_def.origin_id,
+                    error
+                );
+            }
+            Ok(_) => {
+                trace!(
+                    "Sent origin definition (Origin ID: {}) to load balancer",
+                    origin_def.origin_id
+                );
+            }
+        }
+    }
+}
+
+/// Clears the limiters of definitions that disappeared in a reload. API limiters
+/// are cleared by updating the API without any policies.
+async fn send_removed_definitions_to_rate_limiter(
+    rate_limiter_tx: tokio::sync::mpsc::Sender<RateLimiterAPI>,
+    removed_api_ids: Vec<String>,
+    removed_origin_ids: Vec<String>,
+) {
+    for api_id in removed_api_ids {
+        let update_call = RateLimiterAPI::UpdateAPISpecification {
+            api_id: api_id.clone(),
+            rate_limit_policies: vec![],
+            quota_policies: vec![],
+        };
+        if rate_limiter_tx.send(update_call).await.is_err() {
+            trace!(
+                "Failed to clear removed api definition (APIDefinition ID: {}) in rate limiter",
+                api_id
+            );
+        }
+    }
+    for origin_id in removed_origin_ids {
+        let remove_call = RateLimiterAPI::RemoveOriginSpecification {
+            origin_id: origin_id.clone(),
+        };
+        if rate_limiter_tx.send(remove_call).await.is_err() {
+            trace!(
+                "Failed to remove origin definition (Origin ID: {}) from rate limiter",
+                origin_id
+            );
+        }
+    }
+}
+
+async fn send_removed_origins_to_load_balancer(
+    load_balancer_tx: tokio::sync::mpsc::Sender<LoadBalancerAPI>,
+    removed_origin_ids: Vec<String>,
+) {
+    for origin_id in removed_origin_ids {
+        let remove_call = LoadBalancerAPI::RemoveOriginSpecification {
+            origin_id: origin_id.clone(),
+        };
+        if load_balancer_tx.send(remove_call).await.is_err() {
+            trace!(
+                "Failed to remove origin definition (Origin ID: {}) from load balancer",
+                origin_id
+            );
+        }
+    }
+}
+
 async fn initialize(
     rate_limiter_tx: tokio::sync::mpsc::Sender<RateLimiterAPI>,
+    load_balancer_tx: tokio::sync::mpsc::Sender<LoadBalancerAPI>,
 ) -> (HashMap<String, APIDefinition>, HashMap<String, Origin>) {
     let api_definitions = read_all_api_definitions();
     debug!(
@@ -65,7 +194,9 @@ async fn initialize(
 
     let mut api_def_map = HashMap::new();
     let mut origin_def_map = HashMap::new();
-    send_origin_definitions_to_rate_limiter(rate_limiter_tx, &origin_definitions).await;
+    send_origin_definitions_to_rate_limiter(rate_limiter_tx.clone(), &origin_definitions).await;
+    send_api_definitions_to_rate_limiter(rate_limiter_tx, &api_definitions).await;
+    send_origin_definitions_to_load_balancer(load_balancer_tx, &origin_definitions).await;
     for api_def in api_definitions {
         api_def_map.insert(api_def.api_id.clone(), api_def);
     }
@@ -75,6 +206,43 @@ async fn initialize(
     (api_def_map, origin_def_map)
 }
 
+fn load_consumers() -> ConsumerRegistry {
+    let consumer_definitions = read_all_consumer_definitions();
+    debug!(
+        "Configuration manager read {} consumer definitions",
+        consumer_definitions.len()
+    );
+    ConsumerRegistry::from_consumers(consumer_definitions)
+}
+
+/// Applies the change to a copy of the registry and stores the changed consumer
+/// definition. The copy replaces the registry only once it has been stored.
+fn change_consumer(
+    consumers: &ConsumerRegistry,
+    consumer_id: &str,
+    change: ConsumerChange,
+) -> Result<(ConsumerRegistry, Option<Consumer>), ConsumerChangeError> {
+    let mut changed_consumers = consumers.clone();
+    let consumer = changed_consumers.apply(consumer_id, change)?;
+    let store_result = match &consumer {
+        Some(consumer) => write_consumer_definition(consumer),
+        None => remove_consumer_definition(consumer_id),
+    };
+    match store_result {
+        Err(message) => {
+            error!(
+                "Failed to store definition of Consumer (Consumer ID: {}) - {}",
+                consumer_id, message
+            );
+            Err(ConsumerChangeError::StorageFailure(message))
+        }
+        Ok(_) => {
+            info!("Changed Consumer (Consumer ID: {})", consumer_id);
+            Ok((changed_consumers, consumer))
+        }
+    }
+}
+
 fn get_api_def_by_specification(
     query_specification: APISpecification,
     responder: Sender<Option<APIDefinition>>,
@@ -145,44 +313,65 @@ fn get_api_def_by_specification(
     }
 }
 
-fn get_origin_def_by_id(
+fn get_api_origin_defs(
     origin_id: String,
-    responder: Sender<Option<Origin>>,
+    mirror_origin_id: Option<String>,
+    responder: Sender<(Option<Origin>, Option<Origin>)>,
     origin_definitions: Arc<HashMap<String, Origin>>,
 ) {
-    trace!(
-        "Received call for fetching Origin definition by ID (Origin ID: {})",
-        origin_id
-    );
-    match responder.send(
-        origin_definitions
-            .as_ref()
-            .get(&*origin_id)
-            .map(|origin| origin.clone()),
-    ) {
-        Ok(_) => {
-            trace!(
-                "Configuration manager successfully responded to call for fetching Origin definition by ID (Origin ID: {})",
-                origin_id
-            )
-        }
-        Err(_) => {
-            trace!(
-                "Configuration manager failed to respond to call for fetching Origin definition by ID (Origin ID: {})",
-                origin_id
-            )
-        }
+    let origin = origin_definitions.get(&origin_id).cloned();
+    let mirror_origin = mirror_origin_id
+        .and_then(|mirror_origin_id| origin_definitions.get(&mirror_origin_id).cloned());
+    if responder.send((origin, mirror_origin)).is_err() {
+        trace!(
+            "Configuration manager failed to respond to call for fetching Origin definitions of API (Origin ID: {})",
+            origin_id
+        )
     }
 }
 
+/// Rereads all definitions and pushes them to the rate limiter and load balancer,
+/// which keep the state of limiters and Origins that still exist and drop the
+/// state of removed ones.
+async fn reload_definitions(
+    rate_limiter_tx: &tokio::sync::mpsc::Sender<RateLimiterAPI>,
+    load_balancer_tx: &tokio::sync::mpsc::Sender<LoadBalancerAPI>,
+    api_definitions: &HashMap<String, APIDefinition>,
+    origin_definitions: &HashMap<String, Origin>,
+) -> (HashMap<String, APIDefinition>, HashMap<String, Origin>) {
+    info!("Reloading definitions");
+    let (reloaded_api_definitions, reloaded_origin_definitions) =
+        initialize(rate_limiter_tx.clone(), load_balancer_tx.clone()).await;
+    let removed_origin_ids = removed_ids(origin_definitions, &reloaded_origin_definitions);
+    send_removed_definitions_to_rate_limiter(
+        rate_limiter_tx.clone(),
+        removed_ids(api_definitions, &reloaded_api_definitions),
+        removed_origin_ids.clone(),
+    )
+    .await;
+    send_removed_origins_to_load_balancer(load_balancer_tx.clone(), removed_origin_ids).await;
+    (reloaded_api_definitions, reloaded_origin_definitions)
+}
+
+fn removed_ids<T>(previous: &HashMap<String, T>, reloaded: &HashMap<String, T>) -> Vec<String> {
+    previous
+        .keys()
+        .filter(|id| !reloaded.contains_key(*id))
+        .cloned()
+        .collect()
+}
+
 pub(crate) async fn deploy_config_mgr(
     mut receiver: Receiver<ConfigMgrProxyAPI>,
     rate_limiter_tx: tokio::sync::mpsc::Sender<RateLimiterAPI>,
+    load_balancer_tx: tokio::sync::mpsc::Sender<LoadBalancerAPI>,
 ) {
     info!("Deploying configuration manager");
-    let (api_definitions, origin_definitions) = initialize(rate_limiter_tx).await;
-    let api_definitions = Arc::new(api_definitions);
-    let origin_definitions = Arc::new(origin_definitions);
+    let (api_definitions, origin_definitions) =
+        initialize(rate_limiter_tx.clone(), load_balancer_tx.clone()).await;
+    let mut api_definitions = Arc::new(api_definitions);
+    let mut origin_definitions = Arc::new(origin_definitions);
+    let mut consumers = Arc::new(load_consumers());
     debug!(
         "Configuration manager read {} APIDefinition objects",
         api_definitions.as_ref().len()
@@ -196,26 +385,117 @@ pub(crate) async fn deploy_config_mgr(
         if api_call.is_some() {
             trace!("Configuration manager received API call");
             let api_call = api_call.unwrap();
-            let api_definitions = api_definitions.clone();
-            let origin_definitions = origin_definitions.clone();
-            tokio::spawn(async move {
-                match api_call {
-                    ConfigMgrProxyAPI::GetAPIDefinitionBySpecification {
-                        specification,
-                        responder,
-                    } => {
-                        trace!("Configuration manager received call for getting API definition by specification");
-                        get_api_def_by_specification(specification, responder, api_definitions)
+            match api_call {
+                ConfigMgrProxyAPI::ReloadDefinitions { responder } => {
+                    let (reloaded_api_definitions, reloaded_origin_definitions) =
+                        reload_definitions(
+                            &rate_limiter_tx,
+                            &load_balancer_tx,
+                            &api_definitions,
+                            &origin_definitions,
+                        )
+                        .await;
+                    api_definitions = Arc::new(reloaded_api_definitions);
+                    origin_definitions = Arc::new(reloaded_origin_definitions);
+                    consumers = Arc::new(load_consumers());
+                    match responder.send((
+                        api_definitions.len(),
+                        origin_definitions.len(),
+                        consumers.len(),
+                    )) {
+                        Ok(_) => {
+                            trace!("Configuration manager responded successfully to call for reloading definitions")
+                        }
+                        Err(_) => {
+                            trace!("Configuration manager failed to respond to call for reloading definitions")
+                        }
                     }
-                    ConfigMgrProxyAPI::GetOriginDefinitionByID {
-                        origin_id,
-                        responder,
-                    } => {
-                        trace!("Configuration manager received call for getting Origin by ID");
-                        get_origin_def_by_id(origin_id, responder, origin_definitions)
+                }
+                ConfigMgrProxyAPI::ChangeConsumer {
+                    consumer_id,
+                    change,
+                    responder,
+                } => {
+                    let change_result = change_consumer(&consumers, &consumer_id, change).map(
+                        |(changed_consumers, consumer)| {
+                            consumers = Arc::new(changed_consumers);
+                            consumer
+                        },
+                    );
+                    if responder.send(change_result).is_err() {
+                        trace!(
+                            "Configuration manager failed to respond to call for changing Consumer"
+                        )
                     }
                 }
-            });
+                api_call => {
+                    let api_definitions = api_definitions.clone();
+                    let origin_definitions = origin_definitions.clone();
+                    let consumers = consumers.clone();
+                    tokio::spawn(async move {
+                        match api_call {
+                            ConfigMgrProxyAPI::GetAPIDefinitionBySpecification {
+                                specification,
+                                responder,
+                            } => {
+                                trace!("Configuration manager received call for getting API definition by specification");
+                                get_api_def_by_specification(
+                                    specification,
+                                    responder,
+                                    api_definitions,
+                                )
+                            }
+                            ConfigMgrProxyAPI::GetAPIOriginDefinitions {
+                                origin_id,
+                                mirror_origin_id,
+                                responder,
+                            } => {
+                                trace!(
+                                    "Configuration manager received call for getting Origins of API"
+                                );
+                                get_api_origin_defs(
+                                    origin_id,
+                                    mirror_origin_id,
+                                    responder,
+                                    origin_definitions,
+                                )
+                            }
+                            ConfigMgrProxyAPI::GetConsumerByKeyHash {
+                                key_hash,
+                                responder,
+                            } => {
+                                trace!("Configuration manager received call for getting Consumer by key hash");
+                                let consumer = consumers.find_by_key_hash(&key_hash).cloned();
+                                if responder.send(consumer).is_err() {
+                                    trace!("Configuration manager failed to respond to call for getting Consumer by key hash")
+                                }
+                            }
+                            ConfigMgrProxyAPI::GetConsumerByID {
+                                consumer_id,
+                                responder,
+                            } => {
+                                trace!("Configuration manager received call for getting Consumer by ID");
+                                if responder
+                                    .send(consumers.get(&consumer_id).cloned())
+                                    .is_err()
+                                {
+                                    trace!("Configuration manager failed to respond to call for getting Consumer by ID")
+                                }
+                            }
+                            ConfigMgrProxyAPI::GetConsumers { responder } => {
+                                trace!("Configuration manager received call for getting Consumers");
+                                if responder.send(consumers.list()).is_err() {
+                                    trace!("Configuration manager failed to respond to call for getting Consumers")
+                                }
+                            }
+                            // Reloads and consumer changes replace the definitions
+                            // and are handled above
+                            ConfigMgrProxyAPI::ReloadDefinitions { .. }
+                            | ConfigMgrProxyAPI::ChangeConsumer { .. } => {}
+                        }
+                    });
+                }
+            }
         }
     }
 }
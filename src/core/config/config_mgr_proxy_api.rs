@@ -1,15 +1,43 @@
 use tokio::sync::oneshot::Sender;
 
 use crate::configuration_reader::api_def_reader::{APIDefinition, APISpecification};
+use crate::configuration_reader::consumer_def_reader::Consumer;
 use crate::configuration_reader::origin_def_reader::Origin;
+use crate::core::config::consumer_registry::{ConsumerChange, ConsumerChangeError};
 
 pub enum ConfigMgrProxyAPI {
     GetAPIDefinitionBySpecification {
         specification: APISpecification,
         responder: Sender<Option<APIDefinition>>,
     },
-    GetOriginDefinitionByID {
+    /// Looks up the Origin of an API along with the shadow Origin its request is
+    /// mirrored to, if any, in one call.
+    GetAPIOriginDefinitions {
         origin_id: String,
-        responder: Sender<Option<Origin>>,
+        mirror_origin_id: Option<String>,
+        responder: Sender<(Option<Origin>, Option<Origin>)>,
+    },
+    /// Rereads all definitions from disk. Responds with the number of API, Origin
+    /// and Consumer definitions loaded.
+    ReloadDefinitions {
+        responder: Sender<(usize, usize, usize)>,
+    },
+    GetConsumerByKeyHash {
+        key_hash: String,
+        responder: Sender<Option<Consumer>>,
+    },
+    GetConsumerByID {
+        consumer_id: String,
+        responder: Sender<Option<Consumer>>,
+    },
+    GetConsumers {
+        responder: Sender<Vec<Consumer>>,
+    },
+    /// Applies the change and stores the consumer definition. Responds with the
+    /// consumer as it is afterwards, or `None` if it was removed.
+    ChangeConsumer {
+        consumer_id: String,
+        change: ConsumerChange,
+        responder: Sender<Result<Option<Consumer>, ConsumerChangeError>>,
     },
 }
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+
+use log::error;
+
+use crate::configuration_reader::consumer_def_reader::{is_valid_consumer_id, Consumer};
+
+/// A change to a single consumer, applied atomically by the configuration manager.
+pub enum ConsumerChange {
+    Create {
+        consumer_name: String,
+        key_hash: String,
+    },
+    Rename {
+        consumer_name: String,
+    },
+    AddKey {
+        key_hash: String,
+    },
+    RemoveKey {
+        key_hash: String,
+    },
+    Remove,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConsumerChangeError {
+    InvalidConsumerID,
+    ConsumerNotFound,
+    ConsumerExists,
+    KeyNotFound,
+    StorageFailure(String),
+}
+
+/// Consumers by ID, indexed by the hashes of their API keys.
+#[derive(Clone, Default)]
+pub(crate) struct ConsumerRegistry {
+    consumers: HashMap<String, Consumer>,
+    consumer_ids_by_key_hash: HashMap<String, String>,
+}
+
+impl ConsumerRegistry {
+    /// Consumers with unsafe IDs are skipped, and a key hash claimed by several
+    /// consumers only identifies the first of them.
+    pub(crate) fn from_consumers(consumers: Vec<Consumer>) -> Self {
+        let mut registry = ConsumerRegistry::default();
+        for consumer in consumers {
+            if !is_valid_consumer_id(&consumer.consumer_id) {
+                error!(
+                    "Skipping Consumer with invalid ID {:?}, IDs may only contain letters, digits, '-', '_' and '.'",
+                    consumer.consumer_id
+                );
+            } else if registry.consumers.contains_key(&consumer.consumer_id) {
+                error!(
+                    "Skipping duplicate definition of Consumer (Consumer ID: {})",
+                    consumer.consumer_id
+                );
+            } else {
+                registry.insert(consumer);
+            }
+        }
+        registry
+    }
+
+    fn insert(&mut self, consumer: Consumer) {
+        for key_hash in &consumer.key_hashes {
+            if let Some(owner) = self.consumer_ids_by_key_hash.get(key_hash) {
+                error!(
+                    "API key of Consumer (Consumer ID: {}) is already used by Consumer (Consumer ID: {}) and is ignored",
+                    consumer.consumer_id, owner
+                );
+            } else {
+                self.consumer_ids_by_key_hash
+                    .insert(key_hash.clone(), consumer.consumer_id.clone());
+            }
+        }
+        self.consumers
+            .insert(consumer.consumer_id.clone(), consumer);
+    }
+
+    fn remove(&mut self, consumer_id: &str) -> Option<Consumer> {
+        let consumer = self.consumers.remove(consumer_id)?;
+        self.consumer_ids_by_key_hash
+            .retain(|_, owner| owner != consumer_id);
+        Some(consumer)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.consumers.len()
+    }
+
+    pub(crate) fn get(&self, consumer_id: &str) -> Option<&Consumer> {
+        self.consumers.get(consumer_id)
+    }
+
+    pub(crate) fn find_by_key_hash(&self, key_hash: &str) -> Option<&Consumer> {
+        self.consumers
+            .get(self.consumer_ids_by_key_hash.get(key_hash)?)
+    }
+
+    /// All consumers, ordered by ID.
+    pub(crate) fn list(&self) -> Vec<Consumer> {
+        let mut consumers: Vec<Consumer> = self.consumers.values().cloned().collect();
+        consumers.sort_by(|a, b| a.consumer_id.cmp(&b.consumer_id));
+        consumers
+    }
+
+    /// Applies the change and returns the consumer as it is afterwards, or `None`
+    /// if it was removed. The registry is left untouched if the change fails.
+    pub(crate) fn apply(
+        &mut self,
+        consumer_id: &str,
+        change: ConsumerChange,
+    ) -> Result<Option<Consumer>, ConsumerChangeError> {
+        if let ConsumerChange::Create {
+            consumer_name,
+            key_hash,
+        } = change
+        {
+            if !is_valid_consumer_id(consumer_id) {
+                return Err(ConsumerChangeError::InvalidConsumerID);
+            }
+            if self.consumers.contains_key(consumer_id) {
+                return Err(ConsumerChangeError::ConsumerExists);
+            }
+            let consumer = Consumer {
+                consumer_id: String::from(consumer_id),
+                consumer_name,
+                key_hashes: vec![key_hash],
+            };
+            self.insert(consumer.clone());
+            return Ok(Some(consumer));
+        }
+        let mut consumer = self
+            .get(consumer_id)
+            .cloned()
+            .ok_or(ConsumerChangeError::ConsumerNotFound)?;
+        match change {
+            ConsumerChange::Create { .. } => {}
+            ConsumerChange::Rename { consumer_name } => consumer.consumer_name = consumer_name,
+            ConsumerChange::AddKey { key_hash } => consumer.key_hashes.push(key_hash),
+            ConsumerChange::RemoveKey { key_hash } => {
+                if !consumer.key_hashes.contains(&key_hash) {
+                    return Err(ConsumerChangeError::KeyNotFound);
+                }
+                consumer.key_hashes.retain(|existing| *existing != key_hash);
+            }
+            ConsumerChange::Remove => {
+                self.remove(consumer_id);
+                return Ok(None);
+            }
+        }
+        self.remove(consumer_id);
+        self.insert(consumer.clone());
+        Ok(Some(consumer))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configuration_reader::consumer_def_reader::Consumer;
+
+    use super::{ConsumerChange, ConsumerChangeError, ConsumerRegistry};
+
+    fn consumer(consumer_id: &str, key_hashes: &[&str]) -> Consumer {
+        Consumer {
+            consumer_id: String::from(consumer_id),
+            consumer_name: String::from(consumer_id),
+            key_hashes: key_hashes.iter().map(|hash| String::from(*hash)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_consumers_are_found_by_key_hash() {
+        let registry = ConsumerRegistry::from_consumers(vec![
+            consumer("billing", &["aa", "bb"]),
+            consumer("reporting", &["cc", "aa"]),
+            consumer("../escape", &["dd"]),
+        ]);
+        assert_eq!(2, registry.len());
+        assert_eq!(
+            "billing",
+            registry.find_by_key_hash("bb").unwrap().consumer_id
+        );
+        assert_eq!(
+            "reporting",
+            registry.find_by_key_hash("cc").unwrap().consumer_id
+        );
+        assert!(registry.find_by_key_hash("dd").is_none());
+        assert!(registry.find_by_key_hash("ee").is_none());
+    }
+
+    #[test]
+    fn test_changes_keep_the_key_index_current() {
+        let mut registry = ConsumerRegistry::from_consumers(vec![]);
+        let create = |key_hash: &str| ConsumerChange::Create {
+            consumer_name: String::from("Billing"),
+            key_hash: String::from(key_hash),
+        };
+        assert!(registry.apply("billing", create("aa")).is_ok());
+        assert_eq!(
+            Err(ConsumerChangeError::ConsumerExists),
+            registry.apply("billing", create("bb")).map(|_| ())
+        );
+        assert_eq!(
+            Err(ConsumerChangeError::InvalidConsumerID),
+            registry.apply("a/b", create("bb")).map(|_| ())
+        );
+        registry
+            .apply(
+                "billing",
+                ConsumerChange::AddKey {
+                    key_hash: String::from("bb"),
+                },
+            )
+            .unwrap();
+        registry
+            .apply(
+                "billing",
+                ConsumerChange::RemoveKey {
+                    key_hash: String::from("aa"),
+                },
+            )
+            .unwrap();
+        assert!(registry.find_by_key_hash("aa").is_none());
+        assert!(registry.find_by_key_hash("bb").is_some());
+        assert_eq!(
+            Err(ConsumerChangeError::KeyNotFound),
+            registry
+                .apply(
+                    "billing",
+                    ConsumerChange::RemoveKey {
+                        key_hash: String::from("aa"),
+                    },
+                )
+                .map(|_| ())
+        );
+        assert!(registry.find_by_key_hash("bb").is_some());
+        assert!(registry
+            .apply("billing", ConsumerChange::Remove)
+            .unwrap()
+            .is_none());
+        assert!(registry.find_by_key_hash("bb").is_none());
+        assert_eq!(
+            Err(ConsumerChangeError::ConsumerNotFound),
+            registry
+                .apply("billing", ConsumerChange::Remove)
+                .map(|_| ())
+        );
+    }
+}
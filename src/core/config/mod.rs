@@ -1,3 +1,4 @@
 pub(crate) mod config_mgr;
 pub mod config_mgr_proxy_api;
+pub(crate) mod consumer_registry;
 pub(crate) mod read_config;
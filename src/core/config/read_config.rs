@@ -1,11 +1,16 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use log::error;
+use log::{error, info};
 
 use crate::configuration_reader::api_def_reader::APIDefinition;
+use crate::configuration_reader::cluster_config_reader::ClusterConfig;
+use crate::configuration_reader::consumer_def_reader::Consumer;
+use crate::configuration_reader::ip_access_config_reader::IpAccessConfig;
 use crate::configuration_reader::origin_def_reader::Origin;
+use crate::configuration_reader::tls_config_reader::TlsListenerConfig;
 use crate::file_utils::file_reader::FileReader;
+use crate::file_utils::file_writer::FileWriter;
 use crate::utils::path_utils::get_directory_of_executable;
 
 fn read_config_file_paths(current_directory: PathBuf) -> Vec<PathBuf> {
@@ -108,3 +113,163 @@ pub fn read_all_origin_definitions() -> Vec<Origin> {
     }
     origin_definitions
 }
+
+const CONSUMER_DEFINITIONS_DIRECTORY: &str = "resources/definitions/consumer_def";
+
+pub fn read_all_consumer_definitions() -> Vec<Consumer> {
+    let mut consumer_definitions = vec![];
+    let all_file_paths = read_config_file_paths(
+        get_directory_of_executable().join(Path::new(CONSUMER_DEFINITIONS_DIRECTORY)),
+    );
+    for path_buffer in all_file_paths {
+        match FileReader::from_path(path_buffer.to_str().unwrap()).read() {
+            Ok(json_payload) => match Consumer::from_json_string(&json_payload) {
+                Ok(consumer_definition) => {
+                    consumer_definitions.push(consumer_definition);
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to parse JSON content in file {} as Consumer - {}",
+                        path_buffer.to_str().unwrap(),
+                        e
+                    );
+                }
+            },
+            Err(e) => {
+                error!(
+                    "Failed to read file at {} - {}",
+                    path_buffer.to_str().unwrap(),
+                    e.message
+                );
+            }
+        }
+    }
+    consumer_definitions
+}
+
+/// Consumers managed through the management API are stored one per file, named
+/// after the consumer ID.
+fn consumer_definition_path(consumer_id: &str) -> PathBuf {
+    get_directory_of_executable()
+        .join(Path::new(CONSUMER_DEFINITIONS_DIRECTORY))
+        .join(format!("{}.json", consumer_id))
+}
+
+pub fn write_consumer_definition(consumer: &Consumer) -> Result<(), String> {
+    let path_buffer = consumer_definition_path(&consumer.consumer_id);
+    if let Some(directory) = path_buffer.parent() {
+        fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+    }
+    let json_payload = consumer.to_json_pretty().map_err(|e| e.to_string())?;
+    FileWriter::from_path(path_buffer.to_str().unwrap())
+        .write(&json_payload)
+        .map_err(|e| e.message)
+}
+
+pub fn remove_consumer_definition(consumer_id: &str) -> Result<(), String> {
+    match fs::remove_file(consumer_definition_path(consumer_id)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Reads the cluster rate limiting backend configuration. Without one, every
+/// instance enforces its limits on its own.
+pub fn read_cluster_config() -> Option<ClusterConfig> {
+    let path_buffer =
+        get_directory_of_executable().join(Path::new("resources/config/cluster.json"));
+    if !path_buffer.is_file() {
+        info!("No cluster configuration found, rate limits are enforced per instance");
+        return None;
+    }
+    match FileReader::from_path(path_buffer.to_str().unwrap()).read() {
+        Ok(json_payload) => match ClusterConfig::from_json_string(&json_payload) {
+            Ok(cluster_config) => {
+                info!(
+                    "Sharing rate limits with the cluster backend at {}",
+                    cluster_config.address
+                );
+                Some(cluster_config)
+            }
+            Err(e) => {
+                error!(
+                    "Failed to parse JSON content in file {} as ClusterConfig - {}",
+                    path_buffer.to_str().unwrap(),
+                    e
+                );
+                None
+            }
+        },
+        Err(e) => {
+            error!(
+                "Failed to read file at {} - {}",
+                path_buffer.to_str().unwrap(),
+                e.message
+            );
+            None
+        }
+    }
+}
+
+/// Reads the TLS listener configuration. Without one, clients are not asked for
+/// certificates; a configuration that cannot be read yields `None`, so that the
+/// listener does not start without the client authentication it asks for.
+pub fn read_tls_listener_config() -> Option<TlsListenerConfig> {
+    let path_buffer = get_directory_of_executable().join(Path::new("resources/config/tls.json"));
+    if !path_buffer.is_file() {
+        return Some(TlsListenerConfig::default());
+    }
+    match FileReader::from_path(path_buffer.to_str().unwrap()).read() {
+        Ok(json_payload) => match TlsListenerConfig::from_json_string(&json_payload) {
+            Ok(tls_listener_config) => Some(tls_listener_config),
+            Err(e) => {
+                error!(
+                    "Failed to parse JSON content in file {} as TlsListenerConfig - {}",
+                    path_buffer.to_str().unwrap(),
+                    e
+                );
+                None
+            }
+        },
+        Err(e) => {
+            error!(
+                "Failed to read file at {} - {}",
+                path_buffer.to_str().unwrap(),
+                e.message
+            );
+            None
+        }
+    }
+}
+
+/// Reads the gateway wide IP access configuration. Without one, every client
+/// address is admitted; a configuration that cannot be read yields `None`, so
+/// that the proxy does not start without the restrictions it asks for.
+pub fn read_ip_access_config() -> Option<IpAccessConfig> {
+    let path_buffer =
+        get_directory_of_executable().join(Path::new("resources/config/ip_access.json"));
+    if !path_buffer.is_file() {
+        return Some(IpAccessConfig::default());
+    }
+    match FileReader::from_path(path_buffer.to_str().unwrap()).read() {
+        Ok(json_payload) => match IpAccessConfig::from_json_string(&json_payload) {
+            Ok(ip_access_config) => Some(ip_access_config),
+            Err(e) => {
+                error!(
+                    "Failed to parse JSON content in file {} as IpAccessConfig - {}",
+                    path_buffer.to_str().unwrap(),
+                    e
+                );
+                None
+            }
+        },
+        Err(e) => {
+            error!(
+                "Failed to read file at {} - {}",
+                path_buffer.to_str().unwrap(),
+                e.message
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::core::auth::client_cert::ClientCertificate;
+
+/// Details of the client connection a request arrived on.
+#[derive(Clone)]
+pub(crate) struct ConnectionInfo {
+    pub(crate) remote_addr: SocketAddr,
+    /// The verified certificate the client presented on a TLS connection.
+    pub(crate) client_certificate: Option<Arc<ClientCertificate>>,
+}
@@ -0,0 +1,224 @@
+use std::convert::Infallible;
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::trace;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+
+use crate::configuration_reader::consumer_def_reader::Consumer;
+use crate::core::auth::api_key::{generate_api_key, hash_api_key};
+use crate::core::config::config_mgr_proxy_api::ConfigMgrProxyAPI;
+use crate::core::config::consumer_registry::{ConsumerChange, ConsumerChangeError};
+use crate::core::router::create_json_response;
+use crate::core::standard_response::{
+    create_400_bad_request_response, create_404_not_found_response, create_409_conflict_response,
+    create_500_int_error_response,
+};
+use crate::ConfigMgrProxyAPI::{ChangeConsumer, GetConsumerByID, GetConsumers};
+
+#[derive(Deserialize)]
+struct NewConsumer {
+    consumer_id: String,
+    consumer_name: String,
+}
+
+#[derive(Deserialize)]
+struct ConsumerUpdate {
+    consumer_name: String,
+}
+
+/// API keys are only ever returned in the response to the call issuing them.
+#[derive(Serialize)]
+struct IssuedKey {
+    consumer: Consumer,
+    api_key: String,
+}
+
+async fn read_json_body<T: DeserializeOwned>(request: Request<Body>) -> Option<T> {
+    let body = hyper::body::to_bytes(request.into_body()).await.ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+fn create_201_created_response<T: Serialize>(payload: &T) -> Result<Response<Body>, Infallible> {
+    create_json_response(payload).map(|mut response| {
+        if response.status() == StatusCode::OK {
+            *response.status_mut() = StatusCode::CREATED;
+        }
+        response
+    })
+}
+
+fn create_change_error_response(error: ConsumerChangeError) -> Result<Response<Body>, Infallible> {
+    match error {
+        ConsumerChangeError::InvalidConsumerID => create_400_bad_request_response(),
+        ConsumerChangeError::ConsumerNotFound | ConsumerChangeError::KeyNotFound => {
+            create_404_not_found_response()
+        }
+        ConsumerChangeError::ConsumerExists => create_409_conflict_response(),
+        ConsumerChangeError::StorageFailure(_) => create_500_int_error_response(),
+    }
+}
+
+async fn change_consumer(
+    config_mgr_tx: &Sender<ConfigMgrProxyAPI>,
+    consumer_id: &str,
+    change: ConsumerChange,
+) -> Option<Result<Option<Consumer>, ConsumerChangeError>> {
+    let (responder, receiver) = tokio::sync::oneshot::channel();
+    let change_consumer_call = ChangeConsumer {
+        consumer_id: String::from(consumer_id),
+        change,
+        responder,
+    };
+    if config_mgr_tx.send(change_consumer_call).await.is_err() {
+        trace!(
+            "Failed to send change of Consumer (Consumer ID: {}) to configuration manager",
+            consumer_id
+        );
+        return None;
+    }
+    receiver.await.ok()
+}
+
+/// Applies a change that issues `api_key` and responds with the key.
+async fn issue_key(
+    config_mgr_tx: &Sender<ConfigMgrProxyAPI>,
+    consumer_id: &str,
+    change: ConsumerChange,
+    api_key: String,
+) -> Result<Response<Body>, Infallible> {
+    match change_consumer(config_mgr_tx, consumer_id, change).await {
+        None | Some(Ok(None)) => create_500_int_error_response(),
+        Some(Err(error)) => create_change_error_response(error),
+        Some(Ok(Some(consumer))) => create_201_created_response(&IssuedKey { consumer, api_key }),
+    }
+}
+
+async fn create_consumer(
+    config_mgr_tx: &Sender<ConfigMgrProxyAPI>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    match read_json_body::<NewConsumer>(request).await {
+        None => create_400_bad_request_response(),
+        Some(new_consumer) => {
+            let api_key = generate_api_key();
+            let change = ConsumerChange::Create {
+                consumer_name: new_consumer.consumer_name,
+                key_hash: hash_api_key(&api_key),
+            };
+            issue_key(config_mgr_tx, &new_consumer.consumer_id, change, api_key).await
+        }
+    }
+}
+
+async fn add_consumer_key(
+    config_mgr_tx: &Sender<ConfigMgrProxyAPI>,
+    consumer_id: &str,
+) -> Result<Response<Body>, Infallible> {
+    let api_key = generate_api_key();
+    let change = ConsumerChange::AddKey {
+        key_hash: hash_api_key(&api_key),
+    };
+    issue_key(config_mgr_tx, consumer_id, change, api_key).await
+}
+
+async fn update_consumer(
+    config_mgr_tx: &Sender<ConfigMgrProxyAPI>,
+    consumer_id: &str,
+    change: ConsumerChange,
+) -> Result<Response<Body>, Infallible> {
+    match change_consumer(config_mgr_tx, consumer_id, change).await {
+        None => create_500_int_error_response(),
+        Some(Err(error)) => create_change_error_response(error),
+        Some(Ok(None)) => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NO_CONTENT;
+            Ok(response)
+        }
+        Some(Ok(Some(consumer))) => create_json_response(&consumer),
+    }
+}
+
+async fn get_consumers(
+    config_mgr_tx: &Sender<ConfigMgrProxyAPI>,
+) -> Result<Response<Body>, Infallible> {
+    let (responder, receiver) = tokio::sync::oneshot::channel();
+    if config_mgr_tx
+        .send(GetConsumers { responder })
+        .await
+        .is_err()
+    {
+        return create_500_int_error_response();
+    }
+    match receiver.await {
+        Err(_) => create_500_int_error_response(),
+        Ok(consumers) => create_json_response(&consumers),
+    }
+}
+
+async fn get_consumer(
+    config_mgr_tx: &Sender<ConfigMgrProxyAPI>,
+    consumer_id: &str,
+) -> Result<Response<Body>, Infallible> {
+    let (responder, receiver) = tokio::sync::oneshot::channel();
+    let get_consumer_call = GetConsumerByID {
+        consumer_id: String::from(consumer_id),
+        responder,
+    };
+    if config_mgr_tx.send(get_consumer_call).await.is_err() {
+        return create_500_int_error_response();
+    }
+    match receiver.await {
+        Err(_) => create_500_int_error_response(),
+        Ok(None) => create_404_not_found_response(),
+        Ok(Some(consumer)) => create_json_response(&consumer),
+    }
+}
+
+/// Serves the consumer registry below `/consumers`:
+///
+/// - `GET /consumers` and `GET /consumers/{id}` list consumers and their key hashes
+/// - `POST /consumers` creates a consumer and issues its first API key
+/// - `PUT /consumers/{id}` renames a consumer
+/// - `DELETE /consumers/{id}` removes a consumer with all its keys
+/// - `POST /consumers/{id}/keys` issues another API key
+/// - `DELETE /consumers/{id}/keys/{key_hash}` revokes an API key
+pub(crate) async fn route_consumer_mgt(
+    request: Request<Body>,
+    config_mgr_tx: Sender<ConfigMgrProxyAPI>,
+) -> Result<Response<Body>, Infallible> {
+    let method = request.method().clone();
+    let path = request.uri().path().trim_end_matches('/').to_string();
+    let segments: Vec<&str> = path.split('/').skip(2).collect();
+    match (&method, segments.as_slice()) {
+        (&Method::GET, []) => get_consumers(&config_mgr_tx).await,
+        (&Method::POST, []) => create_consumer(&config_mgr_tx, request).await,
+        (&Method::GET, [consumer_id]) => get_consumer(&config_mgr_tx, consumer_id).await,
+        (&Method::PUT, [consumer_id]) => {
+            let consumer_id = String::from(*consumer_id);
+            match read_json_body::<ConsumerUpdate>(request).await {
+                None => create_400_bad_request_response(),
+                Some(update) => {
+                    let change = ConsumerChange::Rename {
+                        consumer_name: update.consumer_name,
+                    };
+                    update_consumer(&config_mgr_tx, &consumer_id, change).await
+                }
+            }
+        }
+        (&Method::DELETE, [consumer_id]) => {
+            update_consumer(&config_mgr_tx, consumer_id, ConsumerChange::Remove).await
+        }
+        (&Method::POST, [consumer_id, "keys"]) => {
+            add_consumer_key(&config_mgr_tx, consumer_id).await
+        }
+        (&Method::DELETE, [consumer_id, "keys", key_hash]) => {
+            let change = ConsumerChange::RemoveKey {
+                key_hash: String::from(*key_hash),
+            };
+            update_consumer(&config_mgr_tx, consumer_id, change).await
+        }
+        (_, _) => create_404_not_found_response(),
+    }
+}
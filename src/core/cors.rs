@@ -0,0 +1,445 @@
+use std::convert::Infallible;
+
+use glob::Pattern;
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+    VARY,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{debug, error};
+
+use crate::configuration_reader::api_def_reader::{APIDefinition, CorsPolicy};
+use crate::core::standard_response::create_403_forbidden_response;
+
+const ANY: &str = "*";
+
+/// The method a preflight request asks permission for, if the request is a
+/// CORS preflight request.
+pub(crate) fn preflight_request_method(request: &Request<Body>) -> Option<&str> {
+    if request.method() != Method::OPTIONS || !request.headers().contains_key(ORIGIN) {
+        return None;
+    }
+    request
+        .headers()
+        .get(ACCESS_CONTROL_REQUEST_METHOD)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// How the origin of a request is allowed: by an entry of `allowed_origins`
+/// other than `*`, or only by `*`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AllowedOrigin<'a> {
+    Listed(&'a str),
+    Any,
+}
+
+fn is_listed_origin(policy: &CorsPolicy, origin: &str) -> bool {
+    policy
+        .allowed_origins
+        .iter()
+        .filter(|allowed| *allowed != ANY)
+        .any(|allowed| match Pattern::new(allowed) {
+            Ok(pattern) => pattern.matches(origin),
+            Err(e) => {
+                error!("Invalid CORS origin pattern {} - {}", allowed, e);
+                false
+            }
+        })
+}
+
+/// The `null` origin of sandboxed documents and local files is only allowed when
+/// listed as such, never by a wildcard.
+fn match_origin<'a>(policy: &CorsPolicy, origin: &'a str) -> Option<AllowedOrigin<'a>> {
+    if origin == "null" {
+        return policy
+            .allowed_origins
+            .iter()
+            .any(|allowed| allowed == "null")
+            .then_some(AllowedOrigin::Listed(origin));
+    }
+    if is_listed_origin(policy, origin) {
+        Some(AllowedOrigin::Listed(origin))
+    } else if policy.allowed_origins.iter().any(|allowed| allowed == ANY) {
+        Some(AllowedOrigin::Any)
+    } else {
+        None
+    }
+}
+
+/// Responses are the same for every origin only when `*` is all the policy
+/// allows.
+fn allows_only_any_origin(policy: &CorsPolicy) -> bool {
+    !policy.allowed_origins.is_empty()
+        && policy.allowed_origins.iter().all(|allowed| allowed == ANY)
+}
+
+/// Reads the origin of the request if the policy allows it.
+fn read_allowed_origin<'a>(
+    policy: &CorsPolicy,
+    request: &'a Request<Body>,
+) -> Option<AllowedOrigin<'a>> {
+    request
+        .headers()
+        .get(ORIGIN)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|origin| match_origin(policy, origin))
+}
+
+fn insert_header(headers: &mut HeaderMap, name: hyper::header::HeaderName, value: &str) {
+    match HeaderValue::from_str(value) {
+        Ok(value) => {
+            headers.insert(name, value);
+        }
+        Err(_) => debug!("Cannot send {} in CORS header {}", value, name),
+    }
+}
+
+/// The headers shared by preflight and actual responses to an allowed origin.
+/// Credentials are never allowed to origins only admitted by `*`.
+fn allow_origin_headers(policy: &CorsPolicy, origin: AllowedOrigin, headers: &mut HeaderMap) {
+    match origin {
+        AllowedOrigin::Any => {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static(ANY));
+        }
+        AllowedOrigin::Listed(origin) => {
+            insert_header(headers, ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            if policy.allow_credentials {
+                headers.insert(
+                    ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                    HeaderValue::from_static("true"),
+                );
+            }
+        }
+    }
+}
+
+/// Answers a preflight request without involving the Origin. Requests from
+/// origins, or asking for methods or headers, the policy does not allow are
+/// refused with 403.
+pub(crate) fn create_preflight_response(
+    api_definition: &APIDefinition,
+    policy: &CorsPolicy,
+    request: &Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let origin = match read_allowed_origin(policy, request) {
+        None => {
+            debug!(
+                "Refused preflight request from a disallowed origin to APIDefinition (APIDefinition ID: {})",
+                api_definition.api_id
+            );
+            return create_403_forbidden_response();
+        }
+        Some(origin) => origin,
+    };
+    let allowed_methods = if policy.allowed_methods.is_empty() {
+        &api_definition.specification.methods
+    } else {
+        &policy.allowed_methods
+    };
+    let requested_method = preflight_request_method(request).unwrap_or_default();
+    let requested_headers: Vec<&str> = request
+        .headers()
+        .get_all(ACCESS_CONTROL_REQUEST_HEADERS)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|header| !header.is_empty())
+        .collect();
+    let allows_any_header = policy.allowed_headers.iter().any(|allowed| allowed == ANY);
+    let headers_allowed = allows_any_header
+        || requested_headers.iter().all(|requested| {
+            policy
+                .allowed_headers
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(requested))
+        });
+    if !allowed_methods
+        .iter()
+        .any(|allowed| allowed == requested_method)
+        || !headers_allowed
+    {
+        debug!(
+            "Refused preflight request for {} with headers {:?} to APIDefinition (APIDefinition ID: {})",
+            requested_method, requested_headers, api_definition.api_id
+        );
+        return create_403_forbidden_response();
+    }
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    let headers = response.headers_mut();
+    allow_origin_headers(policy, origin, headers);
+    insert_header(
+        headers,
+        ACCESS_CONTROL_ALLOW_METHODS,
+        &allowed_methods.join(", "),
+    );
+    if !requested_headers.is_empty() {
+        insert_header(
+            headers,
+            ACCESS_CONTROL_ALLOW_HEADERS,
+            &requested_headers.join(", "),
+        );
+    }
+    if let Some(max_age) = policy.max_age {
+        headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+    }
+    headers.insert(
+        VARY,
+        HeaderValue::from_static(
+            "Origin, Access-Control-Request-Method, Access-Control-Request-Headers",
+        ),
+    );
+    Ok(response)
+}
+
+/// The CORS headers for the response to an actual request, computed before the
+/// request is handed on. Responses depend on the origin of the request unless
+/// `*` is all the policy allows, so caches are told to vary on it either way.
+pub(crate) fn cors_response_headers(policy: &CorsPolicy, request: &Request<Body>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(origin) = read_allowed_origin(policy, request) {
+        allow_origin_headers(policy, origin, &mut headers);
+        if !policy.exposed_headers.is_empty() {
+            insert_header(
+                &mut headers,
+                ACCESS_CONTROL_EXPOSE_HEADERS,
+                &policy.exposed_headers.join(", "),
+            );
+        }
+    }
+    if !allows_only_any_origin(policy) {
+        headers.insert(VARY, HeaderValue::from_static("Origin"));
+    }
+    headers
+}
+
+/// Replaces the CORS headers of the response with the ones of the gateway.
+pub(crate) fn append_cors_headers(
+    cors_headers: Option<HeaderMap>,
+    response: Result<Response<Body>, Infallible>,
+) -> Result<Response<Body>, Infallible> {
+    match cors_headers {
+        None => response,
+        Some(cors_headers) => response.map(|mut response| {
+            let headers = response.headers_mut();
+            for header in [
+                ACCESS_CONTROL_ALLOW_ORIGIN,
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                ACCESS_CONTROL_EXPOSE_HEADERS,
+            ] {
+                headers.remove(header);
+            }
+            for (name, value) in cors_headers.iter() {
+                headers.append(name, value.clone());
+            }
+            response
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::{Body, Request, Response, StatusCode};
+
+    use crate::configuration_reader::api_def_reader::{APIDefinition, CorsPolicy};
+
+    use super::{
+        append_cors_headers, cors_response_headers, create_preflight_response,
+        preflight_request_method,
+    };
+
+    fn api_definition(cors_policy: &str) -> APIDefinition {
+        APIDefinition::from_json_str_slice(&format!(
+            r#"{{
+                "api_id": "billing",
+                "api_name": "Billing API",
+                "api_version": "1.0.0",
+                "api_desc": "Invoices",
+                "specification": {{"methods": ["GET", "PUT"], "paths": ["/invoices"], "hostnames": ["localhost"]}},
+                "backend_response_timeout": 1000,
+                "origin_id": "billing",
+                "cors": {}
+            }}"#,
+            cors_policy
+        ))
+        .unwrap()
+    }
+
+    fn cors_policy(api_definition: &APIDefinition) -> &CorsPolicy {
+        api_definition.cors.as_ref().unwrap()
+    }
+
+    fn preflight_request(origin: &str, method: &str, headers: &str) -> Request<Body> {
+        Request::builder()
+            .method("OPTIONS")
+            .uri("/invoices")
+            .header("origin", origin)
+            .header("access-control-request-method", method)
+            .header("access-control-request-headers", headers)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn header(response: &Response<Body>, name: &str) -> Option<String> {
+        response
+            .headers()
+            .get(name)
+            .map(|value| String::from(value.to_str().unwrap()))
+    }
+
+    #[test]
+    fn test_preflight_requests_are_answered() {
+        let api_definition = api_definition(
+            r#"{
+                "allowed_origins": ["https://*.example.com"],
+                "allowed_headers": ["Content-Type", "X-Request-ID"],
+                "allow_credentials": true,
+                "max_age": 600
+            }"#,
+        );
+        let request = preflight_request(
+            "https://app.example.com",
+            "PUT",
+            "content-type,x-request-id",
+        );
+        assert_eq!(Some("PUT"), preflight_request_method(&request));
+        let response =
+            create_preflight_response(&api_definition, cors_policy(&api_definition), &request)
+                .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+        assert_eq!(
+            Some(String::from("https://app.example.com")),
+            header(&response, "access-control-allow-origin")
+        );
+        assert_eq!(
+            Some(String::from("true")),
+            header(&response, "access-control-allow-credentials")
+        );
+        assert_eq!(
+            Some(String::from("GET, PUT")),
+            header(&response, "access-control-allow-methods")
+        );
+        assert_eq!(
+            Some(String::from("content-type, x-request-id")),
+            header(&response, "access-control-allow-headers")
+        );
+        assert_eq!(
+            Some(String::from("600")),
+            header(&response, "access-control-max-age")
+        );
+
+        for request in [
+            preflight_request("https://app.example.org", "PUT", "content-type"),
+            preflight_request("null", "PUT", "content-type"),
+            preflight_request("https://app.example.com", "DELETE", "content-type"),
+            preflight_request("https://app.example.com", "PUT", "authorization"),
+        ] {
+            let response =
+                create_preflight_response(&api_definition, cors_policy(&api_definition), &request)
+                    .unwrap();
+            assert_eq!(StatusCode::FORBIDDEN, response.status());
+            assert_eq!(None, header(&response, "access-control-allow-origin"));
+        }
+
+        let request = Request::builder()
+            .method("OPTIONS")
+            .header("origin", "https://app.example.com")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(None, preflight_request_method(&request));
+    }
+
+    #[test]
+    fn test_credentials_are_never_allowed_to_any_origin() {
+        let api_definition = api_definition(
+            r#"{"allowed_origins": ["https://app.example.com", "*"], "allow_credentials": true}"#,
+        );
+        let request = |origin: &str| {
+            Request::builder()
+                .uri("/invoices")
+                .header("origin", origin)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let headers = cors_response_headers(
+            cors_policy(&api_definition),
+            &request("https://evil.example"),
+        );
+        assert_eq!("*", headers.get("access-control-allow-origin").unwrap());
+        assert!(headers.get("access-control-allow-credentials").is_none());
+        assert_eq!("Origin", headers.get("vary").unwrap());
+
+        let headers = cors_response_headers(
+            cors_policy(&api_definition),
+            &request("https://app.example.com"),
+        );
+        assert_eq!(
+            "https://app.example.com",
+            headers.get("access-control-allow-origin").unwrap()
+        );
+        assert_eq!(
+            "true",
+            headers.get("access-control-allow-credentials").unwrap()
+        );
+
+        let response = create_preflight_response(
+            &api_definition,
+            cors_policy(&api_definition),
+            &preflight_request("https://evil.example", "GET", ""),
+        )
+        .unwrap();
+        assert_eq!(
+            Some(String::from("*")),
+            header(&response, "access-control-allow-origin")
+        );
+        assert_eq!(None, header(&response, "access-control-allow-credentials"));
+    }
+
+    #[test]
+    fn test_actual_responses_are_decorated() {
+        let public_api = api_definition(
+            r#"{"allowed_origins": ["*"], "exposed_headers": ["X-RateLimit-Remaining"]}"#,
+        );
+        let request = Request::builder()
+            .uri("/invoices")
+            .header("origin", "https://app.example.org")
+            .body(Body::empty())
+            .unwrap();
+        let origin_response = Response::builder()
+            .header("access-control-allow-origin", "https://legacy.example.org")
+            .header("vary", "Accept-Encoding")
+            .body(Body::empty())
+            .unwrap();
+        let response = append_cors_headers(
+            Some(cors_response_headers(cors_policy(&public_api), &request)),
+            Ok(origin_response),
+        )
+        .unwrap();
+        let allowed_origins: Vec<_> = response
+            .headers()
+            .get_all("access-control-allow-origin")
+            .iter()
+            .collect();
+        assert_eq!(vec!["*"], allowed_origins);
+        assert_eq!(
+            Some(String::from("X-RateLimit-Remaining")),
+            header(&response, "access-control-expose-headers")
+        );
+        assert_eq!(1, response.headers().get_all("vary").iter().count());
+
+        let private_api = api_definition(
+            r#"{"allowed_origins": ["https://app.example.com"], "allow_credentials": true}"#,
+        );
+        let response = append_cors_headers(
+            Some(cors_response_headers(cors_policy(&private_api), &request)),
+            Ok(Response::new(Body::empty())),
+        )
+        .unwrap();
+        assert_eq!(None, header(&response, "access-control-allow-origin"));
+        assert_eq!(Some(String::from("Origin")), header(&response, "vary"));
+    }
+}
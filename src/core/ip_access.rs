@@ -0,0 +1,320 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use hyper::header::HeaderName;
+use hyper::{Body, Request};
+use log::error;
+
+use crate::configuration_reader::api_def_reader::APIDefinition;
+use crate::configuration_reader::ip_access_config_reader::IpAccessConfig;
+use crate::core::connection_info::ConnectionInfo;
+
+const DEFAULT_CLIENT_IP_HEADER: &str = "x-forwarded-for";
+
+/// A CIDR range of IPv4 or IPv6 addresses, or a single address without a prefix
+/// length. IPv4 ranges also contain the IPv4-mapped IPv6 form of their addresses.
+#[derive(Debug, PartialEq)]
+pub(crate) struct IpRange {
+    network: IpAddr,
+    prefix_len: u32,
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(range: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match range.split_once('/') {
+            None => (range, None),
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+        };
+        let network = IpAddr::from_str(address.trim())
+            .map_err(|_| format!("Invalid address in IP range {}", range))?;
+        let max_prefix_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            None => max_prefix_len,
+            Some(prefix_len) => prefix_len
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .ok_or_else(|| format!("Invalid prefix length in IP range {}", range))?,
+        };
+        Ok(IpRange {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl IpRange {
+    pub(crate) fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+fn parse_ranges(ranges: &[String]) -> Result<Vec<IpRange>, String> {
+    ranges
+        .iter()
+        .map(|range| IpRange::from_str(range))
+        .collect()
+}
+
+fn in_any(ranges: &[IpRange], address: IpAddr) -> bool {
+    ranges.iter().any(|range| range.contains(address))
+}
+
+/// Denied ranges take precedence over allowed ones; without allowed ranges,
+/// every address that is not denied is admitted.
+pub(crate) struct IpAccessRules {
+    allow: Vec<IpRange>,
+    deny: Vec<IpRange>,
+}
+
+impl IpAccessRules {
+    pub(crate) fn new(allow: &[String], deny: &[String]) -> Result<Self, String> {
+        Ok(IpAccessRules {
+            allow: parse_ranges(allow)?,
+            deny: parse_ranges(deny)?,
+        })
+    }
+
+    pub(crate) fn admits(&self, address: IpAddr) -> bool {
+        !in_any(&self.deny, address) && (self.allow.is_empty() || in_any(&self.allow, address))
+    }
+}
+
+/// The gateway wide IP access rules, along with the proxies trusted to report
+/// the address of the client they forward requests for.
+pub(crate) struct IpAccess {
+    rules: IpAccessRules,
+    trusted_proxies: Vec<IpRange>,
+    client_ip_header: HeaderName,
+}
+
+/// Forwarded addresses may carry the port of the client.
+fn parse_forwarded_address(address: &str) -> Option<IpAddr> {
+    IpAddr::from_str(address)
+        .or_else(|_| SocketAddr::from_str(address).map(|address| address.ip()))
+        .ok()
+}
+
+impl IpAccess {
+    pub(crate) fn from_config(ip_access_config: &IpAccessConfig) -> Result<Self, String> {
+        let client_ip_header = ip_access_config
+            .client_ip_header
+            .as_deref()
+            .unwrap_or(DEFAULT_CLIENT_IP_HEADER);
+        Ok(IpAccess {
+            rules: IpAccessRules::new(&ip_access_config.allow, &ip_access_config.deny)?,
+            trusted_proxies: parse_ranges(&ip_access_config.trusted_proxies)?,
+            client_ip_header: HeaderName::from_str(client_ip_header)
+                .map_err(|_| format!("Invalid client IP header {}", client_ip_header))?,
+        })
+    }
+
+    /// The peer address, unless it is a trusted proxy. Then the forwarded
+    /// addresses are walked from the nearest hop back to the first one that is
+    /// not a trusted proxy, since only the entries appended by trusted proxies
+    /// cannot be forged by the client.
+    pub(crate) fn client_ip(
+        &self,
+        request: &Request<Body>,
+        connection_info: &ConnectionInfo,
+    ) -> IpAddr {
+        let mut client_ip = connection_info.remote_addr.ip();
+        if !in_any(&self.trusted_proxies, client_ip) {
+            return client_ip;
+        }
+        let forwarded_addresses: Vec<&str> = request
+            .headers()
+            .get_all(&self.client_ip_header)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        for forwarded_address in forwarded_addresses.into_iter().rev() {
+            match parse_forwarded_address(forwarded_address) {
+                None => break,
+                Some(forwarded_ip) => {
+                    client_ip = forwarded_ip;
+                    if !in_any(&self.trusted_proxies, forwarded_ip) {
+                        break;
+                    }
+                }
+            }
+        }
+        client_ip
+    }
+
+    pub(crate) fn admits(&self, client_ip: IpAddr) -> bool {
+        self.rules.admits(client_ip)
+    }
+}
+
+/// Checks the client address against the IP access list of the API, if it has
+/// one. Lists that were not parsed when the definition was loaded admit nobody,
+/// so that no client is admitted by mistake.
+pub(crate) fn api_admits(api_definition: &APIDefinition, client_ip: IpAddr) -> bool {
+    match &api_definition.ip_access {
+        None => true,
+        Some(ip_access_list) => match &ip_access_list.rules {
+            Some(rules) => rules.admits(client_ip),
+            None => {
+                error!(
+                    "IP access list of APIDefinition (APIDefinition ID: {}) was not parsed",
+                    api_definition.api_id
+                );
+                false
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, SocketAddr};
+    use std::str::FromStr;
+
+    use hyper::{Body, Request};
+
+    use crate::configuration_reader::api_def_reader::APIDefinition;
+    use crate::configuration_reader::ip_access_config_reader::IpAccessConfig;
+    use crate::core::connection_info::ConnectionInfo;
+
+    use super::{api_admits, IpAccess, IpRange};
+
+    fn ip(address: &str) -> IpAddr {
+        IpAddr::from_str(address).unwrap()
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| String::from(*value)).collect()
+    }
+
+    #[test]
+    fn test_ranges_contain_addresses() {
+        let range = IpRange::from_str("10.1.0.0/16").unwrap();
+        assert!(range.contains(ip("10.1.255.7")));
+        assert!(range.contains(ip("::ffff:10.1.0.1")));
+        assert!(!range.contains(ip("10.2.0.1")));
+        assert!(!range.contains(ip("2001:db8::1")));
+
+        let range = IpRange::from_str("2001:db8::/32").unwrap();
+        assert!(range.contains(ip("2001:db8:ffff::1")));
+        assert!(!range.contains(ip("2001:db9::1")));
+
+        assert!(IpRange::from_str("0.0.0.0/0")
+            .unwrap()
+            .contains(ip("203.0.113.9")));
+        assert!(IpRange::from_str("::/0").unwrap().contains(ip("::1")));
+        assert!(IpRange::from_str("192.0.2.1")
+            .unwrap()
+            .contains(ip("192.0.2.1")));
+        assert!(!IpRange::from_str("192.0.2.1")
+            .unwrap()
+            .contains(ip("192.0.2.2")));
+
+        for invalid_range in ["10.0.0.0/33", "2001:db8::/129", "10.0.0/8", "10.0.0.0/x"] {
+            assert!(IpRange::from_str(invalid_range).is_err());
+        }
+    }
+
+    #[test]
+    fn test_forwarded_addresses_are_only_trusted_from_proxies() {
+        let ip_access = IpAccess::from_config(&IpAccessConfig {
+            trusted_proxies: strings(&["10.0.0.0/8"]),
+            ..IpAccessConfig::default()
+        })
+        .unwrap();
+        let request = Request::builder()
+            .header("x-forwarded-for", "198.51.100.1, 203.0.113.9:41000")
+            .header("x-forwarded-for", "10.0.0.2")
+            .body(Body::empty())
+            .unwrap();
+        let connection_info = |remote_addr: &str| ConnectionInfo {
+            remote_addr: SocketAddr::new(ip(remote_addr), 52000),
+            client_certificate: None,
+        };
+        assert_eq!(
+            ip("203.0.113.9"),
+            ip_access.client_ip(&request, &connection_info("10.0.0.1"))
+        );
+        assert_eq!(
+            ip("192.0.2.1"),
+            ip_access.client_ip(&request, &connection_info("192.0.2.1"))
+        );
+
+        let request = Request::builder()
+            .header("x-forwarded-for", "unknown, 10.0.0.3")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            ip("10.0.0.3"),
+            ip_access.client_ip(&request, &connection_info("10.0.0.1"))
+        );
+    }
+
+    #[test]
+    fn test_denied_ranges_take_precedence() {
+        let ip_access = IpAccess::from_config(&IpAccessConfig {
+            allow: strings(&["192.0.2.0/24", "2001:db8::/32"]),
+            deny: strings(&["192.0.2.128/25"]),
+            ..IpAccessConfig::default()
+        })
+        .unwrap();
+        assert!(ip_access.admits(ip("192.0.2.1")));
+        assert!(ip_access.admits(ip("2001:db8::1")));
+        assert!(!ip_access.admits(ip("192.0.2.200")));
+        assert!(!ip_access.admits(ip("198.51.100.1")));
+
+        assert!(IpAccess::from_config(&IpAccessConfig::default())
+            .unwrap()
+            .admits(ip("198.51.100.1")));
+        assert!(IpAccess::from_config(&IpAccessConfig {
+            deny: strings(&["192.0.2.0/40"]),
+            ..IpAccessConfig::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_api_access_lists() {
+        let api_definition = |ip_access: &str| {
+            APIDefinition::from_json_str_slice(&format!(
+                r#"{{
+                    "api_id": "billing",
+                    "api_name": "Billing API",
+                    "api_version": "1.0.0",
+                    "api_desc": "Invoices",
+                    "specification": {{"methods": ["GET"], "paths": ["/invoices"], "hostnames": ["localhost"]}},
+                    "backend_response_timeout": 1000,
+                    "origin_id": "billing",
+                    "ip_access": {}
+                }}"#,
+                ip_access
+            ))
+        };
+        let internal_only = api_definition(r#"{"allow": ["10.0.0.0/8", "fd00::/8"]}"#).unwrap();
+        assert!(api_admits(&internal_only, ip("10.20.30.40")));
+        assert!(api_admits(&internal_only, ip("fd12::1")));
+        assert!(!api_admits(&internal_only, ip("203.0.113.9")));
+
+        let blocked = api_definition(r#"{"deny": ["203.0.113.0/24"]}"#).unwrap();
+        assert!(api_admits(&blocked, ip("198.51.100.1")));
+        assert!(!api_admits(&blocked, ip("203.0.113.9")));
+
+        // Definitions with invalid ranges are rejected when loaded
+        assert!(api_definition(r#"{"deny": ["203.0.113.0/24", "not-a-range"]}"#).is_err());
+    }
+}
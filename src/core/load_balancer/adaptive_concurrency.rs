@@ -0,0 +1,192 @@
+use std::time::Duration;
+
+use crate::configuration_reader::origin_def_reader::{
+    AdaptiveConcurrencyAlgorithm, AdaptiveConcurrencyConfig,
+};
+
+const DEFAULT_LATENCY_THRESHOLD: u64 = 1000;
+const AIMD_BACKOFF_RATIO: f64 = 0.9;
+const GRADIENT_SMOOTHING: f64 = 0.2;
+const GRADIENT_RTT_TOLERANCE: f64 = 1.5;
+const GRADIENT_LONG_WINDOW: f64 = 600.0;
+
+/// Estimates how many requests an Origin can take in flight from the outcome of
+/// each completed request.
+pub(crate) trait AdaptiveLimit: Send {
+    /// Returns the new limit after a request completed in `latency` while
+    /// `in_flight` requests, including itself, were in flight.
+    fn update(&mut self, latency: Duration, success: bool, in_flight: usize) -> usize;
+}
+
+pub(crate) fn create_adaptive_limit(
+    adaptive_config: &AdaptiveConcurrencyConfig,
+    initial_limit: usize,
+) -> Box<dyn AdaptiveLimit> {
+    let min_limit = adaptive_config.min_limit.max(1);
+    let max_limit = adaptive_config.max_limit.max(min_limit);
+    let initial_limit = initial_limit.clamp(min_limit, max_limit) as f64;
+    match adaptive_config.algorithm {
+        AdaptiveConcurrencyAlgorithm::Aimd => Box::new(AimdLimit {
+            limit: initial_limit,
+            min_limit,
+            max_limit,
+            latency_threshold: Duration::from_millis(
+                adaptive_config
+                    .latency_threshold
+                    .unwrap_or(DEFAULT_LATENCY_THRESHOLD),
+            ),
+        }),
+        AdaptiveConcurrencyAlgorithm::Gradient => Box::new(GradientLimit {
+            limit: initial_limit,
+            min_limit,
+            max_limit,
+            long_rtt: None,
+        }),
+    }
+}
+
+/// Additive increase, multiplicative decrease: grows by one while the Origin
+/// keeps up and backs off when a request fails or exceeds the latency threshold.
+struct AimdLimit {
+    limit: f64,
+    min_limit: usize,
+    max_limit: usize,
+    latency_threshold: Duration,
+}
+
+impl AdaptiveLimit for AimdLimit {
+    fn update(&mut self, latency: Duration, success: bool, in_flight: usize) -> usize {
+        if !success || latency > self.latency_threshold {
+            self.limit *= AIMD_BACKOFF_RATIO;
+        } else if in_flight as f64 * 2.0 >= self.limit {
+            // Only grow while the limit is actually being used
+            self.limit += 1.0;
+        }
+        self.limit = self
+            .limit
+            .clamp(self.min_limit as f64, self.max_limit as f64);
+        self.limit as usize
+    }
+}
+
+/// Compares each latency against a long-term average: the limit shrinks as
+/// latency climbs above what the Origin delivers without queueing, and grows by
+/// roughly its square root while latency stays flat.
+struct GradientLimit {
+    limit: f64,
+    min_limit: usize,
+    max_limit: usize,
+    long_rtt: Option<f64>,
+}
+
+impl AdaptiveLimit for GradientLimit {
+    fn update(&mut self, latency: Duration, success: bool, in_flight: usize) -> usize {
+        let short_rtt = latency.as_secs_f64().max(f64::EPSILON);
+        let long_rtt = match self.long_rtt {
+            None => short_rtt,
+            Some(long_rtt) => {
+                let long_rtt = long_rtt + (short_rtt - long_rtt) / GRADIENT_LONG_WINDOW;
+                // Recover quickly once a latency spike is over, so the spike does
+                // not become the new baseline
+                if long_rtt / short_rtt > 2.0 {
+                    long_rtt * 0.95
+                } else {
+                    long_rtt
+                }
+            }
+        };
+        self.long_rtt = Some(long_rtt);
+
+        // Leave an underused limit alone, it says nothing about the Origin
+        if success && (in_flight as f64) < self.limit / 2.0 {
+            return self.limit as usize;
+        }
+        let gradient = if success {
+            (GRADIENT_RTT_TOLERANCE * long_rtt / short_rtt).clamp(0.5, 1.0)
+        } else {
+            0.5
+        };
+        let new_limit = self.limit * gradient + self.limit.sqrt();
+        self.limit = (self.limit * (1.0 - GRADIENT_SMOOTHING) + new_limit * GRADIENT_SMOOTHING)
+            .clamp(self.min_limit as f64, self.max_limit as f64);
+        self.limit as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::configuration_reader::origin_def_reader::{
+        AdaptiveConcurrencyAlgorithm, AdaptiveConcurrencyConfig,
+    };
+
+    use super::create_adaptive_limit;
+
+    fn adaptive_config(algorithm: AdaptiveConcurrencyAlgorithm) -> AdaptiveConcurrencyConfig {
+        AdaptiveConcurrencyConfig {
+            algorithm,
+            min_limit: 2,
+            max_limit: 50,
+            latency_threshold: Some(100),
+        }
+    }
+
+    #[test]
+    fn test_aimd_grows_additively_and_backs_off() {
+        let mut limit =
+            create_adaptive_limit(&adaptive_config(AdaptiveConcurrencyAlgorithm::Aimd), 10);
+        let fast = Duration::from_millis(20);
+        assert_eq!(11, limit.update(fast, true, 10));
+        assert_eq!(12, limit.update(fast, true, 10));
+        // Latency above the threshold counts as a drop
+        assert_eq!(10, limit.update(Duration::from_millis(150), true, 10));
+        assert_eq!(9, limit.update(fast, false, 10));
+    }
+
+    #[test]
+    fn test_aimd_does_not_grow_unused_limit() {
+        let mut limit =
+            create_adaptive_limit(&adaptive_config(AdaptiveConcurrencyAlgorithm::Aimd), 10);
+        assert_eq!(10, limit.update(Duration::from_millis(20), true, 2));
+    }
+
+    #[test]
+    fn test_aimd_respects_bounds() {
+        let mut limit =
+            create_adaptive_limit(&adaptive_config(AdaptiveConcurrencyAlgorithm::Aimd), 3);
+        for _ in 0..20 {
+            limit.update(Duration::from_millis(20), false, 3);
+        }
+        assert_eq!(2, limit.update(Duration::from_millis(20), false, 3));
+        for _ in 0..100 {
+            limit.update(Duration::from_millis(20), true, 50);
+        }
+        assert_eq!(50, limit.update(Duration::from_millis(20), true, 50));
+    }
+
+    #[test]
+    fn test_gradient_grows_with_steady_latency() {
+        let mut limit =
+            create_adaptive_limit(&adaptive_config(AdaptiveConcurrencyAlgorithm::Gradient), 10);
+        let mut current = 10;
+        for _ in 0..20 {
+            current = limit.update(Duration::from_millis(20), true, current);
+        }
+        assert!(current > 10);
+    }
+
+    #[test]
+    fn test_gradient_shrinks_when_latency_climbs() {
+        let mut limit =
+            create_adaptive_limit(&adaptive_config(AdaptiveConcurrencyAlgorithm::Gradient), 40);
+        for _ in 0..100 {
+            limit.update(Duration::from_millis(20), true, 40);
+        }
+        let mut current = 50;
+        for _ in 0..30 {
+            current = limit.update(Duration::from_millis(200), true, current);
+        }
+        assert!(current < 20);
+    }
+}
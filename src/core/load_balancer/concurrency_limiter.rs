@@ -0,0 +1,377 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+
+use crate::configuration_reader::origin_def_reader::{
+    AdaptiveConcurrencyConfig, ConcurrencyLimitConfig,
+};
+use crate::core::load_balancer::adaptive_concurrency::{create_adaptive_limit, AdaptiveLimit};
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ConcurrencyRejection {
+    QueueFull,
+    QueueTimeout,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct ConcurrencyStatus {
+    pub(crate) origin_id: String,
+    pub(crate) limit: usize,
+    pub(crate) in_flight: usize,
+    pub(crate) queued: usize,
+}
+
+struct ConcurrencyState {
+    limit: usize,
+    max_queue_size: usize,
+    queue_timeout: Duration,
+    in_flight: usize,
+    waiters: VecDeque<oneshot::Sender<()>>,
+    adaptive_config: Option<AdaptiveConcurrencyConfig>,
+    adaptive_limit: Option<Box<dyn AdaptiveLimit>>,
+}
+
+impl ConcurrencyState {
+    /// An adaptive limit whose configuration did not change is kept, along with
+    /// the limit it has learnt, since `max_concurrent_requests` is only where it
+    /// starts from.
+    fn configure(&mut self, concurrency_limit_config: &ConcurrencyLimitConfig) {
+        self.max_queue_size = concurrency_limit_config.max_queue_size;
+        self.queue_timeout = Duration::from_millis(concurrency_limit_config.queue_timeout);
+        if self.adaptive_limit.is_none()
+            || self.adaptive_config != concurrency_limit_config.adaptive
+        {
+            self.limit = concurrency_limit_config.max_concurrent_requests;
+            self.adaptive_config = concurrency_limit_config.adaptive.clone();
+            self.adaptive_limit = concurrency_limit_config
+                .adaptive
+                .as_ref()
+                .map(|adaptive_config| create_adaptive_limit(adaptive_config, self.limit));
+        }
+        self.admit_waiters();
+    }
+
+    /// Admits queued requests while the limit, which may just have grown, allows.
+    fn admit_waiters(&mut self) {
+        while self.in_flight < self.limit && self.hand_over_permit() {
+            self.in_flight += 1;
+        }
+    }
+
+    /// Waiters whose request was cancelled are skipped; a permit is handed over to
+    /// the first live waiter, who takes the place of the releasing request.
+    fn hand_over_permit(&mut self) -> bool {
+        while let Some(waiter) = self.waiters.pop_front() {
+            if waiter.send(()).is_ok() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Counts the requests in flight to an Origin. Requests over the limit queue up
+/// in FIFO order and are admitted as earlier requests release their permits.
+pub(crate) struct ConcurrencyLimiter {
+    state: Mutex<ConcurrencyState>,
+}
+
+/// Held for as long as a request is in flight. Dropping it admits the next
+/// queued request.
+pub(crate) struct ConcurrencyPermit {
+    concurrency_limiter: Arc<ConcurrencyLimiter>,
+    admitted_at: Instant,
+}
+
+impl ConcurrencyPermit {
+    /// Feeds the outcome of the request to the adaptive limit, if any, and
+    /// releases the permit.
+    pub(crate) fn complete(self, success: bool) {
+        self.concurrency_limiter
+            .observe(self.admitted_at.elapsed(), success);
+    }
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        self.concurrency_limiter.release();
+    }
+}
+
+/// A place in the queue. If the waiting request is cancelled after a permit was
+/// already handed to it, the permit is released here instead of leaking.
+struct QueuedRequest {
+    concurrency_limiter: Arc<ConcurrencyLimiter>,
+    receiver: oneshot::Receiver<()>,
+}
+
+impl QueuedRequest {
+    fn take_permit(&mut self) -> bool {
+        self.receiver.close();
+        self.receiver.try_recv().is_ok()
+    }
+}
+
+impl Drop for QueuedRequest {
+    fn drop(&mut self) {
+        if self.take_permit() {
+            self.concurrency_limiter.release();
+        }
+    }
+}
+
+impl ConcurrencyLimiter {
+    pub(crate) fn new(concurrency_limit_config: &ConcurrencyLimitConfig) -> Self {
+        let mut state = ConcurrencyState {
+            limit: 0,
+            max_queue_size: 0,
+            queue_timeout: Duration::ZERO,
+            in_flight: 0,
+            waiters: VecDeque::new(),
+            adaptive_config: None,
+            adaptive_limit: None,
+        };
+        state.configure(concurrency_limit_config);
+        ConcurrencyLimiter {
+            state: Mutex::new(state),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ConcurrencyState> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    fn permit(self: &Arc<Self>) -> ConcurrencyPermit {
+        ConcurrencyPermit {
+            concurrency_limiter: self.clone(),
+            admitted_at: Instant::now(),
+        }
+    }
+
+    pub(crate) async fn acquire(
+        self: Arc<Self>,
+    ) -> Result<ConcurrencyPermit, ConcurrencyRejection> {
+        let (mut queued_request, queue_timeout) = {
+            let mut state = self.lock();
+            state.waiters.retain(|waiter| !waiter.is_closed());
+            if state.waiters.is_empty() && state.in_flight < state.limit {
+                state.in_flight += 1;
+                return Ok(self.permit());
+            }
+            if state.waiters.len() >= state.max_queue_size {
+                return Err(ConcurrencyRejection::QueueFull);
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.waiters.push_back(sender);
+            let queued_request = QueuedRequest {
+                concurrency_limiter: self.clone(),
+                receiver,
+            };
+            (queued_request, state.queue_timeout)
+        };
+        match timeout(queue_timeout, &mut queued_request.receiver).await {
+            Ok(Ok(_)) => Ok(self.permit()),
+            Ok(Err(_)) => Err(ConcurrencyRejection::QueueTimeout),
+            Err(_) => {
+                // A permit may have been handed over right as the timeout fired
+                if queued_request.take_permit() {
+                    Ok(self.permit())
+                } else {
+                    Err(ConcurrencyRejection::QueueTimeout)
+                }
+            }
+        }
+    }
+
+    fn release(&self) {
+        let mut state = self.lock();
+        if state.in_flight <= state.limit && state.hand_over_permit() {
+            return;
+        }
+        state.in_flight = state.in_flight.saturating_sub(1);
+    }
+
+    fn observe(&self, latency: Duration, success: bool) {
+        let mut state = self.lock();
+        let in_flight = state.in_flight;
+        if let Some(adaptive_limit) = state.adaptive_limit.as_mut() {
+            state.limit = adaptive_limit.update(latency, success, in_flight);
+            state.admit_waiters();
+        }
+    }
+
+    /// Applies a new configuration without forgetting the requests in flight.
+    /// Queued requests are admitted right away if the limit grew.
+    pub(crate) fn reconfigure(&self, concurrency_limit_config: &ConcurrencyLimitConfig) {
+        self.lock().configure(concurrency_limit_config);
+    }
+
+    pub(crate) fn status(&self, origin_id: &str) -> ConcurrencyStatus {
+        let state = self.lock();
+        ConcurrencyStatus {
+            origin_id: String::from(origin_id),
+            limit: state.limit,
+            in_flight: state.in_flight,
+            queued: state
+                .waiters
+                .iter()
+                .filter(|waiter| !waiter.is_closed())
+                .count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::configuration_reader::origin_def_reader::{
+        AdaptiveConcurrencyAlgorithm, AdaptiveConcurrencyConfig, ConcurrencyLimitConfig,
+    };
+
+    use super::{ConcurrencyLimiter, ConcurrencyPermit, ConcurrencyRejection};
+
+    fn concurrency_limiter(
+        max_concurrent_requests: usize,
+        max_queue_size: usize,
+        queue_timeout: u64,
+    ) -> Arc<ConcurrencyLimiter> {
+        Arc::new(ConcurrencyLimiter::new(&ConcurrencyLimitConfig {
+            max_concurrent_requests,
+            max_queue_size,
+            queue_timeout,
+            adaptive: None,
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_rejects_when_queue_is_full() {
+        let limiter = concurrency_limiter(1, 0, 100);
+        let _permit = limiter.clone().acquire().await.unwrap();
+        assert_eq!(
+            Some(ConcurrencyRejection::QueueFull),
+            limiter.clone().acquire().await.err()
+        );
+        assert_eq!(1, limiter.status("origin").in_flight);
+    }
+
+    #[tokio::test]
+    async fn test_queued_request_times_out() {
+        let limiter = concurrency_limiter(1, 1, 20);
+        let _permit = limiter.clone().acquire().await.unwrap();
+        assert_eq!(
+            Some(ConcurrencyRejection::QueueTimeout),
+            limiter.clone().acquire().await.err()
+        );
+        assert_eq!(0, limiter.status("origin").queued);
+    }
+
+    #[tokio::test]
+    async fn test_released_permit_is_handed_to_queued_request() {
+        let limiter = concurrency_limiter(1, 1, 1000);
+        let permit = limiter.clone().acquire().await.unwrap();
+        let queued = tokio::spawn(limiter.clone().acquire());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(1, limiter.status("origin").queued);
+        drop(permit);
+        let permit = queued.await.unwrap().unwrap();
+        let status = limiter.status("origin");
+        assert_eq!((1, 0), (status.in_flight, status.queued));
+        drop(permit);
+        assert_eq!(0, limiter.status("origin").in_flight);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_request_leaves_queue() {
+        let limiter = concurrency_limiter(1, 1, 1000);
+        let permit = limiter.clone().acquire().await.unwrap();
+        let queued = tokio::spawn(limiter.clone().acquire());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        queued.abort();
+        let _ = queued.await;
+        assert_eq!(0, limiter.status("origin").queued);
+        drop(permit);
+        assert_eq!(0, limiter.status("origin").in_flight);
+    }
+
+    #[tokio::test]
+    async fn test_raising_limit_admits_queued_requests() {
+        let limiter = concurrency_limiter(1, 1, 1000);
+        let _permit = limiter.clone().acquire().await.unwrap();
+        let queued = tokio::spawn(limiter.clone().acquire());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        limiter.reconfigure(&ConcurrencyLimitConfig {
+            max_concurrent_requests: 2,
+            max_queue_size: 1,
+            queue_timeout: 1000,
+            adaptive: None,
+        });
+        let _queued_permit = queued.await.unwrap().unwrap();
+        assert_eq!(2, limiter.status("origin").in_flight);
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_limit_follows_outcomes() {
+        let limiter = Arc::new(ConcurrencyLimiter::new(&ConcurrencyLimitConfig {
+            max_concurrent_requests: 4,
+            max_queue_size: 0,
+            queue_timeout: 0,
+            adaptive: Some(AdaptiveConcurrencyConfig {
+                algorithm: AdaptiveConcurrencyAlgorithm::Aimd,
+                min_limit: 1,
+                max_limit: 10,
+                latency_threshold: Some(1000),
+            }),
+        }));
+        let permits = acquire_permits(&limiter, 4).await;
+        for permit in permits {
+            permit.complete(true);
+        }
+        assert_eq!(6, limiter.status("origin").limit);
+        let permit = limiter.clone().acquire().await.unwrap();
+        permit.complete(false);
+        assert_eq!(5, limiter.status("origin").limit);
+        assert_eq!(0, limiter.status("origin").in_flight);
+    }
+
+    #[tokio::test]
+    async fn test_reconfiguring_keeps_an_unchanged_adaptive_limit() {
+        let concurrency_limit_config =
+            |max_queue_size: usize, min_limit: usize| ConcurrencyLimitConfig {
+                max_concurrent_requests: 4,
+                max_queue_size,
+                queue_timeout: 0,
+                adaptive: Some(AdaptiveConcurrencyConfig {
+                    algorithm: AdaptiveConcurrencyAlgorithm::Aimd,
+                    min_limit,
+                    max_limit: 10,
+                    latency_threshold: Some(1000),
+                }),
+            };
+        let limiter = Arc::new(ConcurrencyLimiter::new(&concurrency_limit_config(0, 1)));
+        for permit in acquire_permits(&limiter, 4).await {
+            permit.complete(true);
+        }
+        assert_eq!(6, limiter.status("origin").limit);
+        limiter.reconfigure(&concurrency_limit_config(5, 1));
+        assert_eq!(6, limiter.status("origin").limit);
+        limiter.reconfigure(&concurrency_limit_config(5, 2));
+        assert_eq!(4, limiter.status("origin").limit);
+    }
+
+    async fn acquire_permits(
+        limiter: &Arc<ConcurrencyLimiter>,
+        count: usize,
+    ) -> Vec<ConcurrencyPermit> {
+        let mut permits = Vec::new();
+        for _ in 0..count {
+            permits.push(limiter.clone().acquire().await.unwrap());
+        }
+        permits
+    }
+}
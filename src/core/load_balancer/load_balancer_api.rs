@@ -0,0 +1,55 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::oneshot::Sender;
+
+use crate::configuration_reader::origin_def_reader::{OriginSpecification, Server};
+use crate::core::load_balancer::concurrency_limiter::{ConcurrencyLimiter, ConcurrencyStatus};
+
+pub enum LoadBalancerAPI {
+    SelectServer {
+        origin_id: String,
+        preferred_server: Option<String>,
+        excluded_server: Option<String>,
+        responder: Sender<Option<Server>>,
+    },
+    ReportServerOutcome {
+        origin_id: String,
+        server_key: String,
+        success: bool,
+        latency: Duration,
+    },
+    RecordCancelledAttempt {
+        origin_id: String,
+        elapsed: Duration,
+    },
+    GetLatencyPercentile {
+        origin_id: String,
+        percentile: f64,
+        responder: Sender<Option<Duration>>,
+    },
+    AcquireRetryBudget {
+        origin_id: String,
+        budget_percentage: f64,
+        responder: Sender<bool>,
+    },
+    CompleteWarmUp {
+        origin_id: String,
+        server_key: String,
+        success: bool,
+    },
+    GetConcurrencyLimiter {
+        origin_id: String,
+        responder: Sender<Option<Arc<ConcurrencyLimiter>>>,
+    },
+    GetConcurrencyStatus {
+        responder: Sender<Vec<ConcurrencyStatus>>,
+    },
+    UpdateOriginSpecification {
+        origin_id: String,
+        origin_spec: Box<OriginSpecification>,
+    },
+    RemoveOriginSpecification {
+        origin_id: String,
+    },
+}
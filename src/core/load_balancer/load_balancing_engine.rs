@@ -0,0 +1,874 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use hyper::{Body, Method, Request};
+use log::{debug, info, trace};
+use rand::Rng;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+use crate::configuration_reader::origin_def_reader::{
+    OriginSpecification, Server, SlowStartConfig,
+};
+use crate::core::load_balancer::concurrency_limiter::{ConcurrencyLimiter, ConcurrencyStatus};
+use crate::core::load_balancer::load_balancer_api::LoadBalancerAPI;
+use crate::core::origin_client::origin_client;
+use crate::core::router::build_origin_uri;
+
+const WARM_UP_TIMEOUT: Duration = Duration::from_secs(5);
+const WARM_UP_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_LATENCY_SAMPLES: usize = 1000;
+const MIN_LATENCY_SAMPLES: usize = 20;
+/// New latency samples after which the sorted samples are refreshed.
+const LATENCY_SORT_INTERVAL: usize = 50;
+const RETRY_BUDGET_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct ServerHealth {
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+    in_rotation_since: Option<Instant>,
+    warming_up: bool,
+}
+
+impl ServerHealth {
+    fn is_healthy(&self, now: Instant) -> bool {
+        if self.warming_up {
+            return false;
+        }
+        match self.ejected_until {
+            None => true,
+            Some(ejected_until) => now >= ejected_until,
+        }
+    }
+}
+
+/// Bounds retries and hedges of an Origin to a share of the requests that
+/// recently succeeded, so that extra attempts dry up while the Origin is failing.
+struct RetryBudget {
+    window_start: Instant,
+    successful_requests: u64,
+    retries: u64,
+}
+
+impl RetryBudget {
+    fn new(now: Instant) -> Self {
+        RetryBudget {
+            window_start: now,
+            successful_requests: 0,
+            retries: 0,
+        }
+    }
+
+    fn roll_window(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.window_start) >= RETRY_BUDGET_WINDOW {
+            *self = RetryBudget::new(now);
+        }
+    }
+
+    fn record_success(&mut self, now: Instant) {
+        self.roll_window(now);
+        self.successful_requests += 1;
+    }
+
+    fn try_acquire(&mut self, budget_percentage: f64, now: Instant) -> bool {
+        self.roll_window(now);
+        let allowed_retries = (self.successful_requests as f64) * budget_percentage / 100.0;
+        if ((self.retries + 1) as f64) <= allowed_retries {
+            self.retries += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct OriginState {
+    origin_spec: OriginSpecification,
+    server_health: HashMap<String, ServerHealth>,
+    latency_samples: VecDeque<Duration>,
+    /// Latency samples as of the last refresh, sorted for reading percentiles.
+    sorted_latency_samples: Vec<Duration>,
+    unsorted_latency_samples: usize,
+    retry_budget: RetryBudget,
+    concurrency_limiter: Option<Arc<ConcurrencyLimiter>>,
+}
+
+impl OriginState {
+    fn new(origin_spec: OriginSpecification) -> Self {
+        let concurrency_limiter = origin_spec
+            .concurrency_limit
+            .as_ref()
+            .map(|config| Arc::new(ConcurrencyLimiter::new(config)));
+        OriginState {
+            origin_spec,
+            server_health: HashMap::new(),
+            latency_samples: VecDeque::with_capacity(MAX_LATENCY_SAMPLES),
+            sorted_latency_samples: Vec::with_capacity(MAX_LATENCY_SAMPLES),
+            unsorted_latency_samples: 0,
+            retry_budget: RetryBudget::new(Instant::now()),
+            concurrency_limiter,
+        }
+    }
+}
+
+fn latency_percentile(sorted_samples: &[Duration], percentile: f64) -> Option<Duration> {
+    if sorted_samples.len() < MIN_LATENCY_SAMPLES {
+        return None;
+    }
+    let rank = ((percentile.clamp(0.0, 100.0) / 100.0) * sorted_samples.len() as f64).ceil();
+    let index = (rank as usize).clamp(1, sorted_samples.len()) - 1;
+    Some(sorted_samples[index])
+}
+
+/// Collects the healthy servers of the most preferred priority tier (lowest
+/// priority value first). Lower tiers are only added to the pool while the
+/// healthy servers found so far make up less than `min_healthy_percentage` of
+/// their tier, so backup tiers receive traffic once primaries degrade.
+fn candidate_servers<'a>(
+    servers: &'a [Server],
+    server_health: &HashMap<String, ServerHealth>,
+    min_healthy_percentage: u8,
+    now: Instant,
+) -> Vec<&'a Server> {
+    let priorities: BTreeSet<u32> = servers.iter().map(|server| server.priority()).collect();
+    let mut candidates = vec![];
+    for priority in priorities {
+        let tier: Vec<&Server> = servers
+            .iter()
+            .filter(|server| server.priority() == priority)
+            .collect();
+        let healthy: Vec<&Server> = tier
+            .iter()
+            .copied()
+            .filter(|server| {
+                server_health
+                    .get(&server.server_key())
+                    .is_none_or(|health| health.is_healthy(now))
+            })
+            .collect();
+        let tier_is_sufficient =
+            healthy.len() * 100 >= (min_healthy_percentage as usize) * tier.len();
+        candidates.extend(healthy);
+        if !candidates.is_empty() && tier_is_sufficient {
+            break;
+        }
+        trace!(
+            "Priority tier {} is below the healthy threshold, spilling over to the next tier",
+            priority
+        );
+    }
+    candidates
+}
+
+/// Weight of a server relative to a fully warmed server. During the slow start
+/// window the weight ramps linearly from `initial_weight_percentage` to 100%.
+fn effective_weight(
+    health: Option<&ServerHealth>,
+    slow_start: Option<&SlowStartConfig>,
+    now: Instant,
+) -> f64 {
+    let (slow_start, in_rotation_since) = match (slow_start, health) {
+        (Some(slow_start), Some(health)) => match health.in_rotation_since {
+            None => return 1.0,
+            Some(in_rotation_since) => (slow_start, in_rotation_since),
+        },
+        _ => return 1.0,
+    };
+    let window = Duration::from_millis(slow_start.window);
+    let elapsed = now.saturating_duration_since(in_rotation_since);
+    if window.is_zero() || elapsed >= window {
+        return 1.0;
+    }
+    let initial_weight = (slow_start.initial_weight_percentage.clamp(1, 100) as f64) / 100.0;
+    initial_weight + (1.0 - initial_weight) * elapsed.as_secs_f64() / window.as_secs_f64()
+}
+
+fn pick_weighted(weighted_servers: &[(&Server, f64)]) -> Option<Server> {
+    let total_weight: f64 = weighted_servers.iter().map(|(_, weight)| weight).sum();
+    if weighted_servers.is_empty() || total_weight <= 0.0 {
+        return None;
+    }
+    let mut point = rand::thread_rng().gen_range(0.0..total_weight);
+    for (server, weight) in weighted_servers {
+        if point < *weight {
+            return Some((*server).clone());
+        }
+        point -= weight;
+    }
+    weighted_servers.last().map(|(server, _)| (*server).clone())
+}
+
+async fn warm_up_server(
+    load_balancer_tx: Sender<LoadBalancerAPI>,
+    origin_id: String,
+    server: Server,
+    slow_start: SlowStartConfig,
+) {
+    let warm_up_uri = build_origin_uri(&server, slow_start.warm_up_path.as_deref().unwrap_or("/"));
+    let mut warm_up_requests = JoinSet::new();
+    for _ in 0..slow_start.pre_connect {
+        let warm_up_uri = warm_up_uri.clone();
+        warm_up_requests.spawn(async move {
+            let request = Request::builder()
+                .method(Method::HEAD)
+                .uri(warm_up_uri)
+                .body(Body::empty());
+            match request {
+                Err(_) => false,
+                Ok(request) => matches!(
+                    timeout(WARM_UP_TIMEOUT, origin_client().request(request)).await,
+                    Ok(Ok(_))
+                ),
+            }
+        });
+    }
+    let mut success = false;
+    while let Some(result) = warm_up_requests.join_next().await {
+        success |= result.unwrap_or(false);
+    }
+    debug!(
+        "Warm-up of server {} of Origin (Origin ID: {}) finished (success: {})",
+        server.server_key(),
+        origin_id,
+        success
+    );
+    let complete_warm_up_call = LoadBalancerAPI::CompleteWarmUp {
+        origin_id,
+        server_key: server.server_key(),
+        success,
+    };
+    if load_balancer_tx.send(complete_warm_up_call).await.is_err() {
+        trace!("Failed to report warm-up completion to load balancer");
+    }
+}
+
+/// Starts bringing a server into rotation. Servers of Origins that pre-connect
+/// stay out of rotation until their warm-up requests finish; the slow start
+/// window begins once the server actually starts receiving traffic.
+fn bring_into_rotation(
+    load_balancer_tx: &Sender<LoadBalancerAPI>,
+    origin_id: &str,
+    origin_spec: &OriginSpecification,
+    server: &Server,
+    health: &mut ServerHealth,
+    now: Instant,
+) {
+    health.ejected_until = None;
+    health.consecutive_failures = 0;
+    match &origin_spec.slow_start {
+        Some(slow_start) if slow_start.pre_connect > 0 => {
+            health.warming_up = true;
+            tokio::spawn(warm_up_server(
+                load_balancer_tx.clone(),
+                String::from(origin_id),
+                server.clone(),
+                slow_start.clone(),
+            ));
+        }
+        _ => {
+            health.warming_up = false;
+            health.in_rotation_since = Some(now);
+        }
+    }
+}
+
+fn return_recovered_servers(
+    load_balancer_tx: &Sender<LoadBalancerAPI>,
+    origin_id: &str,
+    origin_state: &mut OriginState,
+    now: Instant,
+) {
+    for server in &origin_state.origin_spec.servers {
+        if let Some(health) = origin_state.server_health.get_mut(&server.server_key()) {
+            if health.ejected_until.is_some_and(|until| now >= until) {
+                debug!(
+                    "Server {} of Origin (Origin ID: {}) is returning to rotation",
+                    server.server_key(),
+                    origin_id
+                );
+                bring_into_rotation(
+                    load_balancer_tx,
+                    origin_id,
+                    &origin_state.origin_spec,
+                    server,
+                    health,
+                    now,
+                );
+            }
+        }
+    }
+}
+
+fn select_server(
+    origin_state: &OriginState,
+    preferred_server: Option<String>,
+    excluded_server: Option<String>,
+) -> Option<Server> {
+    let now = Instant::now();
+    if let Some(preferred_server) = preferred_server {
+        let is_healthy = origin_state
+            .server_health
+            .get(&preferred_server)
+            .is_none_or(|health| health.is_healthy(now));
+        let server = origin_state
+            .origin_spec
+            .servers
+            .iter()
+            .find(|server| server.server_key() == preferred_server);
+        match server {
+            Some(server) if is_healthy => return Some(server.clone()),
+            _ => trace!(
+                "Preferred server {} is unavailable, falling back to regular selection",
+                preferred_server
+            ),
+        }
+    }
+    let min_healthy_percentage = origin_state
+        .origin_spec
+        .failover
+        .as_ref()
+        .map_or(0, |failover| failover.min_healthy_percentage);
+    let candidates = candidate_servers(
+        &origin_state.origin_spec.servers,
+        &origin_state.server_health,
+        min_healthy_percentage,
+        now,
+    );
+    let weighted_candidates: Vec<(&Server, f64)> = candidates
+        .into_iter()
+        .filter(|server| excluded_server != Some(server.server_key()))
+        .map(|server| {
+            let weight = effective_weight(
+                origin_state.server_health.get(&server.server_key()),
+                origin_state.origin_spec.slow_start.as_ref(),
+                now,
+            );
+            (server, weight)
+        })
+        .collect();
+    pick_weighted(&weighted_candidates)
+}
+
+fn record_latency_sample(origin_state: &mut OriginState, latency: Duration) {
+    if origin_state.latency_samples.len() >= MAX_LATENCY_SAMPLES {
+        origin_state.latency_samples.pop_front();
+    }
+    origin_state.latency_samples.push_back(latency);
+    origin_state.unsorted_latency_samples += 1;
+    // Sorting on every sample would cost the actor a sort per hedged request
+    if origin_state.unsorted_latency_samples >= LATENCY_SORT_INTERVAL
+        || origin_state.latency_samples.len() <= MIN_LATENCY_SAMPLES
+    {
+        origin_state.sorted_latency_samples.clear();
+        origin_state
+            .sorted_latency_samples
+            .extend(origin_state.latency_samples.iter().copied());
+        origin_state.sorted_latency_samples.sort_unstable();
+        origin_state.unsorted_latency_samples = 0;
+    }
+}
+
+fn report_server_outcome(
+    origin_id: &str,
+    origin_state: &mut OriginState,
+    server_key: String,
+    success: bool,
+    latency: Duration,
+) {
+    if success {
+        record_latency_sample(origin_state, latency);
+        origin_state.retry_budget.record_success(Instant::now());
+    }
+    let failover = match &origin_state.origin_spec.failover {
+        None => return,
+        Some(failover) => failover,
+    };
+    let health = origin_state
+        .server_health
+        .entry(server_key.clone())
+        .or_default();
+    if success {
+        health.consecutive_failures = 0;
+        return;
+    }
+    health.consecutive_failures += 1;
+    if health.consecutive_failures >= failover.max_consecutive_failures {
+        health.consecutive_failures = 0;
+        health.ejected_until =
+            Some(Instant::now() + Duration::from_millis(failover.ejection_duration));
+        info!(
+            "Ejected server {} of Origin (Origin ID: {}) for {} ms",
+            server_key, origin_id, failover.ejection_duration
+        );
+    }
+}
+
+fn complete_warm_up(
+    origin_id: &str,
+    origin_state: &mut OriginState,
+    server_key: String,
+    success: bool,
+) {
+    if let Some(health) = origin_state.server_health.get_mut(&server_key) {
+        let now = Instant::now();
+        health.warming_up = false;
+        if success {
+            health.in_rotation_since = Some(now);
+            info!(
+                "Server {} of Origin (Origin ID: {}) is warmed up and put in rotation",
+                server_key, origin_id
+            );
+        } else {
+            health.ejected_until = Some(now + WARM_UP_RETRY_INTERVAL);
+            info!(
+                "Warm-up of server {} of Origin (Origin ID: {}) failed, retrying in {} ms",
+                server_key,
+                origin_id,
+                WARM_UP_RETRY_INTERVAL.as_millis()
+            );
+        }
+    }
+}
+
+fn update_origin_specification(
+    load_balancer_tx: &Sender<LoadBalancerAPI>,
+    origin_states: &mut HashMap<String, OriginState>,
+    origin_id: String,
+    origin_spec: OriginSpecification,
+) {
+    match origin_states.get_mut(&origin_id) {
+        None => {
+            origin_states.insert(origin_id.clone(), OriginState::new(origin_spec));
+        }
+        Some(origin_state) => {
+            let now = Instant::now();
+            let server_keys: Vec<String> = origin_spec
+                .servers
+                .iter()
+                .map(|server| server.server_key())
+                .collect();
+            let known_server_keys: Vec<String> = origin_state
+                .origin_spec
+                .servers
+                .iter()
+                .map(|server| server.server_key())
+                .collect();
+            origin_state
+                .server_health
+                .retain(|server_key, _| server_keys.contains(server_key));
+            for server in &origin_spec.servers {
+                if !known_server_keys.contains(&server.server_key()) {
+                    debug!(
+                        "Server {} was added to Origin (Origin ID: {})",
+                        server.server_key(),
+                        origin_id
+                    );
+                    let mut health = ServerHealth::default();
+                    bring_into_rotation(
+                        load_balancer_tx,
+                        &origin_id,
+                        &origin_spec,
+                        server,
+                        &mut health,
+                        now,
+                    );
+                    origin_state
+                        .server_health
+                        .insert(server.server_key(), health);
+                }
+            }
+            origin_state.concurrency_limiter = match (
+                origin_spec.concurrency_limit.as_ref(),
+                origin_state.concurrency_limiter.take(),
+            ) {
+                (None, _) => None,
+                (Some(config), None) => Some(Arc::new(ConcurrencyLimiter::new(config))),
+                (Some(config), Some(concurrency_limiter)) => {
+                    concurrency_limiter.reconfigure(config);
+                    Some(concurrency_limiter)
+                }
+            };
+            origin_state.origin_spec = origin_spec;
+        }
+    }
+    debug!(
+        "Origin specification updated in load balancer for Origin (Origin ID: {})",
+        origin_id
+    );
+}
+
+pub(crate) async fn deploy_load_balancer(
+    mut receiver: Receiver<LoadBalancerAPI>,
+    load_balancer_tx: Sender<LoadBalancerAPI>,
+) {
+    let mut origin_states = HashMap::<String, OriginState>::new();
+    while let Some(api_call) = receiver.recv().await {
+        match api_call {
+            LoadBalancerAPI::SelectServer {
+                origin_id,
+                preferred_server,
+                excluded_server,
+                responder,
+            } => {
+                let server = match origin_states.get_mut(&origin_id) {
+                    None => {
+                        debug!(
+                            "No load balancing state found for Origin (Origin ID: {})",
+                            origin_id
+                        );
+                        None
+                    }
+                    Some(origin_state) => {
+                        return_recovered_servers(
+                            &load_balancer_tx,
+                            &origin_id,
+                            origin_state,
+                            Instant::now(),
+                        );
+                        select_server(origin_state, preferred_server, excluded_server)
+                    }
+                };
+                match responder.send(server) {
+                    Ok(_) => {
+                        trace!(
+                            "Load balancer responded successfully for Origin (Origin ID: {})",
+                            origin_id
+                        )
+                    }
+                    Err(_) => {
+                        trace!(
+                            "Load balancer failed to respond for Origin (Origin ID: {})",
+                            origin_id
+                        )
+                    }
+                }
+            }
+            LoadBalancerAPI::ReportServerOutcome {
+                origin_id,
+                server_key,
+                success,
+                latency,
+            } => {
+                if let Some(origin_state) = origin_states.get_mut(&origin_id) {
+                    report_server_outcome(&origin_id, origin_state, server_key, success, latency);
+                }
+            }
+            LoadBalancerAPI::RecordCancelledAttempt { origin_id, elapsed } => {
+                if let Some(origin_state) = origin_states.get_mut(&origin_id) {
+                    record_latency_sample(origin_state, elapsed);
+                }
+            }
+            LoadBalancerAPI::GetLatencyPercentile {
+                origin_id,
+                percentile,
+                responder,
+            } => {
+                let latency = origin_states.get(&origin_id).and_then(|origin_state| {
+                    latency_percentile(&origin_state.sorted_latency_samples, percentile)
+                });
+                if responder.send(latency).is_err() {
+                    trace!(
+                        "Load balancer failed to respond with latency percentile for Origin (Origin ID: {})",
+                        origin_id
+                    )
+                }
+            }
+            LoadBalancerAPI::AcquireRetryBudget {
+                origin_id,
+                budget_percentage,
+                responder,
+            } => {
+                let acquired = origin_states
+                    .get_mut(&origin_id)
+                    .is_some_and(|origin_state| {
+                        origin_state
+                            .retry_budget
+                            .try_acquire(budget_percentage, Instant::now())
+                    });
+                if responder.send(acquired).is_err() {
+                    trace!(
+                        "Load balancer failed to respond with retry budget for Origin (Origin ID: {})",
+                        origin_id
+                    )
+                }
+            }
+            LoadBalancerAPI::CompleteWarmUp {
+                origin_id,
+                server_key,
+                success,
+            } => {
+                if let Some(origin_state) = origin_states.get_mut(&origin_id) {
+                    complete_warm_up(&origin_id, origin_state, server_key, success);
+                }
+            }
+            LoadBalancerAPI::GetConcurrencyLimiter {
+                origin_id,
+                responder,
+            } => {
+                let concurrency_limiter = origin_states
+                    .get(&origin_id)
+                    .and_then(|origin_state| origin_state.concurrency_limiter.clone());
+                if responder.send(concurrency_limiter).is_err() {
+                    trace!(
+                        "Load balancer failed to respond with concurrency limiter for Origin (Origin ID: {})",
+                        origin_id
+                    )
+                }
+            }
+            LoadBalancerAPI::GetConcurrencyStatus { responder } => {
+                let mut concurrency_statuses: Vec<ConcurrencyStatus> = origin_states
+                    .iter()
+                    .filter_map(|(origin_id, origin_state)| {
+                        origin_state
+                            .concurrency_limiter
+                            .as_ref()
+                            .map(|concurrency_limiter| concurrency_limiter.status(origin_id))
+                    })
+                    .collect();
+                concurrency_statuses.sort_by(|a, b| a.origin_id.cmp(&b.origin_id));
+                if responder.send(concurrency_statuses).is_err() {
+                    trace!("Load balancer failed to respond with concurrency status")
+                }
+            }
+            LoadBalancerAPI::UpdateOriginSpecification {
+                origin_id,
+                origin_spec,
+            } => update_origin_specification(
+                &load_balancer_tx,
+                &mut origin_states,
+                origin_id,
+                *origin_spec,
+            ),
+            LoadBalancerAPI::RemoveOriginSpecification { origin_id } => {
+                // Warm-ups still running for the Origin complete into nothing
+                if origin_states.remove(&origin_id).is_some() {
+                    debug!(
+                        "Load balancer dropped the state of removed Origin (Origin ID: {})",
+                        origin_id
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use crate::configuration_reader::origin_def_reader::{
+        OriginSpecification, Server, SlowStartConfig,
+    };
+
+    use super::{
+        candidate_servers, effective_weight, latency_percentile, record_latency_sample,
+        OriginState, RetryBudget, ServerHealth, LATENCY_SORT_INTERVAL, MIN_LATENCY_SAMPLES,
+        RETRY_BUDGET_WINDOW,
+    };
+
+    fn server(hostname: &str, priority: u32) -> Server {
+        Server {
+            hostname: String::from(hostname),
+            port: 8000,
+            secure: false,
+            verify_cert: false,
+            priority: Some(priority),
+        }
+    }
+
+    fn ejected() -> ServerHealth {
+        ServerHealth {
+            ejected_until: Some(Instant::now() + Duration::from_secs(60)),
+            ..Default::default()
+        }
+    }
+
+    fn hostnames(servers: Vec<&Server>) -> Vec<String> {
+        let mut hostnames: Vec<String> = servers
+            .into_iter()
+            .map(|server| server.hostname.clone())
+            .collect();
+        hostnames.sort();
+        hostnames
+    }
+
+    #[test]
+    fn test_only_primary_tier_when_healthy() {
+        let servers = vec![server("a", 0), server("b", 0), server("backup", 1)];
+        let candidates = candidate_servers(&servers, &HashMap::new(), 0, Instant::now());
+        assert_eq!(vec!["a", "b"], hostnames(candidates));
+    }
+
+    #[test]
+    fn test_backup_tier_when_all_primaries_ejected() {
+        let servers = vec![server("a", 0), server("b", 0), server("backup", 1)];
+        let mut server_health = HashMap::new();
+        server_health.insert(servers[0].server_key(), ejected());
+        server_health.insert(servers[1].server_key(), ejected());
+        let candidates = candidate_servers(&servers, &server_health, 0, Instant::now());
+        assert_eq!(vec!["backup"], hostnames(candidates));
+    }
+
+    #[test]
+    fn test_spill_over_below_min_healthy_percentage() {
+        let servers = vec![server("a", 0), server("b", 0), server("backup", 1)];
+        let mut server_health = HashMap::new();
+        server_health.insert(servers[0].server_key(), ejected());
+        let candidates = candidate_servers(&servers, &server_health, 75, Instant::now());
+        assert_eq!(vec!["b", "backup"], hostnames(candidates));
+        let candidates = candidate_servers(&servers, &server_health, 50, Instant::now());
+        assert_eq!(vec!["b"], hostnames(candidates));
+    }
+
+    #[test]
+    fn test_ejection_expires() {
+        let servers = vec![server("a", 0), server("backup", 1)];
+        let mut server_health = HashMap::new();
+        server_health.insert(
+            servers[0].server_key(),
+            ServerHealth {
+                ejected_until: Some(Instant::now()),
+                ..Default::default()
+            },
+        );
+        let candidates = candidate_servers(
+            &servers,
+            &server_health,
+            0,
+            Instant::now() + Duration::from_millis(1),
+        );
+        assert_eq!(vec!["a"], hostnames(candidates));
+    }
+
+    #[test]
+    fn test_no_candidates_when_everything_ejected() {
+        let servers = vec![server("a", 0)];
+        let mut server_health = HashMap::new();
+        server_health.insert(servers[0].server_key(), ejected());
+        assert!(candidate_servers(&servers, &server_health, 0, Instant::now()).is_empty());
+    }
+
+    #[test]
+    fn test_warming_server_is_not_a_candidate() {
+        let servers = vec![server("a", 0), server("b", 0)];
+        let mut server_health = HashMap::new();
+        server_health.insert(
+            servers[0].server_key(),
+            ServerHealth {
+                warming_up: true,
+                ..Default::default()
+            },
+        );
+        let candidates = candidate_servers(&servers, &server_health, 0, Instant::now());
+        assert_eq!(vec!["b"], hostnames(candidates));
+    }
+
+    #[test]
+    fn test_effective_weight_ramps_linearly() {
+        let slow_start = SlowStartConfig {
+            window: 10000,
+            initial_weight_percentage: 10,
+            pre_connect: 0,
+            warm_up_path: None,
+        };
+        let in_rotation_since = Instant::now();
+        let health = ServerHealth {
+            in_rotation_since: Some(in_rotation_since),
+            ..Default::default()
+        };
+        let weight_at = |elapsed_millis: u64| {
+            effective_weight(
+                Some(&health),
+                Some(&slow_start),
+                in_rotation_since + Duration::from_millis(elapsed_millis),
+            )
+        };
+        assert!((weight_at(0) - 0.1).abs() < 1e-9);
+        assert!((weight_at(5000) - 0.55).abs() < 1e-9);
+        assert!((weight_at(10000) - 1.0).abs() < 1e-9);
+        assert!((weight_at(60000) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_effective_weight_without_slow_start() {
+        let health = ServerHealth {
+            in_rotation_since: Some(Instant::now()),
+            ..Default::default()
+        };
+        assert_eq!(1.0, effective_weight(Some(&health), None, Instant::now()));
+        assert_eq!(1.0, effective_weight(None, None, Instant::now()));
+    }
+
+    #[test]
+    fn test_latency_percentile() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(
+            Some(Duration::from_millis(95)),
+            latency_percentile(&samples, 95.0)
+        );
+        assert_eq!(
+            Some(Duration::from_millis(100)),
+            latency_percentile(&samples, 100.0)
+        );
+        assert_eq!(
+            Some(Duration::from_millis(1)),
+            latency_percentile(&samples, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_latency_percentile_needs_enough_samples() {
+        let samples = vec![Duration::from_millis(5); 3];
+        assert_eq!(None, latency_percentile(&samples, 95.0));
+    }
+
+    #[test]
+    fn test_sorted_latency_samples_are_refreshed_periodically() {
+        let origin_spec: OriginSpecification = serde_json::from_str(
+            r#"{
+                "rate_limiter": {"algorithm": "TokenBucket", "time_unit": "Second", "req_per_time_unit": 10},
+                "servers": []
+            }"#,
+        )
+        .unwrap();
+        let mut origin_state = OriginState::new(origin_spec);
+        for _ in 0..MIN_LATENCY_SAMPLES {
+            record_latency_sample(&mut origin_state, Duration::from_millis(10));
+        }
+        // The first samples are sorted right away so hedging can start
+        assert_eq!(
+            Some(Duration::from_millis(10)),
+            latency_percentile(&origin_state.sorted_latency_samples, 50.0)
+        );
+        for _ in 1..LATENCY_SORT_INTERVAL {
+            record_latency_sample(&mut origin_state, Duration::from_millis(30));
+        }
+        assert_eq!(
+            Some(Duration::from_millis(10)),
+            latency_percentile(&origin_state.sorted_latency_samples, 50.0)
+        );
+        record_latency_sample(&mut origin_state, Duration::from_millis(30));
+        assert_eq!(
+            Some(Duration::from_millis(30)),
+            latency_percentile(&origin_state.sorted_latency_samples, 50.0)
+        );
+    }
+
+    #[test]
+    fn test_retry_budget_follows_successful_requests() {
+        let now = Instant::now();
+        let mut retry_budget = RetryBudget::new(now);
+        assert!(!retry_budget.try_acquire(10.0, now));
+        for _ in 0..20 {
+            retry_budget.record_success(now);
+        }
+        assert!(retry_budget.try_acquire(10.0, now));
+        assert!(retry_budget.try_acquire(10.0, now));
+        assert!(!retry_budget.try_acquire(10.0, now));
+        assert!(!retry_budget.try_acquire(10.0, now + RETRY_BUDGET_WINDOW));
+    }
+}
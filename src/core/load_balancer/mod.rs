@@ -0,0 +1,4 @@
+pub(crate) mod adaptive_concurrency;
+pub(crate) mod concurrency_limiter;
+pub(crate) mod load_balancer_api;
+pub(crate) mod load_balancing_engine;
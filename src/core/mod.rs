@@ -1,6 +1,17 @@
+mod auth;
 pub(crate) mod config;
+mod connection_info;
+mod consumer_mgt;
+mod cors;
+pub(crate) mod ip_access;
+pub(crate) mod load_balancer;
+mod origin_client;
 pub(crate) mod rate_limiter;
+mod request_body;
+mod request_hedging;
 pub(crate) mod reverse_proxy;
 mod router;
+mod session_affinity;
 mod standard_response;
 pub(crate) mod tls_reverse_proxy;
+mod traffic_mirror;
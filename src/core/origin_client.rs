@@ -0,0 +1,12 @@
+use std::sync::OnceLock;
+
+use hyper::client::HttpConnector;
+use hyper::Client;
+
+static ORIGIN_CLIENT: OnceLock<Client<HttpConnector>> = OnceLock::new();
+
+/// Client shared by every request sent to an Origin, so that connections are
+/// pooled across requests instead of being opened for each one.
+pub(crate) fn origin_client() -> Client<HttpConnector> {
+    ORIGIN_CLIENT.get_or_init(Client::new).clone()
+}
@@ -0,0 +1,517 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use governor::Quota;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::configuration_reader::cluster_config_reader::{ClusterConfig, ClusterMode};
+use crate::core::rate_limiter::rate_limiter_api::{RateLimitRejection, RateLimitStatus};
+use crate::core::rate_limiter::redis_client::{encode_command, RedisClient, RedisError, RespValue};
+
+const DEFAULT_KEY_PREFIX: &str = "gateman:";
+const DEFAULT_TIMEOUT: u64 = 50;
+const DEFAULT_SYNC_INTERVAL: u64 = 1000;
+/// Strict checks skip the backend for this long after it failed.
+const UNAVAILABLE_BACKOFF: Duration = Duration::from_secs(5);
+
+/// GCRA over a theoretical arrival time in microseconds of the backend's clock, so
+/// that instances with skewed clocks agree. Returns `{1, remaining, backlog}` when
+/// the request is admitted and `{0, retry_after}` otherwise.
+const GCRA_SCRIPT: &str = r"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000000 + tonumber(time[2])
+local emission_interval = tonumber(ARGV[1])
+local burst_tolerance = tonumber(ARGV[2])
+local tat = tonumber(redis.call('GET', KEYS[1]) or now)
+if tat < now then
+    tat = now
+end
+local backlog = tat + emission_interval - now
+if backlog > burst_tolerance then
+    return {0, backlog - burst_tolerance}
+end
+redis.call('SET', KEYS[1], now + backlog, 'PX', math.ceil(backlog / 1000) + 1)
+return {1, math.floor((burst_tolerance - backlog) / emission_interval), backlog}
+";
+
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+struct SyncedWindow {
+    window_index: u64,
+    total: i64,
+}
+
+/// Requests a local bucket admitted, for exchanging with the other instances in
+/// `LocalApproximation` mode.
+pub(crate) struct ClusterCounter {
+    unsynced: AtomicU32,
+    synced: Mutex<SyncedWindow>,
+}
+
+impl ClusterCounter {
+    pub(crate) fn new() -> Self {
+        ClusterCounter {
+            unsynced: AtomicU32::new(0),
+            synced: Mutex::new(SyncedWindow {
+                window_index: 0,
+                total: 0,
+            }),
+        }
+    }
+
+    pub(crate) fn record_admitted(&self) {
+        self.unsynced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Takes in the cluster-wide total of a window after this instance added
+    /// `delta` to it and returns how many requests the other instances admitted
+    /// since the previous sync.
+    fn remote_admitted(&self, window_index: u64, delta: u32, total: i64) -> u32 {
+        let mut synced = self
+            .synced
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if synced.window_index != window_index {
+            synced.window_index = window_index;
+            synced.total = 0;
+        }
+        let remote_admitted = total - synced.total - i64::from(delta);
+        synced.total = total;
+        remote_admitted.clamp(0, i64::from(u32::MAX)) as u32
+    }
+}
+
+/// Backend sharing rate limiter state between Gateman instances.
+pub(crate) struct ClusterBackend {
+    client: RedisClient,
+    mode: ClusterMode,
+    key_prefix: String,
+    timeout: Duration,
+    sync_interval: Duration,
+    epoch: Instant,
+    /// Milliseconds since `epoch`.
+    unavailable_until: AtomicU64,
+    available: AtomicBool,
+}
+
+impl ClusterBackend {
+    pub(crate) fn new(cluster_config: &ClusterConfig) -> Self {
+        ClusterBackend {
+            client: RedisClient::new(&cluster_config.address),
+            mode: cluster_config.mode.clone(),
+            key_prefix: cluster_config
+                .key_prefix
+                .clone()
+                .unwrap_or_else(|| String::from(DEFAULT_KEY_PREFIX)),
+            timeout: Duration::from_millis(cluster_config.timeout.unwrap_or(DEFAULT_TIMEOUT)),
+            sync_interval: Duration::from_millis(
+                cluster_config
+                    .sync_interval
+                    .unwrap_or(DEFAULT_SYNC_INTERVAL)
+                    .max(1),
+            ),
+            epoch: Instant::now(),
+            unavailable_until: AtomicU64::new(0),
+            available: AtomicBool::new(true),
+        }
+    }
+
+    pub(crate) fn mode(&self) -> &ClusterMode {
+        &self.mode
+    }
+
+    pub(crate) fn sync_interval(&self) -> Duration {
+        self.sync_interval
+    }
+
+    /// Key of a bucket in the backend. Client keys may be credentials, so only
+    /// their digest leaves the instance.
+    pub(crate) fn bucket_key(&self, limiter_id: &str, client_key: Option<&str>) -> String {
+        match client_key {
+            None => format!("{}{}", self.key_prefix, limiter_id),
+            Some(client_key) => format!(
+                "{}{}:{}",
+                self.key_prefix,
+                limiter_id,
+                &hex::encode(Sha256::digest(client_key.as_bytes()))[..32]
+            ),
+        }
+    }
+
+    fn elapsed_millis(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+
+    /// Whether strict checks should be sent to the backend.
+    pub(crate) fn is_available(&self) -> bool {
+        self.elapsed_millis() >= self.unavailable_until.load(Ordering::Relaxed)
+    }
+
+    fn record_failure(&self, error: &RedisError) {
+        self.unavailable_until.store(
+            self.elapsed_millis() + UNAVAILABLE_BACKOFF.as_millis() as u64,
+            Ordering::Relaxed,
+        );
+        if self.available.swap(false, Ordering::Relaxed) {
+            warn!(
+                "Cluster rate limiting backend is unavailable, enforcing limits locally - {}",
+                error.message
+            );
+        }
+    }
+
+    fn record_success(&self) {
+        if !self.available.swap(true, Ordering::Relaxed) {
+            info!("Cluster rate limiting backend is available again");
+        }
+    }
+
+    async fn query(
+        &self,
+        commands: &[Vec<u8>],
+        query_timeout: Duration,
+    ) -> Result<Vec<RespValue>, RedisError> {
+        let result = self.client.query(commands, query_timeout).await;
+        match &result {
+            Ok(_) => self.record_success(),
+            Err(error) => self.record_failure(error),
+        }
+        result
+    }
+
+    /// Checks a request against the cluster-wide bucket with GCRA. Whatever the
+    /// configured algorithm, the bucket admits `quota` with its burst size.
+    pub(crate) async fn check(
+        &self,
+        bucket_key: &str,
+        quota: &Quota,
+    ) -> Result<Result<RateLimitStatus, RateLimitRejection>, RedisError> {
+        let emission_interval = (quota.replenish_interval().as_micros() as u64).max(1);
+        let burst_size = quota.burst_size().get();
+        let burst_tolerance = emission_interval * u64::from(burst_size);
+        let command = encode_command(&[
+            b"EVAL",
+            GCRA_SCRIPT.as_bytes(),
+            b"1",
+            format!("{}:gcra", bucket_key).as_bytes(),
+            emission_interval.to_string().as_bytes(),
+            burst_tolerance.to_string().as_bytes(),
+        ]);
+        let reply = self.query(&[command], self.timeout).await?;
+        let values = match reply.into_iter().next() {
+            Some(RespValue::Array(Some(values))) => values,
+            Some(RespValue::Error(message)) => return Err(RedisError { message }),
+            _ => {
+                return Err(RedisError {
+                    message: String::from("Unexpected reply to GCRA script"),
+                })
+            }
+        };
+        let value = |index: usize| -> Result<u64, RedisError> {
+            values
+                .get(index)
+                .ok_or_else(|| RedisError {
+                    message: String::from("Incomplete reply to GCRA script"),
+                })?
+                .as_integer()
+                .map(|value| value.max(0) as u64)
+        };
+        if value(0)? == 1 {
+            Ok(Ok(RateLimitStatus {
+                limit: burst_size,
+                remaining: value(1)? as u32,
+                reset_after: Duration::from_micros(value(2)?),
+                header_style: None,
+            }))
+        } else {
+            Ok(Err(RateLimitRejection::LimitExceeded {
+                limit: burst_size,
+                retry_after: Duration::from_micros(value(1)?),
+                header_style: None,
+            }))
+        }
+    }
+
+    /// Adds the requests admitted since the previous sync to the cluster-wide
+    /// count of each bucket's current window, all in one pipeline. Returns, per
+    /// bucket, how many requests the other instances admitted meanwhile. Local
+    /// admissions are dropped if the sync fails.
+    pub(crate) async fn sync_counters(
+        &self,
+        counters: &[(String, Duration, &ClusterCounter)],
+    ) -> Result<Vec<u32>, RedisError> {
+        let now = unix_time().as_millis() as u64;
+        let mut commands = Vec::with_capacity(counters.len() * 2);
+        let mut deltas = Vec::with_capacity(counters.len());
+        for (bucket_key, window, counter) in counters {
+            let window = (window.as_millis() as u64).max(1);
+            let window_index = now / window;
+            let delta = counter.unsynced.swap(0, Ordering::Relaxed);
+            let key = format!("{}:{}:{}", bucket_key, window, window_index);
+            commands.push(encode_command(&[
+                b"INCRBY",
+                key.as_bytes(),
+                delta.to_string().as_bytes(),
+            ]));
+            commands.push(encode_command(&[
+                b"PEXPIRE",
+                key.as_bytes(),
+                (window * 2).to_string().as_bytes(),
+            ]));
+            deltas.push((window_index, delta));
+        }
+        let replies = self.query(&commands, self.sync_interval).await?;
+        counters
+            .iter()
+            .zip(deltas)
+            .zip(replies.chunks(2))
+            .map(|(((_, _, counter), (window_index, delta)), replies)| {
+                let total = replies[0].as_integer()?;
+                Ok(counter.remote_admitted(window_index, delta, total))
+            })
+            .collect()
+    }
+}
+
+/// In-process stand-in for a Redis server, implementing just the commands the
+/// cluster backend sends. The GCRA script is emulated rather than interpreted.
+#[cfg(test)]
+pub(crate) mod stand_in {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncWriteExt, BufStream};
+    use tokio::net::TcpListener;
+
+    use crate::core::rate_limiter::redis_client::{read_reply, RespValue};
+
+    use super::unix_time;
+
+    fn encode_reply(reply: &RespValue) -> Vec<u8> {
+        match reply {
+            RespValue::Simple(value) => format!("+{}\r\n", value).into_bytes(),
+            RespValue::Error(value) => format!("-{}\r\n", value).into_bytes(),
+            RespValue::Integer(value) => format!(":{}\r\n", value).into_bytes(),
+            RespValue::Bulk(None) => b"$-1\r\n".to_vec(),
+            RespValue::Bulk(Some(value)) => {
+                let mut reply = format!("${}\r\n", value.len()).into_bytes();
+                reply.extend_from_slice(value);
+                reply.extend_from_slice(b"\r\n");
+                reply
+            }
+            RespValue::Array(None) => b"*-1\r\n".to_vec(),
+            RespValue::Array(Some(values)) => {
+                let mut reply = format!("*{}\r\n", values.len()).into_bytes();
+                for value in values {
+                    reply.extend(encode_reply(value));
+                }
+                reply
+            }
+        }
+    }
+
+    fn gcra(
+        values: &mut HashMap<String, i64>,
+        key: &str,
+        interval: i64,
+        tolerance: i64,
+    ) -> RespValue {
+        let now = unix_time().as_micros() as i64;
+        let tat = values.get(key).copied().unwrap_or(now).max(now);
+        let backlog = tat + interval - now;
+        if backlog > tolerance {
+            return RespValue::Array(Some(vec![
+                RespValue::Integer(0),
+                RespValue::Integer(backlog - tolerance),
+            ]));
+        }
+        values.insert(String::from(key), now + backlog);
+        RespValue::Array(Some(vec![
+            RespValue::Integer(1),
+            RespValue::Integer((tolerance - backlog) / interval),
+            RespValue::Integer(backlog),
+        ]))
+    }
+
+    fn execute(values: &Mutex<HashMap<String, i64>>, command: RespValue) -> RespValue {
+        let arguments: Vec<String> = match command {
+            RespValue::Array(Some(arguments)) => arguments
+                .into_iter()
+                .map(|argument| match argument {
+                    RespValue::Bulk(Some(argument)) => {
+                        String::from_utf8_lossy(&argument).to_string()
+                    }
+                    _ => String::new(),
+                })
+                .collect(),
+            _ => return RespValue::Error(String::from("ERR expected a command")),
+        };
+        let integer = |index: usize| arguments[index].parse::<i64>().unwrap_or(0);
+        let mut values = values.lock().unwrap();
+        match arguments[0].as_str() {
+            "PING" => RespValue::Simple(String::from("PONG")),
+            "EVAL" => gcra(&mut values, &arguments[3], integer(4), integer(5)),
+            "INCRBY" => {
+                let value = values.entry(arguments[1].clone()).or_insert(0);
+                *value += integer(2);
+                RespValue::Integer(*value)
+            }
+            "PEXPIRE" => RespValue::Integer(1),
+            command => RespValue::Error(format!("ERR unknown command {}", command)),
+        }
+    }
+
+    /// Starts the stand-in and returns its address.
+    pub(crate) async fn spawn_stand_in() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let values = Arc::new(Mutex::new(HashMap::new()));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let values = values.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufStream::new(stream);
+                    while let Ok(command) = read_reply(&mut stream).await {
+                        let reply = encode_reply(&execute(&values, command));
+                        if stream.write_all(&reply).await.is_err() || stream.flush().await.is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    /// Returns an address nothing listens on.
+    pub(crate) async fn unreachable_address() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    use governor::Quota;
+
+    use crate::configuration_reader::cluster_config_reader::{ClusterConfig, ClusterMode};
+    use crate::core::rate_limiter::rate_limiter_api::RateLimitRejection;
+
+    use super::stand_in::{spawn_stand_in, unreachable_address};
+    use super::{ClusterBackend, ClusterCounter};
+
+    fn cluster_backend(address: &str, mode: ClusterMode) -> ClusterBackend {
+        ClusterBackend::new(&ClusterConfig {
+            address: String::from(address),
+            mode,
+            key_prefix: None,
+            timeout: Some(1000),
+            sync_interval: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_instances_share_the_bucket() {
+        let address = spawn_stand_in().await;
+        let instances = [
+            cluster_backend(&address, ClusterMode::Strict),
+            cluster_backend(&address, ClusterMode::Strict),
+        ];
+        let quota = Quota::per_minute(NonZeroU32::new(4).unwrap());
+        let bucket_key = instances[0].bucket_key("origin:origin", None);
+        let mut admitted = 0;
+        for attempt in 0..10 {
+            let result = instances[attempt % 2]
+                .check(&bucket_key, &quota)
+                .await
+                .unwrap();
+            if let Ok(status) = result {
+                admitted += 1;
+                assert_eq!(4 - admitted, status.remaining);
+            }
+        }
+        assert_eq!(4, admitted);
+        match instances[0].check(&bucket_key, &quota).await.unwrap() {
+            Err(RateLimitRejection::LimitExceeded {
+                limit, retry_after, ..
+            }) => {
+                assert_eq!(4, limit);
+                assert!(
+                    retry_after > Duration::from_secs(14) && retry_after <= Duration::from_secs(15)
+                );
+            }
+            _ => panic!("Expected the cluster-wide bucket to be exhausted"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_keys_are_hashed() {
+        let backend = cluster_backend("127.0.0.1:6379", ClusterMode::Strict);
+        assert_eq!("gateman:origin:a", backend.bucket_key("origin:a", None));
+        let bucket_key = backend.bucket_key("origin:a", Some("secret-api-key"));
+        assert!(bucket_key.starts_with("gateman:origin:a:"));
+        assert!(!bucket_key.contains("secret"));
+        assert_ne!(
+            bucket_key,
+            backend.bucket_key("origin:a", Some("other-api-key"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_reports_requests_admitted_elsewhere() {
+        let address = spawn_stand_in().await;
+        let instances = [
+            cluster_backend(&address, ClusterMode::LocalApproximation),
+            cluster_backend(&address, ClusterMode::LocalApproximation),
+        ];
+        let counters = [ClusterCounter::new(), ClusterCounter::new()];
+        let window = Duration::from_secs(3600);
+        for _ in 0..3 {
+            counters[0].record_admitted();
+        }
+        counters[1].record_admitted();
+        let sync = |instance: usize| {
+            let bucket_key = instances[instance].bucket_key("origin:origin", None);
+            let counter = &counters[instance];
+            let instance = &instances[instance];
+            async move {
+                instance
+                    .sync_counters(&[(bucket_key, window, counter)])
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(vec![0], sync(0).await);
+        assert_eq!(vec![3], sync(1).await);
+        assert_eq!(vec![1], sync(0).await);
+        assert_eq!(vec![0], sync(1).await);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_backend_is_skipped_for_a_while() {
+        let backend = cluster_backend(&unreachable_address().await, ClusterMode::Strict);
+        let quota = Quota::per_minute(NonZeroU32::new(4).unwrap());
+        assert!(backend.is_available());
+        assert!(backend.check("gateman:origin", &quota).await.is_err());
+        assert!(!backend.is_available());
+        let counter = ClusterCounter::new();
+        assert!(backend
+            .sync_counters(&[(
+                String::from("gateman:origin"),
+                Duration::from_secs(60),
+                &counter
+            )])
+            .await
+            .is_err());
+    }
+}
@@ -1,2 +1,9 @@
+pub(crate) mod cluster;
+pub(crate) mod quota;
+pub(crate) mod quota_store;
+pub(crate) mod rate_limit_headers;
+pub(crate) mod rate_limit_key;
 pub(crate) mod rate_limiter_api;
 pub(crate) mod rate_limiting_engine;
+pub(crate) mod redis_client;
+pub(crate) mod shadow;
@@ -0,0 +1,393 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use log::{trace, warn};
+use serde::Serialize;
+
+use crate::configuration_reader::api_def_reader::{QuotaPeriod, QuotaPolicy};
+use crate::core::rate_limiter::rate_limit_key::OVERFLOW_KEY;
+use crate::core::rate_limiter::rate_limiter_api::{RateLimitRejection, RateLimitStatus};
+use crate::core::rate_limiter::rate_limiting_engine::DEFAULT_MAX_KEYS;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct QuotaCounter {
+    pub(crate) period: String,
+    pub(crate) used: u32,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct QuotaUsage {
+    pub(crate) quota_id: String,
+    pub(crate) key: String,
+    pub(crate) period: String,
+    pub(crate) used: u32,
+    pub(crate) limit: u32,
+}
+
+fn parse_timezone(timezone: Option<&String>) -> Tz {
+    match timezone {
+        None => Tz::UTC,
+        Some(timezone) => timezone.parse::<Tz>().unwrap_or_else(|_| {
+            warn!("Unknown quota timezone {}, falling back to UTC", timezone);
+            Tz::UTC
+        }),
+    }
+}
+
+fn start_of_day(timezone: &Tz, date: NaiveDate) -> Option<DateTime<Utc>> {
+    // Midnight does not exist on days when DST starts at midnight
+    (0..3)
+        .filter_map(|hour| date.and_hms_opt(hour, 0, 0))
+        .find_map(|local| timezone.from_local_datetime(&local).earliest())
+        .map(|start| start.with_timezone(&Utc))
+}
+
+/// Names the calendar period containing `now` in `timezone` and returns when it
+/// ends.
+fn current_period(
+    period: &QuotaPeriod,
+    timezone: &Tz,
+    now: DateTime<Utc>,
+) -> (String, DateTime<Utc>) {
+    let local_date = now.with_timezone(timezone).date_naive();
+    let (period_id, next_period_date) = match period {
+        QuotaPeriod::Day => (
+            local_date.format("%Y-%m-%d").to_string(),
+            local_date.succ_opt(),
+        ),
+        QuotaPeriod::Month => {
+            let (year, month) = if local_date.month() == 12 {
+                (local_date.year() + 1, 1)
+            } else {
+                (local_date.year(), local_date.month() + 1)
+            };
+            (
+                local_date.format("%Y-%m").to_string(),
+                NaiveDate::from_ymd_opt(year, month, 1),
+            )
+        }
+    };
+    let period_end = next_period_date
+        .and_then(|date| start_of_day(timezone, date))
+        .unwrap_or_else(|| now + ChronoDuration::days(1));
+    (period_id, period_end)
+}
+
+/// Counts requests per key over calendar periods. Counters of a past period are
+/// simply replaced once a request arrives in the next one. Keys are counted up to
+/// `max_keys`; counters of past periods are dropped to make room for new keys,
+/// which share the overflow counter while the current period has no room left.
+pub(crate) struct QuotaLimiter {
+    period: QuotaPeriod,
+    limit: u32,
+    timezone: Tz,
+    counters: HashMap<String, QuotaCounter>,
+    dirty_keys: HashSet<String>,
+    /// Set once dropping past counters could not make room for a new key, so
+    /// that clients sending new keys cannot make every check scan all counters.
+    key_space_full: bool,
+    max_keys: usize,
+}
+
+impl QuotaLimiter {
+    pub(crate) fn new(quota_policy: &QuotaPolicy, counters: HashMap<String, QuotaCounter>) -> Self {
+        QuotaLimiter {
+            period: quota_policy.period.clone(),
+            limit: quota_policy.limit,
+            timezone: parse_timezone(quota_policy.timezone.as_ref()),
+            counters,
+            dirty_keys: HashSet::new(),
+            key_space_full: false,
+            max_keys: quota_policy.max_keys.unwrap_or(DEFAULT_MAX_KEYS),
+        }
+    }
+
+    fn tracked_keys(&self) -> usize {
+        self.counters.len() - usize::from(self.counters.contains_key(OVERFLOW_KEY))
+    }
+
+    fn drop_past_counters(&mut self, period_id: &str) {
+        self.counters
+            .retain(|_, counter| counter.period == period_id);
+        if self.tracked_keys() < self.max_keys {
+            self.key_space_full = false;
+        }
+    }
+
+    /// Returns the key the request is counted against.
+    fn admit_key(&mut self, key: String, period_id: &str) -> String {
+        if self.counters.contains_key(&key) || self.tracked_keys() < self.max_keys {
+            return key;
+        }
+        if !self.key_space_full {
+            self.drop_past_counters(period_id);
+            if self.tracked_keys() < self.max_keys {
+                return key;
+            }
+            self.key_space_full = true;
+        }
+        trace!("Quota key space is full, using overflow counter");
+        String::from(OVERFLOW_KEY)
+    }
+
+    pub(crate) fn check(
+        &mut self,
+        key: String,
+        now: DateTime<Utc>,
+    ) -> Result<RateLimitStatus, RateLimitRejection> {
+        let (period_id, period_end) = current_period(&self.period, &self.timezone, now);
+        let reset_after = (period_end - now).to_std().unwrap_or_default();
+        let key = self.admit_key(key, &period_id);
+        let counter = self.counters.entry(key.clone()).or_insert(QuotaCounter {
+            period: period_id.clone(),
+            used: 0,
+        });
+        if counter.period != period_id {
+            counter.period = period_id;
+            counter.used = 0;
+        }
+        if counter.used >= self.limit {
+            return Err(RateLimitRejection::LimitExceeded {
+                limit: self.limit,
+                retry_after: reset_after,
+                header_style: None,
+            });
+        }
+        counter.used += 1;
+        let remaining = self.limit - counter.used;
+        self.dirty_keys.insert(key);
+        Ok(RateLimitStatus {
+            limit: self.limit,
+            remaining,
+            reset_after,
+            header_style: None,
+        })
+    }
+
+    /// Counters changed since the last call, for persisting them. Counters of
+    /// past periods are dropped along the way.
+    pub(crate) fn take_dirty_counters(
+        &mut self,
+        now: DateTime<Utc>,
+    ) -> Vec<(String, QuotaCounter)> {
+        let (period_id, _) = current_period(&self.period, &self.timezone, now);
+        self.drop_past_counters(&period_id);
+        let counters = &self.counters;
+        self.dirty_keys
+            .drain()
+            .filter_map(|key| {
+                counters
+                    .get(&key)
+                    .map(|counter| (key.clone(), counter.clone()))
+            })
+            .collect()
+    }
+
+    /// Counters of the current period, for compacting the quota store.
+    pub(crate) fn current_counters(&mut self, now: DateTime<Utc>) -> Vec<(String, QuotaCounter)> {
+        let (period_id, _) = current_period(&self.period, &self.timezone, now);
+        self.drop_past_counters(&period_id);
+        self.counters
+            .iter()
+            .map(|(key, counter)| (key.clone(), counter.clone()))
+            .collect()
+    }
+
+    pub(crate) fn period_end(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        current_period(&self.period, &self.timezone, now).1
+    }
+
+    pub(crate) fn take_counters(&mut self) -> HashMap<String, QuotaCounter> {
+        self.dirty_keys.clear();
+        std::mem::take(&mut self.counters)
+    }
+
+    /// Applies a changed policy while keeping the counters. Counters recorded
+    /// under a different period no longer match and start over.
+    pub(crate) fn reconfigure(&mut self, quota_policy: &QuotaPolicy) {
+        self.period = quota_policy.period.clone();
+        self.limit = quota_policy.limit;
+        self.timezone = parse_timezone(quota_policy.timezone.as_ref());
+        self.max_keys = quota_policy.max_keys.unwrap_or(DEFAULT_MAX_KEYS);
+        self.key_space_full = false;
+    }
+
+    pub(crate) fn usage(&self, quota_id: &str, now: DateTime<Utc>) -> Vec<QuotaUsage> {
+        let (period_id, _) = current_period(&self.period, &self.timezone, now);
+        self.counters
+            .iter()
+            .filter(|(_, counter)| counter.period == period_id)
+            .map(|(key, counter)| QuotaUsage {
+                quota_id: String::from(quota_id),
+                key: key.clone(),
+                period: counter.period.clone(),
+                used: counter.used,
+                limit: self.limit,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use chrono::{DateTime, TimeZone, Utc};
+    use chrono_tz::Tz;
+
+    use crate::configuration_reader::api_def_reader::{QuotaPeriod, QuotaPolicy};
+
+    use crate::core::rate_limiter::rate_limit_key::OVERFLOW_KEY;
+
+    use super::{current_period, QuotaCounter, QuotaLimiter};
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn quota_limiter(period: QuotaPeriod, limit: u32, timezone: &str) -> QuotaLimiter {
+        QuotaLimiter::new(
+            &QuotaPolicy {
+                name: None,
+                period,
+                limit,
+                key: None,
+                max_keys: None,
+                timezone: Some(String::from(timezone)),
+            },
+            HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn test_day_follows_timezone() {
+        let timezone: Tz = "Asia/Kolkata".parse().unwrap();
+        // 20:00 UTC is already the next day in India (UTC+05:30)
+        let (period_id, period_end) =
+            current_period(&QuotaPeriod::Day, &timezone, utc(2026, 3, 14, 20, 0));
+        assert_eq!("2026-03-15", period_id);
+        assert_eq!(utc(2026, 3, 15, 18, 30), period_end);
+    }
+
+    #[test]
+    fn test_month_rolls_over_year_end() {
+        let (period_id, period_end) =
+            current_period(&QuotaPeriod::Month, &Tz::UTC, utc(2026, 12, 31, 23, 59));
+        assert_eq!("2026-12", period_id);
+        assert_eq!(utc(2027, 1, 1, 0, 0), period_end);
+    }
+
+    #[test]
+    fn test_day_on_daylight_saving_change() {
+        let timezone: Tz = "Europe/Berlin".parse().unwrap();
+        // Clocks move forward on 2026-03-29, which is a 23 hour day
+        let (_, period_end) = current_period(&QuotaPeriod::Day, &timezone, utc(2026, 3, 29, 12, 0));
+        assert_eq!(utc(2026, 3, 29, 22, 0), period_end);
+    }
+
+    #[test]
+    fn test_quota_resets_on_calendar_boundary() {
+        let mut limiter = quota_limiter(QuotaPeriod::Day, 2, "UTC");
+        let evening = utc(2026, 5, 1, 23, 0);
+        assert_eq!(
+            1,
+            limiter.check(String::from("a"), evening).unwrap().remaining
+        );
+        assert_eq!(
+            0,
+            limiter.check(String::from("a"), evening).unwrap().remaining
+        );
+        assert!(limiter.check(String::from("a"), evening).is_err());
+        assert!(limiter.check(String::from("b"), evening).is_ok());
+        let status = limiter
+            .check(String::from("a"), utc(2026, 5, 2, 0, 0))
+            .unwrap();
+        assert_eq!(1, status.remaining);
+        assert_eq!(Duration::from_secs(24 * 3600), status.reset_after);
+    }
+
+    #[test]
+    fn test_restored_counters_of_current_period_count() {
+        let mut counters = HashMap::new();
+        counters.insert(
+            String::from("a"),
+            QuotaCounter {
+                period: String::from("2026-05"),
+                used: 10,
+            },
+        );
+        counters.insert(
+            String::from("b"),
+            QuotaCounter {
+                period: String::from("2026-04"),
+                used: 10,
+            },
+        );
+        let policy = QuotaPolicy {
+            name: None,
+            period: QuotaPeriod::Month,
+            limit: 10,
+            key: None,
+            max_keys: None,
+            timezone: None,
+        };
+        let mut limiter = QuotaLimiter::new(&policy, counters);
+        let now = utc(2026, 5, 20, 12, 0);
+        assert!(limiter.check(String::from("a"), now).is_err());
+        assert!(limiter.check(String::from("b"), now).is_ok());
+    }
+
+    #[test]
+    fn test_dirty_counters_are_taken_once() {
+        let mut limiter = quota_limiter(QuotaPeriod::Month, 5, "UTC");
+        let now = utc(2026, 5, 20, 12, 0);
+        limiter.check(String::from("a"), now).unwrap();
+        let dirty_counters = limiter.take_dirty_counters(now);
+        assert_eq!(1, dirty_counters.len());
+        assert_eq!(1, dirty_counters[0].1.used);
+        assert!(limiter.take_dirty_counters(now).is_empty());
+    }
+
+    #[test]
+    fn test_new_keys_overflow_when_key_space_is_full() {
+        let mut limiter = QuotaLimiter::new(
+            &QuotaPolicy {
+                name: None,
+                period: QuotaPeriod::Day,
+                limit: 2,
+                key: None,
+                max_keys: Some(2),
+                timezone: None,
+            },
+            HashMap::new(),
+        );
+        let yesterday = utc(2026, 5, 19, 12, 0);
+        limiter.check(String::from("a"), yesterday).unwrap();
+        limiter.check(String::from("b"), yesterday).unwrap();
+
+        // Counters of past periods make room for new keys
+        let today = utc(2026, 5, 20, 12, 0);
+        limiter.check(String::from("c"), today).unwrap();
+        limiter.check(String::from("d"), today).unwrap();
+        assert_eq!(
+            1,
+            limiter.check(String::from("e"), today).unwrap().remaining
+        );
+        assert_eq!(
+            0,
+            limiter.check(String::from("f"), today).unwrap().remaining
+        );
+        assert!(limiter.check(String::from("g"), today).is_err());
+        assert!(limiter.check(String::from("c"), today).is_ok());
+        let keys: Vec<String> = limiter
+            .usage("quota", today)
+            .into_iter()
+            .map(|usage| usage.key)
+            .collect();
+        assert_eq!(3, keys.len());
+        assert!(keys.contains(&String::from(OVERFLOW_KEY)));
+    }
+}
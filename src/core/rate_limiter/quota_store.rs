@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::core::rate_limiter::quota::QuotaCounter;
+use crate::file_utils::file_reader::FileReader;
+use crate::file_utils::file_writer::FileWriter;
+
+/// Records appended since the last compaction that trigger the next one.
+const COMPACTION_THRESHOLD: usize = 10000;
+
+#[derive(Serialize, Deserialize)]
+struct QuotaUsageRecord {
+    quota_id: String,
+    key: String,
+    period: String,
+    used: u32,
+    /// Unix time the period of the counter ends at; records written before it
+    /// was recorded are kept until their quota claims them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+}
+
+/// Counter held by the store until its quota is configured again.
+struct StoredCounter {
+    counter: QuotaCounter,
+    expires_at: Option<i64>,
+}
+
+/// Current counters of a quota with a limiter, for compacting the log.
+pub(crate) struct LiveQuotaCounters {
+    pub(crate) quota_id: String,
+    pub(crate) period_end: DateTime<Utc>,
+    pub(crate) counters: Vec<(String, QuotaCounter)>,
+}
+
+fn to_records(
+    quota_id: &str,
+    period_end: DateTime<Utc>,
+    counters: Vec<(String, QuotaCounter)>,
+) -> Vec<QuotaUsageRecord> {
+    counters
+        .into_iter()
+        .map(|(key, counter)| QuotaUsageRecord {
+            quota_id: String::from(quota_id),
+            key,
+            period: counter.period,
+            used: counter.used,
+            expires_at: Some(period_end.timestamp()),
+        })
+        .collect()
+}
+
+fn to_lines(records: Vec<QuotaUsageRecord>) -> String {
+    records
+        .iter()
+        .filter_map(|record| serde_json::to_string(record).ok())
+        .map(|line| line + "\n")
+        .collect()
+}
+
+fn is_expired(expires_at: Option<i64>, now: DateTime<Utc>) -> bool {
+    expires_at.is_some_and(|expires_at| expires_at <= now.timestamp())
+}
+
+/// Append-only log of quota counters, one JSON record per line. The latest record
+/// of a quota and key wins. The log is compacted to the counters of current
+/// periods on startup and whenever enough records were appended since.
+pub(crate) struct QuotaStore {
+    filepath: String,
+    counters: HashMap<String, HashMap<String, StoredCounter>>,
+    appended_records: usize,
+}
+
+impl QuotaStore {
+    pub(crate) fn open(path: &Path) -> Self {
+        if let Some(directory) = path.parent() {
+            if let Err(error) = fs::create_dir_all(directory) {
+                warn!("Failed to create quota store directory - {}", error);
+            }
+        }
+        let filepath = path.to_string_lossy().to_string();
+        let mut counters: HashMap<String, HashMap<String, StoredCounter>> = HashMap::new();
+        if let Ok(content) = FileReader::from_path(filepath.as_str()).read() {
+            for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                match serde_json::from_str::<QuotaUsageRecord>(line) {
+                    Err(_) => warn!("Skipped malformed quota usage record {}", line),
+                    Ok(record) => {
+                        counters.entry(record.quota_id).or_default().insert(
+                            record.key,
+                            StoredCounter {
+                                counter: QuotaCounter {
+                                    period: record.period,
+                                    used: record.used,
+                                },
+                                expires_at: record.expires_at,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+        let mut quota_store = QuotaStore {
+            filepath,
+            counters,
+            appended_records: 0,
+        };
+        quota_store.compact(vec![], Utc::now());
+        info!(
+            "Loaded quota counters of {} quotas from {}",
+            quota_store.counters.len(),
+            quota_store.filepath
+        );
+        quota_store
+    }
+
+    pub(crate) fn needs_compaction(&self) -> bool {
+        self.appended_records >= COMPACTION_THRESHOLD
+    }
+
+    /// Rewrites the log with the counters of `live_quotas` and the unexpired
+    /// counters held by the store. Expired counters are dropped from the store.
+    pub(crate) fn compact(&mut self, live_quotas: Vec<LiveQuotaCounters>, now: DateTime<Utc>) {
+        for counters in self.counters.values_mut() {
+            counters.retain(|_, stored_counter| !is_expired(stored_counter.expires_at, now));
+        }
+        self.counters.retain(|_, counters| !counters.is_empty());
+        let mut records: Vec<QuotaUsageRecord> = self
+            .counters
+            .iter()
+            .flat_map(|(quota_id, counters)| {
+                counters
+                    .iter()
+                    .map(move |(key, stored_counter)| QuotaUsageRecord {
+                        quota_id: quota_id.clone(),
+                        key: key.clone(),
+                        period: stored_counter.counter.period.clone(),
+                        used: stored_counter.counter.used,
+                        expires_at: stored_counter.expires_at,
+                    })
+            })
+            .collect();
+        for live_quota in live_quotas {
+            records.extend(to_records(
+                &live_quota.quota_id,
+                live_quota.period_end,
+                live_quota.counters,
+            ));
+        }
+        debug!("Compacting quota store to {} records", records.len());
+        if let Err(error) = FileWriter::from_path(self.filepath.as_str()).write(&to_lines(records))
+        {
+            warn!("Failed to compact quota store - {}", error.message);
+        }
+        self.appended_records = 0;
+    }
+
+    /// Hands the stored counters of a quota over to its limiter.
+    pub(crate) fn take_counters(&mut self, quota_id: &str) -> HashMap<String, QuotaCounter> {
+        self.counters
+            .remove(quota_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, stored_counter)| (key, stored_counter.counter))
+            .collect()
+    }
+
+    /// Takes counters of the period ending at `period_end` back from a limiter
+    /// that is being replaced.
+    pub(crate) fn return_counters(
+        &mut self,
+        quota_id: &str,
+        period_end: DateTime<Utc>,
+        counters: HashMap<String, QuotaCounter>,
+    ) {
+        let counters = counters
+            .into_iter()
+            .map(|(key, counter)| {
+                (
+                    key,
+                    StoredCounter {
+                        counter,
+                        expires_at: Some(period_end.timestamp()),
+                    },
+                )
+            })
+            .collect();
+        self.counters.insert(String::from(quota_id), counters);
+    }
+
+    /// Persists counters of the period ending at `period_end`.
+    pub(crate) fn append(
+        &mut self,
+        quota_id: &str,
+        period_end: DateTime<Utc>,
+        counters: Vec<(String, QuotaCounter)>,
+    ) {
+        if counters.is_empty() {
+            return;
+        }
+        let records = to_records(quota_id, period_end, counters);
+        debug!(
+            "Persisting {} quota counters of quota {}",
+            records.len(),
+            quota_id
+        );
+        self.appended_records += records.len();
+        if let Err(error) = FileWriter::from_path(self.filepath.as_str()).append(&to_lines(records))
+        {
+            warn!("Failed to persist quota counters - {}", error.message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use crate::core::rate_limiter::quota::QuotaCounter;
+
+    use super::{LiveQuotaCounters, QuotaStore, COMPACTION_THRESHOLD};
+
+    fn counter(period: &str, used: u32) -> QuotaCounter {
+        QuotaCounter {
+            period: String::from(period),
+            used,
+        }
+    }
+
+    fn month_end() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2099, 6, 1, 0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_counters_survive_reopening() {
+        let path = std::env::temp_dir()
+            .join(format!("gateman-quota-{}", std::process::id()))
+            .join("quota_usage.log");
+        let _ = fs::remove_file(&path);
+
+        let mut quota_store = QuotaStore::open(&path);
+        quota_store.append(
+            "quota:api#0",
+            month_end(),
+            vec![(String::from("a"), counter("2026-05", 3))],
+        );
+        quota_store.append(
+            "quota:api#0",
+            month_end(),
+            vec![
+                (String::from("a"), counter("2026-05", 7)),
+                (String::from("b"), counter("2026-05", 1)),
+            ],
+        );
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "not a record\n").unwrap();
+
+        let mut quota_store = QuotaStore::open(&path);
+        let counters = quota_store.take_counters("quota:api#0");
+        assert_eq!(Some(&counter("2026-05", 7)), counters.get("a"));
+        assert_eq!(Some(&counter("2026-05", 1)), counters.get("b"));
+        // Reopening compacted the log down to the latest record of each key
+        assert_eq!(2, fs::read_to_string(&path).unwrap().lines().count());
+        assert!(quota_store.take_counters("quota:api#0").is_empty());
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_compaction_drops_counters_of_past_periods() {
+        let path = std::env::temp_dir()
+            .join(format!("gateman-quota-compaction-{}", std::process::id()))
+            .join("quota_usage.log");
+        let _ = fs::remove_file(&path);
+
+        let mut quota_store = QuotaStore::open(&path);
+        quota_store.append(
+            "quota:removed",
+            Utc.with_ymd_and_hms(2026, 5, 1, 0, 0, 0).unwrap(),
+            vec![(String::from("a"), counter("2026-04", 3))],
+        );
+        quota_store.append(
+            "quota:removed",
+            month_end(),
+            vec![(String::from("b"), counter("2099-05", 4))],
+        );
+
+        // Reopening keeps the counter of the unconfigured quota that is current
+        let mut quota_store = QuotaStore::open(&path);
+        assert_eq!(1, fs::read_to_string(&path).unwrap().lines().count());
+        let live_counters = (0..COMPACTION_THRESHOLD)
+            .map(|key| (key.to_string(), counter("2099-05", 1)))
+            .collect();
+        quota_store.append("quota:live", month_end(), live_counters);
+        assert!(quota_store.needs_compaction());
+
+        // The live quota has dropped all but one of its keys since
+        quota_store.compact(
+            vec![LiveQuotaCounters {
+                quota_id: String::from("quota:live"),
+                period_end: month_end(),
+                counters: vec![(String::from("0"), counter("2099-05", 1))],
+            }],
+            Utc::now(),
+        );
+        assert!(!quota_store.needs_compaction());
+        assert_eq!(2, fs::read_to_string(&path).unwrap().lines().count());
+
+        let mut quota_store = QuotaStore::open(&path);
+        let counters = quota_store.take_counters("quota:removed");
+        assert_eq!(None, counters.get("a"));
+        assert_eq!(Some(&counter("2099-05", 4)), counters.get("b"));
+        assert_eq!(1, quota_store.take_counters("quota:live").len());
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
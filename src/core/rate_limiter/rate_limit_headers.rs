@@ -0,0 +1,124 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::HeaderMap;
+
+use crate::configuration_reader::origin_def_reader::RateLimitHeaderStyle;
+
+/// Whole seconds, rounded up so that clients never retry too early.
+pub(crate) fn ceil_seconds(duration: Duration) -> u64 {
+    let seconds = duration.as_secs();
+    if duration.subsec_nanos() > 0 {
+        seconds + 1
+    } else {
+        seconds
+    }
+}
+
+fn reset_value(header_style: &RateLimitHeaderStyle, reset_after: Duration) -> u64 {
+    match header_style {
+        RateLimitHeaderStyle::XRateLimit => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            ceil_seconds(now + reset_after)
+        }
+        _ => ceil_seconds(reset_after),
+    }
+}
+
+/// Appends the limit, remaining budget and reset time in the configured style.
+/// `Draft` reports the reset as delta seconds, `XRateLimit` as a Unix timestamp.
+pub(crate) fn append_rate_limit_headers(
+    headers: &mut HeaderMap,
+    header_style: Option<&RateLimitHeaderStyle>,
+    limit: u32,
+    remaining: u32,
+    reset_after: Duration,
+) {
+    let header_style = header_style.unwrap_or(&RateLimitHeaderStyle::Draft);
+    let header_names = match header_style {
+        RateLimitHeaderStyle::Disabled => return,
+        RateLimitHeaderStyle::Draft => {
+            ["ratelimit-limit", "ratelimit-remaining", "ratelimit-reset"]
+        }
+        RateLimitHeaderStyle::XRateLimit => [
+            "x-ratelimit-limit",
+            "x-ratelimit-remaining",
+            "x-ratelimit-reset",
+        ],
+    };
+    let header_values = [
+        u64::from(limit),
+        u64::from(remaining),
+        reset_value(header_style, reset_after),
+    ];
+    for (header_name, header_value) in header_names.iter().zip(header_values.iter()) {
+        headers.insert(
+            HeaderName::from_static(header_name),
+            HeaderValue::from(*header_value),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use hyper::HeaderMap;
+
+    use crate::configuration_reader::origin_def_reader::RateLimitHeaderStyle;
+
+    use super::{append_rate_limit_headers, ceil_seconds};
+
+    #[test]
+    fn test_ceil_seconds() {
+        assert_eq!(0, ceil_seconds(Duration::from_secs(0)));
+        assert_eq!(2, ceil_seconds(Duration::from_secs(2)));
+        assert_eq!(3, ceil_seconds(Duration::from_millis(2001)));
+    }
+
+    #[test]
+    fn test_draft_headers_are_default() {
+        let mut headers = HeaderMap::new();
+        append_rate_limit_headers(&mut headers, None, 100, 42, Duration::from_millis(1500));
+        assert_eq!("100", headers.get("RateLimit-Limit").unwrap());
+        assert_eq!("42", headers.get("RateLimit-Remaining").unwrap());
+        assert_eq!("2", headers.get("RateLimit-Reset").unwrap());
+    }
+
+    #[test]
+    fn test_x_rate_limit_reset_is_timestamp() {
+        let mut headers = HeaderMap::new();
+        append_rate_limit_headers(
+            &mut headers,
+            Some(&RateLimitHeaderStyle::XRateLimit),
+            10,
+            0,
+            Duration::from_secs(30),
+        );
+        assert_eq!("10", headers.get("X-RateLimit-Limit").unwrap());
+        assert_eq!("0", headers.get("X-RateLimit-Remaining").unwrap());
+        let reset: u64 = headers
+            .get("X-RateLimit-Reset")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(reset > 1_600_000_000);
+    }
+
+    #[test]
+    fn test_disabled_adds_no_headers() {
+        let mut headers = HeaderMap::new();
+        append_rate_limit_headers(
+            &mut headers,
+            Some(&RateLimitHeaderStyle::Disabled),
+            10,
+            5,
+            Duration::from_secs(1),
+        );
+        assert!(headers.is_empty());
+    }
+}
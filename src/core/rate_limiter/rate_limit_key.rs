@@ -0,0 +1,190 @@
+use std::net::IpAddr;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hyper::header::AUTHORIZATION;
+use hyper::{Body, Request};
+use log::trace;
+use serde_json::Value;
+
+use crate::configuration_reader::origin_def_reader::RateLimitKey;
+/// Key shared by every request the configured key could not be extracted from.
+pub(crate) const UNIDENTIFIED_CLIENT_KEY: &str = "-";
+/// Key shared by new keys once a limiter tracks as many keys as it may.
+pub(crate) const OVERFLOW_KEY: &str = "__overflow__";
+
+pub(crate) fn header_value(request: &Request<Body>, header_name: &str) -> Option<String> {
+    request
+        .headers()
+        .get(header_name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+pub(crate) fn query_parameter(request: &Request<Body>, parameter_name: &str) -> Option<String> {
+    request
+        .uri()
+        .query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == parameter_name)
+        .map(|(_, value)| String::from(value))
+}
+
+/// Reads a claim from the payload of a bearer JWT. The signature is not checked
+/// here; the claim only decides which bucket a request is counted against.
+fn jwt_claim(request: &Request<Body>, claim: &str) -> Option<String> {
+    let authorization = header_value(request, AUTHORIZATION.as_str())?;
+    let token = authorization.strip_prefix("Bearer ")?;
+    let payload = token.split('.').nth(1)?;
+    let payload = match URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')) {
+        Ok(payload) => payload,
+        Err(_) => {
+            trace!("Failed to decode JWT payload for rate limiting key");
+            return None;
+        }
+    };
+    match serde_json::from_slice::<Value>(&payload).ok()?.get(claim)? {
+        Value::String(value) => Some(value.clone()),
+        Value::Null => None,
+        value => Some(value.to_string()),
+    }
+}
+
+/// `client_ip` is the address resolved from the forwarding headers of trusted
+/// proxies, so that clients behind a proxy are not all counted as the proxy.
+pub(crate) fn extract_rate_limit_key(
+    rate_limit_key: &RateLimitKey,
+    request: &Request<Body>,
+    client_ip: IpAddr,
+) -> String {
+    let key = match rate_limit_key {
+        RateLimitKey::ClientIp => Some(client_ip.to_string()),
+        RateLimitKey::ApiKey {
+            header,
+            query_parameter: parameter,
+        } => header
+            .as_ref()
+            .and_then(|header| header_value(request, header))
+            .or_else(|| {
+                parameter
+                    .as_ref()
+                    .and_then(|parameter| query_parameter(request, parameter))
+            }),
+        RateLimitKey::JwtClaim { claim } => jwt_claim(request, claim),
+        RateLimitKey::Header { name } => header_value(request, name),
+    };
+    key.unwrap_or_else(|| String::from(UNIDENTIFIED_CLIENT_KEY))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, SocketAddr};
+
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use hyper::{Body, Request};
+
+    use crate::configuration_reader::ip_access_config_reader::IpAccessConfig;
+    use crate::configuration_reader::origin_def_reader::RateLimitKey;
+    use crate::core::connection_info::ConnectionInfo;
+    use crate::core::ip_access::IpAccess;
+
+    use super::{extract_rate_limit_key, UNIDENTIFIED_CLIENT_KEY};
+
+    fn client_ip() -> IpAddr {
+        IpAddr::from([10, 1, 2, 3])
+    }
+
+    #[test]
+    fn test_client_ip_key() {
+        let request = Request::new(Body::empty());
+        assert_eq!(
+            "10.1.2.3",
+            extract_rate_limit_key(&RateLimitKey::ClientIp, &request, client_ip())
+        );
+    }
+
+    #[test]
+    fn test_clients_behind_trusted_proxies_are_keyed_by_their_own_address() {
+        let ip_access = IpAccess::from_config(&IpAccessConfig {
+            trusted_proxies: vec![String::from("10.0.0.0/8")],
+            ..IpAccessConfig::default()
+        })
+        .unwrap();
+        let connection_info = ConnectionInfo {
+            remote_addr: SocketAddr::from(([10, 0, 0, 1], 52000)),
+            client_certificate: None,
+        };
+        let keys: Vec<String> = ["198.51.100.1", "203.0.113.9"]
+            .iter()
+            .map(|forwarded_for| {
+                let request = Request::builder()
+                    .header("x-forwarded-for", *forwarded_for)
+                    .body(Body::empty())
+                    .unwrap();
+                let client_ip = ip_access.client_ip(&request, &connection_info);
+                extract_rate_limit_key(&RateLimitKey::ClientIp, &request, client_ip)
+            })
+            .collect();
+        assert_eq!(vec!["198.51.100.1", "203.0.113.9"], keys);
+    }
+
+    #[test]
+    fn test_api_key_from_header_or_query() {
+        let rate_limit_key = RateLimitKey::ApiKey {
+            header: Some(String::from("X-API-Key")),
+            query_parameter: Some(String::from("api_key")),
+        };
+        let request = Request::builder()
+            .uri("/path?foo=bar&api_key=from-query")
+            .header("X-API-Key", "from-header")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            "from-header",
+            extract_rate_limit_key(&rate_limit_key, &request, client_ip())
+        );
+        let request = Request::builder()
+            .uri("/path?foo=bar&api_key=from-query")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            "from-query",
+            extract_rate_limit_key(&rate_limit_key, &request, client_ip())
+        );
+    }
+
+    #[test]
+    fn test_jwt_claim_key() {
+        let payload = URL_SAFE_NO_PAD.encode(r#"{"sub":"user-42","tenant":7}"#);
+        let token = format!("eyJhbGciOiJIUzI1NiJ9.{}.signature", payload);
+        let request = Request::builder()
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        let sub = RateLimitKey::JwtClaim {
+            claim: String::from("sub"),
+        };
+        let tenant = RateLimitKey::JwtClaim {
+            claim: String::from("tenant"),
+        };
+        assert_eq!(
+            "user-42",
+            extract_rate_limit_key(&sub, &request, client_ip())
+        );
+        assert_eq!("7", extract_rate_limit_key(&tenant, &request, client_ip()));
+    }
+
+    #[test]
+    fn test_missing_key_is_unidentified() {
+        let request = Request::new(Body::empty());
+        let rate_limit_key = RateLimitKey::Header {
+            name: String::from("X-Tenant"),
+        };
+        assert_eq!(
+            UNIDENTIFIED_CLIENT_KEY,
+            extract_rate_limit_key(&rate_limit_key, &request, client_ip())
+        );
+    }
+}
@@ -1,14 +1,180 @@
-use tokio::sync::oneshot::Sender;
+use std::collections::HashMap;
+use std::time::Duration;
 
-use crate::configuration_reader::origin_def_reader::RateLimiterConfig;
+use sha2::{Digest, Sha256};
 
-pub enum RateLimiterAPI {
-    ShouldProgress {
-        origin_id: String,
-        responder: Sender<Result<(), ()>>,
+use crate::configuration_reader::api_def_reader::{APIRateLimitPolicy, QuotaPolicy};
+use crate::configuration_reader::origin_def_reader::{RateLimitHeaderStyle, RateLimiterConfig};
+
+pub struct RateLimitCheck {
+    pub(crate) limiter_id: String,
+    pub(crate) key: Option<String>,
+}
+
+/// Budget left in the most restrictive limiter after a request was admitted,
+/// along with the header style that limiter reports it in. Quotas have no style
+/// of their own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitStatus {
+    pub(crate) limit: u32,
+    pub(crate) remaining: u32,
+    pub(crate) reset_after: Duration,
+    pub(crate) header_style: Option<RateLimitHeaderStyle>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateLimitRejection {
+    LimitExceeded {
+        limit: u32,
+        retry_after: Duration,
+        header_style: Option<RateLimitHeaderStyle>,
     },
+    UnknownLimiter,
+}
+
+/// Configuration updates applied by the rate limiter task.
+#[allow(clippy::enum_variant_names)]
+pub enum RateLimiterAPI {
     UpdateOriginSpecification {
         origin_id: String,
         rate_limiter_spec: RateLimiterConfig,
     },
+    RemoveOriginSpecification {
+        origin_id: String,
+    },
+    UpdateAPISpecification {
+        api_id: String,
+        rate_limit_policies: Vec<APIRateLimitPolicy>,
+        quota_policies: Vec<QuotaPolicy>,
+    },
+}
+
+pub(crate) fn origin_limiter_id(origin_id: &str) -> String {
+    format!("origin:{}", origin_id)
+}
+
+pub(crate) fn api_limiter_prefix(api_id: &str) -> String {
+    format!("api:{}#", api_id)
+}
+
+pub(crate) fn quota_limiter_prefix(api_id: &str) -> String {
+    format!("quota:{}#", api_id)
+}
+
+/// Identifies the limiters of policies by their name, or else by a digest of what
+/// they select, so that reordering the policies of an API never hands the state
+/// of one policy to another. Unnamed policies selecting the same requests are
+/// told apart by their order among each other.
+fn policy_limiter_ids<'a>(
+    prefix: String,
+    policies: impl Iterator<Item = (Option<&'a String>, String)>,
+) -> Vec<String> {
+    let mut occurrences: HashMap<String, usize> = HashMap::new();
+    policies
+        .map(|(name, selector)| match name {
+            Some(name) => format!("{}name:{}", prefix, name),
+            None => {
+                let digest = hex::encode(&Sha256::digest(selector.as_bytes())[..8]);
+                let occurrence = occurrences.entry(digest.clone()).or_insert(0);
+                *occurrence += 1;
+                match *occurrence {
+                    1 => format!("{}selector:{}", prefix, digest),
+                    occurrence => format!("{}selector:{}-{}", prefix, digest, occurrence),
+                }
+            }
+        })
+        .collect()
+}
+
+fn key_selector<T: serde::Serialize>(key: Option<&T>) -> String {
+    key.and_then(|key| serde_json::to_string(key).ok())
+        .unwrap_or_default()
+}
+
+/// The limiter IDs of the rate limit policies of an API, in policy order.
+pub(crate) fn api_limiter_ids(api_id: &str, policies: &[APIRateLimitPolicy]) -> Vec<String> {
+    policy_limiter_ids(
+        api_limiter_prefix(api_id),
+        policies.iter().map(|policy| {
+            let mut methods = policy.methods.clone();
+            methods.sort();
+            methods.dedup();
+            let selector = format!(
+                "{}\n{}",
+                methods.join(","),
+                key_selector(policy.rate_limiter.key.as_ref())
+            );
+            (policy.name.as_ref(), selector)
+        }),
+    )
+}
+
+/// The limiter IDs of the quotas of an API, in policy order.
+pub(crate) fn quota_limiter_ids(api_id: &str, policies: &[QuotaPolicy]) -> Vec<String> {
+    policy_limiter_ids(
+        quota_limiter_prefix(api_id),
+        policies.iter().map(|policy| {
+            let selector = format!(
+                "{}\n{}",
+                key_selector(Some(&policy.period)),
+                key_selector(policy.key.as_ref())
+            );
+            (policy.name.as_ref(), selector)
+        }),
+    )
+}
+
+/// The positional ID quotas were persisted under before they were identified by
+/// name or selector. Counters are still read from it for quotas without counters
+/// of their own, so that upgrading does not reset them.
+pub(crate) fn legacy_quota_limiter_id(api_id: &str, quota_index: usize) -> String {
+    format!("{}{}", quota_limiter_prefix(api_id), quota_index)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configuration_reader::api_def_reader::APIRateLimitPolicy;
+
+    use super::api_limiter_ids;
+
+    fn policy(name: Option<&str>, methods: &str) -> APIRateLimitPolicy {
+        serde_json::from_str(&format!(
+            r#"{{
+                "name": {},
+                "methods": {},
+                "rate_limiter": {{"algorithm": "TokenBucket", "time_unit": "Second", "req_per_time_unit": 10}}
+            }}"#,
+            name.map(|name| format!("\"{}\"", name))
+                .unwrap_or_else(|| String::from("null")),
+            methods
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_api_limiter_ids_do_not_depend_on_policy_order() {
+        let reads = policy(None, r#"["GET", "HEAD"]"#);
+        let writes = policy(None, r#"["POST"]"#);
+        let ids = api_limiter_ids("billing", &[reads.clone(), writes.clone()]);
+        let reordered_ids = api_limiter_ids("billing", &[writes, reads.clone()]);
+        assert_eq!(ids[0], reordered_ids[1]);
+        assert_eq!(ids[1], reordered_ids[0]);
+        assert_eq!(
+            ids[0],
+            api_limiter_ids("billing", &[policy(None, r#"["HEAD", "GET"]"#)])[0]
+        );
+        assert!(ids[0].starts_with("api:billing#selector:"));
+
+        // Policies selecting the same requests still get limiters of their own
+        let ids = api_limiter_ids("billing", &[reads.clone(), reads]);
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[test]
+    fn test_named_policies_are_identified_by_name() {
+        assert_eq!(
+            vec!["api:billing#name:reads"],
+            api_limiter_ids("billing", &[policy(Some("reads"), r#"["GET"]"#)])
+        );
+    }
 }
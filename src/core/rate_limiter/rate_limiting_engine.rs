@@ -1,16 +1,41 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
 
-use governor::clock::DefaultClock;
-use governor::state::{InMemoryState, NotKeyed};
-use governor::{Quota, RateLimiter};
-use log::{debug, trace};
+use arc_swap::ArcSwap;
+use chrono::Utc;
+use governor::Quota;
+use log::{debug, info, trace};
 use nonzero_ext::nonzero;
 use tokio::sync::mpsc::Receiver;
+use tokio::time::interval;
 
-use crate::configuration_reader::origin_def_reader::{RateLimiterConfig, TimeUnit};
+use crate::configuration_reader::api_def_reader::QuotaPolicy;
+use crate::configuration_reader::cluster_config_reader::ClusterMode;
+use crate::configuration_reader::origin_def_reader::{
+    RateLimitAlgorithm, RateLimitHeaderStyle, RateLimitMode, RateLimiterConfig, TimeUnit,
+};
+use crate::core::rate_limiter::cluster::{ClusterBackend, ClusterCounter};
+use crate::core::rate_limiter::quota::{QuotaLimiter, QuotaUsage};
+use crate::core::rate_limiter::quota_store::{LiveQuotaCounters, QuotaStore};
+use crate::core::rate_limiter::rate_limit_key::{OVERFLOW_KEY, UNIDENTIFIED_CLIENT_KEY};
+use crate::core::rate_limiter::rate_limiter_api::{
+    api_limiter_ids, api_limiter_prefix, legacy_quota_limiter_id, origin_limiter_id,
+    quota_limiter_ids, quota_limiter_prefix, RateLimitCheck, RateLimitRejection, RateLimitStatus,
+};
+use crate::core::rate_limiter::shadow::{ShadowStats, ShadowUsage};
+use crate::utils::path_utils::get_directory_of_executable;
 use crate::RateLimiterAPI;
 
+pub(crate) const DEFAULT_MAX_KEYS: usize = 10000;
+const DEFAULT_IDLE_KEY_TIMEOUT: u64 = 300;
+const KEY_EVICTION_INTERVAL: Duration = Duration::from_secs(30);
+const QUOTA_STORE_PATH: &str = "resources/quota/quota_usage.log";
+/// Quota usage recorded since the last flush is lost if the process crashes.
+const QUOTA_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
 fn create_non_zero_u32_from_u32(input: u32) -> NonZeroU32 {
     match NonZeroU32::new(input) {
         None => {
@@ -20,8 +45,8 @@ fn create_non_zero_u32_from_u32(input: u32) -> NonZeroU32 {
     }
 }
 
-fn calculate_quota(rate_limiter_config: RateLimiterConfig) -> Quota {
-    match rate_limiter_config.time_unit {
+fn calculate_quota(rate_limiter_config: &RateLimiterConfig) -> Quota {
+    let quota = match rate_limiter_config.time_unit {
         TimeUnit::Hour => Quota::per_hour(create_non_zero_u32_from_u32(
             rate_limiter_config.req_per_time_unit,
         )),
@@ -31,79 +56,1187 @@ fn calculate_quota(rate_limiter_config: RateLimiterConfig) -> Quota {
         TimeUnit::Second => Quota::per_second(create_non_zero_u32_from_u32(
             rate_limiter_config.req_per_time_unit,
         )),
+    };
+    match rate_limiter_config.burst_size {
+        None => quota,
+        Some(burst_size) => quota.allow_burst(create_non_zero_u32_from_u32(burst_size)),
     }
 }
 
-pub(crate) async fn deploy_rate_limiter(mut receiver: Receiver<RateLimiterAPI>) {
-    let mut api_rate_limiter_map =
-        HashMap::<String, RateLimiter<NotKeyed, InMemoryState, DefaultClock>>::new();
-    loop {
-        let api_call = receiver.recv().await;
-        if api_call.is_some() {
-            let api_call = api_call.unwrap();
-            match api_call {
-                RateLimiterAPI::ShouldProgress {
-                    origin_id,
-                    responder,
-                } => match api_rate_limiter_map.get(&*origin_id) {
-                    None => {
-                        debug!(
-                            "No rate limiter found for Origin (Origin ID: {})",
-                            origin_id
-                        );
-                        match responder.send(Result::Err(())) {
-                            Ok(_) => {
-                                trace!("Rate limiter responded successfully for Origin (Origin ID: {})", origin_id)
-                            }
-                            Err(_) => {
-                                trace!(
-                                    "Rate limiter failed to respond for Origin (Origin ID: {})",
-                                    origin_id
-                                )
-                            }
-                        }
-                    }
-                    Some(rate_limiter) => {
-                        match responder.send(rate_limiter.check().map_err(|_| ())) {
-                            Ok(_) => {
-                                trace!("Rate limiter responded successfully for Origin (Origin ID: {})", origin_id)
-                            }
-                            Err(_) => {
-                                trace!(
-                                    "Rate limiter failed to respond for Origin (Origin ID: {})",
-                                    origin_id
-                                )
-                            }
-                        }
+fn time_unit_duration(time_unit: &TimeUnit) -> Duration {
+    match time_unit {
+        TimeUnit::Hour => Duration::from_secs(3600),
+        TimeUnit::Minute => Duration::from_secs(60),
+        TimeUnit::Second => Duration::from_secs(1),
+    }
+}
+
+/// A single bucket of a rate limiter. Implementations are thread-safe and take
+/// the current instant as an argument so that their boundary behavior can be
+/// tested without sleeping.
+trait RateLimitingAlgorithm: Send + Sync {
+    fn check(&self, now: Instant) -> Result<RateLimitStatus, RateLimitRejection>;
+
+    /// Requests currently counted against the limit.
+    fn consumed(&self, now: Instant) -> u32;
+
+    /// Counts `count` more requests against the limit as if they had been
+    /// admitted just now, up to the limit. Used for capacity carried over from a
+    /// previous configuration and for requests other instances admitted.
+    fn charge(&self, count: u32, now: Instant);
+}
+
+fn create_algorithm(
+    rate_limiter_config: &RateLimiterConfig,
+    now: Instant,
+) -> Box<dyn RateLimitingAlgorithm> {
+    let limit = create_non_zero_u32_from_u32(rate_limiter_config.req_per_time_unit).get();
+    let window = time_unit_duration(&rate_limiter_config.time_unit);
+    match rate_limiter_config.algorithm {
+        RateLimitAlgorithm::TokenBucket => {
+            Box::new(TokenBucket::new(calculate_quota(rate_limiter_config), now))
+        }
+        RateLimitAlgorithm::FixedWindow => Box::new(FixedWindow::new(limit, window, now)),
+        RateLimitAlgorithm::SlidingWindowLog => Box::new(SlidingWindowLog::new(limit, window)),
+        RateLimitAlgorithm::SlidingWindowCounter => {
+            Box::new(SlidingWindowCounter::new(limit, window, now))
+        }
+    }
+}
+
+/// Creates the algorithm for a new configuration with the capacity consumed under
+/// the previous one already counted against it. Windowed algorithms count the
+/// carried requests from now on, which errs on the side of rejecting.
+fn carry_over(
+    previous: &dyn RateLimitingAlgorithm,
+    rate_limiter_config: &RateLimiterConfig,
+    now: Instant,
+) -> Box<dyn RateLimitingAlgorithm> {
+    let algorithm = create_algorithm(rate_limiter_config, now);
+    algorithm.charge(previous.consumed(now), now);
+    algorithm
+}
+
+fn duration_between(later: Instant, earlier: Instant) -> Duration {
+    later.saturating_duration_since(earlier)
+}
+
+/// GCRA: tracks the theoretical arrival time (TAT) of the next request, in
+/// nanoseconds since `epoch`. A request is admitted while the TAT stays within
+/// `burst_size` emission intervals of now.
+struct TokenBucket {
+    epoch: Instant,
+    emission_interval: u64,
+    burst_size: u32,
+    theoretical_arrival_time: AtomicU64,
+}
+
+impl TokenBucket {
+    fn new(quota: Quota, now: Instant) -> Self {
+        TokenBucket {
+            epoch: now,
+            emission_interval: (quota.replenish_interval().as_nanos() as u64).max(1),
+            burst_size: quota.burst_size().get(),
+            theoretical_arrival_time: AtomicU64::new(0),
+        }
+    }
+}
+
+impl RateLimitingAlgorithm for TokenBucket {
+    fn check(&self, now: Instant) -> Result<RateLimitStatus, RateLimitRejection> {
+        let now = duration_between(now, self.epoch).as_nanos() as u64;
+        let burst_tolerance = self.emission_interval * u64::from(self.burst_size);
+        let mut tat = self.theoretical_arrival_time.load(Ordering::Acquire);
+        loop {
+            let next_tat = tat.max(now) + self.emission_interval;
+            let backlog = next_tat - now;
+            if backlog > burst_tolerance {
+                return Err(RateLimitRejection::LimitExceeded {
+                    limit: self.burst_size,
+                    retry_after: Duration::from_nanos(backlog - burst_tolerance),
+                    header_style: None,
+                });
+            }
+            match self.theoretical_arrival_time.compare_exchange_weak(
+                tat,
+                next_tat,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    return Ok(RateLimitStatus {
+                        limit: self.burst_size,
+                        remaining: ((burst_tolerance - backlog) / self.emission_interval) as u32,
+                        reset_after: Duration::from_nanos(backlog),
+                        header_style: None,
+                    })
+                }
+                Err(current_tat) => tat = current_tat,
+            }
+        }
+    }
+
+    fn consumed(&self, now: Instant) -> u32 {
+        let now = duration_between(now, self.epoch).as_nanos() as u64;
+        let backlog = self
+            .theoretical_arrival_time
+            .load(Ordering::Acquire)
+            .saturating_sub(now);
+        let consumed = backlog.div_ceil(self.emission_interval);
+        consumed.min(u64::from(self.burst_size)) as u32
+    }
+
+    fn charge(&self, count: u32, now: Instant) {
+        let now = duration_between(now, self.epoch).as_nanos() as u64;
+        let burst_tolerance = self.emission_interval * u64::from(self.burst_size);
+        let charge = self.emission_interval * u64::from(count.min(self.burst_size));
+        let _ = self.theoretical_arrival_time.fetch_update(
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            |tat| Some((tat.max(now) + charge).min(now + burst_tolerance)),
+        );
+    }
+}
+
+struct WindowState {
+    window_start: Instant,
+    current_count: u32,
+    previous_count: u32,
+}
+
+impl WindowState {
+    fn new(now: Instant) -> Self {
+        WindowState {
+            window_start: now,
+            current_count: 0,
+            previous_count: 0,
+        }
+    }
+
+    /// Moves the window forward to the one containing `now`, keeping the count of
+    /// the window right before it.
+    fn advance(&mut self, window: Duration, now: Instant) {
+        let elapsed_windows =
+            duration_between(now, self.window_start).as_nanos() / window.as_nanos();
+        if elapsed_windows == 0 {
+            return;
+        }
+        self.previous_count = if elapsed_windows == 1 {
+            self.current_count
+        } else {
+            0
+        };
+        self.current_count = 0;
+        self.window_start += Duration::from_nanos((window.as_nanos() * elapsed_windows) as u64);
+    }
+}
+
+/// Counts requests in consecutive windows of one time unit. Clients may send up
+/// to twice the limit around a window boundary.
+struct FixedWindow {
+    limit: u32,
+    window: Duration,
+    state: Mutex<WindowState>,
+}
+
+impl FixedWindow {
+    fn new(limit: u32, window: Duration, now: Instant) -> Self {
+        FixedWindow {
+            limit,
+            window,
+            state: Mutex::new(WindowState::new(now)),
+        }
+    }
+}
+
+impl RateLimitingAlgorithm for FixedWindow {
+    fn check(&self, now: Instant) -> Result<RateLimitStatus, RateLimitRejection> {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        state.advance(self.window, now);
+        let reset_after = duration_between(state.window_start + self.window, now);
+        if state.current_count >= self.limit {
+            return Err(RateLimitRejection::LimitExceeded {
+                limit: self.limit,
+                retry_after: reset_after,
+                header_style: None,
+            });
+        }
+        state.current_count += 1;
+        Ok(RateLimitStatus {
+            limit: self.limit,
+            remaining: self.limit - state.current_count,
+            reset_after,
+            header_style: None,
+        })
+    }
+
+    fn consumed(&self, now: Instant) -> u32 {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        state.advance(self.window, now);
+        state.current_count
+    }
+
+    fn charge(&self, count: u32, now: Instant) {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        state.advance(self.window, now);
+        state.current_count = state.current_count.saturating_add(count).min(self.limit);
+    }
+}
+
+/// Remembers the arrival of every admitted request within the last time unit.
+/// Exact, at the cost of memory proportional to the limit.
+struct SlidingWindowLog {
+    limit: u32,
+    window: Duration,
+    arrivals: Mutex<VecDeque<Instant>>,
+}
+
+impl SlidingWindowLog {
+    fn new(limit: u32, window: Duration) -> Self {
+        SlidingWindowLog {
+            limit,
+            window,
+            arrivals: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Forgets arrivals that left the window and returns the rest.
+    fn arrivals(&self, now: Instant) -> MutexGuard<'_, VecDeque<Instant>> {
+        let mut arrivals = self
+            .arrivals
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        while let Some(oldest) = arrivals.front() {
+            if duration_between(now, *oldest) < self.window {
+                break;
+            }
+            arrivals.pop_front();
+        }
+        arrivals
+    }
+}
+
+impl RateLimitingAlgorithm for SlidingWindowLog {
+    fn check(&self, now: Instant) -> Result<RateLimitStatus, RateLimitRejection> {
+        let mut arrivals = self.arrivals(now);
+        if arrivals.len() >= self.limit as usize {
+            let oldest = arrivals.front().copied().unwrap_or(now);
+            return Err(RateLimitRejection::LimitExceeded {
+                limit: self.limit,
+                retry_after: duration_between(oldest + self.window, now),
+                header_style: None,
+            });
+        }
+        arrivals.push_back(now);
+        Ok(RateLimitStatus {
+            limit: self.limit,
+            remaining: self.limit - arrivals.len() as u32,
+            reset_after: self.window,
+            header_style: None,
+        })
+    }
+
+    fn consumed(&self, now: Instant) -> u32 {
+        self.arrivals(now).len() as u32
+    }
+
+    fn charge(&self, count: u32, now: Instant) {
+        let mut arrivals = self.arrivals(now);
+        let missing = (count as usize).min((self.limit as usize).saturating_sub(arrivals.len()));
+        arrivals.extend(std::iter::repeat_n(now, missing));
+    }
+}
+
+/// Approximates a sliding window by weighting the previous window's count with
+/// the share of it still covered by the sliding window.
+struct SlidingWindowCounter {
+    limit: u32,
+    window: Duration,
+    state: Mutex<WindowState>,
+}
+
+impl SlidingWindowCounter {
+    fn new(limit: u32, window: Duration, now: Instant) -> Self {
+        SlidingWindowCounter {
+            limit,
+            window,
+            state: Mutex::new(WindowState::new(now)),
+        }
+    }
+
+    /// Requests counted in the sliding window ending `elapsed` into the current
+    /// window.
+    fn estimate(&self, state: &WindowState, elapsed: Duration) -> f64 {
+        let previous_weight = 1.0 - elapsed.as_secs_f64() / self.window.as_secs_f64();
+        f64::from(state.previous_count) * previous_weight + f64::from(state.current_count)
+    }
+
+    /// Time from the start of a window until `previous_count * (1 - fraction) +
+    /// current_count + 1` no longer exceeds the limit.
+    fn admission_offset(&self, previous_count: u32, current_count: u32) -> Duration {
+        let spare = f64::from(self.limit) - f64::from(current_count) - 1.0;
+        let fraction = if previous_count == 0 {
+            0.0
+        } else {
+            (1.0 - spare / f64::from(previous_count)).clamp(0.0, 1.0)
+        };
+        self.window.mul_f64(fraction)
+    }
+}
+
+impl RateLimitingAlgorithm for SlidingWindowCounter {
+    fn check(&self, now: Instant) -> Result<RateLimitStatus, RateLimitRejection> {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        state.advance(self.window, now);
+        let elapsed = duration_between(now, state.window_start);
+        let estimate = self.estimate(&state, elapsed);
+        if estimate + 1.0 > f64::from(self.limit) {
+            let retry_after = if state.current_count < self.limit {
+                self.admission_offset(state.previous_count, state.current_count)
+                    .saturating_sub(elapsed)
+            } else {
+                // Nothing more fits in this window; in the next one, the current
+                // count becomes the weighted previous count
+                duration_between(state.window_start + self.window, now)
+                    + self.admission_offset(state.current_count, 0)
+            };
+            return Err(RateLimitRejection::LimitExceeded {
+                limit: self.limit,
+                retry_after: retry_after.max(Duration::from_millis(1)),
+                header_style: None,
+            });
+        }
+        state.current_count += 1;
+        Ok(RateLimitStatus {
+            limit: self.limit,
+            remaining: (f64::from(self.limit) - estimate - 1.0).floor() as u32,
+            reset_after: duration_between(state.window_start + self.window * 2, now),
+            header_style: None,
+        })
+    }
+
+    fn consumed(&self, now: Instant) -> u32 {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        state.advance(self.window, now);
+        let elapsed = duration_between(now, state.window_start);
+        (self.estimate(&state, elapsed).ceil() as u32).min(self.limit)
+    }
+
+    fn charge(&self, count: u32, now: Instant) {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        state.advance(self.window, now);
+        state.current_count = state.current_count.saturating_add(count).min(self.limit);
+    }
+}
+
+struct KeyedBucket {
+    algorithm: Box<dyn RateLimitingAlgorithm>,
+    cluster_counter: Arc<ClusterCounter>,
+    /// Nanoseconds since the `epoch` of the owning limiter.
+    last_seen: AtomicU64,
+}
+
+/// Keyed limiter of an Origin. Keys are admitted up to `max_keys`; keys idle for
+/// longer than `idle_key_timeout` are forgotten so that the key space stays bounded
+/// regardless of how many distinct clients show up. Checks of known keys only take
+/// a read lock.
+///
+/// Buckets are kept in a map of our own rather than governor's keyed state, which
+/// only implements GCRA and cannot hold the algorithm of the configuration, the
+/// cluster counter of a key or the overflow bucket.
+struct KeyedRateLimiter {
+    rate_limiter_config: RateLimiterConfig,
+    epoch: Instant,
+    buckets: RwLock<HashMap<String, Arc<KeyedBucket>>>,
+    /// Set once evicting idle keys could not make room for a new key. New keys
+    /// then go to the overflow bucket until the periodic eviction frees room, so
+    /// that clients sending new keys cannot make every check scan all buckets.
+    key_space_full: AtomicBool,
+    max_keys: usize,
+    idle_key_timeout: Duration,
+    shadow_stats: Option<Arc<ShadowStats>>,
+}
+
+impl KeyedRateLimiter {
+    fn new(rate_limiter_config: &RateLimiterConfig) -> Self {
+        KeyedRateLimiter {
+            rate_limiter_config: rate_limiter_config.clone(),
+            epoch: Instant::now(),
+            buckets: RwLock::new(HashMap::new()),
+            key_space_full: AtomicBool::new(false),
+            max_keys: rate_limiter_config.max_keys.unwrap_or(DEFAULT_MAX_KEYS),
+            idle_key_timeout: Duration::from_secs(
+                rate_limiter_config
+                    .idle_key_timeout
+                    .unwrap_or(DEFAULT_IDLE_KEY_TIMEOUT),
+            ),
+            shadow_stats: shadow_stats(rate_limiter_config, None),
+        }
+    }
+
+    /// Rebuilds the limiter for a new configuration. Buckets of known keys carry
+    /// over their consumed capacity, unless the key now identifies something else.
+    fn reconfigure(&self, rate_limiter_config: &RateLimiterConfig, now: Instant) -> Self {
+        let mut rate_limiter = KeyedRateLimiter::new(rate_limiter_config);
+        rate_limiter.shadow_stats = shadow_stats(rate_limiter_config, self.shadow_stats.as_ref());
+        if self.rate_limiter_config.key == rate_limiter_config.key {
+            rate_limiter.epoch = self.epoch;
+            rate_limiter.buckets = RwLock::new(
+                self.read_buckets()
+                    .iter()
+                    .map(|(key, bucket)| {
+                        let bucket = KeyedBucket {
+                            algorithm: carry_over(
+                                bucket.algorithm.as_ref(),
+                                rate_limiter_config,
+                                now,
+                            ),
+                            cluster_counter: bucket.cluster_counter.clone(),
+                            last_seen: AtomicU64::new(bucket.last_seen.load(Ordering::Relaxed)),
+                        };
+                        (key.clone(), Arc::new(bucket))
+                    })
+                    .collect(),
+            );
+        }
+        rate_limiter
+    }
+
+    fn read_buckets(&self) -> RwLockReadGuard<'_, HashMap<String, Arc<KeyedBucket>>> {
+        self.buckets
+            .read()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+
+    fn write_buckets(&self) -> RwLockWriteGuard<'_, HashMap<String, Arc<KeyedBucket>>> {
+        self.buckets
+            .write()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+
+    fn tracked_keys(buckets: &HashMap<String, Arc<KeyedBucket>>) -> usize {
+        buckets.len() - usize::from(buckets.contains_key(OVERFLOW_KEY))
+    }
+
+    /// Returns the key whose bucket the request is counted against along with the
+    /// bucket, creating the bucket of a new key if the key space allows.
+    fn admit_key(&self, key: String, now: Instant) -> (String, Arc<KeyedBucket>) {
+        let bucket = self.read_buckets().get(&key).cloned();
+        let (key, bucket) = match bucket {
+            Some(bucket) => (key, bucket),
+            None => {
+                let mut buckets = self.write_buckets();
+                let key = if buckets.contains_key(&key)
+                    || KeyedRateLimiter::tracked_keys(&buckets) < self.max_keys
+                {
+                    key
+                } else if self.key_space_full.load(Ordering::Relaxed) {
+                    trace!("Rate limiter key space is full, using overflow bucket");
+                    String::from(OVERFLOW_KEY)
+                } else {
+                    self.evict_idle_buckets(&mut buckets, now);
+                    if KeyedRateLimiter::tracked_keys(&buckets) < self.max_keys {
+                        key
+                    } else {
+                        trace!("Rate limiter key space is full, using overflow bucket");
+                        self.key_space_full.store(true, Ordering::Relaxed);
+                        String::from(OVERFLOW_KEY)
                     }
+                };
+                let bucket = buckets
+                    .entry(key.clone())
+                    .or_insert_with(|| {
+                        Arc::new(KeyedBucket {
+                            algorithm: create_algorithm(&self.rate_limiter_config, now),
+                            cluster_counter: Arc::new(ClusterCounter::new()),
+                            last_seen: AtomicU64::new(0),
+                        })
+                    })
+                    .clone();
+                (key, bucket)
+            }
+        };
+        bucket.last_seen.store(
+            duration_between(now, self.epoch).as_nanos() as u64,
+            Ordering::Relaxed,
+        );
+        (key, bucket)
+    }
+
+    fn evict_idle_buckets(&self, buckets: &mut HashMap<String, Arc<KeyedBucket>>, now: Instant) {
+        let now = duration_between(now, self.epoch).as_nanos() as u64;
+        let idle_key_timeout = self.idle_key_timeout.as_nanos() as u64;
+        buckets.retain(|key, bucket| {
+            key == OVERFLOW_KEY
+                || now.saturating_sub(bucket.last_seen.load(Ordering::Relaxed)) < idle_key_timeout
+        });
+    }
+
+    /// Evicts idle keys and returns how many were evicted.
+    fn evict_idle_keys(&self, now: Instant) -> usize {
+        let mut buckets = self.write_buckets();
+        let tracked_keys = buckets.len();
+        self.evict_idle_buckets(&mut buckets, now);
+        buckets.shrink_to_fit();
+        if KeyedRateLimiter::tracked_keys(&buckets) < self.max_keys {
+            self.key_space_full.store(false, Ordering::Relaxed);
+        }
+        tracked_keys - buckets.len()
+    }
+}
+
+/// Statistics for a limiter in shadow mode, which keep counting across
+/// configuration updates while the limiter stays in shadow mode.
+fn shadow_stats(
+    rate_limiter_config: &RateLimiterConfig,
+    previous: Option<&Arc<ShadowStats>>,
+) -> Option<Arc<ShadowStats>> {
+    match rate_limiter_config.mode {
+        Some(RateLimitMode::Shadow) => Some(
+            previous
+                .cloned()
+                .unwrap_or_else(|| Arc::new(ShadowStats::new())),
+        ),
+        _ => None,
+    }
+}
+
+fn observe_shadow(
+    shadow_stats: Option<&Arc<ShadowStats>>,
+    limiter_id: &str,
+    key: &str,
+    result: Result<RateLimitStatus, RateLimitRejection>,
+) -> Result<RateLimitStatus, RateLimitRejection> {
+    match shadow_stats {
+        None => result,
+        Some(shadow_stats) => shadow_stats.observe(limiter_id, key, result),
+    }
+}
+
+enum ConfiguredRateLimiter {
+    Direct {
+        rate_limiter_config: RateLimiterConfig,
+        algorithm: Box<dyn RateLimitingAlgorithm>,
+        cluster_counter: Arc<ClusterCounter>,
+        shadow_stats: Option<Arc<ShadowStats>>,
+    },
+    Keyed(KeyedRateLimiter),
+    Quota(Mutex<QuotaLimiter>),
+}
+
+impl ConfiguredRateLimiter {
+    fn new(rate_limiter_config: &RateLimiterConfig) -> Self {
+        match rate_limiter_config.key {
+            None => ConfiguredRateLimiter::Direct {
+                rate_limiter_config: rate_limiter_config.clone(),
+                algorithm: create_algorithm(rate_limiter_config, Instant::now()),
+                cluster_counter: Arc::new(ClusterCounter::new()),
+                shadow_stats: shadow_stats(rate_limiter_config, None),
+            },
+            Some(_) => ConfiguredRateLimiter::Keyed(KeyedRateLimiter::new(rate_limiter_config)),
+        }
+    }
+
+    /// Returns the limiter for `rate_limiter_config`, which is `self` if the
+    /// configuration did not change. Otherwise consumed capacity is carried over,
+    /// so that a configuration push never grants a free burst. Switching between
+    /// direct and keyed limiting starts from scratch.
+    fn reconfigure(self: &Arc<Self>, rate_limiter_config: &RateLimiterConfig) -> Arc<Self> {
+        let now = Instant::now();
+        let rate_limiter = match (self.as_ref(), &rate_limiter_config.key) {
+            (
+                ConfiguredRateLimiter::Direct {
+                    rate_limiter_config: previous_config,
+                    algorithm,
+                    cluster_counter,
+                    shadow_stats: previous_shadow_stats,
                 },
-                RateLimiterAPI::UpdateOriginSpecification {
-                    origin_id,
-                    rate_limiter_spec,
-                } => {
-                    api_rate_limiter_map.insert(
-                        origin_id.clone(),
-                        RateLimiter::direct(calculate_quota(rate_limiter_spec)),
-                    );
-                    debug!(
-                        "Origin specification updated in rate limiter for Origin (Origin ID: {})",
-                        origin_id
+                None,
+            ) => {
+                if previous_config == rate_limiter_config {
+                    return self.clone();
+                }
+                ConfiguredRateLimiter::Direct {
+                    rate_limiter_config: rate_limiter_config.clone(),
+                    algorithm: carry_over(algorithm.as_ref(), rate_limiter_config, now),
+                    cluster_counter: cluster_counter.clone(),
+                    shadow_stats: shadow_stats(rate_limiter_config, previous_shadow_stats.as_ref()),
+                }
+            }
+            (ConfiguredRateLimiter::Keyed(rate_limiter), Some(_)) => {
+                if rate_limiter.rate_limiter_config == *rate_limiter_config {
+                    return self.clone();
+                }
+                ConfiguredRateLimiter::Keyed(rate_limiter.reconfigure(rate_limiter_config, now))
+            }
+            _ => ConfiguredRateLimiter::new(rate_limiter_config),
+        };
+        Arc::new(rate_limiter)
+    }
+
+    fn shadow_stats(&self) -> Option<&Arc<ShadowStats>> {
+        match self {
+            ConfiguredRateLimiter::Direct { shadow_stats, .. } => shadow_stats.as_ref(),
+            ConfiguredRateLimiter::Keyed(rate_limiter) => rate_limiter.shadow_stats.as_ref(),
+            ConfiguredRateLimiter::Quota(_) => None,
+        }
+    }
+
+    fn header_style(&self) -> Option<&RateLimitHeaderStyle> {
+        match self {
+            ConfiguredRateLimiter::Direct {
+                rate_limiter_config,
+                ..
+            } => rate_limiter_config.header_style.as_ref(),
+            ConfiguredRateLimiter::Keyed(rate_limiter) => {
+                rate_limiter.rate_limiter_config.header_style.as_ref()
+            }
+            ConfiguredRateLimiter::Quota(_) => None,
+        }
+    }
+
+    /// Checks the request against the limiter of `limiter_id`, which is shared
+    /// with other instances if there is a cluster backend. Quotas are always local.
+    /// Limiters in shadow mode count would-be rejections instead of rejecting.
+    async fn check(
+        &self,
+        limiter_id: &str,
+        key: Option<String>,
+        cluster_backend: Option<&ClusterBackend>,
+    ) -> Result<RateLimitStatus, RateLimitRejection> {
+        match self {
+            ConfiguredRateLimiter::Direct {
+                rate_limiter_config,
+                algorithm,
+                cluster_counter,
+                shadow_stats,
+            } => {
+                let result = match cluster_backend {
+                    None => algorithm.check(Instant::now()),
+                    Some(cluster_backend) => {
+                        check_in_cluster(
+                            cluster_backend,
+                            &cluster_backend.bucket_key(limiter_id, None),
+                            rate_limiter_config,
+                            algorithm.as_ref(),
+                            cluster_counter,
+                        )
+                        .await
+                    }
+                };
+                observe_shadow(
+                    shadow_stats.as_ref(),
+                    limiter_id,
+                    UNIDENTIFIED_CLIENT_KEY,
+                    result,
+                )
+            }
+            ConfiguredRateLimiter::Keyed(rate_limiter) => {
+                let now = Instant::now();
+                let (key, bucket) = rate_limiter.admit_key(
+                    key.unwrap_or_else(|| String::from(UNIDENTIFIED_CLIENT_KEY)),
+                    now,
+                );
+                let result = match cluster_backend {
+                    None => bucket.algorithm.check(now),
+                    Some(cluster_backend) => {
+                        check_in_cluster(
+                            cluster_backend,
+                            &cluster_backend.bucket_key(limiter_id, Some(&key)),
+                            &rate_limiter.rate_limiter_config,
+                            bucket.algorithm.as_ref(),
+                            &bucket.cluster_counter,
+                        )
+                        .await
+                    }
+                };
+                observe_shadow(rate_limiter.shadow_stats.as_ref(), limiter_id, &key, result)
+            }
+            ConfiguredRateLimiter::Quota(quota_limiter) => lock_quota_limiter(quota_limiter).check(
+                key.unwrap_or_else(|| String::from(UNIDENTIFIED_CLIENT_KEY)),
+                Utc::now(),
+            ),
+        }
+    }
+}
+
+/// Checks a bucket shared with the other instances. In `Strict` mode the backend
+/// decides, and admitted requests are charged to the local bucket as well so that
+/// it is current should the backend become unavailable. Otherwise the local bucket
+/// decides, and admitted requests are recorded for the next sync.
+async fn check_in_cluster(
+    cluster_backend: &ClusterBackend,
+    bucket_key: &str,
+    rate_limiter_config: &RateLimiterConfig,
+    algorithm: &dyn RateLimitingAlgorithm,
+    cluster_counter: &ClusterCounter,
+) -> Result<RateLimitStatus, RateLimitRejection> {
+    match cluster_backend.mode() {
+        ClusterMode::Strict => {
+            if cluster_backend.is_available() {
+                let quota = calculate_quota(rate_limiter_config);
+                if let Ok(result) = cluster_backend.check(bucket_key, &quota).await {
+                    if result.is_ok() {
+                        algorithm.charge(1, Instant::now());
+                    }
+                    return result;
+                }
+            }
+            algorithm.check(Instant::now())
+        }
+        ClusterMode::LocalApproximation => {
+            let result = algorithm.check(Instant::now());
+            if result.is_ok() {
+                cluster_counter.record_admitted();
+            }
+            result
+        }
+    }
+}
+
+fn lock_quota_limiter(quota_limiter: &Mutex<QuotaLimiter>) -> MutexGuard<'_, QuotaLimiter> {
+    quota_limiter
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+}
+
+type RateLimiterMap = HashMap<String, Arc<ConfiguredRateLimiter>>;
+
+/// Limiters shared between the proxy, which checks requests against them
+/// directly, and the rate limiter task, which applies configuration updates by
+/// swapping in a new map. Checks never wait for updates or for each other. With a
+/// cluster backend, Origin and API limits are enforced across all instances.
+#[derive(Clone)]
+pub(crate) struct RateLimiters {
+    rate_limiter_map: Arc<ArcSwap<RateLimiterMap>>,
+    cluster_backend: Option<Arc<ClusterBackend>>,
+}
+
+impl RateLimiters {
+    pub(crate) fn new(cluster_backend: Option<ClusterBackend>) -> Self {
+        RateLimiters {
+            rate_limiter_map: Arc::new(ArcSwap::from_pointee(HashMap::new())),
+            cluster_backend: cluster_backend.map(Arc::new),
+        }
+    }
+
+    /// Runs the checks against the current limiters; see `check_all`.
+    pub(crate) async fn check(
+        &self,
+        checks: Vec<RateLimitCheck>,
+    ) -> Result<RateLimitStatus, RateLimitRejection> {
+        match &self.cluster_backend {
+            None => check_all(&self.rate_limiter_map.load(), None, checks).await,
+            Some(cluster_backend) => check_all(&self.load(), Some(cluster_backend), checks).await,
+        }
+    }
+
+    pub(crate) fn quota_usage(&self) -> Vec<QuotaUsage> {
+        quota_usage(&self.rate_limiter_map.load())
+    }
+
+    pub(crate) fn shadow_usage(&self) -> Vec<ShadowUsage> {
+        let mut shadow_usage: Vec<ShadowUsage> = self
+            .rate_limiter_map
+            .load()
+            .iter()
+            .filter_map(|(limiter_id, rate_limiter)| {
+                rate_limiter
+                    .shadow_stats()
+                    .map(|shadow_stats| shadow_stats.usage(limiter_id))
+            })
+            .collect();
+        shadow_usage.sort_by(|a, b| a.limiter_id.cmp(&b.limiter_id));
+        shadow_usage
+    }
+
+    fn load(&self) -> Arc<RateLimiterMap> {
+        self.rate_limiter_map.load_full()
+    }
+
+    /// Publishes a modified copy of the map. Only the rate limiter task updates
+    /// the map, so updates cannot race each other.
+    fn update(&self, modify: impl FnOnce(&mut RateLimiterMap)) {
+        let mut rate_limiter_map = RateLimiterMap::clone(&self.rate_limiter_map.load());
+        modify(&mut rate_limiter_map);
+        self.rate_limiter_map.store(Arc::new(rate_limiter_map));
+    }
+}
+
+fn evict_idle_keys(rate_limiter_map: &RateLimiterMap) {
+    let now = Instant::now();
+    for (limiter_id, rate_limiter) in rate_limiter_map.iter() {
+        if let ConfiguredRateLimiter::Keyed(rate_limiter) = rate_limiter.as_ref() {
+            let evicted_keys = rate_limiter.evict_idle_keys(now);
+            if evicted_keys > 0 {
+                info!(
+                    "Evicted {} idle keys of rate limiter {}",
+                    evicted_keys, limiter_id
+                );
+            }
+        }
+    }
+}
+
+/// Runs every check in order and stops at the first rejection. Checks that ran
+/// before a rejection have consumed capacity, so callers list the most specific
+/// limiters first. On success the status of the limiter with the least remaining
+/// budget is returned. Limiters in shadow mode only decide the status if no
+/// enforced limiter applies. Statuses and rejections carry the header style of
+/// the limiter they come from.
+async fn check_all(
+    rate_limiter_map: &RateLimiterMap,
+    cluster_backend: Option<&ClusterBackend>,
+    checks: Vec<RateLimitCheck>,
+) -> Result<RateLimitStatus, RateLimitRejection> {
+    let mut most_restrictive: Option<RateLimitStatus> = None;
+    let mut most_restrictive_shadow: Option<RateLimitStatus> = None;
+    for check in checks {
+        match rate_limiter_map.get(&check.limiter_id) {
+            None => {
+                debug!("No rate limiter found for limiter {}", check.limiter_id);
+                return Err(RateLimitRejection::UnknownLimiter);
+            }
+            Some(rate_limiter) => match rate_limiter
+                .check(&check.limiter_id, check.key, cluster_backend)
+                .await
+            {
+                Err(mut rejection) => {
+                    trace!("Rate limiter {} rejected the request", check.limiter_id);
+                    if let RateLimitRejection::LimitExceeded { header_style, .. } = &mut rejection {
+                        *header_style = rate_limiter.header_style().cloned();
+                    }
+                    return Err(rejection);
+                }
+                Ok(mut status) => {
+                    status.header_style = rate_limiter.header_style().cloned();
+                    let most_restrictive = match rate_limiter.shadow_stats() {
+                        None => &mut most_restrictive,
+                        Some(_) => &mut most_restrictive_shadow,
+                    };
+                    if most_restrictive
+                        .as_ref()
+                        .is_none_or(|current| status.remaining < current.remaining)
+                    {
+                        *most_restrictive = Some(status);
+                    }
+                }
+            },
+        }
+    }
+    most_restrictive
+        .or(most_restrictive_shadow)
+        .ok_or(RateLimitRejection::UnknownLimiter)
+}
+
+/// Installs the limiter of `limiter_id` for a new configuration, reconfiguring the
+/// existing limiter if there is one.
+fn update_rate_limiter(
+    rate_limiter_map: &mut RateLimiterMap,
+    limiter_id: String,
+    rate_limiter_config: &RateLimiterConfig,
+) {
+    let rate_limiter = match rate_limiter_map.get(&limiter_id) {
+        None => Arc::new(ConfiguredRateLimiter::new(rate_limiter_config)),
+        Some(rate_limiter) => rate_limiter.reconfigure(rate_limiter_config),
+    };
+    rate_limiter_map.insert(limiter_id, rate_limiter);
+}
+
+/// Exchanges the requests admitted locally with the other instances and charges
+/// every bucket with what the others admitted to it since the previous sync.
+async fn sync_cluster_counters(
+    rate_limiter_map: &RateLimiterMap,
+    cluster_backend: &ClusterBackend,
+) {
+    let mut keyed_buckets = vec![];
+    let mut counters = vec![];
+    let mut algorithms = vec![];
+    for (limiter_id, rate_limiter) in rate_limiter_map.iter() {
+        match rate_limiter.as_ref() {
+            ConfiguredRateLimiter::Direct {
+                rate_limiter_config,
+                algorithm,
+                cluster_counter,
+                ..
+            } => {
+                counters.push((
+                    cluster_backend.bucket_key(limiter_id, None),
+                    time_unit_duration(&rate_limiter_config.time_unit),
+                    cluster_counter.as_ref(),
+                ));
+                algorithms.push(algorithm.as_ref());
+            }
+            ConfiguredRateLimiter::Keyed(rate_limiter) => {
+                let window = time_unit_duration(&rate_limiter.rate_limiter_config.time_unit);
+                keyed_buckets.extend(rate_limiter.read_buckets().iter().map(|(key, bucket)| {
+                    (
+                        cluster_backend.bucket_key(limiter_id, Some(key)),
+                        window,
+                        bucket.clone(),
+                    )
+                }));
+            }
+            ConfiguredRateLimiter::Quota(_) => {}
+        }
+    }
+    for (bucket_key, window, bucket) in keyed_buckets.iter() {
+        counters.push((bucket_key.clone(), *window, bucket.cluster_counter.as_ref()));
+        algorithms.push(bucket.algorithm.as_ref());
+    }
+    if counters.is_empty() {
+        return;
+    }
+    // Failures are logged by the backend; local limits apply meanwhile
+    if let Ok(remote_admitted) = cluster_backend.sync_counters(&counters).await {
+        let now = Instant::now();
+        for (algorithm, remote_admitted) in algorithms.into_iter().zip(remote_admitted) {
+            if remote_admitted > 0 {
+                algorithm.charge(remote_admitted, now);
+            }
+        }
+    }
+}
+
+/// Persists the counters changed since the last flush and compacts the quota
+/// store once enough records were appended to it.
+fn flush_quota_usage(rate_limiter_map: &RateLimiterMap, quota_store: &mut QuotaStore) {
+    let now = Utc::now();
+    for (limiter_id, rate_limiter) in rate_limiter_map.iter() {
+        if let ConfiguredRateLimiter::Quota(quota_limiter) = rate_limiter.as_ref() {
+            let mut quota_limiter = lock_quota_limiter(quota_limiter);
+            let dirty_counters = quota_limiter.take_dirty_counters(now);
+            quota_store.append(limiter_id, quota_limiter.period_end(now), dirty_counters);
+        }
+    }
+    if quota_store.needs_compaction() {
+        let live_quotas = rate_limiter_map
+            .iter()
+            .filter_map(|(limiter_id, rate_limiter)| match rate_limiter.as_ref() {
+                ConfiguredRateLimiter::Quota(quota_limiter) => {
+                    let mut quota_limiter = lock_quota_limiter(quota_limiter);
+                    Some(LiveQuotaCounters {
+                        quota_id: limiter_id.clone(),
+                        period_end: quota_limiter.period_end(now),
+                        counters: quota_limiter.current_counters(now),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        quota_store.compact(live_quotas, now);
+    }
+}
+
+/// Updates the quota limiters of an API. Limiters of quotas that still exist keep
+/// their counters; counters of removed quotas are persisted and handed back to
+/// the store in case the quota returns.
+fn update_api_quotas(
+    rate_limiter_map: &mut RateLimiterMap,
+    quota_store: &mut QuotaStore,
+    api_id: &str,
+    quota_policies: &[QuotaPolicy],
+) {
+    let mut policy_limiter_ids = HashSet::new();
+    for (quota_index, (limiter_id, quota_policy)) in quota_limiter_ids(api_id, quota_policies)
+        .into_iter()
+        .zip(quota_policies)
+        .enumerate()
+    {
+        policy_limiter_ids.insert(limiter_id.clone());
+        match rate_limiter_map
+            .get(&limiter_id)
+            .map(|limiter| limiter.as_ref())
+        {
+            Some(ConfiguredRateLimiter::Quota(quota_limiter)) => {
+                lock_quota_limiter(quota_limiter).reconfigure(quota_policy)
+            }
+            _ => {
+                let mut counters = quota_store.take_counters(&limiter_id);
+                if counters.is_empty() {
+                    counters =
+                        quota_store.take_counters(&legacy_quota_limiter_id(api_id, quota_index));
+                }
+                let quota_limiter = QuotaLimiter::new(quota_policy, counters);
+                rate_limiter_map.insert(
+                    limiter_id,
+                    Arc::new(ConfiguredRateLimiter::Quota(Mutex::new(quota_limiter))),
+                );
+            }
+        }
+    }
+    let quota_limiter_prefix = quota_limiter_prefix(api_id);
+    let removed_limiter_ids: Vec<String> = rate_limiter_map
+        .keys()
+        .filter(|limiter_id| {
+            limiter_id.starts_with(quota_limiter_prefix.as_str())
+                && !policy_limiter_ids.contains(*limiter_id)
+        })
+        .cloned()
+        .collect();
+    for limiter_id in removed_limiter_ids {
+        if let Some(rate_limiter) = rate_limiter_map.remove(&limiter_id) {
+            if let ConfiguredRateLimiter::Quota(quota_limiter) = rate_limiter.as_ref() {
+                let mut quota_limiter = lock_quota_limiter(quota_limiter);
+                let now = Utc::now();
+                let period_end = quota_limiter.period_end(now);
+                let dirty_counters = quota_limiter.take_dirty_counters(now);
+                quota_store.append(&limiter_id, period_end, dirty_counters);
+                quota_store.return_counters(&limiter_id, period_end, quota_limiter.take_counters());
+            }
+        }
+    }
+}
+
+fn quota_usage(rate_limiter_map: &RateLimiterMap) -> Vec<QuotaUsage> {
+    let now = Utc::now();
+    let mut quota_usage: Vec<QuotaUsage> = rate_limiter_map
+        .iter()
+        .filter_map(|(limiter_id, rate_limiter)| match rate_limiter.as_ref() {
+            ConfiguredRateLimiter::Quota(quota_limiter) => {
+                Some(lock_quota_limiter(quota_limiter).usage(limiter_id, now))
+            }
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    quota_usage.sort_by(|a, b| (&a.quota_id, &a.key).cmp(&(&b.quota_id, &b.key)));
+    quota_usage
+}
+
+fn handle_api_call(
+    rate_limiters: &RateLimiters,
+    quota_store: &mut QuotaStore,
+    api_call: RateLimiterAPI,
+) {
+    match api_call {
+        RateLimiterAPI::UpdateOriginSpecification {
+            origin_id,
+            rate_limiter_spec,
+        } => {
+            rate_limiters.update(|rate_limiter_map| {
+                update_rate_limiter(
+                    rate_limiter_map,
+                    origin_limiter_id(&origin_id),
+                    &rate_limiter_spec,
+                )
+            });
+            debug!(
+                "Origin specification updated in rate limiter for Origin (Origin ID: {})",
+                origin_id
+            );
+        }
+        RateLimiterAPI::RemoveOriginSpecification { origin_id } => {
+            rate_limiters.update(|rate_limiter_map| {
+                rate_limiter_map.remove(&origin_limiter_id(&origin_id));
+            });
+            debug!(
+                "Origin specification removed from rate limiter for Origin (Origin ID: {})",
+                origin_id
+            );
+        }
+        RateLimiterAPI::UpdateAPISpecification {
+            api_id,
+            rate_limit_policies,
+            quota_policies,
+        } => {
+            rate_limiters.update(|rate_limiter_map| {
+                let mut policy_limiter_ids = HashSet::new();
+                for (limiter_id, rate_limit_policy) in
+                    api_limiter_ids(&api_id, &rate_limit_policies)
+                        .into_iter()
+                        .zip(&rate_limit_policies)
+                {
+                    policy_limiter_ids.insert(limiter_id.clone());
+                    update_rate_limiter(
+                        rate_limiter_map,
+                        limiter_id,
+                        &rate_limit_policy.rate_limiter,
                     );
                 }
+                let api_limiter_prefix = api_limiter_prefix(&api_id);
+                rate_limiter_map.retain(|limiter_id, _| {
+                    !limiter_id.starts_with(api_limiter_prefix.as_str())
+                        || policy_limiter_ids.contains(limiter_id)
+                });
+                update_api_quotas(rate_limiter_map, quota_store, &api_id, &quota_policies);
+            });
+            debug!(
+                "API specification updated in rate limiter for APIDefinition (APIDefinition ID: {})",
+                api_id
+            );
+        }
+    }
+}
+
+/// Applies configuration updates to the shared limiters and does their upkeep.
+/// Requests are checked against the limiters directly through `RateLimiters`.
+pub(crate) async fn deploy_rate_limiter(
+    mut receiver: Receiver<RateLimiterAPI>,
+    rate_limiters: RateLimiters,
+) {
+    let mut quota_store = QuotaStore::open(&get_directory_of_executable().join(QUOTA_STORE_PATH));
+    let mut key_eviction_interval = interval(KEY_EVICTION_INTERVAL);
+    let mut quota_flush_interval = interval(QUOTA_FLUSH_INTERVAL);
+    let cluster_sync_backend = rate_limiters
+        .cluster_backend
+        .clone()
+        .filter(|cluster_backend| *cluster_backend.mode() == ClusterMode::LocalApproximation);
+    let mut cluster_sync_interval = interval(
+        cluster_sync_backend
+            .as_ref()
+            .map_or(KEY_EVICTION_INTERVAL, |cluster_backend| {
+                cluster_backend.sync_interval()
+            }),
+    );
+    loop {
+        tokio::select! {
+            api_call = receiver.recv() => match api_call {
+                None => break,
+                Some(api_call) => handle_api_call(&rate_limiters, &mut quota_store, api_call),
+            },
+            _ = key_eviction_interval.tick() => evict_idle_keys(&rate_limiters.load()),
+            _ = quota_flush_interval.tick() => {
+                flush_quota_usage(&rate_limiters.load(), &mut quota_store)
+            }
+            _ = cluster_sync_interval.tick(), if cluster_sync_backend.is_some() => {
+                if let Some(cluster_backend) = &cluster_sync_backend {
+                    sync_cluster_counters(&rate_limiters.load(), cluster_backend).await
+                }
             }
         }
     }
+    flush_quota_usage(&rate_limiters.load(), &mut quota_store);
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
     use std::num::NonZeroU32;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
     use governor::Quota;
 
-    use crate::configuration_reader::origin_def_reader::{RateLimiterConfig, TimeUnit};
+    use crate::configuration_reader::api_def_reader::{QuotaPeriod, QuotaPolicy};
+    use crate::configuration_reader::origin_def_reader::{
+        RateLimitAlgorithm, RateLimitHeaderStyle, RateLimitKey, RateLimitMode, RateLimiterConfig,
+        TimeUnit,
+    };
+    use crate::core::rate_limiter::rate_limiter_api::{
+        quota_limiter_ids, RateLimitCheck, RateLimitRejection, RateLimitStatus,
+    };
+    use crate::core::rate_limiter::shadow::key_digest;
 
-    use super::{calculate_quota, create_non_zero_u32_from_u32};
+    use super::{
+        calculate_quota, carry_over, check_all, create_algorithm, create_non_zero_u32_from_u32,
+        handle_api_call, sync_cluster_counters, update_rate_limiter, ConfiguredRateLimiter,
+        KeyedRateLimiter, QuotaStore, RateLimiterAPI, RateLimiters, RateLimitingAlgorithm,
+        OVERFLOW_KEY,
+    };
+    use crate::configuration_reader::cluster_config_reader::{ClusterConfig, ClusterMode};
+    use crate::core::rate_limiter::cluster::stand_in::{spawn_stand_in, unreachable_address};
+    use crate::core::rate_limiter::cluster::ClusterBackend;
 
     #[test]
     fn test_create_nonzero_u32_valid() {
@@ -124,36 +1257,961 @@ mod test {
     #[test]
     fn test_calculate_quota_hour() {
         let rate_limiter_config = RateLimiterConfig {
+            algorithm: RateLimitAlgorithm::TokenBucket,
             time_unit: TimeUnit::Hour,
             req_per_time_unit: 45,
+            burst_size: None,
+            key: None,
+            max_keys: None,
+            idle_key_timeout: None,
+            header_style: None,
+            mode: None,
         };
         assert_eq!(
             Quota::per_hour(NonZeroU32::new(45).unwrap()),
-            calculate_quota(rate_limiter_config)
+            calculate_quota(&rate_limiter_config)
         );
     }
 
     #[test]
     fn test_calculate_quota_minute() {
         let rate_limiter_config = RateLimiterConfig {
+            algorithm: RateLimitAlgorithm::TokenBucket,
             time_unit: TimeUnit::Minute,
             req_per_time_unit: 52,
+            burst_size: None,
+            key: None,
+            max_keys: None,
+            idle_key_timeout: None,
+            header_style: None,
+            mode: None,
         };
         assert_eq!(
             Quota::per_minute(NonZeroU32::new(52).unwrap()),
-            calculate_quota(rate_limiter_config)
+            calculate_quota(&rate_limiter_config)
         );
     }
 
     #[test]
     fn test_calculate_quota_second() {
         let rate_limiter_config = RateLimiterConfig {
+            algorithm: RateLimitAlgorithm::TokenBucket,
             time_unit: TimeUnit::Second,
             req_per_time_unit: 24,
+            burst_size: None,
+            key: None,
+            max_keys: None,
+            idle_key_timeout: None,
+            header_style: None,
+            mode: None,
         };
         assert_eq!(
             Quota::per_second(NonZeroU32::new(24).unwrap()),
-            calculate_quota(rate_limiter_config)
+            calculate_quota(&rate_limiter_config)
+        );
+    }
+
+    fn keyed_rate_limiter(req_per_time_unit: u32, max_keys: usize) -> KeyedRateLimiter {
+        KeyedRateLimiter::new(&RateLimiterConfig {
+            algorithm: RateLimitAlgorithm::TokenBucket,
+            time_unit: TimeUnit::Hour,
+            req_per_time_unit,
+            burst_size: None,
+            key: Some(RateLimitKey::ClientIp),
+            max_keys: Some(max_keys),
+            idle_key_timeout: Some(60),
+            header_style: None,
+            mode: None,
+        })
+    }
+
+    #[test]
+    fn test_keys_are_limited_independently() {
+        let rate_limiter = keyed_rate_limiter(2, 10);
+        let check = |key: &str| {
+            let now = Instant::now();
+            rate_limiter
+                .admit_key(String::from(key), now)
+                .1
+                .algorithm
+                .check(now)
+        };
+        assert!(check("a").is_ok());
+        assert!(check("a").is_ok());
+        assert!(check("a").is_err());
+        assert!(check("b").is_ok());
+    }
+
+    #[test]
+    fn test_new_keys_overflow_when_key_space_is_full() {
+        let rate_limiter = keyed_rate_limiter(5, 2);
+        let now = Instant::now();
+        assert_eq!("a", rate_limiter.admit_key(String::from("a"), now).0);
+        assert_eq!("b", rate_limiter.admit_key(String::from("b"), now).0);
+        assert_eq!(
+            OVERFLOW_KEY,
+            rate_limiter.admit_key(String::from("c"), now).0
+        );
+        assert_eq!("a", rate_limiter.admit_key(String::from("a"), now).0);
+    }
+
+    #[test]
+    fn test_idle_keys_are_evicted() {
+        let rate_limiter = keyed_rate_limiter(5, 2);
+        let now = Instant::now();
+        rate_limiter.admit_key(String::from("a"), now);
+        rate_limiter.admit_key(String::from("b"), now);
+        let later = now + Duration::from_secs(61);
+        assert_eq!("c", rate_limiter.admit_key(String::from("c"), later).0);
+        assert_eq!(
+            1,
+            KeyedRateLimiter::tracked_keys(&rate_limiter.read_buckets())
+        );
+    }
+
+    #[test]
+    fn test_full_key_space_is_only_scanned_again_by_the_eviction_timer() {
+        let rate_limiter = keyed_rate_limiter(5, 2);
+        let now = Instant::now();
+        rate_limiter.admit_key(String::from("a"), now);
+        rate_limiter.admit_key(String::from("b"), now);
+        assert_eq!(
+            OVERFLOW_KEY,
+            rate_limiter.admit_key(String::from("c"), now).0
+        );
+        let later = now + Duration::from_secs(61);
+        assert_eq!(
+            OVERFLOW_KEY,
+            rate_limiter.admit_key(String::from("d"), later).0
+        );
+        assert_eq!(2, rate_limiter.evict_idle_keys(later));
+        assert_eq!("e", rate_limiter.admit_key(String::from("e"), later).0);
+    }
+
+    fn direct_rate_limiter(req_per_time_unit: u32) -> ConfiguredRateLimiter {
+        ConfiguredRateLimiter::new(&RateLimiterConfig {
+            algorithm: RateLimitAlgorithm::TokenBucket,
+            time_unit: TimeUnit::Hour,
+            req_per_time_unit,
+            burst_size: None,
+            key: None,
+            max_keys: None,
+            idle_key_timeout: None,
+            header_style: None,
+            mode: None,
+        })
+    }
+
+    fn check(limiter_id: &str) -> RateLimitCheck {
+        RateLimitCheck {
+            limiter_id: String::from(limiter_id),
+            key: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_all_requires_every_limit() {
+        let mut api_rate_limiter_map = HashMap::new();
+        api_rate_limiter_map.insert(String::from("api"), Arc::new(direct_rate_limiter(1)));
+        api_rate_limiter_map.insert(String::from("origin"), Arc::new(direct_rate_limiter(5)));
+        assert!(check_all(
+            &api_rate_limiter_map,
+            None,
+            vec![check("api"), check("origin")]
+        )
+        .await
+        .is_ok());
+        assert!(check_all(
+            &api_rate_limiter_map,
+            None,
+            vec![check("api"), check("origin")]
+        )
+        .await
+        .is_err());
+        // The API limit rejected first, so the origin only consumed one request so far
+        for _ in 0..4 {
+            assert!(
+                check_all(&api_rate_limiter_map, None, vec![check("origin")])
+                    .await
+                    .is_ok()
+            );
+        }
+        assert!(
+            check_all(&api_rate_limiter_map, None, vec![check("origin")])
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_all_rejects_unknown_limiter() {
+        let api_rate_limiter_map = HashMap::new();
+        assert_eq!(
+            Err(RateLimitRejection::UnknownLimiter),
+            check_all(&api_rate_limiter_map, None, vec![check("missing")]).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_all_reports_most_restrictive_status() {
+        let mut api_rate_limiter_map = HashMap::new();
+        api_rate_limiter_map.insert(String::from("api"), Arc::new(direct_rate_limiter(2)));
+        api_rate_limiter_map.insert(String::from("origin"), Arc::new(direct_rate_limiter(10)));
+        let status = check_all(
+            &api_rate_limiter_map,
+            None,
+            vec![check("api"), check("origin")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(2, status.limit);
+        assert_eq!(1, status.remaining);
+        assert_eq!(Duration::from_secs(1800), status.reset_after);
+    }
+
+    #[tokio::test]
+    async fn test_check_all_reports_in_the_style_of_the_deciding_limiter() {
+        let mut api_config = rate_limiter_config(RateLimitAlgorithm::TokenBucket, 1, None);
+        api_config.time_unit = TimeUnit::Hour;
+        api_config.header_style = Some(RateLimitHeaderStyle::XRateLimit);
+        let mut api_rate_limiter_map = HashMap::new();
+        api_rate_limiter_map.insert(
+            String::from("api"),
+            Arc::new(ConfiguredRateLimiter::new(&api_config)),
+        );
+        api_rate_limiter_map.insert(String::from("origin"), Arc::new(direct_rate_limiter(10)));
+        let checks = || vec![check("api"), check("origin")];
+        let status = check_all(&api_rate_limiter_map, None, checks())
+            .await
+            .unwrap();
+        assert_eq!(Some(RateLimitHeaderStyle::XRateLimit), status.header_style);
+        match check_all(&api_rate_limiter_map, None, checks()).await {
+            Err(RateLimitRejection::LimitExceeded { header_style, .. }) => {
+                assert_eq!(Some(RateLimitHeaderStyle::XRateLimit), header_style)
+            }
+            _ => panic!("Expected the request to be rejected"),
+        }
+        let status = check_all(&api_rate_limiter_map, None, vec![check("origin")])
+            .await
+            .unwrap();
+        assert_eq!(None, status.header_style);
+    }
+
+    #[tokio::test]
+    async fn test_shadow_limiter_counts_but_never_rejects() {
+        let mut shadow_config = rate_limiter_config(
+            RateLimitAlgorithm::TokenBucket,
+            1,
+            Some(RateLimitKey::ClientIp),
+        );
+        shadow_config.time_unit = TimeUnit::Hour;
+        shadow_config.mode = Some(RateLimitMode::Shadow);
+        let mut api_rate_limiter_map = HashMap::new();
+        api_rate_limiter_map.insert(
+            String::from("api"),
+            Arc::new(ConfiguredRateLimiter::new(&shadow_config)),
+        );
+        api_rate_limiter_map.insert(String::from("origin"), Arc::new(direct_rate_limiter(3)));
+        let checks = || {
+            vec![
+                RateLimitCheck {
+                    limiter_id: String::from("api"),
+                    key: Some(String::from("client")),
+                },
+                check("origin"),
+            ]
+        };
+        let status = check_all(&api_rate_limiter_map, None, checks())
+            .await
+            .unwrap();
+        assert_eq!(3, status.limit);
+        // The shadow limiter is exhausted, yet the enforced one decides the status
+        let status = check_all(&api_rate_limiter_map, None, checks())
+            .await
+            .unwrap();
+        assert_eq!(1, status.remaining);
+        assert!(check_all(&api_rate_limiter_map, None, checks())
+            .await
+            .is_ok());
+        assert!(check_all(&api_rate_limiter_map, None, checks())
+            .await
+            .is_err());
+
+        let rate_limiters = RateLimiters::new(None);
+        rate_limiters.update(|rate_limiter_map| *rate_limiter_map = api_rate_limiter_map);
+        let usage = rate_limiters.shadow_usage();
+        assert_eq!(1, usage.len());
+        assert_eq!("api", usage[0].limiter_id);
+        assert_eq!(4, usage[0].evaluated);
+        assert_eq!(3, usage[0].would_reject);
+        assert_eq!(key_digest("client"), usage[0].keys[0].key);
+    }
+
+    #[tokio::test]
+    async fn test_shadow_statistics_last_until_the_limit_is_enforced() {
+        let mut config = rate_limiter_config(RateLimitAlgorithm::TokenBucket, 1, None);
+        config.time_unit = TimeUnit::Hour;
+        config.mode = Some(RateLimitMode::Shadow);
+        let rate_limiter = Arc::new(ConfiguredRateLimiter::new(&config));
+        for _ in 0..3 {
+            assert!(rate_limiter.check("origin", None, None).await.is_ok());
+        }
+        // A shadow-only check still reports the exhausted limit
+        assert_eq!(
+            0,
+            rate_limiter
+                .check("origin", None, None)
+                .await
+                .unwrap()
+                .remaining
+        );
+
+        config.req_per_time_unit = 2;
+        let rate_limiter = rate_limiter.reconfigure(&config);
+        assert_eq!(
+            3,
+            rate_limiter
+                .shadow_stats()
+                .unwrap()
+                .usage("origin")
+                .would_reject
+        );
+
+        config.mode = Some(RateLimitMode::Enforce);
+        let rate_limiter = rate_limiter.reconfigure(&config);
+        assert!(rate_limiter.shadow_stats().is_none());
+        assert!(rate_limiter.check("origin", None, None).await.is_ok());
+        assert!(rate_limiter.check("origin", None, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejection_carries_retry_after() {
+        let rate_limiter = direct_rate_limiter(1);
+        assert!(rate_limiter.check("api", None, None).await.is_ok());
+        match rate_limiter.check("api", None, None).await {
+            Err(RateLimitRejection::LimitExceeded {
+                limit, retry_after, ..
+            }) => {
+                assert_eq!(1, limit);
+                assert!(retry_after > Duration::from_secs(3500));
+                assert!(retry_after <= Duration::from_secs(3600));
+            }
+            _ => panic!("Expected the second request to be rejected"),
+        }
+    }
+
+    fn algorithm(
+        algorithm: RateLimitAlgorithm,
+        req_per_time_unit: u32,
+        burst_size: Option<u32>,
+        now: Instant,
+    ) -> Box<dyn RateLimitingAlgorithm> {
+        create_algorithm(
+            &RateLimiterConfig {
+                algorithm,
+                time_unit: TimeUnit::Second,
+                req_per_time_unit,
+                burst_size,
+                key: None,
+                max_keys: None,
+                idle_key_timeout: None,
+                header_style: None,
+                mode: None,
+            },
+            now,
+        )
+    }
+
+    fn retry_after(result: Result<RateLimitStatus, RateLimitRejection>) -> Duration {
+        match result {
+            Err(RateLimitRejection::LimitExceeded { retry_after, .. }) => retry_after,
+            _ => panic!("Expected the request to be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_calculate_quota_with_burst_size() {
+        let rate_limiter_config = RateLimiterConfig {
+            algorithm: RateLimitAlgorithm::TokenBucket,
+            time_unit: TimeUnit::Second,
+            req_per_time_unit: 10,
+            burst_size: Some(25),
+            key: None,
+            max_keys: None,
+            idle_key_timeout: None,
+            header_style: None,
+            mode: None,
+        };
+        let quota = calculate_quota(&rate_limiter_config);
+        assert_eq!(25, quota.burst_size().get());
+        assert_eq!(Duration::from_millis(100), quota.replenish_interval());
+    }
+
+    #[test]
+    fn test_token_bucket_allows_burst_then_one_per_interval() {
+        let now = Instant::now();
+        let rate_limiter = algorithm(RateLimitAlgorithm::TokenBucket, 10, Some(3), now);
+        for remaining in (0..3).rev() {
+            assert_eq!(remaining, rate_limiter.check(now).unwrap().remaining);
+        }
+        assert_eq!(
+            Duration::from_millis(100),
+            retry_after(rate_limiter.check(now))
+        );
+        let almost = now + Duration::from_millis(99);
+        assert_eq!(
+            Duration::from_millis(1),
+            retry_after(rate_limiter.check(almost))
+        );
+        let replenished = now + Duration::from_millis(100);
+        assert_eq!(0, rate_limiter.check(replenished).unwrap().remaining);
+        assert!(rate_limiter.check(replenished).is_err());
+    }
+
+    #[test]
+    fn test_token_bucket_refills_to_burst_size_only() {
+        let now = Instant::now();
+        let rate_limiter = algorithm(RateLimitAlgorithm::TokenBucket, 10, Some(2), now);
+        let idle = now + Duration::from_secs(60);
+        let status = rate_limiter.check(idle).unwrap();
+        assert_eq!(2, status.limit);
+        assert_eq!(1, status.remaining);
+        assert_eq!(Duration::from_millis(100), status.reset_after);
+    }
+
+    #[test]
+    fn test_fixed_window_resets_at_window_boundary() {
+        let now = Instant::now();
+        let rate_limiter = algorithm(RateLimitAlgorithm::FixedWindow, 2, None, now);
+        let end_of_window = now + Duration::from_millis(999);
+        assert!(rate_limiter.check(end_of_window).is_ok());
+        assert!(rate_limiter.check(end_of_window).is_ok());
+        assert_eq!(
+            Duration::from_millis(1),
+            retry_after(rate_limiter.check(end_of_window))
+        );
+        // The full limit is available again right after the boundary
+        let next_window = now + Duration::from_secs(1);
+        assert_eq!(1, rate_limiter.check(next_window).unwrap().remaining);
+        assert_eq!(0, rate_limiter.check(next_window).unwrap().remaining);
+        assert!(rate_limiter.check(next_window).is_err());
+    }
+
+    #[test]
+    fn test_fixed_window_skips_idle_windows() {
+        let now = Instant::now();
+        let rate_limiter = algorithm(RateLimitAlgorithm::FixedWindow, 1, None, now);
+        assert!(rate_limiter.check(now).is_ok());
+        let later = now + Duration::from_millis(5250);
+        assert_eq!(
+            Duration::from_millis(750),
+            rate_limiter.check(later).unwrap().reset_after
+        );
+    }
+
+    #[test]
+    fn test_sliding_window_log_admits_when_oldest_expires() {
+        let now = Instant::now();
+        let rate_limiter = algorithm(RateLimitAlgorithm::SlidingWindowLog, 2, None, now);
+        assert!(rate_limiter.check(now).is_ok());
+        let halfway = now + Duration::from_millis(500);
+        assert!(rate_limiter.check(halfway).is_ok());
+        // Unlike a fixed window, crossing one second does not free both slots
+        let almost = now + Duration::from_millis(999);
+        assert_eq!(
+            Duration::from_millis(1),
+            retry_after(rate_limiter.check(almost))
+        );
+        let expired = now + Duration::from_secs(1);
+        assert_eq!(0, rate_limiter.check(expired).unwrap().remaining);
+        assert_eq!(
+            Duration::from_millis(500),
+            retry_after(rate_limiter.check(expired))
+        );
+    }
+
+    #[test]
+    fn test_sliding_window_counter_weights_previous_window() {
+        let now = Instant::now();
+        let rate_limiter = algorithm(RateLimitAlgorithm::SlidingWindowCounter, 4, None, now);
+        for _ in 0..4 {
+            assert!(rate_limiter.check(now).is_ok());
+        }
+        // Four requests in the full window: the next one fits once 1/4 of the
+        // previous window has slid out
+        assert_eq!(
+            Duration::from_millis(1250),
+            retry_after(rate_limiter.check(now))
+        );
+        let quarter = now + Duration::from_millis(1250);
+        assert!(rate_limiter.check(quarter).is_ok());
+        assert!(rate_limiter.check(quarter).is_err());
+        let half = now + Duration::from_millis(1500);
+        assert!(rate_limiter.check(half).is_ok());
+        assert!(rate_limiter.check(half).is_err());
+    }
+
+    #[test]
+    fn test_sliding_window_counter_forgets_windows_older_than_one() {
+        let now = Instant::now();
+        let rate_limiter = algorithm(RateLimitAlgorithm::SlidingWindowCounter, 2, None, now);
+        assert!(rate_limiter.check(now).is_ok());
+        assert!(rate_limiter.check(now).is_ok());
+        let much_later = now + Duration::from_millis(2100);
+        assert_eq!(1, rate_limiter.check(much_later).unwrap().remaining);
+        assert_eq!(0, rate_limiter.check(much_later).unwrap().remaining);
+    }
+
+    fn rate_limiter_config(
+        algorithm: RateLimitAlgorithm,
+        req_per_time_unit: u32,
+        key: Option<RateLimitKey>,
+    ) -> RateLimiterConfig {
+        RateLimiterConfig {
+            algorithm,
+            time_unit: TimeUnit::Second,
+            req_per_time_unit,
+            burst_size: None,
+            key,
+            max_keys: None,
+            idle_key_timeout: None,
+            header_style: None,
+            mode: None,
+        }
+    }
+
+    /// Sends two requests per 100ms for two seconds against a limit of ten per
+    /// second, optionally reloading the configuration every 250ms, and counts the
+    /// admitted requests.
+    fn admitted_under_sustained_load(algorithm: RateLimitAlgorithm, reload: bool) -> usize {
+        let start = Instant::now();
+        let config = rate_limiter_config(algorithm, 10, None);
+        let mut rate_limiter = create_algorithm(&config, start);
+        let mut admitted = 0;
+        for tick in 0..20u32 {
+            let now = start + Duration::from_millis(100) * tick;
+            if reload && tick % 5 == 0 {
+                rate_limiter = carry_over(rate_limiter.as_ref(), &config, now);
+            }
+            admitted += (0..2).filter(|_| rate_limiter.check(now).is_ok()).count();
+        }
+        admitted
+    }
+
+    #[test]
+    fn test_reload_under_sustained_load_grants_no_free_burst() {
+        for algorithm in [
+            RateLimitAlgorithm::TokenBucket,
+            RateLimitAlgorithm::FixedWindow,
+            RateLimitAlgorithm::SlidingWindowLog,
+            RateLimitAlgorithm::SlidingWindowCounter,
+        ] {
+            let without_reload = admitted_under_sustained_load(algorithm.clone(), false);
+            let with_reload = admitted_under_sustained_load(algorithm, true);
+            assert!(with_reload <= without_reload);
+        }
+    }
+
+    #[test]
+    fn test_carry_over_counts_consumed_capacity_against_new_limit() {
+        let now = Instant::now();
+        for algorithm in [
+            RateLimitAlgorithm::TokenBucket,
+            RateLimitAlgorithm::FixedWindow,
+            RateLimitAlgorithm::SlidingWindowLog,
+            RateLimitAlgorithm::SlidingWindowCounter,
+        ] {
+            let rate_limiter =
+                create_algorithm(&rate_limiter_config(algorithm.clone(), 10, None), now);
+            for _ in 0..6 {
+                rate_limiter.check(now).unwrap();
+            }
+            let lowered = carry_over(
+                rate_limiter.as_ref(),
+                &rate_limiter_config(algorithm.clone(), 5, None),
+                now,
+            );
+            assert!(lowered.check(now).is_err());
+            let raised = carry_over(
+                rate_limiter.as_ref(),
+                &rate_limiter_config(algorithm, 20, None),
+                now,
+            );
+            assert_eq!(13, raised.check(now).unwrap().remaining);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_configuration_keeps_limiter() {
+        let mut api_rate_limiter_map = HashMap::new();
+        let config = rate_limiter_config(RateLimitAlgorithm::FixedWindow, 2, None);
+        update_rate_limiter(&mut api_rate_limiter_map, String::from("origin"), &config);
+        assert!(
+            check_all(&api_rate_limiter_map, None, vec![check("origin")])
+                .await
+                .is_ok()
+        );
+        update_rate_limiter(&mut api_rate_limiter_map, String::from("origin"), &config);
+        assert_eq!(
+            0,
+            check_all(&api_rate_limiter_map, None, vec![check("origin")])
+                .await
+                .unwrap()
+                .remaining
+        );
+        assert!(
+            check_all(&api_rate_limiter_map, None, vec![check("origin")])
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keyed_buckets_carry_over_unless_key_changes() {
+        let config = rate_limiter_config(
+            RateLimitAlgorithm::TokenBucket,
+            2,
+            Some(RateLimitKey::ClientIp),
+        );
+        let rate_limiter = Arc::new(ConfiguredRateLimiter::new(&config));
+        rate_limiter
+            .check("origin", Some(String::from("a")), None)
+            .await
+            .unwrap();
+        rate_limiter
+            .check("origin", Some(String::from("a")), None)
+            .await
+            .unwrap();
+
+        let rate_limiter = rate_limiter.reconfigure(&rate_limiter_config(
+            RateLimitAlgorithm::TokenBucket,
+            3,
+            Some(RateLimitKey::ClientIp),
+        ));
+        assert!(rate_limiter
+            .check("origin", Some(String::from("a")), None)
+            .await
+            .is_ok());
+        assert!(rate_limiter
+            .check("origin", Some(String::from("a")), None)
+            .await
+            .is_err());
+
+        let rekeyed = rate_limiter.reconfigure(&rate_limiter_config(
+            RateLimitAlgorithm::TokenBucket,
+            3,
+            Some(RateLimitKey::Header {
+                name: String::from("x-api-key"),
+            }),
+        ));
+        assert_eq!(
+            2,
+            rekeyed
+                .check("origin", Some(String::from("a")), None)
+                .await
+                .unwrap()
+                .remaining
+        );
+    }
+
+    #[tokio::test]
+    async fn test_removed_origin_limiter_is_unknown() {
+        let path = std::env::temp_dir()
+            .join(format!("gateman-rate-limiter-{}", std::process::id()))
+            .join("quota_usage.log");
+        let mut quota_store = QuotaStore::open(&path);
+        let rate_limiters = RateLimiters::new(None);
+        handle_api_call(
+            &rate_limiters,
+            &mut quota_store,
+            RateLimiterAPI::UpdateOriginSpecification {
+                origin_id: String::from("origin"),
+                rate_limiter_spec: rate_limiter_config(RateLimitAlgorithm::TokenBucket, 5, None),
+            },
+        );
+        assert!(rate_limiters
+            .check(vec![check("origin:origin")])
+            .await
+            .is_ok());
+        handle_api_call(
+            &rate_limiters,
+            &mut quota_store,
+            RateLimiterAPI::RemoveOriginSpecification {
+                origin_id: String::from("origin"),
+            },
+        );
+        assert_eq!(
+            Err(RateLimitRejection::UnknownLimiter),
+            rate_limiters.check(vec![check("origin:origin")]).await
+        );
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_reordered_quotas_keep_their_counters() {
+        let path = std::env::temp_dir()
+            .join(format!("gateman-quota-order-{}", std::process::id()))
+            .join("quota_usage.log");
+        let mut quota_store = QuotaStore::open(&path);
+        let rate_limiters = RateLimiters::new(None);
+        let quota_policy = |period: QuotaPeriod, limit: u32| QuotaPolicy {
+            name: None,
+            period,
+            limit,
+            key: None,
+            max_keys: None,
+            timezone: None,
+        };
+        let mut quota_policies = vec![
+            quota_policy(QuotaPeriod::Day, 1),
+            quota_policy(QuotaPeriod::Month, 100),
+        ];
+        let update_quotas =
+            |quota_policies: Vec<QuotaPolicy>| RateLimiterAPI::UpdateAPISpecification {
+                api_id: String::from("billing"),
+                rate_limit_policies: vec![],
+                quota_policies,
+            };
+        handle_api_call(
+            &rate_limiters,
+            &mut quota_store,
+            update_quotas(quota_policies.clone()),
+        );
+        let daily_quota = quota_limiter_ids("billing", &quota_policies).remove(0);
+        assert!(rate_limiters.check(vec![check(&daily_quota)]).await.is_ok());
+
+        quota_policies.reverse();
+        handle_api_call(
+            &rate_limiters,
+            &mut quota_store,
+            update_quotas(quota_policies.clone()),
+        );
+        assert_eq!(
+            daily_quota,
+            quota_limiter_ids("billing", &quota_policies)[1]
+        );
+        assert!(rate_limiters
+            .check(vec![check(&daily_quota)])
+            .await
+            .is_err());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    fn shared_rate_limiters(
+        limiter_id: &str,
+        rate_limiter_config: &RateLimiterConfig,
+        cluster_backend: Option<ClusterBackend>,
+    ) -> RateLimiters {
+        let rate_limiters = RateLimiters::new(cluster_backend);
+        rate_limiters.update(|rate_limiter_map| {
+            update_rate_limiter(
+                rate_limiter_map,
+                String::from(limiter_id),
+                rate_limiter_config,
+            )
+        });
+        rate_limiters
+    }
+
+    fn cluster_backend(address: &str, mode: ClusterMode) -> ClusterBackend {
+        ClusterBackend::new(&ClusterConfig {
+            address: String::from(address),
+            mode,
+            key_prefix: None,
+            timeout: Some(1000),
+            sync_interval: None,
+        })
+    }
+
+    async fn admitted(rate_limiters: &RateLimiters, attempts: usize) -> usize {
+        let mut admitted = 0;
+        for _ in 0..attempts {
+            let checks = vec![RateLimitCheck {
+                limiter_id: String::from("origin"),
+                key: Some(String::from("client")),
+            }];
+            if rate_limiters.check(checks).await.is_ok() {
+                admitted += 1;
+            }
+        }
+        admitted
+    }
+
+    #[tokio::test]
+    async fn test_strict_cluster_limits_are_shared_between_instances() {
+        let address = spawn_stand_in().await;
+        for key in [None, Some(RateLimitKey::ClientIp)] {
+            let mut config = rate_limiter_config(RateLimitAlgorithm::SlidingWindowLog, 6, key);
+            config.time_unit = TimeUnit::Hour;
+            let instances = [
+                shared_rate_limiters(
+                    "origin",
+                    &config,
+                    Some(cluster_backend(&address, ClusterMode::Strict)),
+                ),
+                shared_rate_limiters(
+                    "origin",
+                    &config,
+                    Some(cluster_backend(&address, ClusterMode::Strict)),
+                ),
+            ];
+            assert_eq!(4, admitted(&instances[0], 4).await);
+            assert_eq!(2, admitted(&instances[1], 4).await);
+            assert_eq!(0, admitted(&instances[0], 1).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_approximation_charges_requests_admitted_elsewhere() {
+        let address = spawn_stand_in().await;
+        let mut config = rate_limiter_config(
+            RateLimitAlgorithm::FixedWindow,
+            5,
+            Some(RateLimitKey::ClientIp),
+        );
+        config.time_unit = TimeUnit::Hour;
+        let backends = [
+            Arc::new(cluster_backend(&address, ClusterMode::LocalApproximation)),
+            Arc::new(cluster_backend(&address, ClusterMode::LocalApproximation)),
+        ];
+        let instances: Vec<RateLimiters> = backends
+            .iter()
+            .map(|backend| {
+                let rate_limiters = shared_rate_limiters("origin", &config, None);
+                RateLimiters {
+                    cluster_backend: Some(backend.clone()),
+                    ..rate_limiters
+                }
+            })
+            .collect();
+        assert_eq!(3, admitted(&instances[0], 3).await);
+        assert_eq!(1, admitted(&instances[1], 1).await);
+        sync_cluster_counters(&instances[0].load(), &backends[0]).await;
+        sync_cluster_counters(&instances[1].load(), &backends[1]).await;
+        // Only the second instance learned of the other's requests so far
+        assert_eq!(1, admitted(&instances[1], 5).await);
+        sync_cluster_counters(&instances[1].load(), &backends[1]).await;
+        sync_cluster_counters(&instances[0].load(), &backends[0]).await;
+        assert_eq!(0, admitted(&instances[0], 5).await);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_backend_degrades_to_local_limits() {
+        let address = unreachable_address().await;
+        for mode in [ClusterMode::Strict, ClusterMode::LocalApproximation] {
+            let mut config = rate_limiter_config(RateLimitAlgorithm::TokenBucket, 3, None);
+            config.time_unit = TimeUnit::Hour;
+            let backend = cluster_backend(&address, mode);
+            let rate_limiters = shared_rate_limiters("origin", &config, Some(backend));
+            assert_eq!(3, admitted(&rate_limiters, 5).await);
+            if let Some(backend) = &rate_limiters.cluster_backend {
+                sync_cluster_counters(&rate_limiters.load(), backend).await;
+                assert!(!backend.is_available());
+            }
+            assert_eq!(0, admitted(&rate_limiters, 1).await);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_concurrent_checks_admit_exactly_the_limit() {
+        for (algorithm, key) in [
+            (RateLimitAlgorithm::TokenBucket, None),
+            (RateLimitAlgorithm::SlidingWindowLog, None),
+            (
+                RateLimitAlgorithm::TokenBucket,
+                Some(RateLimitKey::ClientIp),
+            ),
+        ] {
+            let mut config = rate_limiter_config(algorithm, 100, key);
+            config.time_unit = TimeUnit::Hour;
+            let rate_limiters = shared_rate_limiters("origin", &config, None);
+            let tasks: Vec<_> = (0..8)
+                .map(|_| {
+                    let rate_limiters = rate_limiters.clone();
+                    tokio::spawn(async move {
+                        let mut admitted = 0;
+                        for _ in 0..50 {
+                            let checks = vec![RateLimitCheck {
+                                limiter_id: String::from("origin"),
+                                key: Some(String::from("client")),
+                            }];
+                            if rate_limiters.check(checks).await.is_ok() {
+                                admitted += 1;
+                            }
+                        }
+                        admitted
+                    })
+                })
+                .collect();
+            let mut admitted = 0;
+            for task in tasks {
+                admitted += task.await.unwrap();
+            }
+            assert_eq!(100, admitted);
+        }
+    }
+
+    /// Compares the throughput of checking limits directly against the shared
+    /// limiters with sending every check through a single task, as the proxy did
+    /// before. Run with `cargo test --release -- --ignored --nocapture`.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore]
+    async fn bench_shared_limiters_against_single_task() {
+        const TASKS: usize = 256;
+        const CHECKS_PER_TASK: usize = 2000;
+        let config = rate_limiter_config(RateLimitAlgorithm::TokenBucket, u32::MAX, None);
+        let rate_limiters = shared_rate_limiters("origin", &config, None);
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<(
+            Vec<RateLimitCheck>,
+            tokio::sync::oneshot::Sender<Result<RateLimitStatus, RateLimitRejection>>,
+        )>(32);
+        let single_task_limiters = rate_limiters.clone();
+        tokio::spawn(async move {
+            while let Some((checks, responder)) = receiver.recv().await {
+                let _ = responder.send(single_task_limiters.check(checks).await);
+            }
+        });
+        let start = Instant::now();
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|_| {
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    for _ in 0..CHECKS_PER_TASK {
+                        let (responder, response) = tokio::sync::oneshot::channel();
+                        let _ = sender.send((vec![check("origin")], responder)).await;
+                        let _ = response.await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let single_task = start.elapsed();
+
+        let start = Instant::now();
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|_| {
+                let rate_limiters = rate_limiters.clone();
+                tokio::spawn(async move {
+                    for _ in 0..CHECKS_PER_TASK {
+                        let _ = rate_limiters.check(vec![check("origin")]).await;
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let shared = start.elapsed();
+
+        let checks = (TASKS * CHECKS_PER_TASK) as f64;
+        println!(
+            "single task: {:.0} checks/s, shared limiters: {:.0} checks/s",
+            checks / single_task.as_secs_f64(),
+            checks / shared.as_secs_f64()
         );
     }
 }
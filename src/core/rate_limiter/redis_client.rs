@@ -0,0 +1,244 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+const MAX_IDLE_CONNECTIONS: usize = 16;
+
+#[derive(Debug)]
+pub(crate) struct RedisError {
+    pub(crate) message: String,
+}
+
+impl RedisError {
+    fn new(message: impl Into<String>) -> Self {
+        RedisError {
+            message: message.into(),
+        }
+    }
+}
+
+impl From<std::io::Error> for RedisError {
+    fn from(error: std::io::Error) -> Self {
+        RedisError::new(error.to_string())
+    }
+}
+
+/// A reply in the Redis serialization protocol (RESP2).
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<RespValue>>),
+}
+
+impl RespValue {
+    pub(crate) fn as_integer(&self) -> Result<i64, RedisError> {
+        match self {
+            RespValue::Integer(value) => Ok(*value),
+            RespValue::Bulk(Some(value)) => String::from_utf8_lossy(value)
+                .parse()
+                .map_err(|_| RedisError::new("Expected an integer reply")),
+            RespValue::Error(message) => Err(RedisError::new(message.clone())),
+            _ => Err(RedisError::new("Expected an integer reply")),
+        }
+    }
+}
+
+/// Encodes a command as an array of bulk strings.
+pub(crate) fn encode_command(arguments: &[&[u8]]) -> Vec<u8> {
+    let mut command = format!("*{}\r\n", arguments.len()).into_bytes();
+    for argument in arguments {
+        command.extend_from_slice(format!("${}\r\n", argument.len()).as_bytes());
+        command.extend_from_slice(argument);
+        command.extend_from_slice(b"\r\n");
+    }
+    command
+}
+
+async fn read_line<R: AsyncBufRead + Unpin + Send>(reader: &mut R) -> Result<String, RedisError> {
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line).await?;
+    if !line.ends_with(b"\r\n") {
+        return Err(RedisError::new(
+            "Connection closed in the middle of a reply",
+        ));
+    }
+    line.truncate(line.len() - 2);
+    Ok(String::from_utf8_lossy(&line).to_string())
+}
+
+fn parse_length(value: &str) -> Result<i64, RedisError> {
+    value
+        .parse()
+        .map_err(|_| RedisError::new(format!("Malformed length {}", value)))
+}
+
+/// Reads one reply; arrays are read recursively.
+pub(crate) fn read_reply<'a, R: AsyncBufRead + Unpin + Send>(
+    reader: &'a mut R,
+) -> Pin<Box<dyn Future<Output = Result<RespValue, RedisError>> + Send + 'a>> {
+    Box::pin(async move {
+        let line = read_line(reader).await?;
+        let (kind, value) = line.split_at(line.len().min(1));
+        match kind {
+            "+" => Ok(RespValue::Simple(String::from(value))),
+            "-" => Ok(RespValue::Error(String::from(value))),
+            ":" => Ok(RespValue::Integer(parse_length(value)?)),
+            "$" => {
+                let length = parse_length(value)?;
+                if length < 0 {
+                    return Ok(RespValue::Bulk(None));
+                }
+                let mut bulk = vec![0; length as usize + 2];
+                reader.read_exact(&mut bulk).await?;
+                bulk.truncate(length as usize);
+                Ok(RespValue::Bulk(Some(bulk)))
+            }
+            "*" => {
+                let length = parse_length(value)?;
+                if length < 0 {
+                    return Ok(RespValue::Array(None));
+                }
+                let mut elements = Vec::with_capacity(length as usize);
+                for _ in 0..length {
+                    elements.push(read_reply(reader).await?);
+                }
+                Ok(RespValue::Array(Some(elements)))
+            }
+            _ => Err(RedisError::new(format!("Unknown reply type in {}", line))),
+        }
+    })
+}
+
+/// Minimal client for servers speaking the Redis protocol. Idle connections are
+/// pooled, so concurrent queries do not wait for each other.
+pub(crate) struct RedisClient {
+    address: String,
+    idle_connections: Mutex<Vec<BufStream<TcpStream>>>,
+}
+
+impl RedisClient {
+    pub(crate) fn new(address: &str) -> Self {
+        RedisClient {
+            address: String::from(address),
+            idle_connections: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn take_connection(&self) -> Option<BufStream<TcpStream>> {
+        self.idle_connections
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .pop()
+    }
+
+    fn return_connection(&self, connection: BufStream<TcpStream>) {
+        let mut idle_connections = self
+            .idle_connections
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if idle_connections.len() < MAX_IDLE_CONNECTIONS {
+            idle_connections.push(connection);
+        }
+    }
+
+    /// Sends the commands in one pipeline and returns their replies in order.
+    /// Error replies are returned as `RespValue::Error`; only connection failures
+    /// and timeouts are errors. A connection that timed out is not reused.
+    pub(crate) async fn query(
+        &self,
+        commands: &[Vec<u8>],
+        query_timeout: Duration,
+    ) -> Result<Vec<RespValue>, RedisError> {
+        match timeout(query_timeout, self.query_without_timeout(commands)).await {
+            Ok(result) => result,
+            Err(_) => Err(RedisError::new(format!(
+                "Query to {} timed out",
+                self.address
+            ))),
+        }
+    }
+
+    async fn query_without_timeout(
+        &self,
+        commands: &[Vec<u8>],
+    ) -> Result<Vec<RespValue>, RedisError> {
+        let mut connection = match self.take_connection() {
+            Some(connection) => connection,
+            None => BufStream::new(TcpStream::connect(self.address.as_str()).await?),
+        };
+        for command in commands {
+            connection.write_all(command).await?;
+        }
+        connection.flush().await?;
+        let mut replies = Vec::with_capacity(commands.len());
+        for _ in commands {
+            replies.push(read_reply(&mut connection).await?);
+        }
+        self.return_connection(connection);
+        Ok(replies)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_command, read_reply, RespValue};
+
+    #[test]
+    fn test_encode_command() {
+        assert_eq!(
+            b"*3\r\n$6\r\nINCRBY\r\n$3\r\nkey\r\n$2\r\n12\r\n".to_vec(),
+            encode_command(&[b"INCRBY", b"key", b"12"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_replies() {
+        let mut replies: &[u8] =
+            b"+OK\r\n-ERR nope\r\n:42\r\n$5\r\nhello\r\n$-1\r\n*2\r\n:1\r\n$1\r\nx\r\n";
+        assert_eq!(
+            RespValue::Simple(String::from("OK")),
+            read_reply(&mut replies).await.unwrap()
+        );
+        assert_eq!(
+            RespValue::Error(String::from("ERR nope")),
+            read_reply(&mut replies).await.unwrap()
+        );
+        assert_eq!(
+            42,
+            read_reply(&mut replies)
+                .await
+                .unwrap()
+                .as_integer()
+                .unwrap()
+        );
+        assert_eq!(
+            RespValue::Bulk(Some(b"hello".to_vec())),
+            read_reply(&mut replies).await.unwrap()
+        );
+        assert_eq!(
+            RespValue::Bulk(None),
+            read_reply(&mut replies).await.unwrap()
+        );
+        assert_eq!(
+            RespValue::Array(Some(vec![
+                RespValue::Integer(1),
+                RespValue::Bulk(Some(b"x".to_vec()))
+            ])),
+            read_reply(&mut replies).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_truncated_reply_is_an_error() {
+        let mut replies: &[u8] = b"*2\r\n:1\r\n";
+        assert!(read_reply(&mut replies).await.is_err());
+    }
+}
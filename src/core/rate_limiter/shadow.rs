@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use log::debug;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::core::rate_limiter::rate_limiter_api::{RateLimitRejection, RateLimitStatus};
+
+/// Keys whose would-be rejections are counted individually. Rejections of keys
+/// beyond that are counted together under `OTHER_KEYS`.
+const MAX_TRACKED_KEYS: usize = 10000;
+const OTHER_KEYS: &str = "__other__";
+
+/// Client keys may be API keys or other credentials, so only a truncated SHA-256
+/// digest of them is kept, logged and reported.
+pub(crate) fn key_digest(key: &str) -> String {
+    hex::encode(&Sha256::digest(key.as_bytes())[..8])
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct ShadowKeyUsage {
+    pub(crate) key: String,
+    pub(crate) would_reject: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct ShadowUsage {
+    pub(crate) limiter_id: String,
+    pub(crate) evaluated: u64,
+    pub(crate) would_reject: u64,
+    pub(crate) keys: Vec<ShadowKeyUsage>,
+}
+
+/// Decisions of a limiter in shadow mode since it was configured.
+pub(crate) struct ShadowStats {
+    evaluated: AtomicU64,
+    would_reject: AtomicU64,
+    rejected_keys: Mutex<HashMap<String, u64>>,
+}
+
+impl ShadowStats {
+    pub(crate) fn new() -> Self {
+        ShadowStats {
+            evaluated: AtomicU64::new(0),
+            would_reject: AtomicU64::new(0),
+            rejected_keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts the decision of the limiter for `key`. A request the limiter would
+    /// have rejected is admitted with the status of an exhausted limiter.
+    pub(crate) fn observe(
+        &self,
+        limiter_id: &str,
+        key: &str,
+        result: Result<RateLimitStatus, RateLimitRejection>,
+    ) -> Result<RateLimitStatus, RateLimitRejection> {
+        self.evaluated.fetch_add(1, Ordering::Relaxed);
+        match result {
+            Err(RateLimitRejection::LimitExceeded {
+                limit,
+                retry_after,
+                header_style,
+            }) => {
+                self.would_reject.fetch_add(1, Ordering::Relaxed);
+                let mut rejected_keys = self
+                    .rejected_keys
+                    .lock()
+                    .unwrap_or_else(|error| error.into_inner());
+                let key_digest = key_digest(key);
+                debug!(
+                    "Rate limiter {} in shadow mode would have rejected a request of key {}",
+                    limiter_id, key_digest
+                );
+                let tracked_key = if rejected_keys.contains_key(&key_digest)
+                    || rejected_keys.len() < MAX_TRACKED_KEYS
+                {
+                    key_digest
+                } else {
+                    String::from(OTHER_KEYS)
+                };
+                *rejected_keys.entry(tracked_key).or_insert(0) += 1;
+                Ok(RateLimitStatus {
+                    limit,
+                    remaining: 0,
+                    reset_after: retry_after,
+                    header_style,
+                })
+            }
+            result => result,
+        }
+    }
+
+    /// Keys are listed by their would-be rejections, most rejected first.
+    pub(crate) fn usage(&self, limiter_id: &str) -> ShadowUsage {
+        let mut keys: Vec<ShadowKeyUsage> = self
+            .rejected_keys
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .iter()
+            .map(|(key, would_reject)| ShadowKeyUsage {
+                key: key.clone(),
+                would_reject: *would_reject,
+            })
+            .collect();
+        keys.sort_by(|a, b| (b.would_reject, &a.key).cmp(&(a.would_reject, &b.key)));
+        ShadowUsage {
+            limiter_id: String::from(limiter_id),
+            evaluated: self.evaluated.load(Ordering::Relaxed),
+            would_reject: self.would_reject.load(Ordering::Relaxed),
+            keys,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::core::rate_limiter::rate_limiter_api::{RateLimitRejection, RateLimitStatus};
+
+    use super::{key_digest, ShadowKeyUsage, ShadowStats, MAX_TRACKED_KEYS, OTHER_KEYS};
+
+    fn rejected() -> Result<RateLimitStatus, RateLimitRejection> {
+        Err(RateLimitRejection::LimitExceeded {
+            limit: 5,
+            retry_after: Duration::from_secs(3),
+            header_style: None,
+        })
+    }
+
+    #[test]
+    fn test_would_be_rejections_are_admitted_and_counted_per_key() {
+        let shadow_stats = ShadowStats::new();
+        let admitted = Ok(RateLimitStatus {
+            limit: 5,
+            remaining: 4,
+            reset_after: Duration::from_secs(1),
+            header_style: None,
+        });
+        assert_eq!(
+            admitted,
+            shadow_stats.observe("origin", "a", admitted.clone())
+        );
+        assert_eq!(
+            Ok(RateLimitStatus {
+                limit: 5,
+                remaining: 0,
+                reset_after: Duration::from_secs(3),
+                header_style: None,
+            }),
+            shadow_stats.observe("origin", "a", rejected())
+        );
+        assert!(shadow_stats.observe("origin", "b", rejected()).is_ok());
+        assert!(shadow_stats.observe("origin", "b", rejected()).is_ok());
+        let usage = shadow_stats.usage("origin");
+        assert_eq!(4, usage.evaluated);
+        assert_eq!(3, usage.would_reject);
+        assert_eq!(
+            vec![
+                ShadowKeyUsage {
+                    key: key_digest("b"),
+                    would_reject: 2
+                },
+                ShadowKeyUsage {
+                    key: key_digest("a"),
+                    would_reject: 1
+                },
+            ],
+            usage.keys
+        );
+    }
+
+    #[test]
+    fn test_keys_beyond_the_limit_are_counted_together() {
+        let shadow_stats = ShadowStats::new();
+        for key in 0..MAX_TRACKED_KEYS + 2 {
+            let _ = shadow_stats.observe("origin", &key.to_string(), rejected());
+        }
+        let _ = shadow_stats.observe("origin", "0", rejected());
+        let usage = shadow_stats.usage("origin");
+        assert_eq!(MAX_TRACKED_KEYS + 1, usage.keys.len());
+        let would_reject = |key: &str| {
+            usage
+                .keys
+                .iter()
+                .find(|usage| usage.key == key)
+                .unwrap()
+                .would_reject
+        };
+        assert_eq!(2, would_reject(OTHER_KEYS));
+        assert_eq!(2, would_reject(&key_digest("0")));
+        assert!(usage.keys.iter().all(|usage| usage.key != "0"));
+    }
+}
@@ -0,0 +1,330 @@
+use std::convert::Infallible;
+use std::io::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use async_stream::stream;
+use hyper::body::HttpBody;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Body, Request, Response};
+use log::debug;
+
+use crate::configuration_reader::api_def_reader::{APIDefinition, RequestBodyPolicy};
+use crate::core::standard_response::{
+    create_400_bad_request_response, create_413_payload_too_large_response,
+    create_415_unsupported_media_type_response,
+};
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum BodyRejection {
+    TooLarge,
+    UnsupportedMediaType,
+    Incomplete,
+}
+
+/// Tells whether a streamed request body was cut off for exceeding the size
+/// limit of the API, which only shows once the body has been forwarded. Kept in
+/// the extensions of the request, so that the aborted call is not taken for a
+/// failure of the Origin.
+#[derive(Clone)]
+pub(crate) struct BodyLimit {
+    exceeded: Arc<AtomicBool>,
+}
+
+impl BodyLimit {
+    pub(crate) fn is_exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::Acquire)
+    }
+}
+
+/// Media types are compared without their parameters and case insensitively;
+/// `type/*` allows every subtype.
+fn is_allowed_content_type(policy: &RequestBodyPolicy, content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    policy.allowed_content_types.iter().any(|allowed| {
+        let allowed = allowed.trim().to_ascii_lowercase();
+        match allowed.strip_suffix("/*") {
+            Some(allowed_type) => media_type
+                .split_once('/')
+                .map(|(media_type, _)| media_type == allowed_type)
+                .unwrap_or(false),
+            None => media_type == allowed,
+        }
+    })
+}
+
+fn check_content_type(
+    policy: &RequestBodyPolicy,
+    request: &Request<Body>,
+) -> Result<(), BodyRejection> {
+    if policy.allowed_content_types.is_empty() || request.body().is_end_stream() {
+        return Ok(());
+    }
+    match request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(content_type) if is_allowed_content_type(policy, content_type) => Ok(()),
+        _ => Err(BodyRejection::UnsupportedMediaType),
+    }
+}
+
+/// Streams the body on until more than `max_size` bytes have been received, then
+/// fails it, so that the Origin sees an aborted request rather than a truncated
+/// one. Only bodies of an announced length are streamed, so this is a safeguard
+/// against clients sending more than they announced.
+fn limit_body(request: Request<Body>, max_size: u64) -> Request<Body> {
+    let (parts, mut body) = request.into_parts();
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let exceeded_flag = exceeded.clone();
+    let limited_body = Body::wrap_stream(stream! {
+        let mut received: u64 = 0;
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(bytes) => {
+                    received += bytes.len() as u64;
+                    if received > max_size {
+                        exceeded_flag.store(true, Ordering::Release);
+                        yield Err(Error::other("Request body exceeds the size limit"));
+                        break;
+                    }
+                    yield Ok(bytes);
+                }
+                Err(error) => {
+                    yield Err(Error::other(error));
+                    break;
+                }
+            }
+        }
+    });
+    let mut request = Request::from_parts(parts, limited_body);
+    request.extensions_mut().insert(BodyLimit { exceeded });
+    request
+}
+
+/// Reads a body of unknown length up to `max_size` bytes before the request is
+/// routed, so that no Origin ever receives an oversized upload.
+async fn buffer_body(
+    request: Request<Body>,
+    max_size: u64,
+) -> Result<Request<Body>, BodyRejection> {
+    let (parts, mut body) = request.into_parts();
+    let mut buffered = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let bytes = chunk.map_err(|_| BodyRejection::Incomplete)?;
+        if (buffered.len() + bytes.len()) as u64 > max_size {
+            return Err(BodyRejection::TooLarge);
+        }
+        buffered.extend_from_slice(&bytes);
+    }
+    Ok(Request::from_parts(parts, Body::from(buffered)))
+}
+
+/// Checks the request body against the policy of the API, if it has one. Bodies
+/// announced to exceed the size limit are refused right away, and bodies of
+/// unknown length are buffered up to the limit. Others are limited while they
+/// are streamed to the Origin.
+pub(crate) async fn check_request_body(
+    api_definition: &APIDefinition,
+    request: Request<Body>,
+) -> Result<Request<Body>, BodyRejection> {
+    let policy = match &api_definition.request_body {
+        None => return Ok(request),
+        Some(policy) => policy,
+    };
+    if let Err(rejection) = check_content_type(policy, &request) {
+        debug!(
+            "Rejected request body with unsupported content type to APIDefinition (APIDefinition ID: {})",
+            api_definition.api_id
+        );
+        return Err(rejection);
+    }
+    let max_size = match policy.max_size {
+        None => return Ok(request),
+        Some(max_size) => max_size,
+    };
+    let content_length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if matches!(content_length, Some(content_length) if content_length > max_size) {
+        debug!(
+            "Rejected request body larger than {} bytes to APIDefinition (APIDefinition ID: {})",
+            max_size, api_definition.api_id
+        );
+        return Err(BodyRejection::TooLarge);
+    }
+    if request.body().is_end_stream() {
+        return Ok(request);
+    }
+    match content_length {
+        Some(_) => Ok(limit_body(request, max_size)),
+        None => {
+            let request = buffer_body(request, max_size).await;
+            if request.as_ref().err() == Some(&BodyRejection::TooLarge) {
+                debug!(
+                    "Rejected streamed request body larger than {} bytes to APIDefinition (APIDefinition ID: {})",
+                    max_size, api_definition.api_id
+                );
+            }
+            request
+        }
+    }
+}
+
+pub(crate) fn create_body_rejection_response(
+    rejection: BodyRejection,
+) -> Result<Response<Body>, Infallible> {
+    match rejection {
+        BodyRejection::TooLarge => create_413_payload_too_large_response(),
+        BodyRejection::UnsupportedMediaType => create_415_unsupported_media_type_response(),
+        BodyRejection::Incomplete => create_400_bad_request_response(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use async_stream::stream;
+    use hyper::body::Bytes;
+    use hyper::{Body, Request};
+
+    use crate::configuration_reader::api_def_reader::APIDefinition;
+
+    use super::{check_request_body, BodyLimit, BodyRejection};
+
+    fn api_definition(request_body_policy: &str) -> APIDefinition {
+        APIDefinition::from_json_str_slice(&format!(
+            r#"{{
+                "api_id": "uploads",
+                "api_name": "Uploads API",
+                "api_version": "1.0.0",
+                "api_desc": "Uploads",
+                "specification": {{"methods": ["POST"], "paths": ["/uploads"], "hostnames": ["localhost"]}},
+                "backend_response_timeout": 1000,
+                "origin_id": "uploads",
+                "request_body": {}
+            }}"#,
+            request_body_policy
+        ))
+        .unwrap()
+    }
+
+    fn request(content_type: Option<&str>, body: Body) -> Request<Body> {
+        let mut request = Request::builder().method("POST").uri("/uploads");
+        if let Some(content_type) = content_type {
+            request = request.header("content-type", content_type);
+        }
+        request.body(body).unwrap()
+    }
+
+    /// A body without a known length, as sent with chunked transfer encoding.
+    fn chunked_body(chunks: &'static [&'static str]) -> Body {
+        Body::wrap_stream(stream! {
+            for chunk in chunks {
+                yield Ok::<_, Infallible>(Bytes::from_static(chunk.as_bytes()));
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_content_types_are_enforced() {
+        let api_definition =
+            api_definition(r#"{"allowed_content_types": ["application/json", "image/*"]}"#);
+        for content_type in ["application/json; charset=utf-8", "Image/PNG"] {
+            assert!(check_request_body(
+                &api_definition,
+                request(Some(content_type), Body::from("{}"))
+            )
+            .await
+            .is_ok());
+        }
+        for content_type in [Some("text/plain"), Some("application/json-seq"), None] {
+            assert_eq!(
+                Some(BodyRejection::UnsupportedMediaType),
+                check_request_body(&api_definition, request(content_type, Body::from("{}")))
+                    .await
+                    .err()
+            );
+        }
+        assert!(
+            check_request_body(&api_definition, request(None, Body::empty()))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_announced_sizes_are_enforced() {
+        let api_definition = api_definition(r#"{"max_size": 4}"#);
+        let mut oversized = request(None, Body::from("12345"));
+        oversized
+            .headers_mut()
+            .insert("content-length", "5".parse().unwrap());
+        assert_eq!(
+            Some(BodyRejection::TooLarge),
+            check_request_body(&api_definition, oversized).await.err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bodies_of_unknown_length_are_buffered_up_to_the_limit() {
+        let api_definition = api_definition(r#"{"max_size": 8}"#);
+        let request_within_limit = check_request_body(
+            &api_definition,
+            request(None, chunked_body(&["1234", "5678"])),
+        )
+        .await
+        .unwrap();
+        assert!(request_within_limit
+            .extensions()
+            .get::<BodyLimit>()
+            .is_none());
+        assert_eq!(
+            "12345678",
+            hyper::body::to_bytes(request_within_limit.into_body())
+                .await
+                .unwrap()
+        );
+
+        assert_eq!(
+            Some(BodyRejection::TooLarge),
+            check_request_body(
+                &api_definition,
+                request(None, chunked_body(&["1234", "5678", "9"]))
+            )
+            .await
+            .err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bodies_longer_than_announced_are_limited() {
+        let api_definition = api_definition(r#"{"max_size": 8}"#);
+        let mut understated = request(None, chunked_body(&["1234", "5678", "9"]));
+        understated
+            .headers_mut()
+            .insert("content-length", "8".parse().unwrap());
+        let understated = check_request_body(&api_definition, understated)
+            .await
+            .unwrap();
+        let body_limit = understated
+            .extensions()
+            .get::<BodyLimit>()
+            .cloned()
+            .unwrap();
+        assert!(!body_limit.is_exceeded());
+        assert!(hyper::body::to_bytes(understated.into_body())
+            .await
+            .is_err());
+        assert!(body_limit.is_exceeded());
+    }
+}
@@ -0,0 +1,236 @@
+use std::time::{Duration, Instant};
+
+use hyper::body::HttpBody;
+use hyper::http::request::Parts;
+use hyper::{Body, Method, Request, Response};
+use log::trace;
+use tokio::sync::mpsc::Sender;
+use tokio::time::sleep;
+
+use crate::configuration_reader::api_def_reader::{APIDefinition, HedgingConfig};
+use crate::configuration_reader::origin_def_reader::Server;
+use crate::core::load_balancer::load_balancer_api::LoadBalancerAPI;
+use crate::core::router::{call_server, select_origin_server, OriginCallError};
+use crate::LoadBalancerAPI::{AcquireRetryBudget, GetLatencyPercentile, RecordCancelledAttempt};
+
+/// Hedging duplicates requests, so it is limited to read-only methods of APIs
+/// that opted in. Requests with a body are not hedged, since the body would have
+/// to be buffered in full to be sent twice.
+pub(crate) fn is_hedgeable(api_definition: &APIDefinition, request: &Request<Body>) -> bool {
+    api_definition.hedging.is_some()
+        && matches!(
+            *request.method(),
+            Method::GET | Method::HEAD | Method::OPTIONS
+        )
+        && request.body().is_end_stream()
+}
+
+async fn hedging_delay(
+    load_balancer_tx: &Sender<LoadBalancerAPI>,
+    origin_id: &str,
+    hedging_config: &HedgingConfig,
+) -> Duration {
+    let min_delay = Duration::from_millis(hedging_config.min_delay);
+    let (responder, receiver) = tokio::sync::oneshot::channel();
+    let latency_percentile_call = GetLatencyPercentile {
+        origin_id: String::from(origin_id),
+        percentile: hedging_config.latency_percentile,
+        responder,
+    };
+    if load_balancer_tx
+        .send(latency_percentile_call)
+        .await
+        .is_err()
+    {
+        trace!(
+            "Failed to query latency percentile for Origin (Origin ID: {})",
+            origin_id
+        );
+        return min_delay;
+    }
+    match receiver.await {
+        Ok(Some(latency)) => latency.max(min_delay),
+        _ => min_delay,
+    }
+}
+
+async fn acquire_retry_budget(
+    load_balancer_tx: &Sender<LoadBalancerAPI>,
+    origin_id: &str,
+    hedging_config: &HedgingConfig,
+) -> bool {
+    let (responder, receiver) = tokio::sync::oneshot::channel();
+    let acquire_budget_call = AcquireRetryBudget {
+        origin_id: String::from(origin_id),
+        budget_percentage: hedging_config.budget_percentage,
+        responder,
+    };
+    if load_balancer_tx.send(acquire_budget_call).await.is_err() {
+        trace!(
+            "Failed to acquire retry budget for Origin (Origin ID: {})",
+            origin_id
+        );
+        return false;
+    }
+    receiver.await.unwrap_or(false)
+}
+
+/// The attempt that loses the race is dropped before it can report its latency.
+/// Its elapsed time is still recorded, as a lower bound, so that the latency
+/// percentile the hedging delay is based on does not only see fast responses.
+async fn record_cancelled_attempt(
+    load_balancer_tx: &Sender<LoadBalancerAPI>,
+    origin_id: &str,
+    started: Instant,
+) {
+    let cancelled_attempt_call = RecordCancelledAttempt {
+        origin_id: String::from(origin_id),
+        elapsed: started.elapsed(),
+    };
+    if load_balancer_tx.send(cancelled_attempt_call).await.is_err() {
+        trace!(
+            "Failed to record cancelled attempt for Origin (Origin ID: {})",
+            origin_id
+        );
+    }
+}
+
+/// Only requests without a body are hedged, so copies are sent without one.
+fn copy_request(parts: &Parts) -> Request<Body> {
+    let mut request = Request::new(Body::empty());
+    *request.method_mut() = parts.method.clone();
+    *request.uri_mut() = parts.uri.clone();
+    *request.version_mut() = parts.version;
+    *request.headers_mut() = parts.headers.clone();
+    request
+}
+
+/// Sends the request to `primary_server` and, if it has not answered within the
+/// hedging delay, sends a copy to a different server. The first successful
+/// response wins and the slower request is cancelled by dropping it. Returns the
+/// server whose response is used.
+pub(crate) async fn send_hedged_request(
+    load_balancer_tx: &Sender<LoadBalancerAPI>,
+    api_definition: &APIDefinition,
+    origin_id: &str,
+    primary_server: Server,
+    request: Request<Body>,
+) -> (Result<Response<Body>, OriginCallError>, Server) {
+    let hedging_config = match &api_definition.hedging {
+        None => return (Err(OriginCallError::InvalidRequest), primary_server),
+        Some(hedging_config) => hedging_config,
+    };
+    let response_timeout = Duration::from_millis(api_definition.backend_response_timeout);
+    let (parts, _) = request.into_parts();
+
+    // The primary request is only sent once polled, so the delay is known first
+    let delay = hedging_delay(load_balancer_tx, origin_id, hedging_config).await;
+    let primary_started = Instant::now();
+    let primary = call_server(
+        load_balancer_tx,
+        origin_id,
+        &primary_server,
+        response_timeout,
+        copy_request(&parts),
+    );
+    tokio::pin!(primary);
+    tokio::select! {
+        result = &mut primary => return (result, primary_server.clone()),
+        _ = sleep(delay) => {}
+    }
+
+    if !acquire_retry_budget(load_balancer_tx, origin_id, hedging_config).await {
+        trace!(
+            "Retry budget of Origin (Origin ID: {}) is exhausted, not hedging",
+            origin_id
+        );
+        return (primary.await, primary_server.clone());
+    }
+    let hedge_server = select_origin_server(
+        load_balancer_tx,
+        origin_id,
+        None,
+        Some(primary_server.server_key()),
+    )
+    .await;
+    let hedge_server = match hedge_server {
+        None => return (primary.await, primary_server.clone()),
+        Some(hedge_server) => hedge_server,
+    };
+    trace!(
+        "Hedging request of APIDefinition (APIDefinition ID: {}) to server {} after {} ms",
+        api_definition.api_id,
+        hedge_server.server_key(),
+        delay.as_millis()
+    );
+    let hedge_started = Instant::now();
+    let hedge = call_server(
+        load_balancer_tx,
+        origin_id,
+        &hedge_server,
+        response_timeout,
+        copy_request(&parts),
+    );
+    tokio::pin!(hedge);
+    tokio::select! {
+        result = &mut primary => match result {
+            Ok(response) => {
+                record_cancelled_attempt(load_balancer_tx, origin_id, hedge_started).await;
+                (Ok(response), primary_server.clone())
+            }
+            Err(_) => (hedge.await, hedge_server.clone()),
+        },
+        result = &mut hedge => match result {
+            Ok(response) => {
+                record_cancelled_attempt(load_balancer_tx, origin_id, primary_started).await;
+                (Ok(response), hedge_server.clone())
+            }
+            Err(_) => (primary.await, primary_server.clone()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::{Body, Request};
+
+    use crate::configuration_reader::api_def_reader::APIDefinition;
+
+    use super::is_hedgeable;
+
+    #[test]
+    fn test_only_requests_without_a_body_are_hedged() {
+        let api_definition = APIDefinition::from_json_str_slice(
+            r#"{
+                "api_id": "catalog",
+                "api_name": "Catalog API",
+                "api_version": "1.0.0",
+                "api_desc": "Products",
+                "specification": {"methods": ["GET", "POST"], "paths": ["/products"], "hostnames": ["localhost"]},
+                "backend_response_timeout": 1000,
+                "origin_id": "catalog",
+                "hedging": {"latency_percentile": 95.0, "min_delay": 10, "budget_percentage": 10.0}
+            }"#,
+        )
+        .unwrap();
+        let request = |method: &str, body: Body| {
+            Request::builder()
+                .method(method)
+                .uri("/products")
+                .body(body)
+                .unwrap()
+        };
+        assert!(is_hedgeable(
+            &api_definition,
+            &request("GET", Body::empty())
+        ));
+        assert!(!is_hedgeable(
+            &api_definition,
+            &request("GET", Body::from("{\"query\": \"lamps\"}"))
+        ));
+        assert!(!is_hedgeable(
+            &api_definition,
+            &request("POST", Body::empty())
+        ));
+    }
+}
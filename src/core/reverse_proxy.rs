@@ -1,13 +1,18 @@
 use std::convert::Infallible;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
 use log::{debug, error, info};
 use tokio::sync::mpsc::Sender;
 
+use crate::core::connection_info::ConnectionInfo;
+use crate::core::ip_access::IpAccess;
+use crate::core::rate_limiter::rate_limiting_engine::RateLimiters;
 use crate::core::router::{route_mgt_server, route_proxy_server};
-use crate::{ConfigMgrProxyAPI, RateLimiterAPI};
+use crate::{ConfigMgrProxyAPI, LoadBalancerAPI};
 
 async fn ctrl_c_shutdown_signal() {
     tokio::signal::ctrl_c()
@@ -18,14 +23,23 @@ async fn ctrl_c_shutdown_signal() {
 pub async fn deploy_mgt_server(
     port: u16,
     config_mgr_tx: Sender<ConfigMgrProxyAPI>,
+    rate_limiters: RateLimiters,
+    load_balancer_tx: Sender<LoadBalancerAPI>,
 ) -> hyper::Result<()> {
     info!("Deploying management server");
     let frontend_server_address = SocketAddr::from(([127, 0, 0, 1], port));
     let make_svc_metadata = make_service_fn(move |_| {
         let config_mgr_tx = config_mgr_tx.clone();
+        let rate_limiters = rate_limiters.clone();
+        let load_balancer_tx = load_balancer_tx.clone();
         async move {
             Ok::<_, Infallible>(service_fn(move |request| {
-                route_mgt_server(request, config_mgr_tx.clone())
+                route_mgt_server(
+                    request,
+                    config_mgr_tx.clone(),
+                    rate_limiters.clone(),
+                    load_balancer_tx.clone(),
+                )
             }))
         }
     });
@@ -43,17 +57,32 @@ pub async fn deploy_mgt_server(
 
 pub async fn deploy_reverse_proxy(
     port: u16,
+    ip_access: Arc<IpAccess>,
     config_mgr_tx: Sender<ConfigMgrProxyAPI>,
-    rate_limiter_tx: Sender<RateLimiterAPI>,
+    rate_limiters: RateLimiters,
+    load_balancer_tx: Sender<LoadBalancerAPI>,
 ) -> hyper::Result<()> {
     info!("Deploying reverse proxy server");
     let frontend_server_address = SocketAddr::from(([127, 0, 0, 1], port));
-    let make_svc_metadata = make_service_fn(move |_| {
-        let rate_limiter_tx = rate_limiter_tx.clone();
+    let make_svc_metadata = make_service_fn(move |connection: &AddrStream| {
+        let connection_info = ConnectionInfo {
+            remote_addr: connection.remote_addr(),
+            client_certificate: None,
+        };
+        let ip_access = ip_access.clone();
+        let rate_limiters = rate_limiters.clone();
         let config_mgr_tx = config_mgr_tx.clone();
+        let load_balancer_tx = load_balancer_tx.clone();
         async move {
             Ok::<_, Infallible>(service_fn(move |request| {
-                route_proxy_server(request, config_mgr_tx.clone(), rate_limiter_tx.clone())
+                route_proxy_server(
+                    request,
+                    connection_info.clone(),
+                    ip_access.clone(),
+                    config_mgr_tx.clone(),
+                    rate_limiters.clone(),
+                    load_balancer_tx.clone(),
+                )
             }))
         }
     });
@@ -1,103 +1,465 @@
 use std::convert::Infallible;
-use std::time::Duration;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use hyper::header::{HeaderValue, CONTENT_TYPE};
-use hyper::{Body, Client, Method, Request, Response, Uri};
-use log::trace;
+use hyper::header::{HeaderValue, CONTENT_TYPE, SET_COOKIE};
+use hyper::{Body, Method, Request, Response, Uri};
+use log::{debug, trace};
 use rand::Rng;
+use serde::Serialize;
 use tokio::sync::mpsc::Sender;
 use tokio::time::timeout;
 
 use crate::configuration_reader::api_def_reader::{APIDefinition, APISpecification};
 use crate::configuration_reader::origin_def_reader::{Origin, Server};
+use crate::core::auth::authentication::{
+    append_auth_cookie, authenticate, create_auth_rejection_response, AuthCookie,
+};
+use crate::core::auth::client_cert::authenticate_client_certificate;
 use crate::core::config::config_mgr_proxy_api::ConfigMgrProxyAPI;
-use crate::core::rate_limiter::rate_limiter_api::RateLimiterAPI;
+use crate::core::connection_info::ConnectionInfo;
+use crate::core::consumer_mgt::route_consumer_mgt;
+use crate::core::cors::{
+    append_cors_headers, cors_response_headers, create_preflight_response, preflight_request_method,
+};
+use crate::core::ip_access::{api_admits, IpAccess};
+use crate::core::load_balancer::concurrency_limiter::{ConcurrencyPermit, ConcurrencyRejection};
+use crate::core::load_balancer::load_balancer_api::LoadBalancerAPI;
+use crate::core::origin_client::origin_client;
+use crate::core::rate_limiter::rate_limit_headers::append_rate_limit_headers;
+use crate::core::rate_limiter::rate_limit_key::extract_rate_limit_key;
+use crate::core::rate_limiter::rate_limiter_api::{
+    api_limiter_ids, origin_limiter_id, quota_limiter_ids, RateLimitCheck, RateLimitRejection,
+};
+use crate::core::rate_limiter::rate_limiting_engine::RateLimiters;
+use crate::core::request_body::{check_request_body, create_body_rejection_response, BodyLimit};
+use crate::core::request_hedging::{is_hedgeable, send_hedged_request};
+use crate::core::session_affinity::{create_affinity_cookie, read_affinity_cookie};
 use crate::core::standard_response::{
-    create_404_not_found_response, create_429_too_many_requests_response,
+    create_403_forbidden_response, create_404_not_found_response,
+    create_413_payload_too_large_response, create_429_too_many_requests_response,
     create_500_int_error_response, create_503_service_unavailable_response,
     create_504_gateway_timeout_response,
 };
-use crate::ConfigMgrProxyAPI::{GetAPIDefinitionBySpecification, GetOriginDefinitionByID};
-use crate::RateLimiterAPI::ShouldProgress;
+use crate::core::traffic_mirror::{mirror_origin_id, mirror_request};
+use crate::ConfigMgrProxyAPI::{
+    GetAPIDefinitionBySpecification, GetAPIOriginDefinitions, ReloadDefinitions,
+};
+use crate::LoadBalancerAPI::{
+    GetConcurrencyLimiter, GetConcurrencyStatus, ReportServerOutcome, SelectServer,
+};
 
-fn select_server(servers: &Vec<Server>) -> Option<&Server> {
+pub(crate) fn select_server(servers: &[Server]) -> Option<&Server> {
+    if servers.is_empty() {
+        return None;
+    }
     servers.get(rand::thread_rng().gen_range(0..servers.len()))
 }
 
-async fn process_request_to_origin(
-    rate_limiter_tx: Sender<RateLimiterAPI>,
-    api_definition: APIDefinition,
-    origin_definition: Origin,
-    request: Request<Body>,
-) -> Result<Response<Body>, Infallible> {
+pub(crate) enum OriginCallError {
+    InvalidRequest,
+    Timeout,
+    Unavailable,
+    RequestBodyTooLarge,
+}
+
+pub(crate) fn build_origin_uri(server: &Server, path_and_query: &str) -> String {
+    let mut url_path = String::from("http://");
+    url_path.push_str(server.hostname.as_str());
+    url_path.push(':');
+    url_path.push_str(server.port.to_string().as_str());
+    url_path.push_str(path_and_query);
+    url_path
+}
+
+pub(crate) async fn select_origin_server(
+    load_balancer_tx: &Sender<LoadBalancerAPI>,
+    origin_id: &str,
+    preferred_server: Option<String>,
+    excluded_server: Option<String>,
+) -> Option<Server> {
     let (responder, receiver) = tokio::sync::oneshot::channel();
-    let rate_limit_check_call = ShouldProgress {
-        origin_id: origin_definition.origin_id.clone(),
+    let select_server_call = SelectServer {
+        origin_id: String::from(origin_id),
+        preferred_server,
+        excluded_server,
         responder,
     };
-    match rate_limiter_tx.send(rate_limit_check_call).await {
-        Ok(_) => {
-            trace!(
-                "Successfully queried rate limiter for Origin (Origin ID: {})",
-                origin_definition.origin_id
-            )
-        }
+    match load_balancer_tx.send(select_server_call).await {
         Err(_) => {
             trace!(
-                "Failed to query rate limiter for Origin (Origin ID: {})",
-                origin_definition.origin_id
+                "Failed to query load balancer for Origin (Origin ID: {})",
+                origin_id
+            );
+            None
+        }
+        Ok(_) => receiver.await.unwrap_or(None),
+    }
+}
+
+/// Waits for a slot among the requests in flight to the Origin. Origins without
+/// a concurrency limit need no permit.
+async fn acquire_concurrency_permit(
+    load_balancer_tx: &Sender<LoadBalancerAPI>,
+    origin_id: &str,
+) -> Result<Option<ConcurrencyPermit>, ConcurrencyRejection> {
+    let (responder, receiver) = tokio::sync::oneshot::channel();
+    let concurrency_limiter_call = GetConcurrencyLimiter {
+        origin_id: String::from(origin_id),
+        responder,
+    };
+    if load_balancer_tx
+        .send(concurrency_limiter_call)
+        .await
+        .is_err()
+    {
+        trace!(
+            "Failed to query concurrency limiter for Origin (Origin ID: {})",
+            origin_id
+        );
+        return Ok(None);
+    }
+    match receiver.await {
+        Ok(Some(concurrency_limiter)) => concurrency_limiter.acquire().await.map(Some),
+        _ => Ok(None),
+    }
+}
+
+async fn report_server_outcome(
+    load_balancer_tx: &Sender<LoadBalancerAPI>,
+    origin_id: &str,
+    server: &Server,
+    success: bool,
+    latency: Duration,
+) {
+    let report_outcome_call = ReportServerOutcome {
+        origin_id: String::from(origin_id),
+        server_key: server.server_key(),
+        success,
+        latency,
+    };
+    if load_balancer_tx.send(report_outcome_call).await.is_err() {
+        trace!(
+            "Failed to report server outcome to load balancer for Origin (Origin ID: {})",
+            origin_id
+        );
+    }
+}
+
+pub(crate) async fn call_server(
+    load_balancer_tx: &Sender<LoadBalancerAPI>,
+    origin_id: &str,
+    server: &Server,
+    response_timeout: Duration,
+    mut req_to_origin: Request<Body>,
+) -> Result<Response<Body>, OriginCallError> {
+    let url_path = build_origin_uri(
+        server,
+        req_to_origin
+            .uri()
+            .path_and_query()
+            .map(|path_and_query| path_and_query.as_str())
+            .unwrap_or("/"),
+    );
+    match url_path.as_str().parse::<Uri>() {
+        Err(_) => Err(OriginCallError::InvalidRequest),
+        Ok(uri) => {
+            *req_to_origin.uri_mut() = uri;
+            let body_limit = req_to_origin.extensions().get::<BodyLimit>().cloned();
+            let start = Instant::now();
+            let timeout_result =
+                timeout(response_timeout, origin_client().request(req_to_origin)).await;
+            // A call aborted because the client sent too much says nothing about
+            // the server, so it is not reported
+            if body_limit.is_some_and(|body_limit| body_limit.is_exceeded()) {
+                return Err(OriginCallError::RequestBodyTooLarge);
+            }
+            let success = matches!(timeout_result, Ok(Ok(_)));
+            report_server_outcome(
+                load_balancer_tx,
+                origin_id,
+                server,
+                success,
+                start.elapsed(),
             )
+            .await;
+            match timeout_result {
+                Err(_) => Err(OriginCallError::Timeout),
+                Ok(origin_response) => match origin_response {
+                    Err(_) => Err(OriginCallError::Unavailable),
+                    Ok(response) => Ok(response),
+                },
+            }
         }
     }
-    let rate_limit_check_response = receiver.await;
-    match rate_limit_check_response {
-        Ok(rate_limit_check) => match rate_limit_check {
-            Ok(_) => {
-                let client = Client::new();
-                let mut req_to_origin = Request::from(request);
-                let origin_spec = &(origin_definition.specification);
-                let server = select_server(&origin_spec.servers);
-                match server {
-                    None => create_503_service_unavailable_response(),
-                    Some(server) => {
-                        let mut url_path = String::from("http://");
-                        url_path.push_str(server.hostname.as_str());
-                        url_path.push_str(":");
-                        url_path.push_str(server.port.to_string().as_str());
-                        url_path.push_str(req_to_origin.uri().path_and_query().unwrap().as_str());
-                        let uri_parse_result = url_path.as_str().parse::<Uri>();
-                        match uri_parse_result {
-                            Err(_) => create_500_int_error_response(),
-                            Ok(uri) => {
-                                *req_to_origin.uri_mut() = uri;
-                                let timeout_result = timeout(
-                                    Duration::from_millis(api_definition.backend_response_timeout),
-                                    client.request(req_to_origin),
-                                )
-                                .await;
-                                match timeout_result {
-                                    Err(_) => create_504_gateway_timeout_response(),
-                                    Ok(origin_response) => match origin_response {
-                                        Err(_) => create_503_service_unavailable_response(),
-                                        Ok(response) => Ok(response),
-                                    },
-                                }
+}
+
+/// Lists the limits a request is counted against, API policies matching the
+/// request method first, then the Origin limit and the quotas of the API last, so
+/// that requests rejected by a rate limit do not use up quota.
+fn rate_limit_checks(
+    api_definition: &APIDefinition,
+    origin_definition: &Origin,
+    request: &Request<Body>,
+    client_ip: IpAddr,
+) -> Vec<RateLimitCheck> {
+    let mut checks = vec![];
+    let api_limiter_ids = api_limiter_ids(&api_definition.api_id, &api_definition.rate_limiters);
+    for (limiter_id, rate_limit_policy) in api_limiter_ids
+        .into_iter()
+        .zip(&api_definition.rate_limiters)
+    {
+        if rate_limit_policy.applies_to(request.method().as_str()) {
+            checks.push(RateLimitCheck {
+                limiter_id,
+                key: rate_limit_policy
+                    .rate_limiter
+                    .key
+                    .as_ref()
+                    .map(|key| extract_rate_limit_key(key, request, client_ip)),
+            });
+        }
+    }
+    checks.push(RateLimitCheck {
+        limiter_id: origin_limiter_id(&origin_definition.origin_id),
+        key: origin_definition
+            .specification
+            .rate_limiter
+            .key
+            .as_ref()
+            .map(|key| extract_rate_limit_key(key, request, client_ip)),
+    });
+    let quota_limiter_ids = quota_limiter_ids(&api_definition.api_id, &api_definition.quotas);
+    for (limiter_id, quota_policy) in quota_limiter_ids.into_iter().zip(&api_definition.quotas) {
+        checks.push(RateLimitCheck {
+            limiter_id,
+            key: quota_policy
+                .key
+                .as_ref()
+                .map(|key| extract_rate_limit_key(key, request, client_ip)),
+        });
+    }
+    checks
+}
+
+async fn process_request_to_origin(
+    rate_limiters: RateLimiters,
+    load_balancer_tx: Sender<LoadBalancerAPI>,
+    api_definition: APIDefinition,
+    origin_definition: Origin,
+    mirror_origin: Option<Origin>,
+    request: Request<Body>,
+    client_ip: IpAddr,
+) -> Result<Response<Body>, Infallible> {
+    let rate_limit_check = rate_limiters
+        .check(rate_limit_checks(
+            &api_definition,
+            &origin_definition,
+            &request,
+            client_ip,
+        ))
+        .await;
+    // Limiters without a header style of their own, like quotas, report in the
+    // style of the Origin
+    let origin_header_style = origin_definition
+        .specification
+        .rate_limiter
+        .header_style
+        .clone();
+    match rate_limit_check {
+        Ok(rate_limit_status) => {
+            let concurrency_permit =
+                match acquire_concurrency_permit(&load_balancer_tx, &origin_definition.origin_id)
+                    .await
+                {
+                    Err(rejection) => {
+                        trace!(
+                        "Concurrency limit of Origin (Origin ID: {}) rejected the request - {:?}",
+                        origin_definition.origin_id,
+                        rejection
+                    );
+                        return create_503_service_unavailable_response();
+                    }
+                    Ok(concurrency_permit) => concurrency_permit,
+                };
+            let affinity_config = origin_definition.specification.session_affinity.as_ref();
+            let preferred_server =
+                affinity_config.and_then(|config| read_affinity_cookie(request.headers(), config));
+            let server = select_origin_server(
+                &load_balancer_tx,
+                &origin_definition.origin_id,
+                preferred_server.clone(),
+                None,
+            )
+            .await;
+            match server {
+                None => create_503_service_unavailable_response(),
+                Some(server) => {
+                    let req_to_origin = match mirror_origin {
+                        None => request,
+                        Some(mirror_origin) => {
+                            mirror_request(&api_definition, mirror_origin, request)
+                        }
+                    };
+                    let response_timeout =
+                        Duration::from_millis(api_definition.backend_response_timeout);
+                    let (call_result, server) = if is_hedgeable(&api_definition, &req_to_origin) {
+                        send_hedged_request(
+                            &load_balancer_tx,
+                            &api_definition,
+                            &origin_definition.origin_id,
+                            server,
+                            req_to_origin,
+                        )
+                        .await
+                    } else {
+                        let call_result = call_server(
+                            &load_balancer_tx,
+                            &origin_definition.origin_id,
+                            &server,
+                            response_timeout,
+                            req_to_origin,
+                        )
+                        .await;
+                        (call_result, server)
+                    };
+                    match (concurrency_permit, &call_result) {
+                        (None, _) => {}
+                        (Some(_), Err(OriginCallError::RequestBodyTooLarge)) => {}
+                        (Some(concurrency_permit), call_result) => {
+                            concurrency_permit.complete(matches!(
+                                call_result,
+                                Ok(response) if !response.status().is_server_error()
+                            ))
+                        }
+                    }
+                    match call_result {
+                        Err(OriginCallError::InvalidRequest) => create_500_int_error_response(),
+                        Err(OriginCallError::Timeout) => create_504_gateway_timeout_response(),
+                        Err(OriginCallError::Unavailable) => {
+                            create_503_service_unavailable_response()
+                        }
+                        Err(OriginCallError::RequestBodyTooLarge) => {
+                            create_413_payload_too_large_response()
+                        }
+                        Ok(mut response) => {
+                            let affinity_cookie = affinity_config
+                                .filter(|_| preferred_server != Some(server.server_key()))
+                                .and_then(|config| {
+                                    create_affinity_cookie(config, &server.server_key())
+                                });
+                            if let Some(affinity_cookie) = affinity_cookie {
+                                response.headers_mut().append(SET_COOKIE, affinity_cookie);
                             }
+                            append_rate_limit_headers(
+                                response.headers_mut(),
+                                rate_limit_status
+                                    .header_style
+                                    .as_ref()
+                                    .or(origin_header_style.as_ref()),
+                                rate_limit_status.limit,
+                                rate_limit_status.remaining,
+                                rate_limit_status.reset_after,
+                            );
+                            Ok(response)
                         }
                     }
                 }
             }
-            Err(_) => create_429_too_many_requests_response(),
-        },
+        }
+        Err(RateLimitRejection::LimitExceeded {
+            limit,
+            retry_after,
+            header_style,
+        }) => create_429_too_many_requests_response(Some(retry_after)).map(|mut response| {
+            append_rate_limit_headers(
+                response.headers_mut(),
+                header_style.as_ref().or(origin_header_style.as_ref()),
+                limit,
+                0,
+                retry_after,
+            );
+            response
+        }),
+        Err(RateLimitRejection::UnknownLimiter) => create_429_too_many_requests_response(None),
+    }
+}
+
+pub(crate) fn create_json_response<T: Serialize>(
+    payload: &T,
+) -> Result<Response<Body>, Infallible> {
+    match serde_json::to_string_pretty(payload) {
+        Err(_) => create_500_int_error_response(),
+        Ok(json_payload) => {
+            let response = Response::new(json_payload.into());
+            let (mut parts, body) = response.into_parts();
+            parts
+                .headers
+                .append(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            Ok(Response::from_parts(parts, body))
+        }
+    }
+}
+
+async fn create_concurrency_status_response(
+    load_balancer_tx: Sender<LoadBalancerAPI>,
+) -> Result<Response<Body>, Infallible> {
+    let (responder, receiver) = tokio::sync::oneshot::channel();
+    if load_balancer_tx
+        .send(GetConcurrencyStatus { responder })
+        .await
+        .is_err()
+    {
+        return create_500_int_error_response();
+    }
+    match receiver.await {
         Err(_) => create_500_int_error_response(),
+        Ok(concurrency_statuses) => create_json_response(&concurrency_statuses),
+    }
+}
+
+async fn create_reload_response(
+    config_mgr_tx: Sender<ConfigMgrProxyAPI>,
+) -> Result<Response<Body>, Infallible> {
+    let (responder, receiver) = tokio::sync::oneshot::channel();
+    if config_mgr_tx
+        .send(ReloadDefinitions { responder })
+        .await
+        .is_err()
+    {
+        return create_500_int_error_response();
+    }
+    match receiver.await {
+        Err(_) => create_500_int_error_response(),
+        Ok((api_definitions, origin_definitions, consumers)) => {
+            create_json_response(&serde_json::json!({
+                "api_definitions": api_definitions,
+                "origin_definitions": origin_definitions,
+                "consumers": consumers,
+            }))
+        }
     }
 }
 
 pub async fn route_mgt_server(
     request: Request<Body>,
-    _sender: Sender<ConfigMgrProxyAPI>,
+    config_mgr_tx: Sender<ConfigMgrProxyAPI>,
+    rate_limiters: RateLimiters,
+    load_balancer_tx: Sender<LoadBalancerAPI>,
 ) -> Result<Response<Body>, Infallible> {
     match (request.method(), request.uri().path()) {
+        (&Method::GET, "/origins/concurrency") => {
+            create_concurrency_status_response(load_balancer_tx).await
+        }
+        (&Method::GET, "/quotas") => create_json_response(&rate_limiters.quota_usage()),
+        (&Method::GET, "/rate-limits/shadow") => {
+            create_json_response(&rate_limiters.shadow_usage())
+        }
+        (&Method::POST, "/definitions/reload") => create_reload_response(config_mgr_tx).await,
+        (_, path) if path == "/consumers" || path.starts_with("/consumers/") => {
+            route_consumer_mgt(request, config_mgr_tx).await
+        }
         (&Method::GET, "/status") => {
             let response = Response::new("{\n    \"status\": \"healthy\"\n}".into());
             let (mut parts, body) = response.into_parts();
@@ -110,15 +472,15 @@ pub async fn route_mgt_server(
     }
 }
 
-pub async fn route_proxy_server(
-    request: Request<Body>,
-    config_mgr_tx: Sender<ConfigMgrProxyAPI>,
-    rate_limiter_tx: Sender<RateLimiterAPI>,
-) -> Result<Response<Body>, Infallible> {
+async fn find_api_definition(
+    config_mgr_tx: &Sender<ConfigMgrProxyAPI>,
+    method: &str,
+    request: &Request<Body>,
+) -> Result<Option<APIDefinition>, ()> {
     let (responder, receiver) = tokio::sync::oneshot::channel();
     let find_api_call = GetAPIDefinitionBySpecification {
         specification: APISpecification {
-            methods: vec![request.method().to_string()],
+            methods: vec![String::from(method)],
             paths: vec![request.uri().path().to_string()],
             hostnames: vec![String::from(
                 request
@@ -132,39 +494,55 @@ pub async fn route_proxy_server(
         responder,
     };
     match config_mgr_tx.send(find_api_call).await {
+        Err(_) => Err(()),
+        Ok(_) => receiver.await.map_err(|_| ()),
+    }
+}
+
+async fn route_api_request(
+    api_definition: APIDefinition,
+    request: Request<Body>,
+    connection_info: ConnectionInfo,
+    client_ip: IpAddr,
+    config_mgr_tx: Sender<ConfigMgrProxyAPI>,
+    rate_limiters: RateLimiters,
+    load_balancer_tx: Sender<LoadBalancerAPI>,
+) -> Result<Response<Body>, Infallible> {
+    let request = match authenticate_client_certificate(&api_definition, &connection_info, request)
+    {
+        Err(rejection) => return create_auth_rejection_response(rejection),
+        Ok(request) => request,
+    };
+    let mut request = match authenticate(&config_mgr_tx, &api_definition, request).await {
+        Err(rejection) => return create_auth_rejection_response(rejection),
+        Ok(request) => request,
+    };
+    let auth_cookie = request.extensions_mut().remove::<AuthCookie>();
+    let (responder, receiver) = tokio::sync::oneshot::channel();
+    let find_origin_call = GetAPIOriginDefinitions {
+        origin_id: api_definition.origin_id(),
+        mirror_origin_id: mirror_origin_id(&api_definition),
+        responder,
+    };
+    match config_mgr_tx.send(find_origin_call).await {
         Err(_) => create_500_int_error_response(),
         Ok(_) => {
             let response = receiver.await;
             match response {
                 Ok(result) => match result {
-                    None => create_404_not_found_response(),
-                    Some(api_definition) => {
-                        let (responder, receiver) = tokio::sync::oneshot::channel();
-                        let find_origin_call = GetOriginDefinitionByID {
-                            origin_id: api_definition.origin_id(),
-                            responder,
-                        };
-                        match config_mgr_tx.send(find_origin_call).await {
-                            Err(_) => create_500_int_error_response(),
-                            Ok(_) => {
-                                let response = receiver.await;
-                                match response {
-                                    Ok(result) => match result {
-                                        None => create_503_service_unavailable_response(),
-                                        Some(origin_definition) => {
-                                            process_request_to_origin(
-                                                rate_limiter_tx,
-                                                api_definition,
-                                                origin_definition,
-                                                request,
-                                            )
-                                            .await
-                                        }
-                                    },
-                                    Err(_) => create_500_int_error_response(),
-                                }
-                            }
-                        }
+                    (None, _) => create_503_service_unavailable_response(),
+                    (Some(origin_definition), mirror_origin) => {
+                        let response = process_request_to_origin(
+                            rate_limiters,
+                            load_balancer_tx,
+                            api_definition,
+                            origin_definition,
+                            mirror_origin,
+                            request,
+                            client_ip,
+                        )
+                        .await;
+                        append_auth_cookie(auth_cookie, response)
                     }
                 },
                 Err(_) => create_500_int_error_response(),
@@ -172,3 +550,215 @@ pub async fn route_proxy_server(
         }
     }
 }
+
+pub async fn route_proxy_server(
+    request: Request<Body>,
+    connection_info: ConnectionInfo,
+    ip_access: Arc<IpAccess>,
+    config_mgr_tx: Sender<ConfigMgrProxyAPI>,
+    rate_limiters: RateLimiters,
+    load_balancer_tx: Sender<LoadBalancerAPI>,
+) -> Result<Response<Body>, Infallible> {
+    let client_ip = ip_access.client_ip(&request, &connection_info);
+    if !ip_access.admits(client_ip) {
+        debug!("Rejected request from denied client address {}", client_ip);
+        return create_403_forbidden_response();
+    }
+    // Preflight requests are matched to the API of the request they ask about,
+    // falling back to routing them as is when that API has no CORS policy.
+    if let Some(requested_method) = preflight_request_method(&request) {
+        match find_api_definition(&config_mgr_tx, requested_method, &request).await {
+            Err(_) => return create_500_int_error_response(),
+            Ok(Some(api_definition)) => {
+                if let Some(cors_policy) = &api_definition.cors {
+                    if !api_admits(&api_definition, client_ip) {
+                        return create_403_forbidden_response();
+                    }
+                    return create_preflight_response(&api_definition, cors_policy, &request);
+                }
+            }
+            Ok(None) => {}
+        }
+    }
+    match find_api_definition(&config_mgr_tx, request.method().as_str(), &request).await {
+        Err(_) => create_500_int_error_response(),
+        Ok(None) => create_404_not_found_response(),
+        Ok(Some(api_definition)) => {
+            if !api_admits(&api_definition, client_ip) {
+                debug!(
+                    "Rejected request from client address {} to APIDefinition (APIDefinition ID: {})",
+                    client_ip, api_definition.api_id
+                );
+                return create_403_forbidden_response();
+            }
+            let cors_headers = api_definition
+                .cors
+                .as_ref()
+                .map(|cors_policy| cors_response_headers(cors_policy, &request));
+            let request = match check_request_body(&api_definition, request).await {
+                Err(rejection) => {
+                    return append_cors_headers(
+                        cors_headers,
+                        create_body_rejection_response(rejection),
+                    )
+                }
+                Ok(checked_request) => checked_request,
+            };
+            let response = route_api_request(
+                api_definition,
+                request,
+                connection_info,
+                client_ip,
+                config_mgr_tx,
+                rate_limiters,
+                load_balancer_tx,
+            )
+            .await;
+            append_cors_headers(cors_headers, response)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use async_stream::stream;
+    use hyper::body::Bytes;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, StatusCode};
+    use tokio::sync::mpsc;
+
+    use crate::configuration_reader::api_def_reader::APIDefinition;
+    use crate::configuration_reader::ip_access_config_reader::IpAccessConfig;
+    use crate::configuration_reader::origin_def_reader::Server;
+    use crate::core::config::config_mgr_proxy_api::ConfigMgrProxyAPI;
+    use crate::core::connection_info::ConnectionInfo;
+    use crate::core::ip_access::IpAccess;
+    use crate::core::load_balancer::load_balancer_api::LoadBalancerAPI;
+    use crate::core::rate_limiter::rate_limiting_engine::RateLimiters;
+
+    use super::route_proxy_server;
+
+    /// Origin server counting the requests it received.
+    fn spawn_origin_server() -> (Server, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted_requests = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let counted_requests = counted_requests.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    counted_requests.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        let _ = hyper::body::to_bytes(request.into_body()).await;
+                        Ok::<_, Infallible>(Response::new(Body::empty()))
+                    }
+                }))
+            }
+        });
+        let server =
+            hyper::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let port = server.local_addr().port();
+        tokio::spawn(server);
+        let server = Server {
+            hostname: String::from("127.0.0.1"),
+            port,
+            secure: false,
+            verify_cert: false,
+            priority: None,
+        };
+        (server, requests)
+    }
+
+    /// Configuration manager that only knows `api_definition`.
+    fn spawn_config_mgr(api_definition: APIDefinition) -> mpsc::Sender<ConfigMgrProxyAPI> {
+        let (config_mgr_tx, mut config_mgr_rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Some(api_call) = config_mgr_rx.recv().await {
+                if let ConfigMgrProxyAPI::GetAPIDefinitionBySpecification { responder, .. } =
+                    api_call
+                {
+                    let _ = responder.send(Some(api_definition.clone()));
+                }
+            }
+        });
+        config_mgr_tx
+    }
+
+    /// Load balancer that always selects `server` and counts the outcomes
+    /// reported to it.
+    fn spawn_load_balancer(server: Server) -> (mpsc::Sender<LoadBalancerAPI>, Arc<AtomicUsize>) {
+        let outcomes = Arc::new(AtomicUsize::new(0));
+        let reported_outcomes = outcomes.clone();
+        let (load_balancer_tx, mut load_balancer_rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Some(api_call) = load_balancer_rx.recv().await {
+                match api_call {
+                    LoadBalancerAPI::SelectServer { responder, .. } => {
+                        let _ = responder.send(Some(server.clone()));
+                    }
+                    LoadBalancerAPI::GetConcurrencyLimiter { responder, .. } => {
+                        let _ = responder.send(None);
+                    }
+                    LoadBalancerAPI::ReportServerOutcome { .. } => {
+                        reported_outcomes.fetch_add(1, Ordering::SeqCst);
+                    }
+                    _ => {}
+                }
+            }
+        });
+        (load_balancer_tx, outcomes)
+    }
+
+    #[tokio::test]
+    async fn test_oversized_chunked_uploads_never_reach_the_origin() {
+        let api_definition = APIDefinition::from_json_str_slice(
+            r#"{
+                "api_id": "uploads",
+                "api_name": "Uploads API",
+                "api_version": "1.0.0",
+                "api_desc": "Uploads",
+                "specification": {"methods": ["POST"], "paths": ["/uploads"], "hostnames": ["localhost"]},
+                "backend_response_timeout": 1000,
+                "origin_id": "uploads",
+                "request_body": {"max_size": 8}
+            }"#,
+        )
+        .unwrap();
+        let (server, origin_requests) = spawn_origin_server();
+        let config_mgr_tx = spawn_config_mgr(api_definition);
+        let (load_balancer_tx, reported_outcomes) = spawn_load_balancer(server);
+        let ip_access = Arc::new(IpAccess::from_config(&IpAccessConfig::default()).unwrap());
+        for _ in 0..3 {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/uploads")
+                .header("host", "localhost")
+                .body(Body::wrap_stream(stream! {
+                    for chunk in ["1234", "5678", "9"] {
+                        yield Ok::<_, Infallible>(Bytes::from_static(chunk.as_bytes()));
+                    }
+                }))
+                .unwrap();
+            let response = route_proxy_server(
+                request,
+                ConnectionInfo {
+                    remote_addr: SocketAddr::from(([192, 0, 2, 1], 52000)),
+                    client_certificate: None,
+                },
+                ip_access.clone(),
+                config_mgr_tx.clone(),
+                RateLimiters::new(None),
+                load_balancer_tx.clone(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        }
+        assert_eq!(0, origin_requests.load(Ordering::SeqCst));
+        assert_eq!(0, reported_outcomes.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,117 @@
+use hmac::{Hmac, Mac};
+use hyper::header::{HeaderValue, COOKIE};
+use hyper::HeaderMap;
+use log::trace;
+use sha2::Sha256;
+
+use crate::configuration_reader::origin_def_reader::SessionAffinityConfig;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn sign_server_key(secret: &str, server_key: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(server_key.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn verify_cookie_value(secret: &str, cookie_value: &str) -> Option<String> {
+    let (server_key, signature) = cookie_value.rsplit_once('.')?;
+    let signature = hex::decode(signature).ok()?;
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(server_key.as_bytes());
+    match mac.verify_slice(&signature) {
+        Ok(_) => Some(String::from(server_key)),
+        Err(_) => {
+            trace!("Rejected session affinity cookie with an invalid signature");
+            None
+        }
+    }
+}
+
+/// Returns the server key carried by the affinity cookie, provided its signature
+/// was issued with the Origin's secret.
+pub(crate) fn read_affinity_cookie(
+    headers: &HeaderMap,
+    affinity_config: &SessionAffinityConfig,
+) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header_value| header_value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == affinity_config.cookie_name)
+        .and_then(|(_, value)| verify_cookie_value(&affinity_config.secret, value))
+}
+
+pub(crate) fn create_affinity_cookie(
+    affinity_config: &SessionAffinityConfig,
+    server_key: &str,
+) -> Option<HeaderValue> {
+    let mut cookie = format!(
+        "{}={}.{}; Path=/; HttpOnly; SameSite=Lax",
+        affinity_config.cookie_name,
+        server_key,
+        sign_server_key(&affinity_config.secret, server_key)
+    );
+    if let Some(max_age) = affinity_config.max_age {
+        cookie.push_str(format!("; Max-Age={}", max_age).as_str());
+    }
+    HeaderValue::from_str(cookie.as_str()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::header::{HeaderValue, COOKIE};
+    use hyper::HeaderMap;
+
+    use crate::configuration_reader::origin_def_reader::SessionAffinityConfig;
+
+    use super::{create_affinity_cookie, read_affinity_cookie, sign_server_key};
+
+    fn affinity_config() -> SessionAffinityConfig {
+        SessionAffinityConfig {
+            cookie_name: String::from("GATEMAN_AFFINITY"),
+            secret: String::from("some-secret"),
+            max_age: Some(3600),
+        }
+    }
+
+    #[test]
+    fn test_cookie_round_trip() {
+        let config = affinity_config();
+        let set_cookie = create_affinity_cookie(&config, "backend.example.com:8000").unwrap();
+        let cookie_pair = set_cookie.to_str().unwrap().split(';').next().unwrap();
+        assert!(set_cookie.to_str().unwrap().ends_with("; Max-Age=3600"));
+
+        let mut headers = HeaderMap::new();
+        let cookie_header = format!("other=value; {}", cookie_pair);
+        headers.insert(COOKIE, HeaderValue::from_str(&cookie_header).unwrap());
+        assert_eq!(
+            Some(String::from("backend.example.com:8000")),
+            read_affinity_cookie(&headers, &config)
+        );
+    }
+
+    #[test]
+    fn test_tampered_cookie_is_rejected() {
+        let config = affinity_config();
+        let signature = sign_server_key(&config.secret, "backend-a:8000");
+        let mut headers = HeaderMap::new();
+        let cookie_header = format!("GATEMAN_AFFINITY=backend-b:8000.{}", signature);
+        headers.insert(COOKIE, HeaderValue::from_str(&cookie_header).unwrap());
+        assert_eq!(None, read_affinity_cookie(&headers, &config));
+    }
+
+    #[test]
+    fn test_cookie_signed_with_other_secret_is_rejected() {
+        let config = affinity_config();
+        let signature = sign_server_key("another-secret", "backend-a:8000");
+        let mut headers = HeaderMap::new();
+        let cookie_header = format!("GATEMAN_AFFINITY=backend-a:8000.{}", signature);
+        headers.insert(COOKIE, HeaderValue::from_str(&cookie_header).unwrap());
+        assert_eq!(None, read_affinity_cookie(&headers, &config));
+    }
+}
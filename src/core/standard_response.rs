@@ -1,8 +1,11 @@
 use std::convert::Infallible;
+use std::time::Duration;
 
-use hyper::header::{HeaderValue, CONTENT_ENCODING, CONTENT_TYPE};
+use hyper::header::{HeaderValue, CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER};
 use hyper::{Body, Response, StatusCode};
 
+use crate::core::rate_limiter::rate_limit_headers::ceil_seconds;
+
 pub(crate) fn create_404_not_found_response() -> Result<Response<Body>, Infallible> {
     let response = Response::new("404 Not Found".into());
     let (mut parts, body) = response.into_parts();
@@ -59,7 +62,9 @@ pub(crate) fn create_500_int_error_response() -> Result<Response<Body>, Infallib
     Ok(Response::from_parts(parts, body))
 }
 
-pub(crate) fn create_429_too_many_requests_response() -> Result<Response<Body>, Infallible> {
+pub(crate) fn create_429_too_many_requests_response(
+    retry_after: Option<Duration>,
+) -> Result<Response<Body>, Infallible> {
     let response = Response::new("429 Too Many Requests".into());
     let (mut parts, body) = response.into_parts();
     parts.status = StatusCode::TOO_MANY_REQUESTS;
@@ -67,6 +72,95 @@ pub(crate) fn create_429_too_many_requests_response() -> Result<Response<Body>,
         CONTENT_TYPE,
         HeaderValue::from_static("text/plain; charset=utf-8"),
     );
+    parts
+        .headers
+        .append(CONTENT_ENCODING, HeaderValue::from_static("utf-8"));
+    if let Some(retry_after) = retry_after {
+        parts
+            .headers
+            .append(RETRY_AFTER, HeaderValue::from(ceil_seconds(retry_after)));
+    }
+    Ok(Response::from_parts(parts, body))
+}
+
+pub(crate) fn create_400_bad_request_response() -> Result<Response<Body>, Infallible> {
+    let response = Response::new("400 Bad Request".into());
+    let (mut parts, body) = response.into_parts();
+    parts.status = StatusCode::BAD_REQUEST;
+    parts.headers.append(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    parts
+        .headers
+        .append(CONTENT_ENCODING, HeaderValue::from_static("utf-8"));
+    Ok(Response::from_parts(parts, body))
+}
+
+pub(crate) fn create_401_unauthorized_response() -> Result<Response<Body>, Infallible> {
+    let response = Response::new("401 Unauthorized".into());
+    let (mut parts, body) = response.into_parts();
+    parts.status = StatusCode::UNAUTHORIZED;
+    parts.headers.append(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    parts
+        .headers
+        .append(CONTENT_ENCODING, HeaderValue::from_static("utf-8"));
+    Ok(Response::from_parts(parts, body))
+}
+
+pub(crate) fn create_403_forbidden_response() -> Result<Response<Body>, Infallible> {
+    let response = Response::new("403 Forbidden".into());
+    let (mut parts, body) = response.into_parts();
+    parts.status = StatusCode::FORBIDDEN;
+    parts.headers.append(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    parts
+        .headers
+        .append(CONTENT_ENCODING, HeaderValue::from_static("utf-8"));
+    Ok(Response::from_parts(parts, body))
+}
+
+pub(crate) fn create_409_conflict_response() -> Result<Response<Body>, Infallible> {
+    let response = Response::new("409 Conflict".into());
+    let (mut parts, body) = response.into_parts();
+    parts.status = StatusCode::CONFLICT;
+    parts.headers.append(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    parts
+        .headers
+        .append(CONTENT_ENCODING, HeaderValue::from_static("utf-8"));
+    Ok(Response::from_parts(parts, body))
+}
+
+pub(crate) fn create_413_payload_too_large_response() -> Result<Response<Body>, Infallible> {
+    let response = Response::new("413 Payload Too Large".into());
+    let (mut parts, body) = response.into_parts();
+    parts.status = StatusCode::PAYLOAD_TOO_LARGE;
+    parts.headers.append(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    parts
+        .headers
+        .append(CONTENT_ENCODING, HeaderValue::from_static("utf-8"));
+    Ok(Response::from_parts(parts, body))
+}
+
+pub(crate) fn create_415_unsupported_media_type_response() -> Result<Response<Body>, Infallible> {
+    let response = Response::new("415 Unsupported Media Type".into());
+    let (mut parts, body) = response.into_parts();
+    parts.status = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+    parts.headers.append(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
     parts
         .headers
         .append(CONTENT_ENCODING, HeaderValue::from_static("utf-8"));
@@ -1,20 +1,32 @@
 use std::convert::Infallible;
 use std::fs::File;
 use std::io::{BufReader, Error, ErrorKind};
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
 use std::sync::Arc;
 
 use async_stream::stream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
 use log::{debug, error, info};
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc::Sender;
-use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, WantsServerCert,
+};
+use tokio_rustls::rustls::{Certificate, ConfigBuilder, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
 use tokio_rustls::TlsAcceptor;
 
+use crate::configuration_reader::tls_config_reader::{ClientAuthMode, TlsListenerConfig};
+use crate::core::auth::client_cert::ClientCertificate;
+use crate::core::config::read_config::read_tls_listener_config;
+use crate::core::connection_info::ConnectionInfo;
+use crate::core::ip_access::IpAccess;
+use crate::core::rate_limiter::rate_limiting_engine::RateLimiters;
 use crate::core::router::route_proxy_server;
-use crate::{ConfigMgrProxyAPI, RateLimiterAPI};
+use crate::utils::path_utils::get_directory_of_executable;
+use crate::{ConfigMgrProxyAPI, LoadBalancerAPI};
 
 async fn ctrl_c_shutdown_signal() {
     tokio::signal::ctrl_c()
@@ -62,14 +74,52 @@ fn load_certs(filename: &str) -> Result<Vec<Certificate>, Error> {
     }
 }
 
-fn create_tls_config() -> Option<Arc<ServerConfig>> {
+/// Starts the server configuration with the verification of client certificates
+/// against the CA bundle of the listener, if it asks clients for certificates.
+fn create_tls_config_builder(
+    tls_listener_config: &TlsListenerConfig,
+) -> Result<ConfigBuilder<ServerConfig, WantsServerCert>, Error> {
+    let config_builder = ServerConfig::builder().with_safe_defaults();
+    if tls_listener_config.client_auth == ClientAuthMode::None {
+        return Ok(config_builder.with_no_client_auth());
+    }
+    let client_ca_file = tls_listener_config
+        .client_ca_file
+        .as_ref()
+        .ok_or_else(|| Error::other("Client authentication requires a client CA file"))?;
+    let path_buffer = get_directory_of_executable().join(Path::new(client_ca_file));
+    let mut client_roots = RootCertStore::empty();
+    for certificate in load_certs(path_buffer.to_str().unwrap_or(client_ca_file))? {
+        client_roots.add(&certificate).map_err(Error::other)?;
+    }
+    if client_roots.is_empty() {
+        return Err(Error::other("Expecting client CA certificates, found none"));
+    }
+    info!(
+        "Verifying client certificates against {} CA certificates",
+        client_roots.len()
+    );
+    Ok(match tls_listener_config.client_auth {
+        ClientAuthMode::Require => {
+            config_builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(client_roots))
+        }
+        _ => config_builder
+            .with_client_cert_verifier(AllowAnyAnonymousOrAuthenticatedClient::new(client_roots)),
+    })
+}
+
+fn create_tls_config(tls_listener_config: &TlsListenerConfig) -> Option<Arc<ServerConfig>> {
+    let config_builder = match create_tls_config_builder(tls_listener_config) {
+        Ok(config_builder) => config_builder,
+        Err(error) => {
+            error!("Could not load client CA certificates - {}", error);
+            return None;
+        }
+    };
     match load_certs("resources/certs/proxy/certificate.crt") {
         Ok(certs) => match load_private_key("resources/certs/proxy/private.key") {
             Ok(key) => {
-                let config = ServerConfig::builder()
-                    .with_safe_defaults()
-                    .with_no_client_auth()
-                    .with_single_cert(certs, key);
+                let config = config_builder.with_single_cert(certs, key);
                 match config {
                     Ok(mut config) => {
                         config.alpn_protocols = vec![b"http/1.1".to_vec()];
@@ -95,11 +145,13 @@ fn create_tls_config() -> Option<Arc<ServerConfig>> {
 
 pub async fn deploy_tls_reverse_proxy(
     port: u16,
+    ip_access: Arc<IpAccess>,
     config_mgr_tx: Sender<ConfigMgrProxyAPI>,
-    rate_limiter_tx: Sender<RateLimiterAPI>,
+    rate_limiters: RateLimiters,
+    load_balancer_tx: Sender<LoadBalancerAPI>,
 ) {
     info!("Deploying TLS reverse proxy server");
-    match create_tls_config() {
+    match read_tls_listener_config().and_then(|config| create_tls_config(&config)) {
         None => {
             error!("TLS configuration creation failed. Exiting TLS reverse proxy");
         }
@@ -128,19 +180,38 @@ pub async fn deploy_tls_reverse_proxy(
                             }
                         }
                     });
-                    let make_svc_metadata = make_service_fn(move |_| {
-                        let rate_limiter_tx = rate_limiter_tx.clone();
-                        let config_mgr_tx = config_mgr_tx.clone();
-                        async move {
-                            Ok::<_, Infallible>(service_fn(move |request| {
-                                route_proxy_server(
-                                    request,
-                                    config_mgr_tx.clone(),
-                                    rate_limiter_tx.clone(),
-                                )
-                            }))
-                        }
-                    });
+                    let make_svc_metadata =
+                        make_service_fn(move |connection: &TlsStream<TcpStream>| {
+                            let (tcp_stream, tls_connection) = connection.get_ref();
+                            let connection_info = ConnectionInfo {
+                                remote_addr: tcp_stream.peer_addr().unwrap_or_else(|_| {
+                                    SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+                                }),
+                                client_certificate: tls_connection
+                                    .peer_certificates()
+                                    .and_then(|certificates| certificates.first())
+                                    .and_then(|certificate| {
+                                        ClientCertificate::from_der(&certificate.0)
+                                    })
+                                    .map(Arc::new),
+                            };
+                            let ip_access = ip_access.clone();
+                            let rate_limiters = rate_limiters.clone();
+                            let config_mgr_tx = config_mgr_tx.clone();
+                            let load_balancer_tx = load_balancer_tx.clone();
+                            async move {
+                                Ok::<_, Infallible>(service_fn(move |request| {
+                                    route_proxy_server(
+                                        request,
+                                        connection_info.clone(),
+                                        ip_access.clone(),
+                                        config_mgr_tx.clone(),
+                                        rate_limiters.clone(),
+                                        load_balancer_tx.clone(),
+                                    )
+                                }))
+                            }
+                        });
 
                     let server = Server::builder(accept_stream).serve(make_svc_metadata);
                     let graceful = server.with_graceful_shutdown(ctrl_c_shutdown_signal());
@@ -158,3 +229,35 @@ pub async fn deploy_tls_reverse_proxy(
     }
     debug!("TLS reverse proxy server exited");
 }
+
+#[cfg(test)]
+mod test {
+    use crate::configuration_reader::tls_config_reader::{ClientAuthMode, TlsListenerConfig};
+
+    use super::create_tls_config_builder;
+
+    const CLIENT_CA_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----\nMIIBojCCAUmgAwIBAgIUDtWUd8tuBzhHsi5T4/TBmGUNTJYwCgYIKoZIzj0EAwIw\nJjENMAsGA1UECgwEQWNtZTEVMBMGA1UEAwwMQWNtZSBUZXN0IENBMCAXDTI2MTAx\nOTAxMDgxMloYDzIxMjYwOTI1MDEwODEyWjAmMQ0wCwYDVQQKDARBY21lMRUwEwYD\nVQQDDAxBY21lIFRlc3QgQ0EwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAAQCHALF\nAgVDHz6iJVI3U9Ghcnu0jjA+nqbkv+nkw6+rPG8zRonoIzmzYLoAh1H5TVOAFu4W\nkgMix5TsX/rTY584o1MwUTAdBgNVHQ4EFgQUrcvTrXOhtTqQC8De/nr0E8GHB6ow\nHwYDVR0jBBgwFoAUrcvTrXOhtTqQC8De/nr0E8GHB6owDwYDVR0TAQH/BAUwAwEB\n/zAKBggqhkjOPQQDAgNHADBEAiBl9KRLFM6h5K9HpquRTrI8mIE202ncxCxu1Tkf\nFLDr1QIgSvTbN/X/uxNh3Qftpal33thMIGsy283zeVdc9q/PPzQ=\n-----END CERTIFICATE-----\n";
+
+    #[test]
+    fn test_client_authentication_requires_a_ca_bundle() {
+        let client_ca_file =
+            std::env::temp_dir().join(format!("gateman-client-ca-{}.crt", std::process::id()));
+        std::fs::write(&client_ca_file, CLIENT_CA_CERTIFICATE).unwrap();
+        let client_ca_file = client_ca_file.to_str().unwrap().to_string();
+
+        assert!(create_tls_config_builder(&TlsListenerConfig::default()).is_ok());
+        for client_auth in [ClientAuthMode::Request, ClientAuthMode::Require] {
+            assert!(create_tls_config_builder(&TlsListenerConfig {
+                client_auth: client_auth.clone(),
+                client_ca_file: None,
+            })
+            .is_err());
+            assert!(create_tls_config_builder(&TlsListenerConfig {
+                client_auth,
+                client_ca_file: Some(client_ca_file.clone()),
+            })
+            .is_ok());
+        }
+        std::fs::remove_file(&client_ca_file).unwrap();
+    }
+}
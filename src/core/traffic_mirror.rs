@@ -0,0 +1,216 @@
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_stream::stream;
+use hyper::body::{Bytes, HttpBody};
+use hyper::{Body, Request, Uri};
+use log::{info, trace};
+use rand::Rng;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+use crate::configuration_reader::api_def_reader::APIDefinition;
+use crate::configuration_reader::origin_def_reader::Origin;
+use crate::core::origin_client::origin_client;
+use crate::core::router::{build_origin_uri, select_server};
+
+const MIRROR_LOG_TARGET: &str = "gateman::mirror";
+const MIRROR_BODY_BUFFER_CHUNKS: usize = 64;
+
+fn should_mirror(percentage: f64) -> bool {
+    percentage > 0.0 && rand::thread_rng().gen_range(0.0..100.0) < percentage
+}
+
+/// Decides whether the request is mirrored, returning the shadow Origin to look
+/// up along with the primary one.
+pub(crate) fn mirror_origin_id(api_definition: &APIDefinition) -> Option<String> {
+    let mirror_config = api_definition.traffic_mirror.as_ref()?;
+    if !should_mirror(mirror_config.percentage) {
+        return None;
+    }
+    Some(mirror_config.origin_id.clone())
+}
+
+/// Splits the request body so that every chunk streamed to the primary Origin is
+/// also copied to a shadow request, which is dispatched in the background. The
+/// primary request never waits on the shadow; if the shadow falls behind by more
+/// than `MIRROR_BODY_BUFFER_CHUNKS` chunks, the shadow request is aborted instead.
+/// The shadow body only ends normally once the primary body was read to the end,
+/// so that the shadow Origin never takes a truncated body for a complete one.
+fn tee_body(mut body: Body) -> (Body, Body) {
+    if body.is_end_stream() {
+        return (body, Body::empty());
+    }
+    let (shadow_tx, mut shadow_rx) = mpsc::channel::<Bytes>(MIRROR_BODY_BUFFER_CHUNKS);
+    let primary_completed = Arc::new(AtomicBool::new(false));
+
+    let primary_completed_flag = primary_completed.clone();
+    let primary_body = Body::wrap_stream(stream! {
+        let mut shadow_tx = Some(shadow_tx);
+        while let Some(chunk) = body.data().await {
+            match &chunk {
+                Ok(bytes) => {
+                    if let Some(sender) = shadow_tx.as_ref() {
+                        if sender.try_send(bytes.clone()).is_err() {
+                            trace!("Shadow request fell behind the primary request and is aborted");
+                            shadow_tx = None;
+                        }
+                    }
+                }
+                Err(_) => shadow_tx = None,
+            }
+            yield chunk;
+        }
+        if shadow_tx.is_some() {
+            primary_completed_flag.store(true, Ordering::Release);
+        }
+    });
+
+    let shadow_body = Body::wrap_stream(stream! {
+        while let Some(bytes) = shadow_rx.recv().await {
+            yield Ok(bytes);
+        }
+        if !primary_completed.load(Ordering::Acquire) {
+            yield Err(Error::new(ErrorKind::Interrupted, "Shadow request body aborted"));
+        }
+    });
+    (primary_body, shadow_body)
+}
+
+pub(crate) fn mirror_request(
+    api_definition: &APIDefinition,
+    mirror_origin: Origin,
+    request: Request<Body>,
+) -> Request<Body> {
+    let (parts, body) = request.into_parts();
+    let (primary_body, shadow_body) = tee_body(body);
+
+    let mut shadow_request = Request::new(shadow_body);
+    *shadow_request.method_mut() = parts.method.clone();
+    *shadow_request.uri_mut() = parts.uri.clone();
+    *shadow_request.version_mut() = parts.version;
+    *shadow_request.headers_mut() = parts.headers.clone();
+    tokio::spawn(send_shadow_request(
+        api_definition.api_id.clone(),
+        Duration::from_millis(api_definition.backend_response_timeout),
+        mirror_origin,
+        shadow_request,
+    ));
+
+    Request::from_parts(parts, primary_body)
+}
+
+async fn send_shadow_request(
+    api_id: String,
+    response_timeout: Duration,
+    mirror_origin: Origin,
+    mut shadow_request: Request<Body>,
+) {
+    let server = match select_server(&mirror_origin.specification.servers) {
+        None => {
+            info!(
+                target: MIRROR_LOG_TARGET,
+                "No server available in shadow Origin (Origin ID: {}) for APIDefinition (APIDefinition ID: {})",
+                mirror_origin.origin_id,
+                api_id
+            );
+            return;
+        }
+        Some(server) => server,
+    };
+    let path_and_query = shadow_request
+        .uri()
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or("/");
+    match build_origin_uri(server, path_and_query).parse::<Uri>() {
+        Err(_) => {
+            trace!(
+                "Failed to build URI of shadow Origin (Origin ID: {})",
+                mirror_origin.origin_id
+            );
+        }
+        Ok(uri) => {
+            *shadow_request.uri_mut() = uri;
+            let start = Instant::now();
+            let result = timeout(response_timeout, origin_client().request(shadow_request)).await;
+            let elapsed = start.elapsed().as_millis();
+            match result {
+                Err(_) => info!(
+                    target: MIRROR_LOG_TARGET,
+                    "Shadow request for APIDefinition (APIDefinition ID: {}) to Origin (Origin ID: {}, Server: {}:{}) timed out after {} ms",
+                    api_id, mirror_origin.origin_id, server.hostname, server.port, elapsed
+                ),
+                Ok(Err(error)) => info!(
+                    target: MIRROR_LOG_TARGET,
+                    "Shadow request for APIDefinition (APIDefinition ID: {}) to Origin (Origin ID: {}, Server: {}:{}) failed after {} ms - {}",
+                    api_id, mirror_origin.origin_id, server.hostname, server.port, elapsed, error
+                ),
+                Ok(Ok(response)) => info!(
+                    target: MIRROR_LOG_TARGET,
+                    "Shadow request for APIDefinition (APIDefinition ID: {}) to Origin (Origin ID: {}, Server: {}:{}) responded with status {} in {} ms",
+                    api_id, mirror_origin.origin_id, server.hostname, server.port, response.status().as_u16(), elapsed
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use async_stream::stream;
+    use hyper::body::{Bytes, HttpBody};
+    use hyper::Body;
+
+    use super::{should_mirror, tee_body};
+
+    fn chunked_body(chunks: &'static [&'static str]) -> Body {
+        Body::wrap_stream(stream! {
+            for chunk in chunks {
+                yield Ok::<_, Infallible>(Bytes::from_static(chunk.as_bytes()));
+            }
+        })
+    }
+
+    #[test]
+    fn test_should_mirror_never_for_zero_percentage() {
+        for _ in 0..1000 {
+            assert!(!should_mirror(0.0));
+        }
+    }
+
+    #[test]
+    fn test_should_mirror_always_for_full_percentage() {
+        for _ in 0..1000 {
+            assert!(should_mirror(100.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tee_copies_the_body_read_by_the_primary() {
+        let (primary_body, shadow_body) = tee_body(chunked_body(&["user=", "alice"]));
+        assert_eq!(
+            "user=alice",
+            hyper::body::to_bytes(primary_body).await.unwrap()
+        );
+        assert_eq!(
+            "user=alice",
+            hyper::body::to_bytes(shadow_body).await.unwrap()
+        );
+
+        let (primary_body, shadow_body) = tee_body(Body::empty());
+        assert!(primary_body.is_end_stream() && shadow_body.is_end_stream());
+    }
+
+    #[tokio::test]
+    async fn test_tee_aborts_the_shadow_if_the_primary_stops_reading() {
+        let (mut primary_body, shadow_body) = tee_body(chunked_body(&["user=", "alice"]));
+        assert_eq!("user=", primary_body.data().await.unwrap().unwrap());
+        drop(primary_body);
+        assert!(hyper::body::to_bytes(shadow_body).await.is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::path::Path;
 
@@ -27,6 +27,23 @@ impl FileWriter {
             }),
         }
     }
+    pub(crate) fn append(&self, content: &String) -> Result<(), FileOperationError> {
+        match OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Path::new(self.filepath.as_str()))
+        {
+            Ok(mut file) => match file.write_all(content.as_bytes()) {
+                Ok(_) => Result::Ok(()),
+                Err(reason) => Result::Err(FileOperationError {
+                    message: format!("Failed to append to file because of {} reason", reason),
+                }),
+            },
+            Err(reason) => Result::Err(FileOperationError {
+                message: format!("Failed to append to file because of {} reason", reason),
+            }),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1,10 +1,17 @@
-use log::info;
+use std::sync::Arc;
+
+use log::{error, info};
 use tokio::sync::mpsc;
 
 use crate::core::config::config_mgr::deploy_config_mgr;
 use crate::core::config::config_mgr_proxy_api::ConfigMgrProxyAPI;
+use crate::core::config::read_config::{read_cluster_config, read_ip_access_config};
+use crate::core::ip_access::IpAccess;
+use crate::core::load_balancer::load_balancer_api::LoadBalancerAPI;
+use crate::core::load_balancer::load_balancing_engine::deploy_load_balancer;
+use crate::core::rate_limiter::cluster::ClusterBackend;
 use crate::core::rate_limiter::rate_limiter_api::RateLimiterAPI;
-use crate::core::rate_limiter::rate_limiting_engine::deploy_rate_limiter;
+use crate::core::rate_limiter::rate_limiting_engine::{deploy_rate_limiter, RateLimiters};
 use crate::core::reverse_proxy::{deploy_mgt_server, deploy_reverse_proxy};
 use crate::core::tls_reverse_proxy::deploy_tls_reverse_proxy;
 use crate::utils::path_utils::get_directory_of_executable;
@@ -28,21 +35,52 @@ fn main() {
         .build()
         .unwrap()
         .block_on(async {
+            let ip_access = match read_ip_access_config()
+                .map(|ip_access_config| IpAccess::from_config(&ip_access_config))
+            {
+                Some(Ok(ip_access)) => Arc::new(ip_access),
+                Some(Err(e)) => {
+                    error!("Invalid IP access configuration - {}", e);
+                    return;
+                }
+                None => {
+                    error!("IP access configuration could not be read");
+                    return;
+                }
+            };
+            let rate_limiters = RateLimiters::new(
+                read_cluster_config().map(|cluster_config| ClusterBackend::new(&cluster_config)),
+            );
             let (rate_limiter_tx, rate_limiter_rx) = mpsc::channel::<RateLimiterAPI>(32);
             let (config_mgr_tx, config_mgr_rx) = mpsc::channel::<ConfigMgrProxyAPI>(32);
+            let (load_balancer_tx, load_balancer_rx) = mpsc::channel::<LoadBalancerAPI>(32);
             tokio::select!(
-                _ = tokio::spawn(deploy_rate_limiter(rate_limiter_rx)) => 0,
-                _ = tokio::spawn(deploy_config_mgr(config_mgr_rx, rate_limiter_tx.clone())) => 0,
-                _ = tokio::spawn(deploy_mgt_server(8888, config_mgr_tx.clone())) => 0,
+                _ = tokio::spawn(deploy_rate_limiter(rate_limiter_rx, rate_limiters.clone())) => 0,
+                _ = tokio::spawn(deploy_load_balancer(load_balancer_rx, load_balancer_tx.clone())) => 0,
+                _ = tokio::spawn(deploy_config_mgr(
+                    config_mgr_rx,
+                    rate_limiter_tx.clone(),
+                    load_balancer_tx.clone()
+                )) => 0,
+                _ = tokio::spawn(deploy_mgt_server(
+                    8888,
+                    config_mgr_tx.clone(),
+                    rate_limiters.clone(),
+                    load_balancer_tx.clone()
+                )) => 0,
                 _ = tokio::spawn(deploy_reverse_proxy(
                     8080,
+                    ip_access.clone(),
                     config_mgr_tx.clone(),
-                    rate_limiter_tx.clone()
+                    rate_limiters.clone(),
+                    load_balancer_tx.clone()
                 )) => 0,
                 _ = tokio::spawn(deploy_tls_reverse_proxy(
                     8443,
+                    ip_access.clone(),
                     config_mgr_tx.clone(),
-                    rate_limiter_tx.clone()
+                    rate_limiters.clone(),
+                    load_balancer_tx.clone()
                 )) => 0,
             );
         });